@@ -1,6 +1,7 @@
 use agora_core::{
-    AudioConfig, AudioDevice, AudioPipeline, EncryptedChannel, Identity, IdentityStorage,
-    MixerConfig, MixerManager, NetworkNode, Room, RoomConfig, SessionKey,
+    network::NetworkNodeConfig, AudioConfig, AudioDevice, AudioPipeline, BlocklistStorage,
+    EncryptedChannel, Identity, IdentityStorage, MixerConfig, MixerManager, NetworkNode, Room,
+    RoomConfig, SessionKey,
 };
 use clap::{Parser, Subcommand};
 
@@ -34,6 +35,21 @@ enum Commands {
     ImportIdentity {
         path: String,
     },
+    Block {
+        peer_id: String,
+        #[arg(short, long)]
+        reason: Option<String>,
+    },
+    Unblock {
+        peer_id: String,
+    },
+    ListBlocked,
+    ExportBlocklist {
+        path: String,
+    },
+    ImportBlocklist {
+        path: String,
+    },
     CreateRoom {
         #[arg(short, long)]
         name: Option<String>,
@@ -86,6 +102,11 @@ async fn main() {
         Commands::DeleteIdentity => handle_delete_identity().await,
         Commands::ExportIdentity { path } => handle_export_identity(&path).await,
         Commands::ImportIdentity { path } => handle_import_identity(&path).await,
+        Commands::Block { peer_id, reason } => handle_block(&peer_id, reason),
+        Commands::Unblock { peer_id } => handle_unblock(&peer_id),
+        Commands::ListBlocked => handle_list_blocked(),
+        Commands::ExportBlocklist { path } => handle_export_blocklist(&path),
+        Commands::ImportBlocklist { path } => handle_import_blocklist(&path),
         Commands::CreateRoom { name, password } => handle_create_room(name, password).await,
         Commands::StartNode {
             port,
@@ -309,6 +330,132 @@ async fn handle_import_identity(path: &str) {
     }
 }
 
+fn handle_block(peer_id: &str, reason: Option<String>) {
+    let peer_id = match agora_core::network::parse_peer_id(peer_id) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    let storage = match BlocklistStorage::new() {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Error initializing storage: {}", e);
+            return;
+        }
+    };
+
+    let result = storage.load().and_then(|mut blocklist| {
+        let added = blocklist.block(&peer_id, reason);
+        storage.save(&blocklist).map(|_| added)
+    });
+    match result {
+        Ok(true) => println!("Blocked {}", peer_id),
+        Ok(false) => println!("{} is already blocked", peer_id),
+        Err(e) => println!("Error updating blocklist: {}", e),
+    }
+}
+
+fn handle_unblock(peer_id: &str) {
+    let peer_id = match agora_core::network::parse_peer_id(peer_id) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+    let storage = match BlocklistStorage::new() {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Error initializing storage: {}", e);
+            return;
+        }
+    };
+
+    let result = storage.load().and_then(|mut blocklist| {
+        let removed = blocklist.unblock(&peer_id);
+        storage.save(&blocklist).map(|_| removed)
+    });
+    match result {
+        Ok(true) => println!("Unblocked {}", peer_id),
+        Ok(false) => println!("{} was not blocked", peer_id),
+        Err(e) => println!("Error updating blocklist: {}", e),
+    }
+}
+
+fn handle_list_blocked() {
+    let blocklist = match BlocklistStorage::new().and_then(|s| s.load()) {
+        Ok(b) => b,
+        Err(e) => {
+            println!("Error loading blocklist: {}", e);
+            return;
+        }
+    };
+
+    if blocklist.is_empty() {
+        println!("No blocked peers.");
+    }
+    for entry in blocklist.entries() {
+        match &entry.reason {
+            Some(reason) => println!("{}  ({})", entry.peer_id, reason),
+            None => println!("{}", entry.peer_id),
+        }
+    }
+
+    let shared: Vec<&str> = blocklist.shared_owners().collect();
+    if !shared.is_empty() {
+        println!("\nShared blocklists from:");
+        for owner in shared {
+            println!("  {}", owner);
+        }
+    }
+}
+
+fn handle_export_blocklist(path: &str) {
+    let identity = match IdentityStorage::new().and_then(|s| s.load()) {
+        Ok(id) => id,
+        Err(e) => {
+            println!("Error loading identity: {}", e);
+            println!("Run 'agora save-identity' first; shared blocklists are signed by it.");
+            return;
+        }
+    };
+    let storage = match BlocklistStorage::new() {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Error initializing storage: {}", e);
+            return;
+        }
+    };
+
+    let result = storage.load().and_then(|blocklist| {
+        storage.export_to_file(&blocklist, &identity, std::path::Path::new(path))
+    });
+    match result {
+        Ok(_) => println!("Signed blocklist exported to: {}", path),
+        Err(e) => println!("Error exporting blocklist: {}", e),
+    }
+}
+
+fn handle_import_blocklist(path: &str) {
+    let storage = match BlocklistStorage::new() {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Error initializing storage: {}", e);
+            return;
+        }
+    };
+
+    match storage.import_from_file(std::path::Path::new(path)) {
+        Ok(list) => {
+            println!("Imported blocklist from {}", list.owner);
+            println!("Entries: {}", list.blocked.len());
+        }
+        Err(e) => println!("Error importing blocklist: {}", e),
+    }
+}
+
 async fn handle_create_room(name: Option<String>, password: Option<String>) {
     println!("Creating new room...\n");
 
@@ -352,7 +499,33 @@ async fn handle_start_node(
         Some(listen_addr_str.as_str())
     };
 
-    let mut node = NetworkNode::new(listen_addr)
+    let identity = IdentityStorage::new()
+        .ok()
+        .filter(|storage| storage.has_stored_identity())
+        .and_then(|storage| storage.load().ok());
+    if identity.is_none() {
+        println!("No stored identity found, using a temporary peer ID.");
+        println!("Run 'agora save-identity' to create a persistent one.\n");
+    }
+
+    let blocklist = BlocklistStorage::new()
+        .and_then(|storage| storage.load())
+        .unwrap_or_else(|e| {
+            println!("Failed to load blocklist: {}", e);
+            Default::default()
+        });
+
+    let config = NetworkNodeConfig {
+        identity,
+        blocklist,
+        listen_addr: listen_addr.map(|s| s.to_string()),
+        enable_mdns: true,
+        data_dir: IdentityStorage::new()
+            .ok()
+            .map(|storage| storage.config_dir().to_path_buf()),
+        ..Default::default()
+    };
+    let mut node = NetworkNode::with_config(config)
         .await
         .expect("Failed to start network node");
     println!("Local Peer ID: {}", node.peer_id_string());
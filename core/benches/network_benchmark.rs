@@ -1,6 +1,14 @@
+use agora_core::audio_processor::AudioProcessorConfig;
 use agora_core::ice::{Candidate, CandidateType, IceAgent, IceConfig};
+use agora_core::network::{AudioTransport, NetworkNodeConfig};
+use agora_core::protocol::AUDIO_FRAME_SIZE;
+use agora_core::{AudioPacket, ControlMessage, NetworkCommand, NetworkEvent, NetworkNode};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use libp2p::PeerId;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::{broadcast, mpsc};
 
 fn bench_candidate_creation(c: &mut Criterion) {
     let mut group = c.benchmark_group("ice_candidate_creation");
@@ -95,6 +103,151 @@ fn bench_tcp_hole_punch_config(c: &mut Criterion) {
     group.finish();
 }
 
+const BENCH_ROOM: &str = "bench";
+
+struct AudioLink {
+    commands: mpsc::Sender<NetworkCommand>,
+    received: broadcast::Receiver<NetworkEvent>,
+    receiver_id: PeerId,
+}
+
+async fn spawn_node(transport: AudioTransport) -> (NetworkNode, broadcast::Receiver<NetworkEvent>) {
+    let config = NetworkNodeConfig {
+        listen_addr: Some("/ip4/127.0.0.1/tcp/0".to_string()),
+        stun_servers: vec![],
+        audio: AudioProcessorConfig::default()
+            .with_denoising(false)
+            .with_echo_cancellation(false),
+        audio_transport: transport,
+        ..Default::default()
+    };
+    let node = NetworkNode::with_config(config)
+        .await
+        .expect("failed to create node");
+    let events = node.subscribe_events();
+    (node, events)
+}
+
+async fn connect_audio_link(transport: AudioTransport) -> AudioLink {
+    let (mut receiver, mut received) = spawn_node(transport).await;
+    let (mut sender, mut sender_events) = spawn_node(transport).await;
+    let receiver_id = receiver.local_peer_id();
+    let sender_id = sender.local_peer_id();
+    let receiver_commands = receiver.command_sender();
+    let commands = sender.command_sender();
+
+    tokio::spawn(async move { receiver.run().await });
+    tokio::spawn(async move { sender.run().await });
+
+    let addr = loop {
+        if let Ok(NetworkEvent::Listening(addr)) = received.recv().await {
+            break addr;
+        }
+    };
+
+    commands
+        .send(NetworkCommand::ConnectToPeer { addr })
+        .await
+        .expect("sender node stopped");
+
+    loop {
+        if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = sender_events.recv().await {
+            if peer_id == receiver_id {
+                break;
+            }
+        }
+    }
+
+    // Audio is only sent under a shared room key, so put both in a room.
+    for node in [&receiver_commands, &commands] {
+        node.send(NetworkCommand::JoinRoom {
+            room_id: BENCH_ROOM.to_string(),
+            password: None,
+            creator: None,
+        })
+        .await
+        .expect("node stopped");
+    }
+    commands
+        .send(NetworkCommand::SendControl {
+            peer_id: receiver_id,
+            message: ControlMessage::join_room(BENCH_ROOM.to_string(), sender_id.to_string()),
+        })
+        .await
+        .expect("sender node stopped");
+    for events in [&mut received, &mut sender_events] {
+        loop {
+            if let Ok(NetworkEvent::KeyRotated(event)) = events.recv().await {
+                if event.new_key_id > 1 {
+                    break;
+                }
+            }
+        }
+    }
+
+    AudioLink {
+        commands,
+        received,
+        receiver_id,
+    }
+}
+
+async fn send_and_receive(link: &mut AudioLink, sequence: u64) {
+    let packet = AudioPacket::new(sequence, "bench".to_string(), vec![0.1; AUDIO_FRAME_SIZE]);
+    link.commands
+        .send(NetworkCommand::SendAudio {
+            peer_id: link.receiver_id,
+            packet,
+        })
+        .await
+        .expect("sender node stopped");
+
+    let wait = async {
+        loop {
+            match link.received.recv().await {
+                Ok(NetworkEvent::AudioReceived { packet, .. }) if packet.sequence == sequence => {
+                    break
+                }
+                Err(broadcast::error::RecvError::Closed) => panic!("receiver node stopped"),
+                _ => {}
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .unwrap_or_else(|_| panic!("audio frame {} not delivered", sequence));
+}
+
+fn bench_audio_transport(c: &mut Criterion) {
+    let mut group = c.benchmark_group("audio_transport");
+    group.sample_size(20);
+
+    let rt = Runtime::new().expect("failed to create runtime");
+
+    for (name, transport) in [
+        ("stream_v2", AudioTransport::Stream),
+        ("request_response_v1", AudioTransport::RequestResponse),
+    ] {
+        let mut link = rt.block_on(connect_audio_link(transport));
+        let mut sequence = 0u64;
+
+        group.bench_function(format!("{}_frame_latency", name), |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        sequence += 1;
+                        send_and_receive(&mut link, sequence).await;
+                    }
+                    start.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_candidate_creation,
@@ -104,6 +257,7 @@ criterion_group!(
     bench_nat_type_detection,
     bench_upnp_discovery,
     bench_tcp_hole_punch_config,
+    bench_audio_transport,
 );
 
 criterion_main!(benches);
@@ -165,6 +165,9 @@ impl AudioDevice {
 
 pub type AudioFrame = Vec<f32>;
 
+/// Lowest level [`audio_level`] reports.
+pub const SILENT_AUDIO_LEVEL: u8 = 127;
+
 #[derive(Debug, Clone)]
 pub struct AudioStats {
     pub frames_processed: u64,
@@ -536,6 +539,18 @@ pub fn calculate_db(rms: f32) -> f32 {
     20.0 * rms.log10()
 }
 
+/// Level of a frame in -dBov as in RFC 6464: 0 is full scale and
+/// [`SILENT_AUDIO_LEVEL`] means silence.
+pub fn audio_level(samples: &[f32]) -> u8 {
+    let rms = calculate_rms(samples);
+    if rms <= 0.0 {
+        return SILENT_AUDIO_LEVEL;
+    }
+    (-calculate_db(rms))
+        .round()
+        .clamp(0.0, SILENT_AUDIO_LEVEL as f32) as u8
+}
+
 pub fn normalize_audio(samples: &mut [f32], target_peak: f32) {
     if samples.is_empty() {
         return;
@@ -621,6 +636,13 @@ mod tests {
         assert!((calculate_db(1.0) - 0.0).abs() < 0.001);
     }
 
+    #[test]
+    fn test_audio_level() {
+        assert_eq!(audio_level(&[0.0; 100]), SILENT_AUDIO_LEVEL);
+        assert_eq!(audio_level(&[1.0; 100]), 0);
+        assert_eq!(audio_level(&[0.1; 100]), 20);
+    }
+
     #[test]
     fn test_normalize_audio() {
         let mut samples = vec![0.5, -0.5, 0.25];
@@ -4,6 +4,7 @@ use crate::codec::{EncodedFrame, OpusConfig, OpusDecoder, OpusEncoder, OpusMode}
 use crate::denoise::RnnoiseDenoiser;
 use crate::error::{AgoraResult, Error};
 
+#[derive(Clone)]
 pub struct AudioProcessorConfig {
     pub audio: AudioConfig,
     pub opus: OpusConfig,
@@ -0,0 +1,482 @@
+use crate::network::{read_frame, write_frame};
+use crate::protocol::{EncryptedAudioPacket, AUDIO_STREAM_PROTOCOL, MAX_FRAME_SIZE};
+use futures::{AsyncWriteExt, StreamExt};
+use libp2p::{PeerId, Stream, StreamProtocol};
+use libp2p_stream::{Control, IncomingStreams, OpenStreamError};
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Notify};
+
+pub const AUDIO_STREAM_QUEUE_DEPTH: usize = 8;
+pub const AUDIO_FRAME_MAX_AGE: Duration = Duration::from_millis(100);
+pub const MAX_ROOM_ID_LEN: usize = 256;
+pub const PER_FRAME_STREAM_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn audio_stream_protocol() -> StreamProtocol {
+    StreamProtocol::new(AUDIO_STREAM_PROTOCOL)
+}
+
+struct QueuedFrame {
+    data: Vec<u8>,
+    queued_at: Instant,
+}
+
+#[derive(Default)]
+struct QueueState {
+    frames: VecDeque<QueuedFrame>,
+    closed: bool,
+}
+
+/// Bounded frame queue between the network loop and a stream writer.
+///
+/// Pushing never blocks: when the writer falls behind, the oldest frames are
+/// dropped, and frames that waited longer than `max_age` are skipped on pop.
+/// For live audio a late frame is worth less than a missing one.
+pub struct FrameQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    capacity: usize,
+    max_age: Duration,
+    dropped: AtomicU64,
+}
+
+impl FrameQueue {
+    pub fn new(capacity: usize, max_age: Duration) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+            capacity: capacity.max(1),
+            max_age,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn push(&self, data: Vec<u8>) -> bool {
+        {
+            let mut state = match self.state.lock() {
+                Ok(guard) => guard,
+                Err(e) => {
+                    tracing::error!("Audio frame queue mutex poisoned: {}", e);
+                    return false;
+                }
+            };
+
+            if state.closed {
+                return false;
+            }
+
+            while state.frames.len() >= self.capacity {
+                state.frames.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+
+            state.frames.push_back(QueuedFrame {
+                data,
+                queued_at: Instant::now(),
+            });
+        }
+
+        self.notify.notify_one();
+        true
+    }
+
+    pub async fn pop(&self) -> Option<Vec<u8>> {
+        loop {
+            {
+                let mut state = self.state.lock().ok()?;
+
+                while let Some(frame) = state.frames.pop_front() {
+                    if frame.queued_at.elapsed() <= self.max_age {
+                        return Some(frame.data);
+                    }
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            state.frames.clear();
+        }
+        self.notify.notify_one();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().map(|s| s.frames.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStatus {
+    Opening,
+    Open,
+    Closed,
+    Unsupported,
+}
+
+impl StreamStatus {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => StreamStatus::Opening,
+            1 => StreamStatus::Open,
+            2 => StreamStatus::Closed,
+            _ => StreamStatus::Unsupported,
+        }
+    }
+}
+
+/// How an [`AudioStreamSender`] maps frames onto substreams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamMode {
+    /// One substream for the lifetime of the sender. Best for TCP, where
+    /// opening a yamux substream per frame is expensive.
+    Persistent,
+    /// A fresh substream per frame. On QUIC streams are cheap and independent,
+    /// so a lost packet only delays its own frame.
+    PerFrame,
+}
+
+/// Outbound half of a `/agora/audio/2.0.0` stream for one peer and room.
+///
+/// Substreams are opened by a background task. Every substream starts with the
+/// room id as a header frame, followed by audio frames drained from the
+/// [`FrameQueue`].
+pub struct AudioStreamSender {
+    queue: Arc<FrameQueue>,
+    status: Arc<AtomicU8>,
+    mode: StreamMode,
+}
+
+impl AudioStreamSender {
+    pub fn spawn(control: Control, peer_id: PeerId, room_id: String, mode: StreamMode) -> Self {
+        let queue = Arc::new(FrameQueue::new(
+            AUDIO_STREAM_QUEUE_DEPTH,
+            AUDIO_FRAME_MAX_AGE,
+        ));
+        let status = Arc::new(AtomicU8::new(StreamStatus::Opening as u8));
+
+        match mode {
+            StreamMode::Persistent => {
+                tokio::spawn(run_sender(
+                    control,
+                    peer_id,
+                    room_id,
+                    queue.clone(),
+                    status.clone(),
+                ));
+            }
+            StreamMode::PerFrame => {
+                tokio::spawn(run_per_frame_sender(
+                    control,
+                    peer_id,
+                    room_id,
+                    queue.clone(),
+                    status.clone(),
+                ));
+            }
+        }
+
+        Self {
+            queue,
+            status,
+            mode,
+        }
+    }
+
+    pub fn mode(&self) -> StreamMode {
+        self.mode
+    }
+
+    pub fn send(&self, packet: &EncryptedAudioPacket) -> io::Result<()> {
+        let data = packet.encode()?;
+        if self.queue.push(data) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Audio stream closed",
+            ))
+        }
+    }
+
+    pub fn status(&self) -> StreamStatus {
+        StreamStatus::from_u8(self.status.load(Ordering::Acquire))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(
+            self.status(),
+            StreamStatus::Closed | StreamStatus::Unsupported
+        )
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.queue.dropped()
+    }
+}
+
+impl Drop for AudioStreamSender {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+async fn run_sender(
+    mut control: Control,
+    peer_id: PeerId,
+    room_id: String,
+    queue: Arc<FrameQueue>,
+    status: Arc<AtomicU8>,
+) {
+    let mut stream = match control.open_stream(peer_id, audio_stream_protocol()).await {
+        Ok(stream) => stream,
+        Err(OpenStreamError::UnsupportedProtocol(_)) => {
+            tracing::debug!("Peer {} does not support audio streams", peer_id);
+            status.store(StreamStatus::Unsupported as u8, Ordering::Release);
+            queue.close();
+            return;
+        }
+        Err(e) => {
+            tracing::warn!("Failed to open audio stream to {}: {}", peer_id, e);
+            status.store(StreamStatus::Closed as u8, Ordering::Release);
+            queue.close();
+            return;
+        }
+    };
+
+    if let Err(e) = write_frame(&mut stream, room_id.as_bytes()).await {
+        tracing::warn!("Failed to write audio stream header to {}: {}", peer_id, e);
+        status.store(StreamStatus::Closed as u8, Ordering::Release);
+        queue.close();
+        return;
+    }
+
+    status.store(StreamStatus::Open as u8, Ordering::Release);
+    tracing::debug!("Audio stream to {} for room '{}' open", peer_id, room_id);
+
+    while let Some(data) = queue.pop().await {
+        if let Err(e) = write_frame(&mut stream, &data).await {
+            tracing::debug!("Audio stream to {} closed: {}", peer_id, e);
+            break;
+        }
+    }
+
+    status.store(StreamStatus::Closed as u8, Ordering::Release);
+    queue.close();
+    let _ = stream.close().await;
+
+    if queue.dropped() > 0 {
+        tracing::debug!(
+            "Audio stream to {} dropped {} stale frames",
+            peer_id,
+            queue.dropped()
+        );
+    }
+}
+
+async fn run_per_frame_sender(
+    control: Control,
+    peer_id: PeerId,
+    room_id: String,
+    queue: Arc<FrameQueue>,
+    status: Arc<AtomicU8>,
+) {
+    let room_id: Arc<str> = room_id.into();
+    status.store(StreamStatus::Open as u8, Ordering::Release);
+
+    while let Some(data) = queue.pop().await {
+        let mut control = control.clone();
+        let room_id = room_id.clone();
+        let queue = queue.clone();
+        let status = status.clone();
+
+        tokio::spawn(async move {
+            let send = send_single_frame(&mut control, peer_id, &room_id, &data);
+            match tokio::time::timeout(PER_FRAME_STREAM_TIMEOUT, send).await {
+                Ok(Ok(())) => {}
+                Ok(Err(OpenStreamError::UnsupportedProtocol(_))) => {
+                    tracing::debug!("Peer {} does not support audio streams", peer_id);
+                    status.store(StreamStatus::Unsupported as u8, Ordering::Release);
+                    queue.close();
+                }
+                Ok(Err(e)) => {
+                    tracing::debug!("Audio frame stream to {} failed: {}", peer_id, e);
+                }
+                Err(_) => {
+                    tracing::trace!("Audio frame stream to {} timed out", peer_id);
+                }
+            }
+        });
+    }
+
+    if status.load(Ordering::Acquire) != StreamStatus::Unsupported as u8 {
+        status.store(StreamStatus::Closed as u8, Ordering::Release);
+    }
+}
+
+async fn send_single_frame(
+    control: &mut Control,
+    peer_id: PeerId,
+    room_id: &str,
+    data: &[u8],
+) -> Result<(), OpenStreamError> {
+    let mut stream = control
+        .open_stream(peer_id, audio_stream_protocol())
+        .await?;
+    write_frame(&mut stream, room_id.as_bytes()).await?;
+    write_frame(&mut stream, data).await?;
+    stream.close().await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct InboundAudio {
+    pub peer_id: PeerId,
+    pub room_id: String,
+    pub packet: EncryptedAudioPacket,
+}
+
+/// Accepts inbound audio streams and forwards decoded packets to `tx`.
+///
+/// Frames are dropped rather than queued when `tx` is full, so a slow consumer
+/// never stalls the remote writer.
+pub async fn accept_audio_streams(mut incoming: IncomingStreams, tx: mpsc::Sender<InboundAudio>) {
+    while let Some((peer_id, stream)) = incoming.next().await {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = read_audio_stream(peer_id, stream, tx).await {
+                tracing::debug!("Inbound audio stream from {} ended: {}", peer_id, e);
+            }
+        });
+    }
+}
+
+async fn read_audio_stream(
+    peer_id: PeerId,
+    mut stream: Stream,
+    tx: mpsc::Sender<InboundAudio>,
+) -> io::Result<()> {
+    let header = read_frame(&mut stream, MAX_ROOM_ID_LEN).await?;
+    let room_id =
+        String::from_utf8(header).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    tracing::debug!(
+        "Inbound audio stream from {} for room '{}'",
+        peer_id,
+        room_id
+    );
+
+    loop {
+        let data = match read_frame(&mut stream, MAX_FRAME_SIZE).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let packet = EncryptedAudioPacket::decode(&data)?;
+
+        let inbound = InboundAudio {
+            peer_id,
+            room_id: room_id.clone(),
+            packet,
+        };
+
+        match tx.try_send(inbound) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(dropped)) => {
+                tracing::trace!(
+                    "Dropping audio frame {} from {}: receiver busy",
+                    dropped.packet.sequence,
+                    peer_id
+                );
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_queue_fifo() {
+        let queue = FrameQueue::new(4, Duration::from_secs(1));
+        assert!(queue.push(vec![1]));
+        assert!(queue.push(vec![2]));
+
+        assert_eq!(queue.pop().await, Some(vec![1]));
+        assert_eq!(queue.pop().await, Some(vec![2]));
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_frame_queue_drops_oldest_when_full() {
+        let queue = FrameQueue::new(2, Duration::from_secs(1));
+        queue.push(vec![1]);
+        queue.push(vec![2]);
+        queue.push(vec![3]);
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop().await, Some(vec![2]));
+        assert_eq!(queue.pop().await, Some(vec![3]));
+    }
+
+    #[tokio::test]
+    async fn test_frame_queue_skips_stale_frames() {
+        let queue = FrameQueue::new(4, Duration::from_millis(10));
+        queue.push(vec![1]);
+        std::thread::sleep(Duration::from_millis(20));
+        queue.push(vec![2]);
+
+        assert_eq!(queue.pop().await, Some(vec![2]));
+        assert_eq!(queue.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_frame_queue_close() {
+        let queue = FrameQueue::new(4, Duration::from_secs(1));
+        queue.push(vec![1]);
+        queue.close();
+
+        assert!(!queue.push(vec![2]));
+        assert_eq!(queue.pop().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_frame_queue_wakes_waiting_writer() {
+        let queue = Arc::new(FrameQueue::new(4, Duration::from_secs(1)));
+        let writer = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.pop().await })
+        };
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.push(vec![42]);
+
+        let popped = tokio::time::timeout(Duration::from_secs(1), writer)
+            .await
+            .expect("writer should be woken")
+            .unwrap();
+        assert_eq!(popped, Some(vec![42]));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::error::{AgoraResult, Error};
+use crate::identity::{verifying_key_from_peer_id, Identity};
+use crate::reputation::VouchManager;
+use ed25519_dalek::{Signature, Verifier};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIGNING_DOMAIN: &str = "agora blocklist v1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockEntry {
+    pub peer_id: String,
+    pub reason: Option<String>,
+    pub blocked_at: u64,
+}
+
+/// A blocklist as shared with contacts: only the blocked peer IDs, signed by
+/// the owner so it cannot be altered or attributed to someone else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedBlocklist {
+    pub owner: String,
+    pub blocked: Vec<String>,
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct SigningPayload<'a> {
+    domain: &'static str,
+    owner: &'a str,
+    blocked: &'a [String],
+    issued_at: u64,
+}
+
+impl SignedBlocklist {
+    fn signing_bytes(&self) -> AgoraResult<Vec<u8>> {
+        postcard::to_allocvec(&SigningPayload {
+            domain: SIGNING_DOMAIN,
+            owner: &self.owner,
+            blocked: &self.blocked,
+            issued_at: self.issued_at,
+        })
+        .map_err(|e| Error::Crypto(format!("Failed to encode blocklist: {}", e)))
+    }
+
+    pub fn verify(&self) -> AgoraResult<()> {
+        let owner: PeerId = self
+            .owner
+            .parse()
+            .map_err(|e| Error::Crypto(format!("Invalid owner {}: {}", self.owner, e)))?;
+        let key = verifying_key_from_peer_id(&owner)?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|e| Error::Crypto(format!("Invalid signature: {}", e)))?;
+        key.verify(&self.signing_bytes()?, &signature)
+            .map_err(|_| Error::Crypto("Blocklist signature mismatch".to_string()))
+    }
+}
+
+/// The local user's blocks, plus blocklists shared by contacts.
+///
+/// Only the user's own entries block anyone. Shared lists are opinions of
+/// others and only feed [`Blocklist::independent_blocks`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Blocklist {
+    entries: BTreeMap<String, BlockEntry>,
+    shared: HashMap<String, SignedBlocklist>,
+}
+
+impl Blocklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns false if the peer was already blocked.
+    pub fn block(&mut self, peer_id: &PeerId, reason: Option<String>) -> bool {
+        let key = peer_id.to_string();
+        if self.entries.contains_key(&key) {
+            return false;
+        }
+        self.entries.insert(
+            key.clone(),
+            BlockEntry {
+                peer_id: key,
+                reason,
+                blocked_at: now_secs(),
+            },
+        );
+        true
+    }
+
+    pub fn unblock(&mut self, peer_id: &PeerId) -> bool {
+        self.entries.remove(&peer_id.to_string()).is_some()
+    }
+
+    pub fn is_blocked(&self, peer_id: &PeerId) -> bool {
+        self.entries.contains_key(&peer_id.to_string())
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &BlockEntry> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Signs the blocked peer IDs for sharing. Reasons stay private.
+    pub fn export_signed(&self, identity: &Identity) -> AgoraResult<SignedBlocklist> {
+        let mut signed = SignedBlocklist {
+            owner: identity.peer_id(),
+            blocked: self.entries.keys().cloned().collect(),
+            issued_at: now_secs(),
+            signature: Vec::new(),
+        };
+        signed.signature = identity.sign(&signed.signing_bytes()?).to_bytes().to_vec();
+        Ok(signed)
+    }
+
+    /// Stores a contact's list after checking its signature. An older list
+    /// from the same owner never replaces a newer one.
+    pub fn import_shared(&mut self, list: SignedBlocklist) -> AgoraResult<()> {
+        list.verify()?;
+        if let Some(existing) = self.shared.get(&list.owner) {
+            if existing.issued_at > list.issued_at {
+                return Err(Error::Storage(format!(
+                    "Already have a newer blocklist from {}",
+                    list.owner
+                )));
+            }
+        }
+        self.shared.insert(list.owner.clone(), list);
+        Ok(())
+    }
+
+    pub fn remove_shared(&mut self, owner: &PeerId) -> bool {
+        self.shared.remove(&owner.to_string()).is_some()
+    }
+
+    pub fn shared_owners(&self) -> impl Iterator<Item = &str> {
+        self.shared.keys().map(String::as_str)
+    }
+
+    /// Counts shared lists blocking `peer_id` from owners that are vouched
+    /// for independently: each counted owner needs a voucher not already
+    /// used for another, so one voucher's sybils count once.
+    pub fn independent_blocks(&self, peer_id: &PeerId, vouches: &VouchManager) -> u32 {
+        let target = peer_id.to_string();
+        let mut owners: Vec<&str> = self
+            .shared
+            .values()
+            .filter(|list| list.owner != target && list.blocked.contains(&target))
+            .map(|list| list.owner.as_str())
+            .collect();
+        owners.sort_unstable();
+
+        let mut used_vouchers = HashSet::new();
+        owners
+            .into_iter()
+            .filter(|owner| {
+                vouches
+                    .get_vouches_for_vouchee(owner)
+                    .into_iter()
+                    .filter(|v| v.is_valid() && v.voucher_peer_id != target)
+                    .any(|v| used_vouchers.insert(v.voucher_peer_id.clone()))
+            })
+            .count() as u32
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reputation::VouchLimits;
+
+    #[test]
+    fn test_block_and_unblock() {
+        let peer = Identity::generate().unwrap().libp2p_peer_id();
+        let mut blocklist = Blocklist::new();
+
+        assert!(blocklist.block(&peer, Some("spam".to_string())));
+        assert!(!blocklist.block(&peer, None));
+        assert!(blocklist.is_blocked(&peer));
+
+        assert!(blocklist.unblock(&peer));
+        assert!(!blocklist.is_blocked(&peer));
+    }
+
+    #[test]
+    fn test_signed_export_roundtrip() {
+        let owner = Identity::generate().unwrap();
+        let peer = Identity::generate().unwrap().libp2p_peer_id();
+        let mut blocklist = Blocklist::new();
+        blocklist.block(&peer, Some("private reason".to_string()));
+
+        let signed = blocklist.export_signed(&owner).unwrap();
+        assert_eq!(signed.blocked, vec![peer.to_string()]);
+        signed.verify().unwrap();
+
+        let mut tampered = signed.clone();
+        tampered.blocked.clear();
+        assert!(tampered.verify().is_err());
+
+        let mut forged = signed;
+        forged.owner = Identity::generate().unwrap().peer_id();
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn test_shared_lists_do_not_block() {
+        let owner = Identity::generate().unwrap();
+        let peer = Identity::generate().unwrap().libp2p_peer_id();
+        let mut theirs = Blocklist::new();
+        theirs.block(&peer, None);
+
+        let mut ours = Blocklist::new();
+        ours.import_shared(theirs.export_signed(&owner).unwrap())
+            .unwrap();
+        assert!(!ours.is_blocked(&peer));
+    }
+
+    #[test]
+    fn test_independent_blocks_require_vouched_owners() {
+        let target = Identity::generate().unwrap().libp2p_peer_id();
+        let voucher = Identity::generate().unwrap();
+        let mut vouches = VouchManager::new(VouchLimits::default());
+        let mut ours = Blocklist::new();
+
+        let owners: Vec<Identity> = (0..3).map(|_| Identity::generate().unwrap()).collect();
+        for owner in &owners {
+            let mut theirs = Blocklist::new();
+            theirs.block(&target, None);
+            ours.import_shared(theirs.export_signed(owner).unwrap())
+                .unwrap();
+        }
+        assert_eq!(ours.independent_blocks(&target, &vouches), 0);
+
+        // One voucher behind several blockers counts once.
+        let voucher = voucher.peer_id();
+        for owner in &owners[..2] {
+            vouches
+                .create_vouch(voucher.clone(), owner.peer_id(), None)
+                .unwrap();
+        }
+        assert_eq!(ours.independent_blocks(&target, &vouches), 1);
+
+        let second = Identity::generate().unwrap().peer_id();
+        vouches
+            .create_vouch(second, owners[2].peer_id(), None)
+            .unwrap();
+        assert_eq!(ours.independent_blocks(&target, &vouches), 2);
+    }
+}
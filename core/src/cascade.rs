@@ -0,0 +1,229 @@
+use crate::election::MixerGroup;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A dedicated node known from node discovery, with the latency we measured
+/// to it. Preferred as a mixer for its region while it is in the room.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MixerNode {
+    pub peer_id: String,
+    pub region: String,
+    pub latency_ms: Option<u32>,
+    /// Discovery score from 0 to 1, covering load, uptime and reputation.
+    pub score: f32,
+    /// The node's reputation from 0 to 1; only trusted nodes are preferred.
+    pub reputation: f32,
+}
+
+/// A room participant that could mix for part of a cascaded room.
+#[derive(Debug, Clone)]
+pub struct CascadeCandidate {
+    pub peer_id: String,
+    pub region: Option<String>,
+    pub score: f64,
+}
+
+/// Splits a room into groups of at most `capacity` participants, each with
+/// its own mixer. Regions big enough to fill half a group get groups of
+/// their own, so audio only crosses regions between mixers; the rest share.
+/// Within a pool, dedicated nodes come first, nearest first, then the
+/// best-scored participants.
+pub fn plan_cascade(
+    candidates: &[CascadeCandidate],
+    nodes: &[MixerNode],
+    capacity: usize,
+) -> Vec<MixerGroup> {
+    let capacity = capacity.max(2);
+    let node = |peer_id: &str| nodes.iter().find(|n| n.peer_id == peer_id);
+
+    let mut regions: BTreeMap<Option<String>, Vec<&CascadeCandidate>> = BTreeMap::new();
+    for candidate in candidates {
+        let region = node(&candidate.peer_id)
+            .map(|n| n.region.clone())
+            .or_else(|| candidate.region.clone());
+        regions.entry(region).or_default().push(candidate);
+    }
+
+    let mut shared = Vec::new();
+    regions.retain(|_, peers| {
+        if peers.len() * 2 >= capacity {
+            return true;
+        }
+        shared.append(peers);
+        false
+    });
+    let mut pools: Vec<Vec<&CascadeCandidate>> = regions.into_values().collect();
+    if !shared.is_empty() {
+        pools.push(shared);
+    }
+
+    let mut groups = Vec::new();
+    for mut pool in pools {
+        pool.sort_by(|a, b| {
+            let (node_a, node_b) = (node(&a.peer_id), node(&b.peer_id));
+            node_b
+                .is_some()
+                .cmp(&node_a.is_some())
+                .then_with(|| {
+                    let latency = |n: Option<&MixerNode>| n.and_then(|n| n.latency_ms);
+                    match (latency(node_a), latency(node_b)) {
+                        (Some(a), Some(b)) => a.cmp(&b),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    }
+                })
+                .then_with(|| {
+                    let score = |n: Option<&MixerNode>| n.map_or(0.0, |n| n.score);
+                    score(node_b)
+                        .partial_cmp(&score(node_a))
+                        .unwrap_or(Ordering::Equal)
+                })
+                .then_with(|| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal))
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+
+        let count = pool.len().div_ceil(capacity);
+        let (mixers, members) = pool.split_at(count);
+        let mut members = members.to_vec();
+        members.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+
+        let first = groups.len();
+        groups.extend(mixers.iter().map(|mixer| MixerGroup {
+            mixer: mixer.peer_id.clone(),
+            members: Vec::new(),
+        }));
+        for (i, member) in members.into_iter().enumerate() {
+            groups[first + i % count]
+                .members
+                .push(member.peer_id.clone());
+        }
+    }
+    groups
+}
+
+/// The mixer serving `peer_id`. Participants who joined after the plan was
+/// made are spread over the groups by a hash of their ID, which every peer
+/// computes the same way until the next election places them properly.
+pub(crate) fn mixer_for<'a>(cascade: &'a [MixerGroup], peer_id: &str) -> Option<&'a str> {
+    if let Some(group) = cascade
+        .iter()
+        .find(|g| g.mixer == peer_id || g.members.iter().any(|m| m == peer_id))
+    {
+        return Some(&group.mixer);
+    }
+    if cascade.is_empty() {
+        return None;
+    }
+    // FNV-1a, which unlike the std hasher is the same on every peer.
+    let hash = peer_id.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    Some(&cascade[(hash % cascade.len() as u64) as usize].mixer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(peer_id: &str, region: Option<&str>, score: f64) -> CascadeCandidate {
+        CascadeCandidate {
+            peer_id: peer_id.to_string(),
+            region: region.map(str::to_string),
+            score,
+        }
+    }
+
+    fn node(peer_id: &str, region: &str, latency_ms: u32) -> MixerNode {
+        MixerNode {
+            peer_id: peer_id.to_string(),
+            region: region.to_string(),
+            latency_ms: Some(latency_ms),
+            score: 0.9,
+            reputation: 0.9,
+        }
+    }
+
+    #[test]
+    fn test_plan_covers_everyone_within_capacity() {
+        let candidates: Vec<_> = (0..45)
+            .map(|i| candidate(&format!("peer{:02}", i), None, i as f64 / 100.0))
+            .collect();
+        let groups = plan_cascade(&candidates, &[], 20);
+
+        assert_eq!(groups.len(), 3);
+        // The best-scored participants mix.
+        assert_eq!(groups[0].mixer, "peer44");
+        let mut everyone: Vec<&str> = groups
+            .iter()
+            .flat_map(|g| std::iter::once(&g.mixer).chain(&g.members))
+            .map(String::as_str)
+            .collect();
+        assert!(groups.iter().all(|g| g.members.len() < 20));
+        everyone.sort();
+        everyone.dedup();
+        assert_eq!(everyone.len(), 45);
+    }
+
+    #[test]
+    fn test_plan_keeps_regions_together() {
+        let mut candidates = Vec::new();
+        for i in 0..15 {
+            candidates.push(candidate(&format!("eu{:02}", i), Some("eu-west"), 0.5));
+            candidates.push(candidate(&format!("us{:02}", i), Some("us-east"), 0.5));
+        }
+        candidates.push(candidate("ap00", Some("ap-south"), 0.9));
+        candidates.push(candidate("unknown", None, 0.1));
+
+        let groups = plan_cascade(&candidates, &[], 20);
+        assert_eq!(groups.len(), 3);
+        for group in &groups {
+            let prefix = &group.mixer[..2];
+            if prefix == "eu" || prefix == "us" {
+                assert!(group.members.iter().all(|m| m.starts_with(prefix)));
+            }
+        }
+        // Regions too small for a group share one.
+        assert!(groups
+            .iter()
+            .any(|g| g.mixer == "ap00" && g.members == vec!["unknown".to_string()]));
+    }
+
+    #[test]
+    fn test_plan_prefers_nearest_dedicated_node() {
+        let mut candidates: Vec<_> = (0..10)
+            .map(|i| candidate(&format!("peer{}", i), Some("eu-west"), 0.9))
+            .collect();
+        candidates.push(candidate("far-node", None, 0.1));
+        candidates.push(candidate("near-node", None, 0.1));
+        let nodes = [
+            node("far-node", "eu-west", 120),
+            node("near-node", "eu-west", 15),
+        ];
+
+        let groups = plan_cascade(&candidates, &nodes, 20);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].mixer, "near-node");
+    }
+
+    #[test]
+    fn test_late_joiners_get_a_stable_mixer() {
+        let cascade = vec![
+            MixerGroup {
+                mixer: "a".to_string(),
+                members: vec!["b".to_string()],
+            },
+            MixerGroup {
+                mixer: "c".to_string(),
+                members: vec!["d".to_string()],
+            },
+        ];
+        assert_eq!(mixer_for(&cascade, "a"), Some("a"));
+        assert_eq!(mixer_for(&cascade, "d"), Some("c"));
+        let late = mixer_for(&cascade, "late");
+        assert!(late.is_some());
+        assert_eq!(mixer_for(&cascade, "late"), late);
+        assert_eq!(mixer_for(&[], "late"), None);
+    }
+}
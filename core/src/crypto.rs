@@ -1,7 +1,7 @@
 use crate::error::{AgoraResult, Error};
 use base64::Engine;
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     ChaCha20Poly1305, Nonce,
 };
 use hkdf::Hkdf;
@@ -298,12 +298,178 @@ struct RoomKeys {
     next_rotation: Instant,
 }
 
+pub const REPLAY_WINDOW_SIZE: u64 = 128;
+
+/// Sliding anti-replay window over one sender's frame counters.
+///
+/// Accepts counters above the highest seen and unseen counters up to
+/// [`REPLAY_WINDOW_SIZE`] behind it, so reordered frames still decrypt.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    bitmap: u128,
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&self, counter: u64) -> bool {
+        let Some(highest) = self.highest else {
+            return true;
+        };
+
+        if counter > highest {
+            return true;
+        }
+
+        let offset = highest - counter;
+        offset < REPLAY_WINDOW_SIZE && (self.bitmap >> offset) & 1 == 0
+    }
+
+    pub fn update(&mut self, counter: u64) {
+        match self.highest {
+            None => {
+                self.highest = Some(counter);
+                self.bitmap = 1;
+            }
+            Some(highest) if counter > highest => {
+                let shift = counter - highest;
+                self.bitmap = if shift >= REPLAY_WINDOW_SIZE {
+                    0
+                } else {
+                    self.bitmap << shift
+                };
+                self.bitmap |= 1;
+                self.highest = Some(counter);
+            }
+            Some(highest) => {
+                let offset = highest - counter;
+                if offset < REPLAY_WINDOW_SIZE {
+                    self.bitmap |= 1 << offset;
+                }
+            }
+        }
+    }
+}
+
+/// Per-sender key material derived from a room key, in the style of SFrame
+/// (RFC 9605).
+///
+/// Every participant shares the room key, so encrypting directly under it
+/// makes two senders' counter-0 frames reuse the same (key, nonce) pair.
+/// Deriving the key and nonce salt from the sender id gives each sender its
+/// own nonce space.
+struct SenderKey {
+    cipher: Cipher,
+    salt: [u8; 12],
+    send_counter: u64,
+    replay: ReplayWindow,
+}
+
+impl SenderKey {
+    fn derive(room_key: &SessionKey, key_id: u64, sender_id: &str) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(Some(&key_id.to_be_bytes()), room_key.as_bytes());
+
+        let mut key = [0u8; 32];
+        hkdf.expand_multi_info(&[b"agora sframe key", sender_id.as_bytes()], &mut key)
+            .expect("HKDF expand should never fail");
+
+        let mut salt = [0u8; 12];
+        hkdf.expand_multi_info(&[b"agora sframe salt", sender_id.as_bytes()], &mut salt)
+            .expect("HKDF expand should never fail");
+
+        Self {
+            cipher: Cipher::new_from_slice(&key).expect("Key must be 32 bytes"),
+            salt,
+            send_counter: 0,
+            replay: ReplayWindow::new(),
+        }
+    }
+
+    fn nonce(&self, counter: u64) -> [u8; 12] {
+        let mut nonce = self.salt;
+        for (byte, ctr) in nonce[4..].iter_mut().zip(counter.to_be_bytes()) {
+            *byte ^= ctr;
+        }
+        nonce
+    }
+
+    fn counter(&self, nonce: &[u8; 12]) -> Option<u64> {
+        if nonce[..4] != self.salt[..4] {
+            return None;
+        }
+
+        let mut counter = [0u8; 8];
+        for (i, byte) in counter.iter_mut().enumerate() {
+            *byte = nonce[4 + i] ^ self.salt[4 + i];
+        }
+        Some(u64::from_be_bytes(counter))
+    }
+
+    fn encrypt(&mut self, aad: &[u8], plaintext: &[u8]) -> AgoraResult<EncryptedMessage> {
+        let nonce = self.nonce(self.send_counter);
+        self.send_counter = self.send_counter.wrapping_add(1);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|e| Error::Crypto(format!("Encryption failed: {}", e)))?;
+
+        Ok(EncryptedMessage::new(nonce, ciphertext))
+    }
+
+    fn decrypt(&mut self, aad: &[u8], message: &EncryptedMessage) -> AgoraResult<Vec<u8>> {
+        let counter = self
+            .counter(&message.nonce)
+            .ok_or_else(|| Error::Crypto("Nonce does not match sender".to_string()))?;
+
+        if !self.replay.check(counter) {
+            return Err(Error::Crypto("Replay attack detected".to_string()));
+        }
+
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&message.nonce),
+                Payload {
+                    msg: message.ciphertext.as_slice(),
+                    aad,
+                },
+            )
+            .map_err(|e| Error::Crypto(format!("Decryption failed: {}", e)))?;
+
+        self.replay.update(counter);
+        Ok(plaintext)
+    }
+}
+
+/// Additional authenticated data binding an audio frame to its header,
+/// including the clear-text level forwarding nodes pick speakers by.
+pub fn audio_frame_aad(key_id: u64, sender_id: &str, sequence: u64, audio_level: u8) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(16 + 16 + 1 + sender_id.len());
+    aad.extend_from_slice(b"agora-audio-v1");
+    aad.extend_from_slice(&key_id.to_be_bytes());
+    aad.extend_from_slice(&sequence.to_be_bytes());
+    aad.push(audio_level);
+    aad.extend_from_slice(sender_id.as_bytes());
+    aad
+}
+
 struct SessionKeyInfo {
     id: u64,
     key: SessionKey,
     cipher: Cipher,
     send_counter: u64,
     recv_counters: HashMap<u64, Instant>,
+    senders: HashMap<String, SenderKey>,
 }
 
 impl SessionKeyInfo {
@@ -315,7 +481,47 @@ impl SessionKeyInfo {
             cipher,
             send_counter: 0,
             recv_counters: HashMap::new(),
+            senders: HashMap::new(),
+        }
+    }
+
+    fn encrypt_as(
+        &mut self,
+        sender_id: &str,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> AgoraResult<EncryptedMessage> {
+        if self.key.is_expired() {
+            return Err(Error::Crypto("Session key expired".to_string()));
         }
+
+        let (key, id) = (&self.key, self.id);
+        self.senders
+            .entry(sender_id.to_string())
+            .or_insert_with(|| SenderKey::derive(key, id, sender_id))
+            .encrypt(aad, plaintext)
+    }
+
+    fn decrypt_from(
+        &mut self,
+        sender_id: &str,
+        aad: &[u8],
+        message: &EncryptedMessage,
+    ) -> AgoraResult<Vec<u8>> {
+        if self.key.is_expired() {
+            return Err(Error::Crypto("Session key expired".to_string()));
+        }
+
+        if let Some(sender) = self.senders.get_mut(sender_id) {
+            return sender.decrypt(aad, message);
+        }
+
+        // Only remember senders whose first frame authenticates, so forged
+        // sender ids cannot grow the table.
+        let mut sender = SenderKey::derive(&self.key, self.id, sender_id);
+        let plaintext = sender.decrypt(aad, message)?;
+        self.senders.insert(sender_id.to_string(), sender);
+        Ok(plaintext)
     }
 
     fn encrypt(&mut self, plaintext: &[u8]) -> AgoraResult<EncryptedMessage> {
@@ -414,6 +620,57 @@ impl SessionKeyManager {
         tracing::debug!("Removed session for room {}", room_id);
     }
 
+    /// Installs an externally agreed key as the room's current key, keeping
+    /// the replaced one as the previous key. Creates the room if needed.
+    pub fn install_key(&mut self, room_id: &str, key_id: u64, key: SessionKey) -> KeyRotationEvent {
+        let next_rotation = Instant::now() + self.rotation_interval;
+        let key_info = SessionKeyInfo::new(key_id, key);
+
+        let previous_key_id = match self.rooms.get_mut(room_id) {
+            Some(room) => {
+                let old_key = std::mem::replace(&mut room.current_key, key_info);
+                let previous_id = old_key.id;
+                room.previous_key = Some(old_key);
+                room.next_rotation = next_rotation;
+                Some(previous_id)
+            }
+            None => {
+                self.rooms.insert(
+                    room_id.to_string(),
+                    RoomKeys {
+                        current_key: key_info,
+                        previous_key: None,
+                        next_rotation,
+                    },
+                );
+                None
+            }
+        };
+
+        tracing::debug!("Installed key {} for room {}", key_id, room_id);
+        KeyRotationEvent {
+            room_id: room_id.to_string(),
+            new_key_id: key_id,
+            previous_key_id,
+        }
+    }
+
+    /// Whether `key_id` is the current or a still-valid previous key.
+    pub fn has_key_id(&self, room_id: &str, key_id: u64) -> bool {
+        self.rooms.get(room_id).is_some_and(|room| {
+            room.current_key.id == key_id
+                || room
+                    .previous_key
+                    .as_ref()
+                    .is_some_and(|prev| prev.id == key_id && !prev.is_expired())
+        })
+    }
+
+    /// Encrypts directly under the shared room key.
+    ///
+    /// The nonce only depends on a local counter, so this is only safe when
+    /// the local node is the sole sender. Use [`Self::encrypt_as`] for traffic
+    /// that several participants send under the same room key.
     pub fn encrypt(&mut self, room_id: &str, plaintext: &[u8]) -> AgoraResult<EncryptedMessage> {
         let room = self
             .rooms
@@ -442,6 +699,53 @@ impl SessionKeyManager {
         Err(Error::Crypto("Decryption failed with all keys".to_string()))
     }
 
+    /// Encrypts under `sender_id`'s key derived from the current room key.
+    /// Returns the room key id alongside the message.
+    pub fn encrypt_as(
+        &mut self,
+        room_id: &str,
+        sender_id: &str,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> AgoraResult<(u64, EncryptedMessage)> {
+        let room = self
+            .rooms
+            .get_mut(room_id)
+            .ok_or_else(|| Error::Crypto(format!("Room {} not found", room_id)))?;
+
+        let key_id = room.current_key.id;
+        let message = room.current_key.encrypt_as(sender_id, aad, plaintext)?;
+        Ok((key_id, message))
+    }
+
+    pub fn decrypt_from(
+        &mut self,
+        room_id: &str,
+        key_id: u64,
+        sender_id: &str,
+        aad: &[u8],
+        message: &EncryptedMessage,
+    ) -> AgoraResult<Vec<u8>> {
+        let room = self
+            .rooms
+            .get_mut(room_id)
+            .ok_or_else(|| Error::Crypto(format!("Room {} not found", room_id)))?;
+
+        if room.current_key.id == key_id {
+            return room.current_key.decrypt_from(sender_id, aad, message);
+        }
+
+        match room.previous_key {
+            Some(ref mut prev_key) if prev_key.id == key_id && !prev_key.is_expired() => {
+                prev_key.decrypt_from(sender_id, aad, message)
+            }
+            _ => Err(Error::Crypto(format!(
+                "Unknown key ID {} for room {}",
+                key_id, room_id
+            ))),
+        }
+    }
+
     pub fn check_rotation(&mut self) -> Vec<KeyRotationEvent> {
         let mut events = Vec::new();
         let now = Instant::now();
@@ -570,6 +874,77 @@ impl SecureAudioChannel {
         self.key_manager.remove_room(room_id);
     }
 
+    pub fn install_key(&mut self, room_id: &str, key_id: u64, key: SessionKey) -> KeyRotationEvent {
+        self.key_manager.install_key(room_id, key_id, key)
+    }
+
+    pub fn has_key_id(&self, room_id: &str, key_id: u64) -> bool {
+        self.key_manager.has_key_id(room_id, key_id)
+    }
+
+    /// Encrypts an Opus frame for the room under the sender's per-sender key.
+    pub fn encrypt_encoded(
+        &mut self,
+        room_id: &str,
+        packet: &crate::protocol::EncodedAudioPacket,
+    ) -> AgoraResult<crate::protocol::EncryptedAudioPacket> {
+        let plaintext = packet
+            .encode()
+            .map_err(|e| Error::Crypto(format!("Failed to encode packet: {}", e)))?;
+
+        let key_id = self
+            .key_manager
+            .get_current_key_id(room_id)
+            .ok_or_else(|| Error::Crypto("Room not found".to_string()))?;
+        let aad = audio_frame_aad(key_id, &packet.peer_id, packet.sequence, packet.audio_level);
+        let (key_id, encrypted) =
+            self.key_manager
+                .encrypt_as(room_id, &packet.peer_id, &aad, &plaintext)?;
+
+        let mut encrypted = crate::protocol::EncryptedAudioPacket::from_encrypted_message(
+            packet.sequence,
+            packet.peer_id.clone(),
+            encrypted,
+            key_id,
+        );
+        encrypted.timestamp = packet.timestamp;
+        encrypted.audio_level = packet.audio_level;
+        Ok(encrypted)
+    }
+
+    /// Decrypts and replay-checks an Opus frame sent to the room.
+    pub fn decrypt_encoded(
+        &mut self,
+        room_id: &str,
+        encrypted_packet: &crate::protocol::EncryptedAudioPacket,
+    ) -> AgoraResult<crate::protocol::EncodedAudioPacket> {
+        let encrypted_msg = encrypted_packet.to_encrypted_message();
+        let aad = audio_frame_aad(
+            encrypted_packet.key_id,
+            &encrypted_packet.peer_id,
+            encrypted_packet.sequence,
+            encrypted_packet.audio_level,
+        );
+        let plaintext = self.key_manager.decrypt_from(
+            room_id,
+            encrypted_packet.key_id,
+            &encrypted_packet.peer_id,
+            &aad,
+            &encrypted_msg,
+        )?;
+
+        let packet = crate::protocol::EncodedAudioPacket::decode(&plaintext)
+            .map_err(|e| Error::Crypto(format!("Failed to decode packet: {}", e)))?;
+
+        if packet.peer_id != encrypted_packet.peer_id
+            || packet.sequence != encrypted_packet.sequence
+        {
+            return Err(Error::Crypto("Packet header mismatch".to_string()));
+        }
+
+        Ok(packet)
+    }
+
     pub fn encrypt_packet(
         &mut self,
         room_id: &str,
@@ -579,11 +954,19 @@ impl SecureAudioChannel {
             .encode()
             .map_err(|e| Error::Crypto(format!("Failed to encode packet: {}", e)))?;
 
-        let encrypted = self.key_manager.encrypt(room_id, &plaintext)?;
         let key_id = self
             .key_manager
             .get_current_key_id(room_id)
             .ok_or_else(|| Error::Crypto("Room not found".to_string()))?;
+        let aad = audio_frame_aad(
+            key_id,
+            &packet.peer_id,
+            packet.sequence,
+            crate::audio::SILENT_AUDIO_LEVEL,
+        );
+        let (key_id, encrypted) =
+            self.key_manager
+                .encrypt_as(room_id, &packet.peer_id, &aad, &plaintext)?;
 
         Ok(
             crate::protocol::EncryptedAudioPacket::from_encrypted_message(
@@ -601,10 +984,30 @@ impl SecureAudioChannel {
         encrypted_packet: &crate::protocol::EncryptedAudioPacket,
     ) -> AgoraResult<crate::protocol::AudioPacket> {
         let encrypted_msg = encrypted_packet.to_encrypted_message();
-        let plaintext = self.key_manager.decrypt(room_id, &encrypted_msg)?;
+        let aad = audio_frame_aad(
+            encrypted_packet.key_id,
+            &encrypted_packet.peer_id,
+            encrypted_packet.sequence,
+            encrypted_packet.audio_level,
+        );
+        let plaintext = self.key_manager.decrypt_from(
+            room_id,
+            encrypted_packet.key_id,
+            &encrypted_packet.peer_id,
+            &aad,
+            &encrypted_msg,
+        )?;
+
+        let packet = crate::protocol::AudioPacket::decode(&plaintext)
+            .map_err(|e| Error::Crypto(format!("Failed to decode packet: {}", e)))?;
+
+        if packet.peer_id != encrypted_packet.peer_id
+            || packet.sequence != encrypted_packet.sequence
+        {
+            return Err(Error::Crypto("Packet header mismatch".to_string()));
+        }
 
-        crate::protocol::AudioPacket::decode(&plaintext)
-            .map_err(|e| Error::Crypto(format!("Failed to decode packet: {}", e)))
+        Ok(packet)
     }
 
     pub fn check_rotation(&mut self) -> Vec<KeyRotationEvent> {
@@ -919,6 +1322,11 @@ mod tests {
         assert_eq!(encrypted.peer_id, packet.peer_id);
         assert!(!encrypted.encrypted_frame.is_empty());
 
+        // The clear-text level is authenticated even though it is unused here.
+        let mut tampered = encrypted.clone();
+        tampered.audio_level = 0;
+        assert!(channel.decrypt_packet("test-room", &tampered).is_err());
+
         let decrypted = channel.decrypt_packet("test-room", &encrypted).unwrap();
         assert_eq!(decrypted.sequence, packet.sequence);
         assert_eq!(decrypted.peer_id, packet.peer_id);
@@ -996,4 +1404,201 @@ mod tests {
         let decrypted = channel.decrypt_packet("test-room", &decoded).unwrap();
         assert_eq!(decrypted.frame, packet.frame);
     }
+
+    fn room_channel(room_key: [u8; 32]) -> SecureAudioChannel {
+        let mut channel = SecureAudioChannel::new();
+        channel.create_room("test-room", SessionKey::new(room_key));
+        channel
+    }
+
+    #[test]
+    fn test_shared_room_key_counter_collision() {
+        // The pre-SFrame scheme: two senders on the same room key both start
+        // at counter 0, so their first frames share a (key, nonce) pair and
+        // leak the XOR of the plaintexts.
+        let mut alice = SessionKeyManager::new();
+        let mut bob = SessionKeyManager::new();
+        alice.create_room("test-room", SessionKey::new([7u8; 32]));
+        bob.create_room("test-room", SessionKey::new([7u8; 32]));
+
+        let plain_a = [0x11u8; 32];
+        let plain_b = [0x22u8; 32];
+        let enc_a = alice.encrypt("test-room", &plain_a).unwrap();
+        let enc_b = bob.encrypt("test-room", &plain_b).unwrap();
+
+        assert_eq!(enc_a.nonce, enc_b.nonce);
+        let xor_c: Vec<u8> = enc_a.ciphertext[..32]
+            .iter()
+            .zip(&enc_b.ciphertext[..32])
+            .map(|(a, b)| a ^ b)
+            .collect();
+        let xor_p: Vec<u8> = plain_a.iter().zip(&plain_b).map(|(a, b)| a ^ b).collect();
+        assert_eq!(xor_c, xor_p);
+    }
+
+    #[test]
+    fn test_per_sender_keys_remove_counter_collision() {
+        let mut alice = SessionKeyManager::new();
+        let mut bob = SessionKeyManager::new();
+        alice.create_room("test-room", SessionKey::new([7u8; 32]));
+        bob.create_room("test-room", SessionKey::new([7u8; 32]));
+
+        let plain_a = [0x11u8; 32];
+        let plain_b = [0x22u8; 32];
+        let (_, enc_a) = alice
+            .encrypt_as("test-room", "alice", b"", &plain_a)
+            .unwrap();
+        let (_, enc_b) = bob.encrypt_as("test-room", "bob", b"", &plain_b).unwrap();
+
+        assert_ne!(enc_a.nonce, enc_b.nonce);
+        let xor_c: Vec<u8> = enc_a.ciphertext[..32]
+            .iter()
+            .zip(&enc_b.ciphertext[..32])
+            .map(|(a, b)| a ^ b)
+            .collect();
+        let xor_p: Vec<u8> = plain_a.iter().zip(&plain_b).map(|(a, b)| a ^ b).collect();
+        assert_ne!(xor_c, xor_p);
+    }
+
+    #[test]
+    fn test_secure_audio_channel_same_sequence_from_two_senders() {
+        use crate::protocol::AudioPacket;
+
+        let mut alice = room_channel([9u8; 32]);
+        let mut bob = room_channel([9u8; 32]);
+        let mut carol = room_channel([9u8; 32]);
+
+        let from_alice = alice
+            .encrypt_packet(
+                "test-room",
+                &AudioPacket::new(0, "alice".into(), vec![0.1; 960]),
+            )
+            .unwrap();
+        let from_bob = bob
+            .encrypt_packet(
+                "test-room",
+                &AudioPacket::new(0, "bob".into(), vec![0.2; 960]),
+            )
+            .unwrap();
+
+        assert_ne!(from_alice.nonce, from_bob.nonce);
+
+        // A shared replay window would reject the second counter-0 frame.
+        let dec_alice = carol.decrypt_packet("test-room", &from_alice).unwrap();
+        let dec_bob = carol.decrypt_packet("test-room", &from_bob).unwrap();
+        assert_eq!(dec_alice.peer_id, "alice");
+        assert_eq!(dec_bob.peer_id, "bob");
+
+        assert!(carol.decrypt_packet("test-room", &from_alice).is_err());
+        assert!(carol.decrypt_packet("test-room", &from_bob).is_err());
+    }
+
+    #[test]
+    fn test_secure_audio_channel_rejects_spoofed_sender() {
+        use crate::protocol::AudioPacket;
+
+        let mut alice = room_channel([9u8; 32]);
+        let mut carol = room_channel([9u8; 32]);
+
+        let mut packet = alice
+            .encrypt_packet(
+                "test-room",
+                &AudioPacket::new(5, "alice".into(), vec![0.1; 960]),
+            )
+            .unwrap();
+        packet.peer_id = "mallory".to_string();
+        assert!(carol.decrypt_packet("test-room", &packet).is_err());
+
+        packet.peer_id = "alice".to_string();
+        packet.sequence = 6;
+        assert!(carol.decrypt_packet("test-room", &packet).is_err());
+
+        packet.sequence = 5;
+        assert!(carol.decrypt_packet("test-room", &packet).is_ok());
+    }
+
+    #[test]
+    fn test_secure_audio_channel_unknown_key_id() {
+        use crate::protocol::AudioPacket;
+
+        let mut alice = room_channel([9u8; 32]);
+        let mut carol = room_channel([9u8; 32]);
+
+        let mut packet = alice
+            .encrypt_packet(
+                "test-room",
+                &AudioPacket::new(1, "alice".into(), vec![0.1; 960]),
+            )
+            .unwrap();
+        packet.key_id = 42;
+
+        let err = carol.decrypt_packet("test-room", &packet).unwrap_err();
+        assert!(err.to_string().contains("Unknown key ID"));
+    }
+
+    #[test]
+    fn test_secure_audio_channel_encoded_roundtrip_and_replay() {
+        use crate::codec::EncodedFrame;
+        use crate::protocol::EncodedAudioPacket;
+
+        let mut alice = room_channel([9u8; 32]);
+        let mut bob = room_channel([9u8; 32]);
+
+        let frame = EncodedFrame {
+            data: vec![1, 2, 3, 4],
+            sequence: 7,
+            timestamp: 1234,
+            bitrate: 32000,
+        };
+        let packet = EncodedAudioPacket::from_encoded_frame(7, 1234, "alice".into(), frame, true)
+            .with_audio_level(30);
+
+        let encrypted = alice.encrypt_encoded("test-room", &packet).unwrap();
+        assert_ne!(encrypted.encrypted_frame, packet.payload);
+        assert_eq!(encrypted.timestamp, 1234);
+        assert_eq!(encrypted.audio_level, 30);
+
+        // A relay rewriting the clear-text level is caught on decryption.
+        let mut tampered = encrypted.clone();
+        tampered.audio_level = 0;
+        assert!(bob.decrypt_encoded("test-room", &tampered).is_err());
+
+        let decrypted = bob.decrypt_encoded("test-room", &encrypted).unwrap();
+        assert_eq!(decrypted.payload, vec![1, 2, 3, 4]);
+        assert_eq!(decrypted.audio_level, 30);
+        assert!(bob.decrypt_encoded("test-room", &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_install_key_keeps_previous() {
+        let mut channel = SecureAudioChannel::new();
+        let event = channel.install_key("room", 5, SessionKey::new([1u8; 32]));
+        assert_eq!(event.previous_key_id, None);
+
+        let event = channel.install_key("room", 6, SessionKey::new([2u8; 32]));
+        assert_eq!(event.previous_key_id, Some(5));
+        assert_eq!(channel.get_current_key_id("room"), Some(6));
+        assert!(channel.has_key_id("room", 5));
+        assert!(channel.has_key_id("room", 6));
+
+        channel.install_key("room", 7, SessionKey::new([3u8; 32]));
+        assert!(!channel.has_key_id("room", 5));
+    }
+
+    #[test]
+    fn test_replay_window_out_of_order() {
+        let mut window = ReplayWindow::new();
+
+        window.update(10);
+        assert!(window.check(8));
+        window.update(8);
+        assert!(!window.check(8));
+        assert!(!window.check(10));
+        assert!(window.check(9));
+        assert!(window.check(11));
+
+        window.update(10 + REPLAY_WINDOW_SIZE);
+        assert!(!window.check(9));
+        assert!(window.check(11));
+    }
 }
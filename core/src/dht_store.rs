@@ -0,0 +1,476 @@
+use crate::error::{AgoraResult, Error};
+use libp2p::kad::store::{self, MemoryStore, MemoryStoreConfig, RecordStore};
+use libp2p::kad::{ProviderRecord, Record, RecordKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const RECORDS_FILE: &str = "dht_records.bin";
+const PEERS_FILE: &str = "known_peers.bin";
+/// Known peers not seen for this long are forgotten.
+pub const PEER_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const MAX_KNOWN_PEERS: usize = 256;
+const MAX_ADDRS_PER_PEER: usize = 8;
+
+fn unix_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn expiry_to_unix(expires: Option<Instant>) -> Option<u64> {
+    expires.map(|at| (unix_now() + at.saturating_duration_since(Instant::now())).as_millis() as u64)
+}
+
+/// `None` inside means the expiry has already passed.
+fn expiry_from_unix(expires_at: Option<u64>) -> Option<Option<Instant>> {
+    match expires_at {
+        None => Some(None),
+        Some(ms) => {
+            let left = Duration::from_millis(ms).checked_sub(unix_now())?;
+            Some(Some(Instant::now() + left))
+        }
+    }
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> AgoraResult<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| Error::Storage(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+fn read_file<T: for<'de> Deserialize<'de>>(path: &Path) -> AgoraResult<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = std::fs::read(path)
+        .map_err(|e| Error::Storage(format!("Failed to read {}: {}", path.display(), e)))?;
+    postcard::from_bytes(&bytes)
+        .map(Some)
+        .map_err(|e| Error::Storage(format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<Vec<u8>>,
+    /// Unix time in milliseconds.
+    expires_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+    key: Vec<u8>,
+    provider: Vec<u8>,
+    expires_at: Option<u64>,
+    addresses: Vec<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StoredRecords {
+    records: Vec<StoredRecord>,
+    providers: Vec<StoredProvider>,
+}
+
+impl StoredRecord {
+    fn from_record(record: &Record) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            value: record.value.clone(),
+            publisher: record.publisher.map(|p| p.to_bytes()),
+            expires_at: expiry_to_unix(record.expires),
+        }
+    }
+
+    fn into_record(self) -> Option<Record> {
+        let publisher = match self.publisher {
+            Some(bytes) => Some(PeerId::from_bytes(&bytes).ok()?),
+            None => None,
+        };
+        Some(Record {
+            key: RecordKey::from(self.key),
+            value: self.value,
+            publisher,
+            expires: expiry_from_unix(self.expires_at)?,
+        })
+    }
+}
+
+impl StoredProvider {
+    fn from_record(record: &ProviderRecord) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            provider: record.provider.to_bytes(),
+            expires_at: expiry_to_unix(record.expires),
+            addresses: record.addresses.iter().map(Multiaddr::to_vec).collect(),
+        }
+    }
+
+    fn into_record(self) -> Option<ProviderRecord> {
+        Some(ProviderRecord {
+            key: RecordKey::from(self.key),
+            provider: PeerId::from_bytes(&self.provider).ok()?,
+            expires: expiry_from_unix(self.expires_at)?,
+            addresses: self
+                .addresses
+                .into_iter()
+                .filter_map(|addr| Multiaddr::try_from(addr).ok())
+                .collect(),
+        })
+    }
+}
+
+/// Kademlia record store that keeps records and other peers' provider
+/// records on disk, so a restarted node can serve them again right away.
+/// Writes happen in [`PersistentStore::save`], not on every change.
+pub struct PersistentStore {
+    inner: MemoryStore,
+    local_peer_id: PeerId,
+    path: Option<PathBuf>,
+    /// `MemoryStore` cannot list provider records, so their keys are kept
+    /// here to find them again when saving.
+    provider_keys: HashSet<RecordKey>,
+    dirty: bool,
+}
+
+impl PersistentStore {
+    /// Loads what was saved in `dir`, dropping expired entries. Without a
+    /// directory the store behaves like a `MemoryStore`.
+    pub fn new(local_peer_id: PeerId, config: MemoryStoreConfig, dir: Option<&Path>) -> Self {
+        let mut store = Self {
+            inner: MemoryStore::with_config(local_peer_id, config),
+            local_peer_id,
+            path: dir.map(|dir| dir.join(RECORDS_FILE)),
+            provider_keys: HashSet::new(),
+            dirty: false,
+        };
+
+        let stored = match store.path.as_deref().map(read_file::<StoredRecords>) {
+            Some(Ok(Some(stored))) => stored,
+            Some(Err(e)) => {
+                tracing::warn!("Ignoring saved DHT records: {}", e);
+                return store;
+            }
+            _ => return store,
+        };
+        for record in stored.records.into_iter().filter_map(|r| r.into_record()) {
+            let _ = store.inner.put(record);
+        }
+        for record in stored.providers.into_iter().filter_map(|p| p.into_record()) {
+            store.provider_keys.insert(record.key.clone());
+            let _ = store.inner.add_provider(record);
+        }
+        store
+    }
+
+    pub fn save(&mut self) -> AgoraResult<()> {
+        let Some(path) = self.path.as_deref().filter(|_| self.dirty) else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let live = |expires: Option<Instant>| expires.is_none_or(|at| at > now);
+        let stored = StoredRecords {
+            records: self
+                .inner
+                .records()
+                .filter(|r| live(r.expires))
+                .map(|r| StoredRecord::from_record(&r))
+                .collect(),
+            // Our own provider records are re-announced by whoever joins a
+            // room again; saving them would advertise rooms we have left.
+            providers: self
+                .provider_keys
+                .iter()
+                .flat_map(|key| self.inner.providers(key))
+                .filter(|p| p.provider != self.local_peer_id && live(p.expires))
+                .map(|p| StoredProvider::from_record(&p))
+                .collect(),
+        };
+        let bytes = postcard::to_allocvec(&stored)
+            .map_err(|e| Error::Storage(format!("Failed to encode DHT records: {}", e)))?;
+        write_atomic(path, &bytes)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl RecordStore for PersistentStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> store::Result<()> {
+        self.inner.put(r)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.inner.remove(k);
+        self.dirty = true;
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> store::Result<()> {
+        let key = record.key.clone();
+        self.inner.add_provider(record)?;
+        self.provider_keys.insert(key);
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.inner.remove_provider(k, p);
+        if self.inner.providers(k).is_empty() {
+            self.provider_keys.remove(k);
+        }
+        self.dirty = true;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPeer {
+    peer_id: Vec<u8>,
+    addrs: Vec<Vec<u8>>,
+    /// Unix time in seconds.
+    last_seen: u64,
+}
+
+#[derive(Debug, Clone)]
+struct KnownPeer {
+    addrs: Vec<Multiaddr>,
+    last_seen: u64,
+}
+
+/// Addresses of peers seen recently, used to fill the routing table on the
+/// next start instead of relying on bootstrap peers alone.
+pub struct AddressBook {
+    path: Option<PathBuf>,
+    peers: HashMap<PeerId, KnownPeer>,
+    dirty: bool,
+}
+
+impl AddressBook {
+    pub fn load(dir: Option<&Path>) -> Self {
+        let mut book = Self {
+            path: dir.map(|dir| dir.join(PEERS_FILE)),
+            peers: HashMap::new(),
+            dirty: false,
+        };
+
+        let stored = match book.path.as_deref().map(read_file::<Vec<StoredPeer>>) {
+            Some(Ok(Some(stored))) => stored,
+            Some(Err(e)) => {
+                tracing::warn!("Ignoring saved peer addresses: {}", e);
+                return book;
+            }
+            _ => return book,
+        };
+        for peer in stored {
+            let Ok(peer_id) = PeerId::from_bytes(&peer.peer_id) else {
+                continue;
+            };
+            let addrs: Vec<Multiaddr> = peer
+                .addrs
+                .into_iter()
+                .filter_map(|addr| Multiaddr::try_from(addr).ok())
+                .collect();
+            book.peers.insert(
+                peer_id,
+                KnownPeer {
+                    addrs,
+                    last_seen: peer.last_seen,
+                },
+            );
+        }
+        book.prune(PEER_MAX_AGE);
+        book.dirty = false;
+        book
+    }
+
+    /// Marks `peer_id` as seen now; `addrs` go first in its address list.
+    pub fn record(&mut self, peer_id: PeerId, addrs: impl IntoIterator<Item = Multiaddr>) {
+        let entry = self.peers.entry(peer_id).or_insert_with(|| KnownPeer {
+            addrs: Vec::new(),
+            last_seen: 0,
+        });
+        for addr in addrs {
+            entry.addrs.retain(|known| *known != addr);
+            entry.addrs.insert(0, addr);
+        }
+        entry.addrs.truncate(MAX_ADDRS_PER_PEER);
+        entry.last_seen = unix_now().as_secs();
+        self.dirty = true;
+    }
+
+    pub fn addresses(&self, peer_id: &PeerId) -> Option<&[Multiaddr]> {
+        self.peers.get(peer_id).map(|peer| peer.addrs.as_slice())
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &[Multiaddr])> {
+        self.peers
+            .iter()
+            .map(|(peer_id, peer)| (peer_id, peer.addrs.as_slice()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Forgets peers not seen within `max_age`, then the least recently
+    /// seen ones beyond `MAX_KNOWN_PEERS`.
+    pub fn prune(&mut self, max_age: Duration) {
+        let before = self.peers.len();
+        let cutoff = unix_now().saturating_sub(max_age).as_secs();
+        self.peers
+            .retain(|_, peer| peer.last_seen >= cutoff && !peer.addrs.is_empty());
+
+        if self.peers.len() > MAX_KNOWN_PEERS {
+            let mut seen: Vec<u64> = self.peers.values().map(|p| p.last_seen).collect();
+            seen.sort_unstable_by(|a, b| b.cmp(a));
+            let oldest_kept = seen[MAX_KNOWN_PEERS - 1];
+            self.peers.retain(|_, peer| peer.last_seen >= oldest_kept);
+        }
+        self.dirty |= self.peers.len() != before;
+    }
+
+    pub fn save(&mut self) -> AgoraResult<()> {
+        let Some(path) = self.path.as_deref().filter(|_| self.dirty) else {
+            return Ok(());
+        };
+
+        let stored: Vec<StoredPeer> = self
+            .peers
+            .iter()
+            .map(|(peer_id, peer)| StoredPeer {
+                peer_id: peer_id.to_bytes(),
+                addrs: peer.addrs.iter().map(Multiaddr::to_vec).collect(),
+                last_seen: peer.last_seen,
+            })
+            .collect();
+        let bytes = postcard::to_allocvec(&stored)
+            .map_err(|e| Error::Storage(format!("Failed to encode peer addresses: {}", e)))?;
+        write_atomic(path, &bytes)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+    use tempfile::tempdir;
+
+    fn peer() -> PeerId {
+        Identity::generate().unwrap().libp2p_peer_id()
+    }
+
+    #[test]
+    fn test_records_survive_restart() {
+        let dir = tempdir().unwrap();
+        let local = peer();
+        let remote = peer();
+        let addr: Multiaddr = "/ip4/192.0.2.1/tcp/7001".parse().unwrap();
+
+        let mut store = PersistentStore::new(local, Default::default(), Some(dir.path()));
+        let mut record = Record::new(RecordKey::new(&"room"), b"value".to_vec());
+        record.expires = Some(Instant::now() + Duration::from_secs(3600));
+        store.put(record).unwrap();
+        let mut expired = Record::new(RecordKey::new(&"gone"), b"old".to_vec());
+        expired.expires = Some(Instant::now());
+        store.put(expired).unwrap();
+        for provider in [remote, local] {
+            store
+                .add_provider(ProviderRecord {
+                    key: RecordKey::new(&"room"),
+                    provider,
+                    expires: None,
+                    addresses: vec![addr.clone()],
+                })
+                .unwrap();
+        }
+        store.save().unwrap();
+
+        let store = PersistentStore::new(local, Default::default(), Some(dir.path()));
+        let record = store.get(&RecordKey::new(&"room")).unwrap();
+        assert_eq!(record.value, b"value");
+        assert!(record.expires.unwrap() > Instant::now() + Duration::from_secs(3500));
+        assert!(store.get(&RecordKey::new(&"gone")).is_none());
+
+        let providers = store.providers(&RecordKey::new(&"room"));
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].provider, remote);
+        assert_eq!(providers[0].addresses, vec![addr]);
+    }
+
+    #[test]
+    fn test_store_without_dir_is_in_memory() {
+        let mut store = PersistentStore::new(peer(), Default::default(), None);
+        store
+            .put(Record::new(RecordKey::new(&"key"), vec![1]))
+            .unwrap();
+        store.save().unwrap();
+        assert!(store.get(&RecordKey::new(&"key")).is_some());
+    }
+
+    #[test]
+    fn test_address_book_roundtrip_and_prune() {
+        let dir = tempdir().unwrap();
+        let fresh = peer();
+        let stale = peer();
+        let addr: Multiaddr = "/ip4/192.0.2.1/tcp/7001".parse().unwrap();
+        let newer: Multiaddr = "/ip4/192.0.2.2/tcp/7001".parse().unwrap();
+
+        let mut book = AddressBook::load(Some(dir.path()));
+        book.record(fresh, [addr.clone()]);
+        book.record(fresh, [newer.clone(), addr.clone()]);
+        book.record(stale, [addr.clone()]);
+        book.peers.get_mut(&stale).unwrap().last_seen -= PEER_MAX_AGE.as_secs() + 60;
+        book.save().unwrap();
+
+        let book = AddressBook::load(Some(dir.path()));
+        assert_eq!(book.len(), 1);
+        assert_eq!(book.addresses(&fresh).unwrap(), [addr, newer]);
+    }
+
+    #[test]
+    fn test_address_book_keeps_most_recent_peers() {
+        let mut book = AddressBook::load(None);
+        let addr: Multiaddr = "/ip4/192.0.2.1/tcp/7001".parse().unwrap();
+        let peers: Vec<PeerId> = (0..MAX_KNOWN_PEERS + 4).map(|_| peer()).collect();
+        for (i, peer_id) in peers.iter().enumerate() {
+            book.record(*peer_id, [addr.clone()]);
+            book.peers.get_mut(peer_id).unwrap().last_seen -= (peers.len() - i) as u64;
+        }
+
+        book.prune(PEER_MAX_AGE);
+        assert_eq!(book.len(), MAX_KNOWN_PEERS);
+        assert!(book.addresses(&peers[0]).is_none());
+        assert!(book.addresses(peers.last().unwrap()).is_some());
+    }
+}
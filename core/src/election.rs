@@ -0,0 +1,298 @@
+use crate::error::{AgoraResult, Error};
+use crate::identity::{verifying_key_from_peer_id, Identity};
+use crate::mixer::ParticipantStats;
+use ed25519_dalek::{Signature, Verifier};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const STATS_SIGNING_DOMAIN: &str = "agora mixer stats v1";
+const ELECTION_SIGNING_DOMAIN: &str = "agora mixer election v1";
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// The part of `ParticipantStats` a participant publishes to its room so
+/// every peer scores mixer candidates from the same numbers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MixerStats {
+    pub bandwidth_bps: u64,
+    pub latency_ms: u32,
+    pub latency_variance: f32,
+    pub cpu_usage_percent: f32,
+    pub memory_usage_percent: f32,
+    pub session_duration_ms: u64,
+    pub packet_loss_percent: f32,
+    pub region: Option<String>,
+}
+
+impl From<&ParticipantStats> for MixerStats {
+    fn from(stats: &ParticipantStats) -> Self {
+        Self {
+            bandwidth_bps: stats.bandwidth_bps,
+            latency_ms: stats.latency_ms,
+            latency_variance: stats.latency_variance,
+            cpu_usage_percent: stats.cpu_usage_percent,
+            memory_usage_percent: stats.memory_usage_percent,
+            session_duration_ms: stats.session_duration.as_millis() as u64,
+            packet_loss_percent: stats.packet_loss_percent,
+            region: stats.region.clone(),
+        }
+    }
+}
+
+impl From<&MixerStats> for ParticipantStats {
+    fn from(stats: &MixerStats) -> Self {
+        Self {
+            bandwidth_bps: stats.bandwidth_bps,
+            latency_ms: stats.latency_ms,
+            latency_variance: stats.latency_variance,
+            cpu_usage_percent: stats.cpu_usage_percent,
+            memory_usage_percent: stats.memory_usage_percent,
+            session_duration: Duration::from_millis(stats.session_duration_ms),
+            packet_loss_percent: stats.packet_loss_percent,
+            region: stats.region.clone(),
+            last_updated: Instant::now(),
+        }
+    }
+}
+
+/// One mixer of a cascaded room and the participants it serves.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MixerGroup {
+    pub mixer: String,
+    pub members: Vec<String>,
+}
+
+/// The outcome of one election round. Each new round has a higher epoch
+/// than the one it replaces.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MixerElection {
+    pub epoch: u64,
+    /// The room's mixer, or the first of several when cascaded.
+    pub mixer: String,
+    pub score: f64,
+    /// Every mixer and the participants it serves when the room is too big
+    /// for one mixer; empty otherwise.
+    pub cascade: Vec<MixerGroup>,
+}
+
+impl MixerElection {
+    /// Whether peers holding `other` should switch to this result. Later
+    /// epochs win; within an epoch, the higher score and then the smaller
+    /// mixer ID, so every peer settles on the same side of a split brain.
+    pub fn outranks(&self, other: &MixerElection) -> bool {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| {
+                self.score
+                    .partial_cmp(&other.score)
+                    .unwrap_or(Ordering::Equal)
+            })
+            .then_with(|| other.mixer.cmp(&self.mixer))
+            == Ordering::Greater
+    }
+}
+
+/// A participant's stats for a room, signed so they cannot be forged on its
+/// behalf.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedMixerStats {
+    pub room_id: String,
+    pub peer_id: String,
+    pub stats: MixerStats,
+    /// Unix time in milliseconds.
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct StatsSigningPayload<'a> {
+    domain: &'static str,
+    room_id: &'a str,
+    peer_id: &'a str,
+    stats: &'a MixerStats,
+    issued_at: u64,
+}
+
+impl SignedMixerStats {
+    pub fn sign(identity: &Identity, room_id: &str, stats: MixerStats) -> AgoraResult<Self> {
+        let mut signed = Self {
+            room_id: room_id.to_string(),
+            peer_id: identity.peer_id(),
+            stats,
+            issued_at: now_millis(),
+            signature: Vec::new(),
+        };
+        signed.signature = identity.sign(&signed.signing_bytes()?).to_bytes().to_vec();
+        Ok(signed)
+    }
+
+    fn signing_bytes(&self) -> AgoraResult<Vec<u8>> {
+        postcard::to_allocvec(&StatsSigningPayload {
+            domain: STATS_SIGNING_DOMAIN,
+            room_id: &self.room_id,
+            peer_id: &self.peer_id,
+            stats: &self.stats,
+            issued_at: self.issued_at,
+        })
+        .map_err(|e| Error::Crypto(format!("Failed to encode mixer stats: {}", e)))
+    }
+
+    /// Checks that the stats were signed by `from`.
+    pub fn verify(&self, from: &PeerId) -> AgoraResult<()> {
+        if self.peer_id != from.to_string() {
+            return Err(Error::Crypto(format!(
+                "Mixer stats for {} sent by {}",
+                self.peer_id, from
+            )));
+        }
+        verify_signature(from, &self.signing_bytes()?, &self.signature)
+            .map_err(|_| Error::Crypto("Mixer stats signature mismatch".to_string()))
+    }
+
+    /// Whether the stats were issued within `max_age`, allowing for skew.
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        is_fresh(self.issued_at, max_age)
+    }
+}
+
+/// An election result announced by the participant that ran it. Peers pass
+/// on the announcement they adopted, so it need not come from its elector.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedMixerElection {
+    pub room_id: String,
+    pub election: MixerElection,
+    pub elector: String,
+    /// Unix time in milliseconds.
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct ElectionSigningPayload<'a> {
+    domain: &'static str,
+    room_id: &'a str,
+    election: &'a MixerElection,
+    elector: &'a str,
+    issued_at: u64,
+}
+
+impl SignedMixerElection {
+    pub fn sign(identity: &Identity, room_id: &str, election: MixerElection) -> AgoraResult<Self> {
+        let mut signed = Self {
+            room_id: room_id.to_string(),
+            election,
+            elector: identity.peer_id(),
+            issued_at: now_millis(),
+            signature: Vec::new(),
+        };
+        signed.signature = identity.sign(&signed.signing_bytes()?).to_bytes().to_vec();
+        Ok(signed)
+    }
+
+    fn signing_bytes(&self) -> AgoraResult<Vec<u8>> {
+        postcard::to_allocvec(&ElectionSigningPayload {
+            domain: ELECTION_SIGNING_DOMAIN,
+            room_id: &self.room_id,
+            election: &self.election,
+            elector: &self.elector,
+            issued_at: self.issued_at,
+        })
+        .map_err(|e| Error::Crypto(format!("Failed to encode mixer election: {}", e)))
+    }
+
+    pub fn elector_peer_id(&self) -> AgoraResult<PeerId> {
+        self.elector
+            .parse()
+            .map_err(|e| Error::Crypto(format!("Invalid elector {}: {}", self.elector, e)))
+    }
+
+    /// Checks the signature against the key embedded in the elector's peer ID.
+    pub fn verify(&self) -> AgoraResult<()> {
+        verify_signature(
+            &self.elector_peer_id()?,
+            &self.signing_bytes()?,
+            &self.signature,
+        )
+        .map_err(|_| Error::Crypto("Mixer election signature mismatch".to_string()))
+    }
+
+    /// Whether the announcement was issued within `max_age`, allowing for
+    /// skew. Peers passing on an older one sign it again themselves.
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        is_fresh(self.issued_at, max_age)
+    }
+}
+
+fn is_fresh(issued_at: u64, max_age: Duration) -> bool {
+    let now = now_millis();
+    now.saturating_sub(issued_at) <= max_age.as_millis() as u64
+        && issued_at <= now + MAX_CLOCK_SKEW.as_millis() as u64
+}
+
+fn verify_signature(signer: &PeerId, message: &[u8], signature: &[u8]) -> AgoraResult<()> {
+    let key = verifying_key_from_peer_id(signer)?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|e| Error::Crypto(format!("Invalid signature: {}", e)))?;
+    key.verify(message, &signature)
+        .map_err(|e| Error::Crypto(e.to_string()))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn election(epoch: u64, mixer: &str, score: f64) -> MixerElection {
+        MixerElection {
+            epoch,
+            mixer: mixer.to_string(),
+            score,
+            cascade: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_election_ordering() {
+        assert!(election(2, "b", 0.1).outranks(&election(1, "a", 0.9)));
+        assert!(election(1, "b", 0.9).outranks(&election(1, "a", 0.1)));
+        assert!(election(1, "a", 0.5).outranks(&election(1, "b", 0.5)));
+        assert!(!election(1, "a", 0.5).outranks(&election(1, "a", 0.5)));
+    }
+
+    #[test]
+    fn test_signed_stats_roundtrip() {
+        let identity = Identity::generate().unwrap();
+        let peer_id: PeerId = identity.peer_id().parse().unwrap();
+        let stats = MixerStats::from(&ParticipantStats::new());
+        let mut signed = SignedMixerStats::sign(&identity, "room", stats).unwrap();
+
+        assert!(signed.verify(&peer_id).is_ok());
+        assert!(signed.is_fresh(Duration::from_secs(10)));
+        assert!(signed.verify(&PeerId::random()).is_err());
+
+        signed.stats.bandwidth_bps = 10_000_000;
+        assert!(signed.verify(&peer_id).is_err());
+    }
+
+    #[test]
+    fn test_signed_election_rejects_tampering() {
+        let identity = Identity::generate().unwrap();
+        let mut signed =
+            SignedMixerElection::sign(&identity, "room", election(3, "mixer", 0.5)).unwrap();
+        assert!(signed.verify().is_ok());
+        assert!(signed.is_fresh(Duration::from_secs(10)));
+
+        signed.issued_at -= 60_000;
+        assert!(!signed.is_fresh(Duration::from_secs(10)));
+
+        signed.election.epoch = 4;
+        assert!(signed.verify().is_err());
+    }
+}
@@ -0,0 +1,674 @@
+use crate::crypto::{
+    generate_ephemeral_key, KeyRotationEvent, SessionKey, DEFAULT_KEY_ROTATION_INTERVAL,
+};
+use crate::error::{AgoraResult, Error};
+use crate::identity::{verifying_key_from_peer_id, Identity};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use libp2p::PeerId;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+const SEAL_INFO: &[u8] = b"agora group key seal v1";
+
+/// A room key encrypted to one member's identity key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedGroupKey {
+    pub ephemeral_public: [u8; 32],
+    pub ciphertext: Vec<u8>,
+}
+
+/// Recovers the X25519 form of the Ed25519 key embedded in a libp2p peer ID.
+pub fn x25519_public_from_peer_id(peer_id: &PeerId) -> AgoraResult<PublicKey> {
+    let verifying_key = verifying_key_from_peer_id(peer_id)?;
+    Ok(PublicKey::from(verifying_key.to_montgomery().to_bytes()))
+}
+
+fn seal_aad(room_id: &str, key_id: u64, members: &[PeerId]) -> Vec<u8> {
+    let mut aad = Vec::new();
+    aad.extend_from_slice(&(room_id.len() as u32).to_be_bytes());
+    aad.extend_from_slice(room_id.as_bytes());
+    aad.extend_from_slice(&key_id.to_be_bytes());
+    for member in members {
+        aad.extend_from_slice(&member.to_bytes());
+    }
+    aad
+}
+
+fn seal_cipher(
+    shared: &[u8; 32],
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> ChaCha20Poly1305 {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared);
+    let mut key = [0u8; 32];
+    hkdf.expand(SEAL_INFO, &mut key)
+        .expect("HKDF expand should never fail");
+    ChaCha20Poly1305::new_from_slice(&key).expect("Key must be 32 bytes")
+}
+
+/// Seals `key` to `recipient` with an ephemeral X25519 exchange. The room,
+/// key id and member roster are authenticated alongside it.
+pub fn seal_group_key(
+    recipient: &PeerId,
+    room_id: &str,
+    key_id: u64,
+    members: &[PeerId],
+    key: &SessionKey,
+) -> AgoraResult<SealedGroupKey> {
+    let recipient_public = x25519_public_from_peer_id(recipient)?;
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let shared = ephemeral.diffie_hellman(&recipient_public);
+    if !shared.was_contributory() {
+        return Err(Error::Crypto("Non-contributory key exchange".to_string()));
+    }
+
+    let cipher = seal_cipher(shared.as_bytes(), &ephemeral_public, &recipient_public);
+    let aad = seal_aad(room_id, key_id, members);
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&[0u8; 12]),
+            Payload {
+                msg: key.as_bytes(),
+                aad: &aad,
+            },
+        )
+        .map_err(|e| Error::Crypto(format!("Sealing failed: {}", e)))?;
+
+    Ok(SealedGroupKey {
+        ephemeral_public: *ephemeral_public.as_bytes(),
+        ciphertext,
+    })
+}
+
+pub fn open_group_key(
+    identity: &Identity,
+    room_id: &str,
+    key_id: u64,
+    members: &[PeerId],
+    sealed: &SealedGroupKey,
+) -> AgoraResult<[u8; 32]> {
+    let ephemeral_public = PublicKey::from(sealed.ephemeral_public);
+    let shared = identity.x25519_secret().diffie_hellman(&ephemeral_public);
+    if !shared.was_contributory() {
+        return Err(Error::Crypto("Non-contributory key exchange".to_string()));
+    }
+
+    let cipher = seal_cipher(
+        shared.as_bytes(),
+        &ephemeral_public,
+        &identity.x25519_public(),
+    );
+    let aad = seal_aad(room_id, key_id, members);
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&[0u8; 12]),
+            Payload {
+                msg: sealed.ciphertext.as_slice(),
+                aad: &aad,
+            },
+        )
+        .map_err(|e| Error::Crypto(format!("Failed to open group key: {}", e)))?;
+
+    plaintext
+        .try_into()
+        .map_err(|_| Error::Crypto("Invalid group key length".to_string()))
+}
+
+/// A new room key produced locally, with one sealed copy per other member.
+#[derive(Debug, Clone)]
+pub struct GroupRekey {
+    pub event: KeyRotationEvent,
+    pub members: Vec<PeerId>,
+    pub sealed: Vec<(PeerId, SealedGroupKey)>,
+}
+
+struct GroupRoom {
+    members: BTreeSet<PeerId>,
+    /// Members this node admitted itself, as opposed to ones it only knows
+    /// from another leader's roster.
+    verified: BTreeSet<PeerId>,
+    key_id: u64,
+    key: SessionKey,
+    author: PeerId,
+    rotated_at: Instant,
+}
+
+/// Leader-distributed group keys for rooms.
+///
+/// The member with the lowest peer ID among those this node verified leads.
+/// Whenever membership changes the leader draws a fresh random key, so a
+/// member who left cannot compute later keys and a joiner cannot compute
+/// earlier ones. Each key is sealed to the verified members' identity keys and
+/// carries the roster it was issued for.
+pub struct GroupKeyManager {
+    identity: Identity,
+    local_peer_id: PeerId,
+    rooms: HashMap<String, GroupRoom>,
+    rotation_interval: Duration,
+}
+
+impl GroupKeyManager {
+    pub fn new(identity: Identity) -> Self {
+        let local_peer_id = identity.libp2p_peer_id();
+        Self {
+            identity,
+            local_peer_id,
+            rooms: HashMap::new(),
+            rotation_interval: DEFAULT_KEY_ROTATION_INTERVAL,
+        }
+    }
+
+    pub fn with_rotation_interval(mut self, interval: Duration) -> Self {
+        self.rotation_interval = interval;
+        self
+    }
+
+    fn fresh_key(&self) -> SessionKey {
+        // Keys outlive one rotation interval so frames in flight during a
+        // rotation still decrypt under the previous key.
+        SessionKey::with_expiry(generate_ephemeral_key(), self.rotation_interval * 2)
+    }
+
+    /// Starts a room with this node as its only member and key id 1.
+    pub fn create_room(&mut self, room_id: &str) -> KeyRotationEvent {
+        let key = self.fresh_key();
+        let members = BTreeSet::from([self.local_peer_id]);
+
+        self.rooms.insert(
+            room_id.to_string(),
+            GroupRoom {
+                verified: members.clone(),
+                members,
+                key_id: 1,
+                key,
+                author: self.local_peer_id,
+                rotated_at: Instant::now(),
+            },
+        );
+
+        KeyRotationEvent {
+            room_id: room_id.to_string(),
+            new_key_id: 1,
+            previous_key_id: None,
+        }
+    }
+
+    pub fn leave_room(&mut self, room_id: &str) {
+        self.rooms.remove(room_id);
+    }
+
+    pub fn has_room(&self, room_id: &str) -> bool {
+        self.rooms.contains_key(room_id)
+    }
+
+    pub fn has_rooms(&self) -> bool {
+        !self.rooms.is_empty()
+    }
+
+    pub fn rooms(&self) -> impl Iterator<Item = &str> {
+        self.rooms.keys().map(String::as_str)
+    }
+
+    pub fn members(&self, room_id: &str) -> Vec<PeerId> {
+        self.rooms
+            .get(room_id)
+            .map(|r| r.members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn leader(&self, room_id: &str) -> Option<PeerId> {
+        self.rooms
+            .get(room_id)
+            .and_then(|r| r.members.intersection(&r.verified).next().copied())
+    }
+
+    /// Whether `peer_id` was admitted to the room by this node.
+    pub fn is_verified(&self, room_id: &str, peer_id: &PeerId) -> bool {
+        self.rooms
+            .get(room_id)
+            .is_some_and(|r| r.verified.contains(peer_id))
+    }
+
+    pub fn is_leader(&self, room_id: &str) -> bool {
+        self.leader(room_id) == Some(self.local_peer_id)
+    }
+
+    pub fn current_key(&self, room_id: &str) -> Option<(u64, SessionKey)> {
+        self.rooms.get(room_id).map(|r| (r.key_id, r.key.clone()))
+    }
+
+    pub fn add_member(
+        &mut self,
+        room_id: &str,
+        peer_id: PeerId,
+    ) -> AgoraResult<Option<GroupRekey>> {
+        self.add_members(room_id, std::iter::once(peer_id))
+    }
+
+    /// Admits peers the caller has verified, rekeying if this node leads.
+    pub fn add_members(
+        &mut self,
+        room_id: &str,
+        peers: impl IntoIterator<Item = PeerId>,
+    ) -> AgoraResult<Option<GroupRekey>> {
+        let Some(room) = self.rooms.get_mut(room_id) else {
+            return Ok(None);
+        };
+
+        let mut changed = false;
+        for peer_id in peers {
+            changed |= room.members.insert(peer_id);
+            changed |= room.verified.insert(peer_id);
+        }
+
+        if changed && self.is_leader(room_id) {
+            return self.rekey(room_id).map(Some);
+        }
+        Ok(None)
+    }
+
+    pub fn remove_member(
+        &mut self,
+        room_id: &str,
+        peer_id: &PeerId,
+    ) -> AgoraResult<Option<GroupRekey>> {
+        let Some(room) = self.rooms.get_mut(room_id) else {
+            return Ok(None);
+        };
+
+        room.verified.remove(peer_id);
+        if room.members.remove(peer_id) && self.is_leader(room_id) {
+            return self.rekey(room_id).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Rotates keys the local node leads once they reach the rotation interval.
+    pub fn check_rotation(&mut self) -> Vec<AgoraResult<GroupRekey>> {
+        let due: Vec<String> = self
+            .rooms
+            .iter()
+            .filter(|(_, r)| r.rotated_at.elapsed() >= self.rotation_interval)
+            .map(|(room_id, _)| room_id.clone())
+            .filter(|room_id| self.is_leader(room_id))
+            .collect();
+
+        due.iter().map(|room_id| self.rekey(room_id)).collect()
+    }
+
+    pub fn rekey(&mut self, room_id: &str) -> AgoraResult<GroupRekey> {
+        let key = self.fresh_key();
+        let room = self
+            .rooms
+            .get_mut(room_id)
+            .ok_or_else(|| Error::Crypto(format!("Room {} not found", room_id)))?;
+
+        // Members we only know from an earlier leader's roster get the key
+        // once they have joined through us.
+        room.members.retain(|m| room.verified.contains(m));
+        let previous_key_id = room.key_id;
+        let key_id = previous_key_id + 1;
+        let members: Vec<PeerId> = room.members.iter().copied().collect();
+
+        let mut sealed = Vec::with_capacity(members.len().saturating_sub(1));
+        for member in members.iter().filter(|m| **m != self.local_peer_id) {
+            sealed.push((
+                *member,
+                seal_group_key(member, room_id, key_id, &members, &key)?,
+            ));
+        }
+
+        room.key_id = key_id;
+        room.key = key;
+        room.author = self.local_peer_id;
+        room.rotated_at = Instant::now();
+
+        tracing::info!(
+            "Rekeyed room {} for {} members: {} -> {}",
+            room_id,
+            members.len(),
+            previous_key_id,
+            key_id
+        );
+
+        Ok(GroupRekey {
+            event: KeyRotationEvent {
+                room_id: room_id.to_string(),
+                new_key_id: key_id,
+                previous_key_id: Some(previous_key_id),
+            },
+            members,
+            sealed,
+        })
+    }
+
+    /// Installs a key distributed by `from`.
+    ///
+    /// The sender must be a member this node verified, must lead the roster
+    /// it sent, and the roster must include this node. A key from the current
+    /// author must have a higher id; a different author takes over only if it
+    /// sorts lower or the current author has left as far as this node knows,
+    /// not merely because the sender's roster omits it. The roster then
+    /// replaces the local view.
+    pub fn accept_key(
+        &mut self,
+        room_id: &str,
+        from: PeerId,
+        key_id: u64,
+        members: &[PeerId],
+        sealed: &SealedGroupKey,
+    ) -> AgoraResult<KeyRotationEvent> {
+        let room = self
+            .rooms
+            .get(room_id)
+            .ok_or_else(|| Error::Crypto(format!("Not in room {}", room_id)))?;
+
+        if !room.verified.contains(&from) {
+            return Err(Error::Crypto(format!(
+                "Key for room {} from unverified peer {}",
+                room_id, from
+            )));
+        }
+        if !members.contains(&self.local_peer_id) {
+            return Err(Error::Crypto(format!(
+                "Key roster for room {} does not include us",
+                room_id
+            )));
+        }
+        if members.iter().min() != Some(&from) {
+            return Err(Error::Crypto(format!(
+                "Key for room {} from {} who does not lead its roster",
+                room_id, from
+            )));
+        }
+
+        let newer = if from == room.author {
+            key_id > room.key_id
+        } else {
+            from < room.author || !room.verified.contains(&room.author)
+        };
+        if !newer {
+            return Err(Error::Crypto(format!(
+                "Stale key {} for room {} from {} (current {})",
+                key_id, room_id, from, room.key_id
+            )));
+        }
+
+        let key_bytes = open_group_key(&self.identity, room_id, key_id, members, sealed)?;
+        let expiry = self.rotation_interval * 2;
+
+        let room = self
+            .rooms
+            .get_mut(room_id)
+            .ok_or_else(|| Error::Crypto(format!("Not in room {}", room_id)))?;
+        let previous_key_id = room.key_id;
+        room.key_id = key_id;
+        room.key = SessionKey::with_expiry(key_bytes, expiry);
+        room.author = from;
+        room.members = members.iter().copied().collect();
+        room.rotated_at = Instant::now();
+
+        tracing::info!(
+            "Installed key {} for room {} from {}",
+            key_id,
+            room_id,
+            from
+        );
+
+        Ok(KeyRotationEvent {
+            room_id: room_id.to_string(),
+            new_key_id: key_id,
+            previous_key_id: Some(previous_key_id),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager() -> (GroupKeyManager, PeerId) {
+        let identity = Identity::generate().unwrap();
+        let peer_id = identity.libp2p_peer_id();
+        (GroupKeyManager::new(identity), peer_id)
+    }
+
+    fn deliver(rekey: &GroupRekey, to: &mut GroupKeyManager, from: PeerId) -> KeyRotationEvent {
+        let (_, sealed) = rekey
+            .sealed
+            .iter()
+            .find(|(peer, _)| *peer == to.local_peer_id)
+            .expect("no sealed key for recipient");
+        to.accept_key(
+            &rekey.event.room_id,
+            from,
+            rekey.event.new_key_id,
+            &rekey.members,
+            sealed,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let identity = Identity::generate().unwrap();
+        let peer_id = identity.libp2p_peer_id();
+        let key = SessionKey::new([5u8; 32]);
+
+        let sealed = seal_group_key(&peer_id, "room", 3, &[peer_id], &key).unwrap();
+        let opened = open_group_key(&identity, "room", 3, &[peer_id], &sealed).unwrap();
+        assert_eq!(&opened, key.as_bytes());
+    }
+
+    #[test]
+    fn test_sealed_key_bound_to_recipient_and_context() {
+        let alice = Identity::generate().unwrap();
+        let bob = Identity::generate().unwrap();
+        let alice_id = alice.libp2p_peer_id();
+        let key = SessionKey::new([5u8; 32]);
+
+        let sealed = seal_group_key(&alice_id, "room", 3, &[alice_id], &key).unwrap();
+
+        assert!(open_group_key(&bob, "room", 3, &[alice_id], &sealed).is_err());
+        assert!(open_group_key(&alice, "other", 3, &[alice_id], &sealed).is_err());
+        assert!(open_group_key(&alice, "room", 4, &[alice_id], &sealed).is_err());
+        assert!(open_group_key(&alice, "room", 3, &[], &sealed).is_err());
+    }
+
+    #[test]
+    fn test_rekey_on_join_and_leave() {
+        // Order by peer ID so the first manager is the leader.
+        let mut managers = vec![manager(), manager(), manager()];
+        managers.sort_by_key(|(_, peer_id)| *peer_id);
+        let [(mut leader, leader_id), (mut m1, m1_id), (mut m2, m2_id)]: [_; 3] =
+            managers.try_into().ok().unwrap();
+        let (leader, m1, m2) = (&mut leader, &mut m1, &mut m2);
+
+        leader.create_room("room");
+        m1.create_room("room");
+        m2.create_room("room");
+        m1.add_member("room", leader_id).unwrap();
+        m2.add_member("room", leader_id).unwrap();
+
+        let rekey = leader
+            .add_members("room", [m1_id, m2_id])
+            .unwrap()
+            .expect("leader should rekey on join");
+        assert_eq!(rekey.event.new_key_id, 2);
+        assert_eq!(rekey.sealed.len(), 2);
+        deliver(&rekey, m1, leader_id);
+        deliver(&rekey, m2, leader_id);
+
+        let (id, key) = leader.current_key("room").unwrap();
+        assert_eq!(m1.current_key("room").unwrap().0, id);
+        assert_eq!(m2.current_key("room").unwrap().1.as_bytes(), key.as_bytes());
+        assert_eq!(m1.leader("room"), Some(leader_id));
+
+        // m2 leaves: the new key is not sealed to it and is unrelated to the old one.
+        let rekey = leader
+            .remove_member("room", &m2_id)
+            .unwrap()
+            .expect("leader should rekey on leave");
+        assert!(rekey.sealed.iter().all(|(peer, _)| *peer != m2_id));
+        deliver(&rekey, m1, leader_id);
+
+        let (new_id, new_key) = m1.current_key("room").unwrap();
+        assert_eq!(new_id, 3);
+        assert_ne!(new_key.as_bytes(), key.as_bytes());
+        assert_eq!(m2.current_key("room").unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_non_leader_does_not_rekey() {
+        let (mut a, a_id) = manager();
+        let (_, b_id) = manager();
+        a.create_room("room");
+
+        let rekey = a.add_member("room", b_id).unwrap();
+        assert_eq!(rekey.is_some(), a_id < b_id);
+    }
+
+    #[test]
+    fn test_accept_rejects_stale_and_non_leader_keys() {
+        let mut managers = vec![manager(), manager(), manager()];
+        managers.sort_by_key(|(_, peer_id)| *peer_id);
+        let [(mut leader, leader_id), (mut member, member_id), (mut high, _)]: [_; 3] =
+            managers.try_into().ok().unwrap();
+
+        leader.create_room("room");
+        member.create_room("room");
+        high.create_room("room");
+        member
+            .add_members("room", [leader_id, high.local_peer_id])
+            .unwrap();
+
+        let rekey = leader.add_member("room", member_id).unwrap().unwrap();
+        deliver(&rekey, &mut member, leader_id);
+
+        // Replaying the same distribution is stale.
+        let (_, sealed) = &rekey.sealed[0];
+        assert!(member
+            .accept_key(
+                "room",
+                leader_id,
+                rekey.event.new_key_id,
+                &rekey.members,
+                sealed
+            )
+            .is_err());
+
+        // A higher peer cannot push a key while the leader is present.
+        high.add_member("room", member_id).unwrap();
+        let forged = high.rekey("room").unwrap();
+        let (_, sealed) = &forged.sealed[0];
+        assert!(member
+            .accept_key("room", high.local_peer_id, 10, &forged.members, sealed)
+            .is_err());
+
+        // A roster that leaves us out is refused even from the leader.
+        let key = SessionKey::new([9u8; 32]);
+        let sealed = seal_group_key(&member_id, "room", 10, &[leader_id], &key).unwrap();
+        assert!(member
+            .accept_key("room", leader_id, 10, &[leader_id], &sealed)
+            .is_err());
+        assert_eq!(member.current_key("room").unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_keys_only_from_and_to_verified_members() {
+        let mut managers = vec![manager(), manager(), manager()];
+        managers.sort_by_key(|(_, peer_id)| *peer_id);
+        let [(mut low, low_id), (mut mid, mid_id), (_, high_id)]: [_; 3] =
+            managers.try_into().ok().unwrap();
+
+        low.create_room("room");
+        mid.create_room("room");
+
+        // A lower peer ID alone does not make a leader.
+        let rekey = low.add_member("room", mid_id).unwrap().unwrap();
+        let (_, sealed) = &rekey.sealed[0];
+        assert!(mid
+            .accept_key("room", low_id, 2, &rekey.members, sealed)
+            .is_err());
+        assert_eq!(mid.leader("room"), Some(mid_id));
+
+        // Once verified, its roster is installed, but peers only listed in
+        // it are not sealed to when this node takes over.
+        mid.add_member("room", low_id).unwrap();
+        let rekey = low.add_member("room", high_id).unwrap().unwrap();
+        deliver(&rekey, &mut mid, low_id);
+        assert!(mid.members("room").contains(&high_id));
+        assert!(!mid.is_verified("room", &high_id));
+
+        let rekey = mid.remove_member("room", &low_id).unwrap().unwrap();
+        assert!(rekey.sealed.is_empty());
+        assert_eq!(rekey.members, vec![mid_id]);
+    }
+
+    #[test]
+    fn test_partial_roster_does_not_unseat_leader() {
+        let mut managers = vec![manager(), manager(), manager()];
+        managers.sort_by_key(|(_, peer_id)| *peer_id);
+        let [(mut low, low_id), (mut mid, mid_id), (mut high, high_id)]: [_; 3] =
+            managers.try_into().ok().unwrap();
+
+        low.create_room("room");
+        mid.create_room("room");
+        high.create_room("room");
+
+        high.add_member("room", low_id).unwrap();
+        let rekey = low.add_member("room", high_id).unwrap().unwrap();
+        deliver(&rekey, &mut high, low_id);
+
+        // A peer that has not met the leader yet leaves it off its roster.
+        high.add_member("room", mid_id).unwrap();
+        let rekey = mid.add_member("room", high_id).unwrap().unwrap();
+        assert_eq!(rekey.members, vec![mid_id, high_id]);
+        let (_, sealed) = &rekey.sealed[0];
+        let key_id = rekey.event.new_key_id;
+        assert!(high
+            .accept_key("room", mid_id, key_id, &rekey.members, sealed)
+            .is_err());
+        assert!(high.members("room").contains(&low_id));
+
+        // Once the leader has left as far as this node knows, it may.
+        high.remove_member("room", &low_id).unwrap();
+        assert!(high
+            .accept_key("room", mid_id, key_id, &rekey.members, sealed)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_check_rotation_only_for_leader() {
+        let identity = Identity::generate().unwrap();
+        let mut manager =
+            GroupKeyManager::new(identity).with_rotation_interval(Duration::from_millis(10));
+        manager.create_room("room");
+
+        std::thread::sleep(Duration::from_millis(20));
+        let rotations = manager.check_rotation();
+        assert_eq!(rotations.len(), 1);
+        assert_eq!(rotations[0].as_ref().unwrap().event.new_key_id, 2);
+    }
+
+    #[test]
+    fn test_peer_id_public_key_extraction() {
+        let identity = Identity::generate().unwrap();
+        let public = x25519_public_from_peer_id(&identity.libp2p_peer_id()).unwrap();
+        assert_eq!(public.as_bytes(), identity.x25519_public().as_bytes());
+    }
+}
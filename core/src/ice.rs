@@ -1,8 +1,30 @@
 use crate::error::{AgoraResult, Error};
-use crate::stun::{StunBinding, StunClient};
-use std::collections::HashSet;
+use crate::identity::{verifying_key_from_peer_id, Identity};
+use crate::stun::StunClient;
+use ed25519_dalek::{Signature, Verifier};
+use libp2p::{Multiaddr, PeerId};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stun::attributes::{
+    ATTR_FINGERPRINT, ATTR_ICE_CONTROLLED, ATTR_ICE_CONTROLLING, ATTR_MESSAGE_INTEGRITY,
+    ATTR_PRIORITY, ATTR_USERNAME, ATTR_USE_CANDIDATE,
+};
+use stun::error_code::{
+    ErrorCode, ErrorCodeAttribute, CODE_BAD_REQUEST, CODE_ROLE_CONFLICT, CODE_UNAUTHORIZED,
+};
+use stun::fingerprint::FINGERPRINT;
+use stun::integrity::MessageIntegrity;
+use stun::message::{
+    Getter, Message, MessageType, Setter, BINDING_ERROR, BINDING_REQUEST, BINDING_SUCCESS,
+};
+use stun::textattrs::TextAttribute;
+use stun::xoraddr::XorMappedAddress;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
 
 pub const ICE_DEFAULT_STUN_SERVERS: &[&str] =
     &["stun.l.google.com:19302", "stun1.l.google.com:19302"];
@@ -10,6 +32,14 @@ pub const ICE_DEFAULT_STUN_SERVERS: &[&str] =
 pub const ICE_CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(5);
 pub const ICE_CHECK_INTERVAL: Duration = Duration::from_millis(50);
 pub const ICE_NOMINATION_TIMEOUT: Duration = Duration::from_secs(3);
+pub const ICE_CONSENT_INTERVAL: Duration = Duration::from_secs(5);
+pub const ICE_CONSENT_TIMEOUT: Duration = Duration::from_secs(30);
+const ICE_CHECK_TIMEOUT: Duration = Duration::from_millis(500);
+const ICE_CHECK_RTO: Duration = Duration::from_millis(100);
+const INBOUND_CHECK_QUEUE: usize = 64;
+
+const SIGNING_DOMAIN: &str = "agora ice candidates v1";
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CandidateType {
@@ -95,6 +125,26 @@ impl Candidate {
         }
     }
 
+    /// A candidate learned from a connectivity check rather than gathered;
+    /// `priority` is the one carried in the check.
+    pub fn new_peer_reflexive(
+        addr: SocketAddr,
+        base_addr: SocketAddr,
+        priority: u32,
+        component_id: u16,
+    ) -> Self {
+        Self {
+            foundation: format!("prflx-{}", addr),
+            component_id,
+            transport: TransportType::Udp,
+            priority,
+            connection_addr: addr,
+            base_addr,
+            candidate_type: CandidateType::PeerReflexive,
+            related_addr: (base_addr != addr).then_some(base_addr),
+        }
+    }
+
     fn calculate_priority(
         candidate_type: CandidateType,
         local_pref: u16,
@@ -176,8 +226,14 @@ pub struct CandidatePair {
     pub nominated: bool,
     pub last_check: Option<Instant>,
     pub round_trip_time: Option<Duration>,
+    /// Nominate the pair once a check on it succeeds.
+    use_candidate: bool,
+    /// The peer checked the pair while our own check was in flight.
+    retrigger: bool,
 }
 
+type PairKey = (SocketAddr, SocketAddr);
+
 impl CandidatePair {
     pub fn new(local: Candidate, remote: Candidate) -> Self {
         let priority = Self::calculate_pair_priority(&local, &remote);
@@ -190,6 +246,8 @@ impl CandidatePair {
             nominated: false,
             last_check: None,
             round_trip_time: None,
+            use_candidate: false,
+            retrigger: false,
         }
     }
 
@@ -202,6 +260,25 @@ impl CandidatePair {
 
         (2u64.pow(32) * g.min(d)) + (2u64 * g.max(d)) + (g.max(d) - g.min(d))
     }
+
+    /// RFC 8445 pair priority, which depends on which side controls.
+    fn role_priority(&self, role: IceRole) -> u64 {
+        let (g, d) = match role {
+            IceRole::Controlling => (self.local.priority as u64, self.remote.priority as u64),
+            IceRole::Controlled => (self.remote.priority as u64, self.local.priority as u64),
+        };
+
+        (1u64 << 32) * g.min(d) + 2 * g.max(d) + u64::from(g > d)
+    }
+
+    /// Pairs sharing a foundation are likely to succeed or fail together.
+    pub fn foundation(&self) -> String {
+        format!("{}:{}", self.local.foundation, self.remote.foundation)
+    }
+
+    fn key(&self) -> PairKey {
+        (self.local.base_addr, self.remote.connection_addr)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -210,6 +287,33 @@ pub enum IceRole {
     Controlled,
 }
 
+/// Short-term credentials that authenticate connectivity checks. The
+/// password only proves a check belongs to this session; the connection
+/// itself is secured by the transport.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IceCredentials {
+    pub ufrag: String,
+    pub pwd: String,
+}
+
+impl IceCredentials {
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut random = |len: usize| -> String {
+            (&mut rng)
+                .sample_iter(rand::distributions::Alphanumeric)
+                .take(len)
+                .map(char::from)
+                .collect()
+        };
+
+        Self {
+            ufrag: random(8),
+            pwd: random(24),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct IceConfig {
     pub stun_servers: Vec<String>,
@@ -217,6 +321,13 @@ pub struct IceConfig {
     pub local_preferences: u16,
     pub connectivity_timeout: Duration,
     pub nomination_mode: NominationMode,
+    /// Addresses to gather host candidates on; empty means the addresses of
+    /// the default routes.
+    pub host_addresses: Vec<IpAddr>,
+    /// How often the selected pair is re-checked to keep consent (RFC 7675).
+    pub consent_interval: Duration,
+    /// How long without a consent response before the pair is abandoned.
+    pub consent_timeout: Duration,
 }
 
 impl Default for IceConfig {
@@ -230,6 +341,9 @@ impl Default for IceConfig {
             local_preferences: 65535,
             connectivity_timeout: ICE_CONNECTIVITY_TIMEOUT,
             nomination_mode: NominationMode::Aggressive,
+            host_addresses: Vec::new(),
+            consent_interval: ICE_CONSENT_INTERVAL,
+            consent_timeout: ICE_CONSENT_TIMEOUT,
         }
     }
 }
@@ -260,37 +374,485 @@ pub enum ConnectionState {
 
 pub struct IceAgent {
     config: IceConfig,
-    role: IceRole,
     local_candidates: Vec<Candidate>,
     remote_candidates: Vec<Candidate>,
-    candidate_pairs: Vec<CandidatePair>,
     checklist: Vec<CandidatePair>,
+    triggered: VecDeque<PairKey>,
     selected_pair: Option<CandidatePair>,
     state: ConnectionState,
-    tie_breaker: u64,
     component_id: u16,
+    /// Kept alive so relayed candidates stay allocated on their servers.
+    turn_clients: Vec<crate::turn::TurnClient>,
+    host_sockets: Vec<HostSocket>,
+    session: SharedSession,
+    inbound_tx: mpsc::Sender<InboundCheck>,
+    inbound_rx: Option<mpsc::Receiver<InboundCheck>>,
+    events_tx: mpsc::UnboundedSender<AgentEvent>,
+    events_rx: Option<mpsc::UnboundedReceiver<AgentEvent>>,
+    gathering_complete: bool,
+    remote_complete: bool,
+    consent: Option<ConsentMonitor>,
+}
+
+/// Candidates that arrive while the agent may be busy checking.
+enum AgentEvent {
+    Local(Candidate, Option<crate::turn::TurnClient>),
+    LocalComplete,
+    Remote(Candidate),
+    RemoteComplete,
+}
+
+/// Hands trickled remote candidates to an agent, including while its
+/// connectivity checks are running.
+#[derive(Clone)]
+pub struct IceTrickle {
+    events: mpsc::UnboundedSender<AgentEvent>,
+}
+
+impl IceTrickle {
+    pub fn add_remote_candidate(&self, candidate: Candidate) {
+        let _ = self.events.send(AgentEvent::Remote(candidate));
+    }
+
+    /// The peer has sent all its candidates.
+    pub fn end_of_candidates(&self) {
+        let _ = self.events.send(AgentEvent::RemoteComplete);
+    }
+}
+
+/// Reports candidates gathered in the background to the agent and to
+/// whoever trickles them to the peer.
+struct CandidateEmitter {
+    events: mpsc::UnboundedSender<AgentEvent>,
+    trickled: mpsc::UnboundedSender<Candidate>,
+}
+
+impl CandidateEmitter {
+    fn emit(&self, candidate: Candidate, turn_client: Option<crate::turn::TurnClient>) {
+        let _ = self.trickled.send(candidate.clone());
+        let _ = self.events.send(AgentEvent::Local(candidate, turn_client));
+    }
+}
+
+impl Drop for CandidateEmitter {
+    fn drop(&mut self) {
+        let _ = self.events.send(AgentEvent::LocalComplete);
+    }
+}
+
+/// Session state the socket readers need to answer checks on their own.
+struct SessionState {
+    local: IceCredentials,
+    remote: Option<IceCredentials>,
+    role: IceRole,
+    tie_breaker: u64,
+}
+
+type SharedSession = Arc<Mutex<SessionState>>;
+
+type PendingChecks = Arc<Mutex<HashMap<[u8; 12], oneshot::Sender<(Message, SocketAddr)>>>>;
+
+/// An authenticated check the remote agent sent us.
+struct InboundCheck {
+    local: SocketAddr,
+    remote: SocketAddr,
+    priority: u32,
+    use_candidate: bool,
+}
+
+enum CheckOutcome {
+    Success { mapped: SocketAddr, rtt: Duration },
+    RoleConflict,
+    Failed(String),
+}
+
+struct CheckResult {
+    key: PairKey,
+    role: IceRole,
+    priority: u32,
+    use_candidate: bool,
+    outcome: CheckOutcome,
+}
+
+/// A host candidate's socket. It stays open for the agent's lifetime so the
+/// remote side's checks reach us; a reader task answers their Binding
+/// requests and hands responses to our own checks.
+struct HostSocket {
+    sender: CheckSender,
+    local_addr: SocketAddr,
+    reader: tokio::task::JoinHandle<()>,
+}
+
+/// Sends checks from a host socket; its reader routes the responses back.
+#[derive(Clone)]
+struct CheckSender {
+    socket: Arc<UdpSocket>,
+    pending: PendingChecks,
+}
+
+impl HostSocket {
+    async fn bind(
+        addr: SocketAddr,
+        session: SharedSession,
+        inbound: mpsc::Sender<InboundCheck>,
+    ) -> std::io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let local_addr = socket.local_addr()?;
+        let pending: PendingChecks = Arc::default();
+        let reader = tokio::spawn(serve_host_socket(
+            socket.clone(),
+            pending.clone(),
+            session,
+            inbound,
+        ));
+
+        Ok(Self {
+            sender: CheckSender { socket, pending },
+            local_addr,
+            reader,
+        })
+    }
+}
+
+impl Drop for HostSocket {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Keeps consent for the selected pair by re-checking it periodically.
+struct ConsentMonitor {
+    last_response: Arc<Mutex<Instant>>,
+    timeout: Duration,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ConsentMonitor {
+    fn expired(&self) -> bool {
+        self.last_response.lock().unwrap().elapsed() > self.timeout
+    }
+}
+
+impl Drop for ConsentMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve_host_socket(
+    socket: Arc<UdpSocket>,
+    pending: PendingChecks,
+    session: SharedSession,
+    inbound: mpsc::Sender<InboundCheck>,
+) {
+    let mut buf = vec![0u8; 1500];
+    let local_addr = match socket.local_addr() {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::debug!("ICE host socket receive failed: {}", e);
+                continue;
+            }
+        };
+
+        let mut message = Message::new();
+        message.raw = buf[..n].to_vec();
+        if message.decode().is_err() {
+            continue;
+        }
+        if message.contains(ATTR_FINGERPRINT) && FINGERPRINT.check(&message).is_err() {
+            continue;
+        }
+
+        if message.typ == BINDING_REQUEST {
+            let (response, check) = answer_check(&mut message, from, local_addr, &session);
+            if let Some(response) = response {
+                let _ = socket.send_to(&response.raw, from).await;
+            }
+            if let Some(check) = check {
+                // Checks arriving while no one is checking are only
+                // answered; a full queue drops the rest.
+                let _ = inbound.try_send(check);
+            }
+        } else if message.typ == BINDING_SUCCESS || message.typ == BINDING_ERROR {
+            let waiter = pending.lock().unwrap().remove(&message.transaction_id.0);
+            if let Some(waiter) = waiter {
+                let _ = waiter.send((message, from));
+            }
+        }
+    }
+}
+
+/// Authenticates a Binding request and resolves role conflicts (RFC 8445
+/// section 7.3), returning the response and the check to hand the agent.
+fn answer_check(
+    request: &mut Message,
+    from: SocketAddr,
+    local_addr: SocketAddr,
+    session: &SharedSession,
+) -> (Option<Message>, Option<InboundCheck>) {
+    let mut session = session.lock().unwrap();
+
+    let username = match TextAttribute::get_from_as(request, ATTR_USERNAME) {
+        Ok(username) if request.contains(ATTR_MESSAGE_INTEGRITY) => username.text,
+        _ => return (error_response(request, CODE_BAD_REQUEST, None), None),
+    };
+    let integrity = MessageIntegrity::new_short_term_integrity(session.local.pwd.clone());
+    if username.split(':').next() != Some(session.local.ufrag.as_str())
+        || integrity.check(request).is_err()
+    {
+        return (error_response(request, CODE_UNAUTHORIZED, None), None);
+    }
+
+    let tie_breaker = |attribute| {
+        let value: [u8; 8] = request.get(attribute).ok()?.try_into().ok()?;
+        Some(u64::from_be_bytes(value))
+    };
+    match session.role {
+        IceRole::Controlling => {
+            if let Some(theirs) = tie_breaker(ATTR_ICE_CONTROLLING) {
+                if session.tie_breaker >= theirs {
+                    let response = error_response(request, CODE_ROLE_CONFLICT, Some(&integrity));
+                    return (response, None);
+                }
+                tracing::debug!("ICE role conflict, switching to controlled");
+                session.role = IceRole::Controlled;
+            }
+        }
+        IceRole::Controlled => {
+            if let Some(theirs) = tie_breaker(ATTR_ICE_CONTROLLED) {
+                if session.tie_breaker < theirs {
+                    let response = error_response(request, CODE_ROLE_CONFLICT, Some(&integrity));
+                    return (response, None);
+                }
+                tracing::debug!("ICE role conflict, switching to controlling");
+                session.role = IceRole::Controlling;
+            }
+        }
+    }
+
+    let mut response = response_to(request, BINDING_SUCCESS);
+    let mapped = XorMappedAddress {
+        ip: from.ip(),
+        port: from.port(),
+    };
+    let attributes: [&dyn Setter; 3] = [&mapped, &integrity, &FINGERPRINT];
+    if attributes.iter().any(|a| a.add_to(&mut response).is_err()) {
+        return (None, None);
+    }
+
+    let priority = request
+        .get(ATTR_PRIORITY)
+        .ok()
+        .and_then(|v| <[u8; 4]>::try_from(v.as_slice()).ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or_default();
+    let check = InboundCheck {
+        local: local_addr,
+        remote: from,
+        priority,
+        use_candidate: request.contains(ATTR_USE_CANDIDATE),
+    };
+    (Some(response), Some(check))
+}
+
+fn response_to(request: &Message, typ: MessageType) -> Message {
+    let mut response = Message::new();
+    response.typ = typ;
+    response.transaction_id = request.transaction_id;
+    response.write_header();
+    response
+}
+
+fn error_response(
+    request: &Message,
+    code: ErrorCode,
+    integrity: Option<&MessageIntegrity>,
+) -> Option<Message> {
+    let mut response = response_to(request, BINDING_ERROR);
+    code.add_to(&mut response).ok()?;
+    if let Some(integrity) = integrity {
+        integrity.add_to(&mut response).ok()?;
+    }
+    FINGERPRINT.add_to(&mut response).ok()?;
+    Some(response)
+}
+
+/// Builds an authenticated Binding request for a check sent as `role`.
+fn check_request(
+    session: &SessionState,
+    priority: u32,
+    use_candidate: bool,
+) -> AgoraResult<(Message, MessageIntegrity)> {
+    let remote = session
+        .remote
+        .as_ref()
+        .ok_or_else(|| Error::Network("Remote ICE credentials not set".to_string()))?;
+
+    let mut request = Message::new();
+    request
+        .build(&[
+            Box::new(BINDING_REQUEST),
+            Box::new(stun::agent::TransactionId::new()),
+        ])
+        .map_err(|e| Error::Network(format!("Failed to build STUN request: {}", e)))?;
+
+    let username = format!("{}:{}", remote.ufrag, session.local.ufrag);
+    TextAttribute::new(ATTR_USERNAME, username)
+        .add_to(&mut request)
+        .map_err(|e| Error::Network(format!("Failed to add ICE username: {}", e)))?;
+    request.add(ATTR_PRIORITY, &priority.to_be_bytes());
+    if use_candidate {
+        request.add(ATTR_USE_CANDIDATE, &[]);
+    }
+    let role_attribute = match session.role {
+        IceRole::Controlling => ATTR_ICE_CONTROLLING,
+        IceRole::Controlled => ATTR_ICE_CONTROLLED,
+    };
+    request.add(role_attribute, &session.tie_breaker.to_be_bytes());
+
+    let integrity = MessageIntegrity::new_short_term_integrity(remote.pwd.clone());
+    integrity
+        .add_to(&mut request)
+        .and_then(|_| FINGERPRINT.add_to(&mut request))
+        .map_err(|e| Error::Network(format!("Failed to sign ICE check: {}", e)))?;
+
+    Ok((request, integrity))
+}
+
+impl CheckSender {
+    /// Sends `request`, retransmitting with backoff until a response arrives
+    /// or the check times out, and validates the response.
+    async fn check(
+        &self,
+        remote_addr: SocketAddr,
+        request: Message,
+        integrity: MessageIntegrity,
+    ) -> CheckOutcome {
+        let transaction_id = request.transaction_id.0;
+        let (tx, mut rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(transaction_id, tx);
+
+        let start = Instant::now();
+        let mut rto = ICE_CHECK_RTO;
+        let response = loop {
+            if let Err(e) = self.socket.send_to(&request.raw, remote_addr).await {
+                break Err(format!("Failed to send STUN request: {}", e));
+            }
+            let remaining = ICE_CHECK_TIMEOUT.saturating_sub(start.elapsed());
+            match tokio::time::timeout(rto.min(remaining), &mut rx).await {
+                Ok(Ok(response)) => break Ok(response),
+                Ok(Err(_)) => break Err("Check abandoned".to_string()),
+                Err(_) if start.elapsed() >= ICE_CHECK_TIMEOUT => {
+                    break Err("No response received".to_string())
+                }
+                Err(_) => rto *= 2,
+            }
+        };
+        self.pending.lock().unwrap().remove(&transaction_id);
+
+        let (mut response, from) = match response {
+            Ok(response) => response,
+            Err(reason) => return CheckOutcome::Failed(reason),
+        };
+        // Responses must come back from where the check went (section 7.2.5.2.1).
+        if from != remote_addr {
+            return CheckOutcome::Failed(format!("Response from unexpected address {}", from));
+        }
+
+        if response.typ == BINDING_ERROR {
+            let mut error = ErrorCodeAttribute::default();
+            let _ = error.get_from(&response);
+            if error.code != CODE_ROLE_CONFLICT {
+                return CheckOutcome::Failed(format!("Check rejected with {}", error.code.0));
+            }
+        }
+        if integrity.check(&mut response).is_err() {
+            return CheckOutcome::Failed("Response failed integrity check".to_string());
+        }
+        if response.typ == BINDING_ERROR {
+            return CheckOutcome::RoleConflict;
+        }
+
+        let mut mapped = XorMappedAddress::default();
+        match mapped.get_from(&response) {
+            Ok(()) => CheckOutcome::Success {
+                mapped: SocketAddr::new(mapped.ip, mapped.port),
+                rtt: start.elapsed(),
+            },
+            Err(e) => CheckOutcome::Failed(format!("Missing mapped address: {}", e)),
+        }
+    }
+}
+
+/// Re-checks the selected pair until consent lapses (RFC 7675).
+async fn keep_consent(
+    sender: CheckSender,
+    session: SharedSession,
+    remote_addr: SocketAddr,
+    priority: u32,
+    interval: Duration,
+    timeout: Duration,
+    last_response: Arc<Mutex<Instant>>,
+) {
+    loop {
+        // Randomised so both sides do not check in lockstep.
+        let jitter = rand::thread_rng().gen_range(0.8..1.2);
+        tokio::time::sleep(interval.mul_f64(jitter)).await;
+        if last_response.lock().unwrap().elapsed() > timeout {
+            tracing::info!("ICE consent to send to {} expired", remote_addr);
+            return;
+        }
+
+        let request = check_request(&session.lock().unwrap(), priority, false);
+        let Ok((request, integrity)) = request else {
+            continue;
+        };
+        if let CheckOutcome::Success { .. } = sender.check(remote_addr, request, integrity).await {
+            *last_response.lock().unwrap() = Instant::now();
+        }
+    }
 }
 
 impl IceAgent {
     pub fn new(config: Option<IceConfig>) -> Self {
-        use rand::Rng;
+        let (inbound_tx, inbound_rx) = mpsc::channel(INBOUND_CHECK_QUEUE);
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
 
         Self {
             config: config.unwrap_or_default(),
-            role: IceRole::Controlling,
             local_candidates: Vec::new(),
             remote_candidates: Vec::new(),
-            candidate_pairs: Vec::new(),
             checklist: Vec::new(),
+            triggered: VecDeque::new(),
             selected_pair: None,
             state: ConnectionState::New,
-            tie_breaker: rand::thread_rng().gen(),
             component_id: 1,
+            turn_clients: Vec::new(),
+            host_sockets: Vec::new(),
+            session: Arc::new(Mutex::new(SessionState {
+                local: IceCredentials::generate(),
+                remote: None,
+                role: IceRole::Controlling,
+                tie_breaker: rand::thread_rng().gen(),
+            })),
+            inbound_tx,
+            inbound_rx: Some(inbound_rx),
+            events_tx,
+            events_rx: Some(events_rx),
+            gathering_complete: false,
+            remote_complete: false,
+            consent: None,
         }
     }
 
-    pub fn with_role(mut self, role: IceRole) -> Self {
-        self.role = role;
+    pub fn with_role(self, role: IceRole) -> Self {
+        self.session.lock().unwrap().role = role;
         self
     }
 
@@ -322,7 +884,14 @@ impl IceAgent {
         !self.config.turn_servers.is_empty()
     }
 
+    /// The connection state; a connected agent whose consent lapsed reports
+    /// `Failed`.
     pub fn state(&self) -> ConnectionState {
+        if self.state == ConnectionState::Connected
+            && self.consent.as_ref().is_some_and(ConsentMonitor::expired)
+        {
+            return ConnectionState::Failed;
+        }
         self.state
     }
 
@@ -338,151 +907,148 @@ impl IceAgent {
         &self.remote_candidates
     }
 
+    pub fn checklist(&self) -> &[CandidatePair] {
+        &self.checklist
+    }
+
+    /// May change during checks when a role conflict is resolved.
     pub fn role(&self) -> IceRole {
-        self.role
+        self.session.lock().unwrap().role
     }
 
     pub fn tie_breaker(&self) -> u64 {
-        self.tie_breaker
+        self.session.lock().unwrap().tie_breaker
     }
 
-    pub async fn gather_candidates(&mut self) -> AgoraResult<()> {
-        tracing::info!("Starting ICE candidate gathering");
+    pub fn local_credentials(&self) -> IceCredentials {
+        self.session.lock().unwrap().local.clone()
+    }
 
-        self.gather_host_candidates()?;
-        self.gather_server_reflexive_candidates().await?;
-        self.gather_relayed_candidates().await?;
+    /// Sets the peer's credentials. New credentials for a peer we already
+    /// had mean it restarted ICE, so its old candidates are dropped.
+    pub fn set_remote_credentials(&mut self, credentials: IceCredentials) {
+        let previous = self
+            .session
+            .lock()
+            .unwrap()
+            .remote
+            .replace(credentials.clone());
+        if previous.is_some_and(|previous| previous != credentials) {
+            tracing::info!("Remote ICE agent restarted");
+            self.reset_checks();
+        }
+    }
 
-        self.form_candidate_pairs();
+    /// Restarts ICE (RFC 8445 section 9): new local credentials, and checks
+    /// start over once the peer's new credentials and candidates arrive.
+    /// Gathered candidates are kept.
+    pub fn restart(&mut self) {
+        {
+            let mut session = self.session.lock().unwrap();
+            session.local = IceCredentials::generate();
+            session.remote = None;
+        }
+        self.reset_checks();
+        tracing::info!("ICE restarted");
+    }
+
+    fn reset_checks(&mut self) {
+        self.local_candidates
+            .retain(|c| c.candidate_type != CandidateType::PeerReflexive);
+        self.remote_candidates.clear();
+        self.checklist.clear();
+        self.triggered.clear();
+        self.selected_pair = None;
+        self.consent = None;
+        self.remote_complete = false;
+        self.state = ConnectionState::New;
+    }
+
+    /// Gathers every candidate before returning. Prefer `start_gathering` to
+    /// trickle candidates while checks already run.
+    pub async fn gather_candidates(&mut self) -> AgoraResult<()> {
+        drop(self.start_gathering().await?);
+
+        while !self.gathering_complete {
+            let event = match self.events_rx.as_mut() {
+                Some(events) => events.recv().await,
+                None => None,
+            };
+            match event {
+                Some(event) => self.handle_event(event),
+                None => break,
+            }
+        }
 
         tracing::info!(
             local_count = self.local_candidates.len(),
             remote_count = self.remote_candidates.len(),
-            pair_count = self.candidate_pairs.len(),
+            pair_count = self.checklist.len(),
             "Candidate gathering complete"
         );
 
         Ok(())
     }
 
-    fn gather_host_candidates(&mut self) -> AgoraResult<()> {
-        use std::net::UdpSocket;
-
-        let local_addrs = get_local_addresses()
-            .map_err(|e| Error::Network(format!("Failed to get local addresses: {}", e)))?;
+    /// Gathers host candidates, which are in `local_candidates` once this
+    /// returns, and gathers server reflexive and relayed candidates in the
+    /// background. Those are sent on the returned channel as they are found;
+    /// it closes when gathering is complete.
+    pub async fn start_gathering(&mut self) -> AgoraResult<mpsc::UnboundedReceiver<Candidate>> {
+        tracing::info!("Starting ICE candidate gathering");
 
-        for addr in local_addrs {
-            let socket_addr = SocketAddr::new(addr, 0);
+        self.gathering_complete = false;
+        self.gather_host_candidates().await?;
+        self.form_candidate_pairs();
 
-            if let Ok(socket) = UdpSocket::bind(socket_addr) {
-                if let Ok(local_addr) = socket.local_addr() {
-                    let candidate = Candidate::new_host(local_addr, self.component_id);
-                    self.local_candidates.push(candidate);
-                    tracing::debug!("Added host candidate: {}", local_addr);
-                }
-            }
-        }
+        let (trickled, candidates) = mpsc::unbounded_channel();
+        let emitter = CandidateEmitter {
+            events: self.events_tx.clone(),
+            trickled,
+        };
+        let stun_servers = self.config.stun_servers.clone();
+        let turn_servers = self.config.turn_servers.clone();
+        let base = self.find_base_address();
+        let component_id = self.component_id;
+        tokio::spawn(async move {
+            tokio::join!(
+                gather_server_reflexive(&stun_servers, base, component_id, &emitter),
+                gather_relayed(&turn_servers, component_id, &emitter),
+            );
+        });
 
-        Ok(())
+        Ok(candidates)
     }
 
-    async fn gather_server_reflexive_candidates(&mut self) -> AgoraResult<()> {
-        for stun_server in &self.config.stun_servers {
-            let stun_client = StunClient::with_servers(vec![stun_server.clone()]);
-
-            if let Ok(binding) = stun_client.get_public_address().await {
-                let base_addr = self.find_base_address(&binding);
+    async fn gather_host_candidates(&mut self) -> AgoraResult<()> {
+        let local_addrs = if self.config.host_addresses.is_empty() {
+            get_local_addresses()
+                .map_err(|e| Error::Network(format!("Failed to get local addresses: {}", e)))?
+        } else {
+            self.config.host_addresses.clone()
+        };
 
-                if let Some(base) = base_addr {
-                    let candidate = Candidate::new_server_reflexive(
-                        SocketAddr::new(binding.public_ip, binding.public_port),
-                        base,
-                        self.component_id,
-                    );
+        for addr in local_addrs {
+            let socket_addr = SocketAddr::new(addr, 0);
 
-                    self.local_candidates.push(candidate);
-                    tracing::debug!(
-                        "Added srflx candidate: {} (base: {})",
-                        SocketAddr::new(binding.public_ip, binding.public_port),
-                        base
-                    );
-                }
+            let bound =
+                HostSocket::bind(socket_addr, self.session.clone(), self.inbound_tx.clone()).await;
+            if let Ok(socket) = bound {
+                let candidate = Candidate::new_host(socket.local_addr, self.component_id);
+                self.local_candidates.push(candidate);
+                tracing::debug!("Added host candidate: {}", socket.local_addr);
+                self.host_sockets.push(socket);
             }
         }
 
         Ok(())
     }
 
-    async fn gather_relayed_candidates(&mut self) -> AgoraResult<()> {
-        for turn_server in &self.config.turn_servers {
-            if turn_server.username.is_none() || turn_server.password.is_none() {
-                tracing::warn!(
-                    "TURN server {} has no credentials, skipping",
-                    turn_server.url
-                );
-                continue;
-            }
-
-            tracing::info!(
-                "Attempting to create TURN allocation on {}",
-                turn_server.url
-            );
-
-            let server = if let Ok(addr) = turn_server.url.parse::<SocketAddr>() {
-                crate::turn::TurnServer::new(addr)
-            } else if let Ok(ip) = turn_server.url.parse::<std::net::IpAddr>() {
-                crate::turn::TurnServer::from_ip(ip, None)
-            } else {
-                let port = turn_server
-                    .url
-                    .split(':')
-                    .nth(1)
-                    .and_then(|p| p.parse().ok());
-                crate::turn::TurnServer::from_host(
-                    turn_server
-                        .url
-                        .split(':')
-                        .next()
-                        .unwrap_or(&turn_server.url),
-                    port,
-                )
-                .map_err(|e| Error::Network(format!("Invalid TURN server: {}", e)))?
-            };
-
-            let turn_config = crate::turn::TurnConfig {
-                servers: vec![server.clone()],
-                username: turn_server.username.clone(),
-                password: turn_server.password.clone(),
-                ..Default::default()
-            };
-
-            let mut turn_client = crate::turn::TurnClient::new(turn_config);
-
-            match turn_client.create_allocation(&server).await {
-                Ok(allocation) => {
-                    let candidate = Candidate::new_relayed(
-                        allocation.relayed_addr,
-                        server.address,
-                        self.component_id,
-                    );
-
-                    self.local_candidates.push(candidate);
-                    tracing::info!(
-                        "Added relayed candidate: {} via {}",
-                        allocation.relayed_addr,
-                        server.address
-                    );
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to create TURN allocation: {}", e);
-                }
-            }
-        }
-
-        Ok(())
+    pub fn turn_clients_mut(&mut self) -> &mut [crate::turn::TurnClient] {
+        &mut self.turn_clients
     }
 
-    fn find_base_address(&self, _binding: &StunBinding) -> Option<SocketAddr> {
+    fn find_base_address(&self) -> Option<SocketAddr> {
         for candidate in &self.local_candidates {
             if candidate.candidate_type == CandidateType::Host {
                 if let IpAddr::V4(local_ip) = candidate.connection_addr.ip() {
@@ -499,6 +1065,38 @@ impl IceAgent {
             .map(|c| c.connection_addr)
     }
 
+    /// A handle for trickling remote candidates into this agent.
+    pub fn trickle(&self) -> IceTrickle {
+        IceTrickle {
+            events: self.events_tx.clone(),
+        }
+    }
+
+    /// The peer has sent all its candidates, so checks may fail early once
+    /// every pair has.
+    pub fn end_of_remote_candidates(&mut self) {
+        self.remote_complete = true;
+    }
+
+    pub fn gathering_complete(&self) -> bool {
+        self.gathering_complete
+    }
+
+    fn handle_event(&mut self, event: AgentEvent) {
+        match event {
+            AgentEvent::Local(candidate, turn_client) => {
+                self.turn_clients.extend(turn_client);
+                if !self.local_candidates.contains(&candidate) {
+                    self.local_candidates.push(candidate);
+                    self.form_candidate_pairs();
+                }
+            }
+            AgentEvent::LocalComplete => self.gathering_complete = true,
+            AgentEvent::Remote(candidate) => self.add_remote_candidate(candidate),
+            AgentEvent::RemoteComplete => self.remote_complete = true,
+        }
+    }
+
     pub fn add_remote_candidate(&mut self, candidate: Candidate) {
         if !self.remote_candidates.contains(&candidate) {
             tracing::debug!("Adding remote candidate: {}", candidate.connection_addr);
@@ -513,166 +1111,391 @@ impl IceAgent {
         }
     }
 
+    /// Pairs local and remote candidates of the same address family.
+    /// Server-reflexive candidates are checked from their base, so their
+    /// pairs would duplicate the host ones (section 6.1.2.4).
     fn form_candidate_pairs(&mut self) {
-        let mut new_pairs: Vec<CandidatePair> = Vec::new();
-
         for local in &self.local_candidates {
-            for remote in &self.remote_candidates {
-                let pair = CandidatePair::new(local.clone(), remote.clone());
-
-                let exists = self.candidate_pairs.iter().any(|p| {
-                    p.local.connection_addr == pair.local.connection_addr
-                        && p.remote.connection_addr == pair.remote.connection_addr
-                });
+            if !matches!(
+                local.candidate_type,
+                CandidateType::Host | CandidateType::Relayed
+            ) {
+                continue;
+            }
 
-                if !exists {
-                    new_pairs.push(pair);
+            for remote in &self.remote_candidates {
+                let exists = self
+                    .checklist
+                    .iter()
+                    .any(|p| p.key() == (local.base_addr, remote.connection_addr));
+                if exists
+                    || local.component_id != remote.component_id
+                    || local.base_addr.is_ipv4() != remote.connection_addr.is_ipv4()
+                {
+                    continue;
                 }
+
+                self.checklist
+                    .push(CandidatePair::new(local.clone(), remote.clone()));
             }
         }
 
-        self.candidate_pairs.extend(new_pairs);
         self.sort_pairs_by_priority();
-        self.update_checklist();
+        self.unfreeze_foundations();
+
+        if self.state == ConnectionState::New && !self.checklist.is_empty() {
+            self.state = ConnectionState::Checking;
+        }
     }
 
     fn sort_pairs_by_priority(&mut self) {
-        self.candidate_pairs
-            .sort_by(|a, b| b.priority.cmp(&a.priority));
+        let role = self.role();
+        for pair in &mut self.checklist {
+            pair.priority = pair.role_priority(role);
+        }
+        self.checklist
+            .sort_by_key(|p| std::cmp::Reverse(p.priority));
     }
 
-    fn update_checklist(&mut self) {
-        self.checklist = self.candidate_pairs.clone();
-
-        let mut seen_foundation: HashSet<String> = HashSet::new();
+    /// Moves the best frozen pair of every foundation with nothing waiting
+    /// or in progress to waiting (section 6.1.4.2).
+    fn unfreeze_foundations(&mut self) {
+        let mut active: HashSet<String> = self
+            .checklist
+            .iter()
+            .filter(|p| {
+                matches!(
+                    p.state,
+                    CandidatePairState::Waiting | CandidatePairState::InProgress
+                )
+            })
+            .map(CandidatePair::foundation)
+            .collect();
 
         for pair in &mut self.checklist {
-            if !seen_foundation.contains(&pair.local.foundation) {
+            if pair.state == CandidatePairState::Frozen && active.insert(pair.foundation()) {
                 pair.state = CandidatePairState::Waiting;
-                seen_foundation.insert(pair.local.foundation.clone());
             }
         }
+    }
 
-        if self.state == ConnectionState::New && !self.checklist.is_empty() {
-            self.state = ConnectionState::Checking;
-        }
+    fn pair_index(&self, key: PairKey) -> Option<usize> {
+        self.checklist.iter().position(|p| p.key() == key)
     }
 
+    /// Runs checks until a pair is nominated or the connectivity timeout
+    /// passes. One check starts every `ICE_CHECK_INTERVAL`, triggered checks
+    /// first, then the best waiting pair.
     pub async fn perform_connectivity_checks(&mut self) -> AgoraResult<()> {
         tracing::info!("Starting connectivity checks");
 
-        let start_time = Instant::now();
-        let timeout = self.config.connectivity_timeout;
+        if self.session.lock().unwrap().remote.is_none() {
+            return Err(Error::Network("Remote ICE credentials not set".to_string()));
+        }
+        let (Some(mut inbound), Some(mut events)) = (self.inbound_rx.take(), self.events_rx.take())
+        else {
+            return Err(Error::Network(
+                "Connectivity checks already running".to_string(),
+            ));
+        };
 
-        while start_time.elapsed() < timeout {
-            let pending_pairs: Vec<(usize, SocketAddr, SocketAddr)> = self
+        let deadline = tokio::time::Instant::now() + self.config.connectivity_timeout;
+        let (results_tx, mut results) = mpsc::unbounded_channel();
+        let mut pacer = tokio::time::interval(ICE_CHECK_INTERVAL);
+        let mut first_valid: Option<Instant> = None;
+
+        while !self.checklist.iter().any(|p| p.nominated) && !self.checks_exhausted() {
+            tokio::select! {
+                _ = pacer.tick() => {
+                    self.nominate_regular(&mut first_valid);
+                    if let Some(idx) = self.next_check() {
+                        self.start_check(idx, &results_tx);
+                    }
+                }
+                Some(result) = results.recv() => self.handle_check_result(result),
+                Some(check) = inbound.recv() => self.handle_inbound_check(check),
+                Some(event) = events.recv() => self.handle_event(event),
+                _ = tokio::time::sleep_until(deadline) => break,
+            }
+        }
+
+        self.inbound_rx = Some(inbound);
+        self.events_rx = Some(events);
+        self.finalize_selection()
+    }
+
+    /// Once both sides are done gathering, a checklist in which every pair
+    /// failed cannot recover (RFC 8838 section 8).
+    fn checks_exhausted(&self) -> bool {
+        self.gathering_complete
+            && self.remote_complete
+            && self.triggered.is_empty()
+            && self
                 .checklist
                 .iter()
-                .enumerate()
-                .filter(|(_, pair)| {
-                    matches!(
-                        pair.state,
-                        CandidatePairState::Waiting | CandidatePairState::InProgress
-                    )
-                })
-                .map(|(i, pair)| (i, pair.local.base_addr, pair.remote.connection_addr))
-                .collect();
-
-            if pending_pairs.is_empty() {
-                break;
-            }
-
-            for (idx, local_addr, remote_addr) in pending_pairs {
-                self.checklist[idx].state = CandidatePairState::InProgress;
-                self.checklist[idx].last_check = Some(Instant::now());
-
-                let result = self.check_connectivity_impl(local_addr, remote_addr).await;
-
-                match result {
-                    Ok(rtt) => {
-                        self.checklist[idx].state = CandidatePairState::Succeeded;
-                        self.checklist[idx].round_trip_time = Some(rtt);
-
-                        tracing::debug!(
-                            local = ?local_addr,
-                            remote = ?remote_addr,
-                            rtt_ms = rtt.as_millis(),
-                            "Connectivity check succeeded"
-                        );
+                .all(|p| p.state == CandidatePairState::Failed)
+    }
 
-                        if self.config.nomination_mode == NominationMode::Aggressive {
-                            self.nominate_pair(idx);
-                            break;
-                        }
-                    }
-                    Err(_) => {
-                        self.checklist[idx].state = CandidatePairState::Failed;
-                        tracing::debug!(
-                            local = ?local_addr,
-                            remote = ?remote_addr,
-                            "Connectivity check failed"
-                        );
-                    }
+    fn next_check(&mut self) -> Option<usize> {
+        while let Some(key) = self.triggered.pop_front() {
+            match self.pair_index(key) {
+                Some(idx) if self.checklist[idx].state != CandidatePairState::InProgress => {
+                    return Some(idx);
                 }
+                _ => {}
             }
+        }
 
-            tokio::time::sleep(ICE_CHECK_INTERVAL).await;
+        if !self
+            .checklist
+            .iter()
+            .any(|p| p.state == CandidatePairState::Waiting)
+        {
+            self.unfreeze_foundations();
         }
+        self.checklist
+            .iter()
+            .position(|p| p.state == CandidatePairState::Waiting)
+    }
 
-        self.finalize_selection()
+    fn start_check(&mut self, idx: usize, results: &mpsc::UnboundedSender<CheckResult>) {
+        let aggressive = self.config.nomination_mode == NominationMode::Aggressive;
+        let session = self.session.lock().unwrap();
+        let role = session.role;
+        let pair = &mut self.checklist[idx];
+        let key = pair.key();
+        // Our address as the peer would learn it, should it turn out to be
+        // peer reflexive.
+        let priority =
+            Candidate::compute_priority(CandidateType::PeerReflexive, 0, pair.local.component_id);
+        let use_candidate = role == IceRole::Controlling && (aggressive || pair.use_candidate);
+
+        pair.state = CandidatePairState::InProgress;
+        pair.last_check = Some(Instant::now());
+        pair.retrigger = false;
+
+        let sender = self
+            .host_sockets
+            .iter()
+            .find(|s| s.local_addr == key.0)
+            .map(|s| s.sender.clone());
+        let request = check_request(&session, priority, use_candidate);
+        drop(session);
+
+        let (sender, (request, integrity)) = match (sender, request) {
+            (Some(sender), Ok(request)) => (sender, request),
+            (None, _) => {
+                tracing::debug!(local = ?key.0, "No open socket for pair");
+                self.checklist[idx].state = CandidatePairState::Failed;
+                return;
+            }
+            (_, Err(e)) => {
+                tracing::debug!("Failed to build check: {}", e);
+                self.checklist[idx].state = CandidatePairState::Failed;
+                return;
+            }
+        };
+
+        let results = results.clone();
+        tokio::spawn(async move {
+            let outcome = sender.check(key.1, request, integrity).await;
+            let _ = results.send(CheckResult {
+                key,
+                role,
+                priority,
+                use_candidate,
+                outcome,
+            });
+        });
     }
 
-    async fn check_connectivity_impl(
-        &self,
-        local_addr: SocketAddr,
-        remote_addr: SocketAddr,
-    ) -> AgoraResult<Duration> {
-        use tokio::net::UdpSocket;
+    fn handle_check_result(&mut self, result: CheckResult) {
+        let Some(idx) = self.pair_index(result.key) else {
+            return;
+        };
+        let (local_addr, remote_addr) = result.key;
+
+        match result.outcome {
+            CheckOutcome::Success { mapped, rtt } => {
+                tracing::debug!(
+                    local = ?local_addr,
+                    remote = ?remote_addr,
+                    rtt_ms = rtt.as_millis(),
+                    "Connectivity check succeeded"
+                );
 
-        let socket = UdpSocket::bind(local_addr)
-            .await
-            .map_err(|e| Error::Network(format!("Failed to bind socket: {}", e)))?;
+                if !self
+                    .local_candidates
+                    .iter()
+                    .any(|c| c.connection_addr == mapped)
+                {
+                    tracing::debug!("Learned peer reflexive candidate {}", mapped);
+                    self.local_candidates.push(Candidate::new_peer_reflexive(
+                        mapped,
+                        local_addr,
+                        result.priority,
+                        self.component_id,
+                    ));
+                }
 
-        let start = Instant::now();
+                let controlled = self.role() == IceRole::Controlled;
+                let pair = &mut self.checklist[idx];
+                pair.state = CandidatePairState::Succeeded;
+                pair.round_trip_time = Some(rtt);
+                let nominate = result.use_candidate || (controlled && pair.use_candidate);
 
-        let stun_request = self.create_binding_request();
+                let foundation = pair.foundation();
+                for pair in &mut self.checklist {
+                    if pair.state == CandidatePairState::Frozen && pair.foundation() == foundation {
+                        pair.state = CandidatePairState::Waiting;
+                    }
+                }
 
-        socket
-            .send_to(&stun_request, remote_addr)
-            .await
-            .map_err(|e| Error::Network(format!("Failed to send STUN request: {}", e)))?;
+                if nominate {
+                    self.nominate_pair(idx);
+                }
+            }
+            CheckOutcome::RoleConflict => {
+                {
+                    let mut session = self.session.lock().unwrap();
+                    if session.role == result.role {
+                        session.role = match result.role {
+                            IceRole::Controlling => IceRole::Controlled,
+                            IceRole::Controlled => IceRole::Controlling,
+                        };
+                        tracing::debug!("ICE role conflict, switching to {:?}", session.role);
+                    }
+                }
+                self.checklist[idx].state = CandidatePairState::Waiting;
+                self.triggered.push_back(result.key);
+                self.sort_pairs_by_priority();
+            }
+            CheckOutcome::Failed(reason) => {
+                tracing::debug!(
+                    local = ?local_addr,
+                    remote = ?remote_addr,
+                    "Connectivity check failed: {}",
+                    reason
+                );
 
-        let mut buf = vec![0u8; 1500];
+                let pair = &mut self.checklist[idx];
+                if pair.retrigger {
+                    pair.state = CandidatePairState::Waiting;
+                    self.triggered.push_back(result.key);
+                } else if pair.state != CandidatePairState::Succeeded {
+                    pair.state = CandidatePairState::Failed;
+                }
+            }
+        }
+    }
 
-        let recv_result =
-            tokio::time::timeout(Duration::from_millis(500), socket.recv_from(&mut buf)).await;
+    /// Answers a check from the peer with a triggered check of our own,
+    /// learning its address as a peer reflexive candidate if it is new
+    /// (section 7.3.1.3).
+    fn handle_inbound_check(&mut self, check: InboundCheck) {
+        let key = (check.local, check.remote);
+        let idx = match self.pair_index(key) {
+            Some(idx) => idx,
+            None => {
+                let Some(local) = self
+                    .local_candidates
+                    .iter()
+                    .find(|c| c.candidate_type == CandidateType::Host && c.base_addr == check.local)
+                    .cloned()
+                else {
+                    return;
+                };
+                let remote = match self
+                    .remote_candidates
+                    .iter()
+                    .find(|c| c.connection_addr == check.remote)
+                {
+                    Some(remote) => remote.clone(),
+                    None => {
+                        tracing::debug!("Learned remote peer reflexive candidate {}", check.remote);
+                        let remote = Candidate::new_peer_reflexive(
+                            check.remote,
+                            check.remote,
+                            check.priority,
+                            self.component_id,
+                        );
+                        self.remote_candidates.push(remote.clone());
+                        remote
+                    }
+                };
 
-        match recv_result {
-            Ok(Ok((n, from_addr))) => {
-                if from_addr == remote_addr && n > 0 {
-                    Ok(start.elapsed())
-                } else {
-                    Err(Error::Network("Response from wrong address".to_string()))
+                let mut pair = CandidatePair::new(local, remote);
+                pair.state = CandidatePairState::Waiting;
+                self.checklist.push(pair);
+                self.sort_pairs_by_priority();
+                match self.pair_index(key) {
+                    Some(idx) => idx,
+                    None => return,
+                }
+            }
+        };
+
+        let controlled = self.role() == IceRole::Controlled;
+        let pair = &mut self.checklist[idx];
+        if controlled && check.use_candidate {
+            pair.use_candidate = true;
+        }
+
+        match pair.state {
+            CandidatePairState::Succeeded => {
+                if controlled && check.use_candidate {
+                    self.nominate_pair(idx);
+                }
+            }
+            CandidatePairState::InProgress => pair.retrigger = true,
+            _ => {
+                pair.state = CandidatePairState::Waiting;
+                if !self.triggered.contains(&key) {
+                    self.triggered.push_back(key);
                 }
             }
-            _ => Err(Error::Network("No response received".to_string())),
         }
     }
 
-    fn create_binding_request(&self) -> Vec<u8> {
-        use stun::agent::TransactionId;
-        use stun::message::{Message, BINDING_REQUEST};
+    /// With regular nomination the controlling agent lets checks run, then
+    /// repeats the check on the best valid pair with USE-CANDIDATE.
+    fn nominate_regular(&mut self, first_valid: &mut Option<Instant>) {
+        if self.config.nomination_mode != NominationMode::Regular
+            || self.role() != IceRole::Controlling
+            || self.checklist.iter().any(|p| p.use_candidate)
+        {
+            return;
+        }
+        let Some(best) = self
+            .checklist
+            .iter()
+            .position(|p| p.state == CandidatePairState::Succeeded)
+        else {
+            return;
+        };
 
-        let mut msg = Message::new();
-        let _ = msg.build(&[Box::new(BINDING_REQUEST), Box::new(TransactionId::new())]);
+        let started = *first_valid.get_or_insert_with(Instant::now);
+        let pending = self.checklist.iter().any(|p| {
+            matches!(
+                p.state,
+                CandidatePairState::Frozen
+                    | CandidatePairState::Waiting
+                    | CandidatePairState::InProgress
+            )
+        });
+        if pending && started.elapsed() < ICE_NOMINATION_TIMEOUT {
+            return;
+        }
 
-        msg.raw.clone()
+        let pair = &mut self.checklist[best];
+        pair.use_candidate = true;
+        pair.state = CandidatePairState::Waiting;
+        self.triggered.push_front(pair.key());
     }
 
     fn nominate_pair(&mut self, idx: usize) {
         if let Some(pair) = self.checklist.get_mut(idx) {
             pair.nominated = true;
-            self.selected_pair = Some(pair.clone());
 
             tracing::info!(
                 local = ?pair.local.connection_addr,
@@ -680,38 +1503,66 @@ impl IceAgent {
                 "Nominated pair selected"
             );
         }
+
+        self.selected_pair = self.checklist.iter().find(|p| p.nominated).cloned();
     }
 
     fn finalize_selection(&mut self) -> AgoraResult<()> {
-        let succeeded_pairs: Vec<&CandidatePair> = self
-            .checklist
-            .iter()
-            .filter(|p| p.state == CandidatePairState::Succeeded)
-            .collect();
-
-        if succeeded_pairs.is_empty() {
+        let Some(selected) = self.selected_pair.clone() else {
             self.state = ConnectionState::Failed;
-            return Err(Error::Network("No valid candidate pairs found".to_string()));
-        }
-
-        if self.selected_pair.is_none() {
-            if let Some(best_pair) = succeeded_pairs.first() {
-                self.selected_pair = Some((*best_pair).clone());
-            }
-        }
+            let reason = if self
+                .checklist
+                .iter()
+                .any(|p| p.state == CandidatePairState::Succeeded)
+            {
+                "No candidate pair nominated"
+            } else {
+                "No valid candidate pairs found"
+            };
+            return Err(Error::Network(reason.to_string()));
+        };
 
         self.state = ConnectionState::Connected;
+        self.start_consent_checks(&selected);
 
         tracing::info!(
-            selected_local = ?self.selected_pair.as_ref().map(|p| p.local.connection_addr),
-            selected_remote = ?self.selected_pair.as_ref().map(|p| p.remote.connection_addr),
-            succeeded_pairs = succeeded_pairs.len(),
+            selected_local = ?selected.local.connection_addr,
+            selected_remote = ?selected.remote.connection_addr,
+            rtt_ms = ?selected.round_trip_time.map(|rtt| rtt.as_millis()),
             "ICE connection established"
         );
 
         Ok(())
     }
 
+    fn start_consent_checks(&mut self, pair: &CandidatePair) {
+        let Some(socket) = self
+            .host_sockets
+            .iter()
+            .find(|s| s.local_addr == pair.local.base_addr)
+        else {
+            return;
+        };
+
+        let last_response = Arc::new(Mutex::new(Instant::now()));
+        let priority =
+            Candidate::compute_priority(CandidateType::PeerReflexive, 0, pair.local.component_id);
+        let task = tokio::spawn(keep_consent(
+            socket.sender.clone(),
+            self.session.clone(),
+            pair.remote.connection_addr,
+            priority,
+            self.config.consent_interval,
+            self.config.consent_timeout,
+            last_response.clone(),
+        ));
+        self.consent = Some(ConsentMonitor {
+            last_response,
+            timeout: self.config.consent_timeout,
+            task,
+        });
+    }
+
     pub fn get_local_sdp(&self) -> String {
         self.local_candidates
             .iter()
@@ -725,6 +1576,254 @@ impl IceAgent {
             .as_ref()
             .map(|p| (p.local.base_addr, p.remote.connection_addr))
     }
+
+    /// Stops consent checks and closes the host sockets, waiting until their
+    /// ports are free so another protocol can take over the selected pair.
+    /// The selected pair stays readable.
+    pub async fn close(&mut self) {
+        self.consent = None;
+        // The controlled agent selects the nominated pair only once its own
+        // check on it succeeds, so keep answering for a while.
+        if self.role() == IceRole::Controlling {
+            tokio::time::sleep(ICE_CHECK_TIMEOUT).await;
+        }
+        let sockets: Vec<_> = self
+            .host_sockets
+            .drain(..)
+            .map(|socket| Arc::downgrade(&socket.sender.socket))
+            .collect();
+        // Aborted readers and in-flight checks release their handles shortly.
+        let released = async {
+            while sockets.iter().any(|socket| socket.strong_count() > 0) {
+                tokio::time::sleep(ICE_CHECK_INTERVAL).await;
+            }
+        };
+        let _ = tokio::time::timeout(ICE_CHECK_TIMEOUT * 2, released).await;
+        self.state = ConnectionState::Closed;
+    }
+}
+
+async fn gather_server_reflexive(
+    stun_servers: &[String],
+    base: Option<SocketAddr>,
+    component_id: u16,
+    emitter: &CandidateEmitter,
+) {
+    let Some(base) = base else {
+        return;
+    };
+
+    for stun_server in stun_servers {
+        let stun_client = StunClient::with_servers(vec![stun_server.clone()]);
+
+        if let Ok(binding) = stun_client.get_public_address().await {
+            let public = SocketAddr::new(binding.public_ip, binding.public_port);
+            tracing::debug!("Added srflx candidate: {} (base: {})", public, base);
+            emitter.emit(
+                Candidate::new_server_reflexive(public, base, component_id),
+                None,
+            );
+        }
+    }
+}
+
+async fn gather_relayed(
+    turn_servers: &[TurnServer],
+    component_id: u16,
+    emitter: &CandidateEmitter,
+) {
+    for turn_server in turn_servers {
+        if turn_server.username.is_none() || turn_server.password.is_none() {
+            tracing::warn!(
+                "TURN server {} has no credentials, skipping",
+                turn_server.url
+            );
+            continue;
+        }
+
+        tracing::info!(
+            "Attempting to create TURN allocation on {}",
+            turn_server.url
+        );
+
+        let server = if let Ok(addr) = turn_server.url.parse::<SocketAddr>() {
+            crate::turn::TurnServer::new(addr)
+        } else if let Ok(ip) = turn_server.url.parse::<std::net::IpAddr>() {
+            crate::turn::TurnServer::from_ip(ip, None)
+        } else {
+            let port = turn_server
+                .url
+                .split(':')
+                .nth(1)
+                .and_then(|p| p.parse().ok());
+            let host = turn_server
+                .url
+                .split(':')
+                .next()
+                .unwrap_or(&turn_server.url);
+            match crate::turn::TurnServer::from_host(host, port) {
+                Ok(server) => server,
+                Err(e) => {
+                    tracing::warn!("Invalid TURN server {}: {}", turn_server.url, e);
+                    continue;
+                }
+            }
+        };
+
+        let turn_config = crate::turn::TurnConfig {
+            servers: vec![server.clone()],
+            username: turn_server.username.clone(),
+            password: turn_server.password.clone(),
+            ..Default::default()
+        };
+
+        let mut turn_client = crate::turn::TurnClient::new(turn_config);
+
+        match turn_client.create_allocation(&server).await {
+            Ok(allocation) => {
+                let mut candidate =
+                    Candidate::new_relayed(allocation.relayed_addr, server.address, component_id);
+                if let Some(mapped) = allocation.mapped_addr {
+                    candidate.related_addr = Some(mapped);
+                }
+
+                tracing::info!(
+                    "Added relayed candidate: {} via {}",
+                    allocation.relayed_addr,
+                    server.address
+                );
+                emitter.emit(candidate, Some(turn_client));
+            }
+            Err(e) => {
+                tracing::warn!("Failed to create TURN allocation: {}", e);
+            }
+        }
+    }
+}
+
+/// Candidates and libp2p addresses one peer offers another for an ICE
+/// session. Signed by the sender so the record can travel through the DHT.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedIceCandidates {
+    pub from: String,
+    pub to: String,
+    pub credentials: IceCredentials,
+    /// Candidates in SDP attribute form.
+    pub candidates: Vec<String>,
+    /// libp2p addresses to dial once a pair is nominated.
+    pub addrs: Vec<String>,
+    /// Set on the last offer, once gathering has finished.
+    pub end_of_candidates: bool,
+    /// Unix time in milliseconds.
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct SigningPayload<'a> {
+    domain: &'static str,
+    from: &'a str,
+    to: &'a str,
+    credentials: &'a IceCredentials,
+    candidates: &'a [String],
+    addrs: &'a [String],
+    end_of_candidates: bool,
+    issued_at: u64,
+}
+
+impl SignedIceCandidates {
+    pub fn sign(
+        identity: &Identity,
+        to: &PeerId,
+        credentials: &IceCredentials,
+        candidates: &[Candidate],
+        addrs: &[Multiaddr],
+        end_of_candidates: bool,
+    ) -> AgoraResult<Self> {
+        let mut signed = Self {
+            from: identity.peer_id(),
+            to: to.to_string(),
+            credentials: credentials.clone(),
+            candidates: candidates.iter().map(Candidate::to_sdp).collect(),
+            addrs: addrs.iter().map(Multiaddr::to_string).collect(),
+            end_of_candidates,
+            issued_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            signature: Vec::new(),
+        };
+        signed.signature = identity.sign(&signed.signing_bytes()?).to_bytes().to_vec();
+        Ok(signed)
+    }
+
+    fn signing_bytes(&self) -> AgoraResult<Vec<u8>> {
+        postcard::to_allocvec(&SigningPayload {
+            domain: SIGNING_DOMAIN,
+            from: &self.from,
+            to: &self.to,
+            credentials: &self.credentials,
+            candidates: &self.candidates,
+            addrs: &self.addrs,
+            end_of_candidates: self.end_of_candidates,
+            issued_at: self.issued_at,
+        })
+        .map_err(|e| Error::Crypto(format!("Failed to encode ICE candidates: {}", e)))
+    }
+
+    pub fn from_peer_id(&self) -> AgoraResult<PeerId> {
+        self.from
+            .parse()
+            .map_err(|e| Error::Crypto(format!("Invalid sender {}: {}", self.from, e)))
+    }
+
+    /// Checks that the record was signed by `from` and is addressed to `to`.
+    pub fn verify(&self, from: &PeerId, to: &PeerId) -> AgoraResult<()> {
+        if self.from_peer_id()? != *from || self.to != to.to_string() {
+            return Err(Error::Crypto(
+                "ICE candidates addressed to another session".to_string(),
+            ));
+        }
+
+        let key = verifying_key_from_peer_id(from)?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|e| Error::Crypto(format!("Invalid signature: {}", e)))?;
+        key.verify(&self.signing_bytes()?, &signature)
+            .map_err(|_| Error::Crypto("ICE candidates signature mismatch".to_string()))
+    }
+
+    pub fn to_bytes(&self) -> AgoraResult<Vec<u8>> {
+        postcard::to_allocvec(self)
+            .map_err(|e| Error::Network(format!("Failed to encode ICE candidates: {}", e)))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> AgoraResult<Self> {
+        postcard::from_bytes(bytes)
+            .map_err(|e| Error::Network(format!("Failed to decode ICE candidates: {}", e)))
+    }
+
+    /// Whether the record was issued within `max_age`, allowing for skew.
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        now.saturating_sub(self.issued_at) <= max_age.as_millis() as u64
+            && self.issued_at <= now + MAX_CLOCK_SKEW.as_millis() as u64
+    }
+
+    /// Parsed candidates; malformed entries are skipped.
+    pub fn parsed_candidates(&self) -> Vec<Candidate> {
+        self.candidates
+            .iter()
+            .filter_map(|sdp| parse_candidate_from_sdp(sdp))
+            .collect()
+    }
+
+    /// Parsed libp2p addresses; malformed entries are skipped.
+    pub fn parsed_addrs(&self) -> Vec<Multiaddr> {
+        self.addrs.iter().filter_map(|a| a.parse().ok()).collect()
+    }
 }
 
 fn get_local_addresses() -> std::io::Result<Vec<IpAddr>> {
@@ -918,4 +2017,366 @@ mod tests {
         assert!(sdp.contains("typ relay"));
         assert!(sdp.contains("203.0.113.1"));
     }
+
+    fn loopback_config() -> IceConfig {
+        IceConfig {
+            stun_servers: vec![],
+            host_addresses: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            connectivity_timeout: Duration::from_secs(2),
+            ..Default::default()
+        }
+    }
+
+    async fn loopback_agent(config: IceConfig, role: IceRole) -> IceAgent {
+        let mut agent = IceAgent::new(Some(config)).with_role(role);
+        agent.gather_candidates().await.unwrap();
+        agent
+    }
+
+    fn exchange(first: &mut IceAgent, second: &mut IceAgent) {
+        first.set_remote_credentials(second.local_credentials());
+        second.set_remote_credentials(first.local_credentials());
+        first.add_remote_candidates(second.local_candidates().to_vec());
+        second.add_remote_candidates(first.local_candidates().to_vec());
+    }
+
+    async fn connect(first: &mut IceAgent, second: &mut IceAgent) {
+        let (a, b) = tokio::join!(
+            first.perform_connectivity_checks(),
+            second.perform_connectivity_checks()
+        );
+        a.unwrap();
+        b.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_agents_nominate_pair_over_loopback() {
+        let mut controlling = loopback_agent(loopback_config(), IceRole::Controlling).await;
+        let mut controlled = loopback_agent(loopback_config(), IceRole::Controlled).await;
+        exchange(&mut controlling, &mut controlled);
+        connect(&mut controlling, &mut controlled).await;
+
+        assert_eq!(controlling.state(), ConnectionState::Connected);
+        assert_eq!(controlled.state(), ConnectionState::Connected);
+        let (local, remote) = controlling.get_selected_connection().unwrap();
+        assert_eq!(local, controlling.local_candidates()[0].base_addr);
+        assert_eq!(remote, controlled.local_candidates()[0].connection_addr);
+        assert_eq!(
+            controlled.get_selected_connection(),
+            Some((remote, local)),
+            "both sides select the same pair"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_regular_nomination() {
+        let config = IceConfig {
+            nomination_mode: NominationMode::Regular,
+            ..loopback_config()
+        };
+        let mut controlling = loopback_agent(config.clone(), IceRole::Controlling).await;
+        let mut controlled = loopback_agent(config, IceRole::Controlled).await;
+        exchange(&mut controlling, &mut controlled);
+        connect(&mut controlling, &mut controlled).await;
+
+        assert!(controlling.selected_pair().unwrap().nominated);
+        assert!(controlled.selected_pair().unwrap().nominated);
+    }
+
+    #[tokio::test]
+    async fn test_checks_fail_without_responder() {
+        let mut agent = loopback_agent(
+            IceConfig {
+                connectivity_timeout: Duration::from_millis(600),
+                ..loopback_config()
+            },
+            IceRole::Controlling,
+        )
+        .await;
+
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent.set_remote_credentials(IceCredentials::generate());
+        agent.add_remote_candidate(Candidate::new_host(silent.local_addr().unwrap(), 1));
+
+        assert!(agent.perform_connectivity_checks().await.is_err());
+        assert_eq!(agent.state(), ConnectionState::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_start_gathering_returns_host_candidates_first() {
+        let mut agent = IceAgent::new(Some(loopback_config()));
+        let mut trickled = agent.start_gathering().await.unwrap();
+
+        assert_eq!(agent.local_candidates().len(), 1);
+        assert_eq!(
+            agent.local_candidates()[0].candidate_type,
+            CandidateType::Host
+        );
+        assert!(trickled.recv().await.is_none(), "no STUN or TURN servers");
+    }
+
+    #[tokio::test]
+    async fn test_trickled_candidates_accepted_mid_check() {
+        let mut controlling = loopback_agent(loopback_config(), IceRole::Controlling).await;
+        let mut controlled = loopback_agent(loopback_config(), IceRole::Controlled).await;
+        controlling.set_remote_credentials(controlled.local_credentials());
+        controlled.set_remote_credentials(controlling.local_credentials());
+
+        let to_controlling = controlling.trickle();
+        let to_controlled = controlled.trickle();
+        let controlling_candidates = controlling.local_candidates().to_vec();
+        let controlled_candidates = controlled.local_candidates().to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            for candidate in controlled_candidates {
+                to_controlling.add_remote_candidate(candidate);
+            }
+            for candidate in controlling_candidates {
+                to_controlled.add_remote_candidate(candidate);
+            }
+            to_controlling.end_of_candidates();
+            to_controlled.end_of_candidates();
+        });
+        connect(&mut controlling, &mut controlled).await;
+
+        assert_eq!(controlling.state(), ConnectionState::Connected);
+        assert_eq!(
+            controlled.selected_pair().unwrap().remote.candidate_type,
+            CandidateType::Host
+        );
+    }
+
+    #[tokio::test]
+    async fn test_end_of_candidates_fails_checks_early() {
+        let mut agent = loopback_agent(
+            IceConfig {
+                connectivity_timeout: Duration::from_secs(10),
+                ..loopback_config()
+            },
+            IceRole::Controlling,
+        )
+        .await;
+
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent.set_remote_credentials(IceCredentials::generate());
+        agent.add_remote_candidate(Candidate::new_host(silent.local_addr().unwrap(), 1));
+        agent.end_of_remote_candidates();
+
+        let started = Instant::now();
+        assert!(agent.perform_connectivity_checks().await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_checks_require_remote_credentials() {
+        let mut agent = loopback_agent(loopback_config(), IceRole::Controlling).await;
+        assert!(agent.perform_connectivity_checks().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_checks_with_wrong_password_fail() {
+        let config = IceConfig {
+            connectivity_timeout: Duration::from_millis(600),
+            ..loopback_config()
+        };
+        let mut controlling = loopback_agent(config.clone(), IceRole::Controlling).await;
+        let mut controlled = loopback_agent(config, IceRole::Controlled).await;
+        exchange(&mut controlling, &mut controlled);
+        controlling
+            .session
+            .lock()
+            .unwrap()
+            .remote
+            .as_mut()
+            .unwrap()
+            .pwd = "wrong".repeat(5);
+
+        let (first, second) = tokio::join!(
+            controlling.perform_connectivity_checks(),
+            controlled.perform_connectivity_checks()
+        );
+        assert!(first.is_err());
+        assert!(second.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_role_conflict_resolved_by_tie_breaker() {
+        let mut first = loopback_agent(loopback_config(), IceRole::Controlling).await;
+        let mut second = loopback_agent(loopback_config(), IceRole::Controlling).await;
+        exchange(&mut first, &mut second);
+        connect(&mut first, &mut second).await;
+
+        let (winner, loser) = if first.tie_breaker() >= second.tie_breaker() {
+            (&first, &second)
+        } else {
+            (&second, &first)
+        };
+        assert_eq!(winner.role(), IceRole::Controlling);
+        assert_eq!(loser.role(), IceRole::Controlled);
+    }
+
+    #[tokio::test]
+    async fn test_peer_reflexive_remote_learned_from_check() {
+        let mut controlling = loopback_agent(loopback_config(), IceRole::Controlling).await;
+        let mut controlled = loopback_agent(loopback_config(), IceRole::Controlled).await;
+        // The controlled side has credentials but never hears of the
+        // controlling side's candidates.
+        controlling.set_remote_credentials(controlled.local_credentials());
+        controlled.set_remote_credentials(controlling.local_credentials());
+        controlling.add_remote_candidates(controlled.local_candidates().to_vec());
+        connect(&mut controlling, &mut controlled).await;
+
+        let remote = &controlled.selected_pair().unwrap().remote;
+        assert_eq!(remote.candidate_type, CandidateType::PeerReflexive);
+        assert_eq!(
+            remote.connection_addr,
+            controlling.local_candidates()[0].connection_addr
+        );
+    }
+
+    #[test]
+    fn test_frozen_pairs_unfreeze_per_foundation() {
+        let mut agent = IceAgent::new(None);
+        agent
+            .local_candidates
+            .push(Candidate::new_host("10.0.0.1:5000".parse().unwrap(), 1));
+        let remote = |addr: &str, foundation: &str| Candidate {
+            foundation: foundation.to_string(),
+            ..Candidate::new_host(addr.parse().unwrap(), 1)
+        };
+        agent.add_remote_candidates(vec![
+            remote("10.0.0.2:5000", "a"),
+            remote("10.0.0.3:5000", "a"),
+            remote("10.0.0.4:5000", "b"),
+        ]);
+
+        let state = |agent: &IceAgent, addr: &str| {
+            let addr: SocketAddr = addr.parse().unwrap();
+            agent
+                .checklist()
+                .iter()
+                .find(|p| p.remote.connection_addr == addr)
+                .unwrap()
+                .state
+        };
+        let frozen = ["10.0.0.2:5000", "10.0.0.3:5000"]
+            .into_iter()
+            .filter(|addr| state(&agent, addr) == CandidatePairState::Frozen)
+            .collect::<Vec<_>>();
+        assert_eq!(frozen.len(), 1, "one pair per foundation starts waiting");
+        assert_eq!(state(&agent, "10.0.0.4:5000"), CandidatePairState::Waiting);
+
+        let waiting = if frozen[0] == "10.0.0.2:5000" {
+            "10.0.0.3:5000"
+        } else {
+            "10.0.0.2:5000"
+        };
+        let key = ("10.0.0.1:5000".parse().unwrap(), waiting.parse().unwrap());
+        agent.handle_check_result(CheckResult {
+            key,
+            role: IceRole::Controlling,
+            priority: 0,
+            use_candidate: false,
+            outcome: CheckOutcome::Success {
+                mapped: key.0,
+                rtt: Duration::from_millis(1),
+            },
+        });
+        assert_eq!(state(&agent, frozen[0]), CandidatePairState::Waiting);
+    }
+
+    #[tokio::test]
+    async fn test_consent_expires_when_peer_leaves() {
+        let config = IceConfig {
+            consent_interval: Duration::from_millis(50),
+            consent_timeout: Duration::from_millis(400),
+            ..loopback_config()
+        };
+        let mut controlling = loopback_agent(config.clone(), IceRole::Controlling).await;
+        let mut controlled = loopback_agent(config, IceRole::Controlled).await;
+        exchange(&mut controlling, &mut controlled);
+        connect(&mut controlling, &mut controlled).await;
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(controlling.state(), ConnectionState::Connected);
+
+        drop(controlled);
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert_eq!(controlling.state(), ConnectionState::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_restart_renews_credentials_and_reconnects() {
+        let mut controlling = loopback_agent(loopback_config(), IceRole::Controlling).await;
+        let mut controlled = loopback_agent(loopback_config(), IceRole::Controlled).await;
+        exchange(&mut controlling, &mut controlled);
+        connect(&mut controlling, &mut controlled).await;
+
+        let old = controlling.local_credentials();
+        controlling.restart();
+        assert_ne!(controlling.local_credentials(), old);
+        assert_eq!(controlling.state(), ConnectionState::New);
+        assert!(controlling.selected_pair().is_none());
+        assert!(controlling.remote_candidates().is_empty());
+
+        // The peer sees new credentials and starts over as well.
+        controlled.set_remote_credentials(controlling.local_credentials());
+        assert!(controlled.checklist().is_empty());
+
+        exchange(&mut controlling, &mut controlled);
+        connect(&mut controlling, &mut controlled).await;
+        assert_eq!(controlling.state(), ConnectionState::Connected);
+    }
+
+    #[test]
+    fn test_signed_ice_candidates_roundtrip() {
+        let identity = Identity::generate().unwrap();
+        let from = identity.libp2p_peer_id();
+        let to = Identity::generate().unwrap().libp2p_peer_id();
+        let candidates = [Candidate::new_host("127.0.0.1:5000".parse().unwrap(), 1)];
+        let addrs: [Multiaddr; 1] = ["/ip4/127.0.0.1/tcp/4001".parse().unwrap()];
+        let credentials = IceCredentials::generate();
+
+        let signed =
+            SignedIceCandidates::sign(&identity, &to, &credentials, &candidates, &addrs, true)
+                .unwrap();
+        let decoded = SignedIceCandidates::from_bytes(&signed.to_bytes().unwrap()).unwrap();
+
+        decoded.verify(&from, &to).unwrap();
+        assert!(decoded.is_fresh(Duration::from_secs(30)));
+        assert_eq!(decoded.parsed_addrs(), addrs);
+        assert_eq!(decoded.credentials, credentials);
+        assert!(decoded.end_of_candidates);
+        assert_eq!(
+            decoded.parsed_candidates()[0].connection_addr,
+            candidates[0].connection_addr
+        );
+    }
+
+    #[test]
+    fn test_signed_ice_candidates_reject_tampering() {
+        let identity = Identity::generate().unwrap();
+        let from = identity.libp2p_peer_id();
+        let to = Identity::generate().unwrap().libp2p_peer_id();
+        let signed =
+            SignedIceCandidates::sign(&identity, &to, &IceCredentials::generate(), &[], &[], false)
+                .unwrap();
+
+        let other = Identity::generate().unwrap().libp2p_peer_id();
+        assert!(signed.verify(&from, &other).is_err());
+
+        let mut redirected = signed.clone();
+        redirected
+            .addrs
+            .push("/ip4/203.0.113.7/tcp/4001".to_string());
+        assert!(redirected.verify(&from, &to).is_err());
+
+        let mut ended = signed.clone();
+        ended.end_of_candidates = true;
+        assert!(ended.verify(&from, &to).is_err());
+
+        let mut stale = signed;
+        stale.issued_at -= 60_000;
+        assert!(!stale.is_fresh(Duration::from_secs(30)));
+    }
 }
@@ -0,0 +1,199 @@
+use libp2p::core::transport::{ListenerId, Transport, TransportError, TransportEvent};
+use libp2p::multiaddr::Protocol;
+use libp2p::quic;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// The pairs ICE nominated, by remote address, that libp2p should run over.
+#[derive(Clone, Default)]
+pub struct IcePaths(Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>);
+
+impl IcePaths {
+    pub fn insert(&self, local: SocketAddr, remote: SocketAddr) {
+        self.0.lock().unwrap().insert(remote, local);
+    }
+
+    pub fn remove(&self, remote: &SocketAddr) {
+        self.0.lock().unwrap().remove(remote);
+    }
+
+    /// Whether `addr` is the remote end of a path, which only lasts as long
+    /// as the session's connection.
+    pub fn serves(&self, addr: &Multiaddr) -> bool {
+        quic_socket_addr(addr).is_some_and(|remote| self.local_for(&remote).is_some())
+    }
+
+    fn local_for(&self, remote: &SocketAddr) -> Option<SocketAddr> {
+        self.0.lock().unwrap().get(remote).copied()
+    }
+
+    fn has_local(&self, local: &SocketAddr) -> bool {
+        self.0.lock().unwrap().values().any(|addr| addr == local)
+    }
+}
+
+/// QUIC over the sockets of nominated ICE pairs. Each path gets its own
+/// endpoint bound to the pair's local port, so the peer is dialed from the
+/// port the checks opened in both NATs. Addresses that are not the end of a
+/// registered path are left to the other transports.
+pub struct IceTransport {
+    config: quic::Config,
+    paths: IcePaths,
+    endpoints: Vec<(ListenerId, SocketAddr, quic::tokio::Transport)>,
+    waker: Option<Waker>,
+}
+
+impl IceTransport {
+    pub fn new(config: quic::Config, paths: IcePaths) -> Self {
+        Self {
+            config,
+            paths,
+            endpoints: Vec::new(),
+            waker: None,
+        }
+    }
+
+    fn endpoint_for(&mut self, addr: &Multiaddr) -> Option<&mut quic::tokio::Transport> {
+        let local = self.paths.local_for(&quic_socket_addr(addr)?)?;
+        self.endpoints
+            .iter_mut()
+            .find(|(_, addr, _)| *addr == local)
+            .map(|(_, _, endpoint)| endpoint)
+    }
+}
+
+impl Transport for IceTransport {
+    type Output = (PeerId, quic::Connection);
+    type Error = quic::Error;
+    type ListenerUpgrade = quic::Connecting;
+    type Dial = <quic::tokio::Transport as Transport>::Dial;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        let Some(local) = quic_socket_addr(&addr).filter(|local| self.paths.has_local(local))
+        else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        let mut endpoint = quic::tokio::Transport::new(self.config.clone());
+        endpoint.listen_on(id, addr)?;
+        self.endpoints.push((id, local, endpoint));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.endpoints
+            .iter_mut()
+            .find(|(listener_id, _, _)| *listener_id == id)
+            .is_some_and(|(_, _, endpoint)| endpoint.remove_listener(id))
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        match self.endpoint_for(&addr) {
+            Some(endpoint) => endpoint.dial(addr),
+            None => Err(TransportError::MultiaddrNotSupported(addr)),
+        }
+    }
+
+    fn dial_as_listener(
+        &mut self,
+        addr: Multiaddr,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        match self.endpoint_for(&addr) {
+            Some(endpoint) => endpoint.dial_as_listener(addr),
+            None => Err(TransportError::MultiaddrNotSupported(addr)),
+        }
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let this = self.get_mut();
+        for i in 0..this.endpoints.len() {
+            let Poll::Ready(event) = Pin::new(&mut this.endpoints[i].2).poll(cx) else {
+                continue;
+            };
+            // Connections keep the endpoint's socket open on their own.
+            if matches!(event, TransportEvent::ListenerClosed { .. }) {
+                this.endpoints.remove(i);
+            }
+            return Poll::Ready(event);
+        }
+        this.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn address_translation(&self, _listen: &Multiaddr, _observed: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+/// The QUIC multiaddr for a socket address, e.g. the end of a nominated pair.
+pub fn quic_multiaddr(addr: SocketAddr) -> Multiaddr {
+    Multiaddr::from(addr.ip())
+        .with(Protocol::Udp(addr.port()))
+        .with(Protocol::QuicV1)
+}
+
+fn quic_socket_addr(addr: &Multiaddr) -> Option<SocketAddr> {
+    let mut protocols = addr.iter();
+    let ip = match protocols.next()? {
+        Protocol::Ip4(ip) => IpAddr::V4(ip),
+        Protocol::Ip6(ip) => IpAddr::V6(ip),
+        _ => return None,
+    };
+    let Protocol::Udp(port) = protocols.next()? else {
+        return None;
+    };
+    let Protocol::QuicV1 = protocols.next()? else {
+        return None;
+    };
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some(SocketAddr::new(ip, port)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_registered_paths_are_served() {
+        let paths = IcePaths::default();
+        let local: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let remote: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+        paths.insert(local, remote);
+
+        let peer = PeerId::random();
+        assert!(paths.serves(&quic_multiaddr(remote)));
+        assert!(paths.serves(&quic_multiaddr(remote).with(Protocol::P2p(peer))));
+        assert!(!paths.serves(&quic_multiaddr(local)));
+        assert!(!paths.serves(&"/ip4/127.0.0.1/tcp/40001".parse().unwrap()));
+
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let mut transport = IceTransport::new(quic::Config::new(&keypair), paths.clone());
+        let other = quic_multiaddr("127.0.0.1:40002".parse().unwrap());
+        assert!(matches!(
+            transport.listen_on(ListenerId::next(), other.clone()),
+            Err(TransportError::MultiaddrNotSupported(_))
+        ));
+        assert!(matches!(
+            transport.dial(other),
+            Err(TransportError::MultiaddrNotSupported(_))
+        ));
+
+        paths.remove(&remote);
+        assert!(!paths.serves(&quic_multiaddr(remote)));
+    }
+}
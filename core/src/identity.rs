@@ -1,13 +1,19 @@
 use crate::error::{AgoraResult, Error};
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use libp2p::{identity::Keypair, PeerId};
 use multibase::Base;
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+/// A user's long-term Ed25519 identity.
+///
+/// The same key signs application messages and authenticates the libp2p
+/// transport, so [`Identity::peer_id`] is the peer ID seen on the wire.
 #[derive(Debug, Clone)]
 pub struct Identity {
     signing_key: SigningKey,
+    keypair: Keypair,
     display_name: Option<String>,
 }
 
@@ -20,11 +26,7 @@ pub struct PeerInfo {
 
 impl Identity {
     pub fn generate() -> AgoraResult<Self> {
-        let signing_key = SigningKey::generate(&mut OsRng);
-        Ok(Self {
-            signing_key,
-            display_name: None,
-        })
+        Self::from_signing_key(SigningKey::generate(&mut OsRng))
     }
 
     pub fn from_bytes(bytes: &[u8]) -> AgoraResult<Self> {
@@ -33,8 +35,15 @@ impl Identity {
                 .try_into()
                 .map_err(|_| Error::Identity("Invalid key bytes".to_string()))?,
         );
+        Self::from_signing_key(signing_key)
+    }
+
+    fn from_signing_key(signing_key: SigningKey) -> AgoraResult<Self> {
+        let keypair = Keypair::ed25519_from_bytes(signing_key.to_bytes())
+            .map_err(|e| Error::Identity(format!("Invalid Ed25519 key: {}", e)))?;
         Ok(Self {
             signing_key,
+            keypair,
             display_name: None,
         })
     }
@@ -44,15 +53,24 @@ impl Identity {
     }
 
     pub fn peer_id(&self) -> String {
-        let verifying_key = self.signing_key.verifying_key();
-        let public_key_bytes = verifying_key.as_bytes();
-        let mut hasher = Sha256::new();
-        hasher.update(public_key_bytes);
-        let hash = hasher.finalize();
-        format!(
-            "12D3KooW{}",
-            multibase::encode(Base::Base32Lower, &hash[..20])
-        )
+        self.libp2p_peer_id().to_string()
+    }
+
+    pub fn libp2p_peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    pub fn keypair(&self) -> Keypair {
+        self.keypair.clone()
+    }
+
+    /// X25519 form of the identity key, for sealing data to this identity.
+    pub fn x25519_secret(&self) -> x25519_dalek::StaticSecret {
+        x25519_dalek::StaticSecret::from(self.signing_key.to_scalar_bytes())
+    }
+
+    pub fn x25519_public(&self) -> x25519_dalek::PublicKey {
+        x25519_dalek::PublicKey::from(self.public_key().to_montgomery().to_bytes())
     }
 
     pub fn public_key(&self) -> VerifyingKey {
@@ -91,6 +109,28 @@ impl Identity {
     }
 }
 
+const IDENTITY_MULTIHASH_CODE: u64 = 0x00;
+
+/// Recovers the Ed25519 key embedded in a libp2p peer ID, so signatures
+/// can be checked against the peer ID alone.
+pub fn verifying_key_from_peer_id(peer_id: &PeerId) -> AgoraResult<VerifyingKey> {
+    let multihash = peer_id.as_ref();
+    if multihash.code() != IDENTITY_MULTIHASH_CODE {
+        return Err(Error::Crypto(format!(
+            "Peer ID {} does not embed its public key",
+            peer_id
+        )));
+    }
+
+    let public = libp2p::identity::PublicKey::try_decode_protobuf(multihash.digest())
+        .map_err(|e| Error::Crypto(format!("Invalid public key in peer ID: {}", e)))?
+        .try_into_ed25519()
+        .map_err(|_| Error::Crypto(format!("Peer ID {} is not an Ed25519 key", peer_id)))?;
+
+    VerifyingKey::from_bytes(&public.to_bytes())
+        .map_err(|e| Error::Crypto(format!("Invalid Ed25519 key: {}", e)))
+}
+
 impl PeerInfo {
     pub fn fingerprint(&self) -> String {
         let bytes = self.public_key.as_bytes();
@@ -127,6 +167,37 @@ mod tests {
         assert_eq!(identity.peer_id(), restored.peer_id());
     }
 
+    #[test]
+    fn test_peer_id_is_valid_libp2p_peer_id() {
+        let identity = Identity::generate().unwrap();
+        let parsed: PeerId = identity.peer_id().parse().unwrap();
+        assert_eq!(parsed, identity.libp2p_peer_id());
+        assert_eq!(parsed, PeerId::from(identity.keypair().public()));
+    }
+
+    #[test]
+    fn test_keypair_matches_signing_key() {
+        let identity = Identity::generate().unwrap();
+        let message = b"Hello, Agora!";
+        let signature = identity.keypair().sign(message).unwrap();
+        let signature = Signature::from_slice(&signature).unwrap();
+        assert!(identity.verify(message, &signature));
+    }
+
+    #[test]
+    fn test_verifying_key_from_peer_id() {
+        let identity = Identity::generate().unwrap();
+        let key = verifying_key_from_peer_id(&identity.libp2p_peer_id()).unwrap();
+        assert_eq!(key, identity.public_key());
+    }
+
+    #[test]
+    fn test_x25519_keys_match() {
+        let identity = Identity::generate().unwrap();
+        let derived = x25519_dalek::PublicKey::from(&identity.x25519_secret());
+        assert_eq!(derived.as_bytes(), identity.x25519_public().as_bytes());
+    }
+
     #[test]
     fn test_sign_verify() {
         let identity = Identity::generate().unwrap();
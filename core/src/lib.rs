@@ -1,14 +1,22 @@
 pub mod aec;
 pub mod audio;
 pub mod audio_processor;
+pub mod audio_stream;
+pub mod blocklist;
+pub mod cascade;
 pub mod codec;
 pub mod crypto;
 pub mod denoise;
+pub mod dht_store;
+pub mod election;
 pub mod error;
+pub mod group_key;
 pub mod handshake;
 pub mod ice;
+pub mod ice_transport;
 pub mod identity;
 pub mod mixer;
+pub mod moderation;
 pub mod nat;
 pub mod network;
 pub mod protocol;
@@ -28,6 +36,8 @@ pub use audio::{AudioConfig, AudioDevice, AudioPipeline};
 pub use audio_processor::{
     AdaptiveBitrateController, AudioProcessor, AudioProcessorConfig, BitrateLevel, ProcessorStats,
 };
+pub use blocklist::{Blocklist, SignedBlocklist};
+pub use cascade::MixerNode;
 pub use codec::{
     AudioDecoder, AudioEncoder, EncodedFrame, OpusConfig, OpusDecoder, OpusEncoder, OpusMode,
 };
@@ -35,19 +45,23 @@ pub use crypto::{
     EncryptedChannel, KeyRotationEvent, SecureAudioChannel, SessionKey, SessionKeyManager,
 };
 pub use denoise::{Denoiser, RnnoiseDenoiser};
+pub use election::{MixerElection, MixerGroup, MixerStats, SignedMixerElection, SignedMixerStats};
 pub use error::AgoraResult as Result;
+pub use group_key::{GroupKeyManager, GroupRekey, SealedGroupKey};
 pub use handshake::{HandshakeMessage, HandshakeState, NoiseSession};
 pub use ice::{
-    Candidate, CandidatePair, CandidateType, ConnectionState, IceAgent, IceConfig, IceRole,
+    Candidate, CandidatePair, CandidateType, ConnectionState, IceAgent, IceConfig, IceCredentials,
+    IceRole, IceTrickle, SignedIceCandidates,
 };
 pub use identity::Identity;
 pub use libp2p::Multiaddr;
-pub use mixer::{MixerConfig, MixerManager, MixerRole, Participant};
+pub use mixer::{MediaMode, MixerConfig, MixerManager, MixerRole, Participant};
+pub use moderation::{ModerationAction, RoomModeration, SignedModeration};
 pub use nat::{NatTraversal, NatType, ObservedAddr};
 pub use network::{NetworkCommand, NetworkEvent, NetworkNode};
 pub use protocol::{
-    AudioPacket, ControlMessage, ControlMessageType, EncryptedAudioPacket,
-    ParticipantInfo as ProtocolParticipantInfo,
+    AudioPacket, CodecId, ControlMessage, ControlMessageType, EncodedAudioPacket,
+    EncryptedAudioPacket, ParticipantInfo as ProtocolParticipantInfo,
 };
 pub use reputation::{
     Challenge, ChallengeResult, ChallengeType, ChallengeVerifier, ReputationConfig,
@@ -55,13 +69,16 @@ pub use reputation::{
 };
 pub use room::Room;
 pub use room::RoomConfig;
-pub use storage::IdentityStorage;
+pub use storage::{BlocklistStorage, IdentityStorage};
 pub use stun::{StunBinding, StunClient, StunResult};
 pub use tcp_punch::{
     SignalingChannel, TcpHolePunchConfig, TcpHolePunchResult, TcpHolePuncher, TcpPunchMethod,
 };
-pub use turn::{TurnAllocation, TurnCandidate, TurnClient, TurnConfig, TurnPermission, TurnServer};
+pub use turn::{
+    TurnAllocation, TurnCandidate, TurnClient, TurnConfig, TurnCredentialRequest, TurnCredentials,
+    TurnPermission, TurnServer,
+};
 pub use upnp::{
-    NatPmpClient, NatPmpConfig, PortForwarder, PortMapping, Protocol, UpnpClient, UpnpConfig,
-    UpnpDevice,
+    NatPmpClient, NatPmpConfig, PcpClient, PcpConfig, PortForwarder, PortMapping, Protocol,
+    UpnpClient, UpnpConfig, UpnpDevice,
 };
@@ -1,10 +1,42 @@
-use crate::audio::{mix_audio, AudioFrame};
-use std::collections::HashMap;
+use crate::audio::{calculate_rms, mix_audio, AudioFrame, SILENT_AUDIO_LEVEL};
+use crate::cascade::{self, CascadeCandidate, MixerNode};
+use crate::codec::{EncodedFrame, OpusConfig, OpusDecoder, OpusEncoder, OPUS_FRAME_SIZE};
+use crate::election::{MixerElection, MixerGroup};
+use crate::error::AgoraResult;
+use crate::protocol::EncryptedAudioPacket;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::time::{Duration, Instant};
 
 pub const FULL_MESH_MAX_PARTICIPANTS: usize = 5;
 pub const MIXER_ROTATION_INTERVAL: Duration = Duration::from_secs(1800); // 30 minutes
 pub const SCORE_TIE_THRESHOLD: f64 = 0.05; // 5% difference
+/// Frames quieter than this RMS level are treated as silence when mixing.
+pub const SILENCE_RMS_THRESHOLD: f32 = 0.001;
+/// Frames held per participant between mixes; older ones are dropped.
+const MAX_BUFFERED_FRAMES: usize = 5;
+pub const DEFAULT_MAX_FORWARDED_SPEAKERS: usize = 3;
+/// Levels (in -dBov) at or above this count as silence when picking speakers;
+/// matches `SILENCE_RMS_THRESHOLD`.
+const SPEAKING_AUDIO_LEVEL: f32 = 60.0;
+/// A speaker whose packets stop for this long loses its forwarding slot.
+const ACTIVE_SPEAKER_TIMEOUT: Duration = Duration::from_millis(500);
+/// Weight of a quieter packet's level in a participant's smoothed level.
+/// Louder packets count at once, so speech onsets are not clipped.
+const SPEAKER_LEVEL_SMOOTHING: f32 = 0.3;
+/// How much louder, in dB, a speaker must be to take a forwarded speaker's
+/// slot, so slots do not flap between speakers of similar volume.
+const SPEAKER_SWITCH_MARGIN: f32 = 6.0;
+/// How long the outgoing mixer keeps receiving and forwarding audio after a
+/// new one is elected, so nothing is lost while peers switch over.
+pub const HANDOVER_GRACE: Duration = Duration::from_secs(2);
+/// How far an announced election's score may be from the one we compute
+/// from the stats we hold; stats published a few seconds apart differ a
+/// little.
+const ELECTION_SCORE_TOLERANCE: f64 = 0.1;
+/// Reputation a dedicated node needs before it is preferred as mixer over
+/// the room's own participants.
+pub const DEDICATED_MIXER_MIN_REPUTATION: f32 = 0.7;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MixerRole {
@@ -21,6 +53,7 @@ pub struct ParticipantStats {
     pub memory_usage_percent: f32,
     pub session_duration: Duration,
     pub packet_loss_percent: f32,
+    pub region: Option<String>,
     pub last_updated: Instant,
 }
 
@@ -34,6 +67,7 @@ impl ParticipantStats {
             memory_usage_percent: 0.0,
             session_duration: Duration::ZERO,
             packet_loss_percent: 0.0,
+            region: None,
             last_updated: Instant::now(),
         }
     }
@@ -70,6 +104,9 @@ pub struct Participant {
     pub audio_buffer: Vec<AudioFrame>,
     pub mixer_start_time: Option<Instant>,
     pub score: f64,
+    /// Smoothed level of the participant's forwarded packets, in -dBov.
+    pub audio_level: f32,
+    pub last_audio: Option<Instant>,
 }
 
 impl Participant {
@@ -82,9 +119,18 @@ impl Participant {
             audio_buffer: Vec::new(),
             mixer_start_time: None,
             score: 0.0,
+            audio_level: SILENT_AUDIO_LEVEL as f32,
+            last_audio: None,
         }
     }
 
+    fn is_speaking(&self) -> bool {
+        self.audio_level < SPEAKING_AUDIO_LEVEL
+            && self
+                .last_audio
+                .is_some_and(|t| t.elapsed() < ACTIVE_SPEAKER_TIMEOUT)
+    }
+
     pub fn calculate_score(&mut self, weights: &ScoreWeights) -> f64 {
         let bandwidth_score = self.calculate_bandwidth_score();
         let stability_score = self.stats.get_stability_score() as f64;
@@ -139,19 +185,54 @@ impl Default for ScoreWeights {
     }
 }
 
+/// What the elected mixer does with the audio it receives in SFU topology.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MediaMode {
+    /// Decode every stream and send each participant a mix-minus.
+    ///
+    /// `NetworkNode` does not mix: in this mode participants keep sending
+    /// their audio to everyone, and applications that want mixes drive
+    /// `push_encoded_audio` and `mix_minus` themselves.
+    Mix,
+    /// Relay the loudest speakers' encrypted packets unchanged, so audio
+    /// stays end-to-end encrypted and nothing is re-encoded.
+    #[default]
+    Forward,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TopologyMode {
     FullMesh,
     SFU,
+    /// Several mixers, each serving part of the room and relaying its
+    /// speakers to the others.
+    Cascade,
 }
 
 #[derive(Debug, Clone)]
 pub struct MixerConfig {
+    /// Participants one mixer serves; bigger rooms are split between
+    /// several mixers in `TopologyMode::Cascade`.
     pub max_participants: usize,
     pub full_mesh_threshold: usize,
     pub rotation_interval: Duration,
     pub score_weights: ScoreWeights,
     pub mixing_sample_rate: u32,
+    /// Encoder settings for the mixes sent back to participants.
+    pub opus: OpusConfig,
+    pub media_mode: MediaMode,
+    /// Speakers forwarded at once in `MediaMode::Forward`, which caps each
+    /// participant's downstream bandwidth.
+    pub max_forwarded_speakers: usize,
+    /// Our region, published with our stats so cascaded rooms can group
+    /// participants by region.
+    pub region: Option<String>,
+    /// Run as a dedicated node: mix for every room that has us, even small
+    /// ones, and never hand the role to a participant.
+    pub dedicated: bool,
+    /// Reputation a dedicated node from `set_mixer_nodes` needs to be
+    /// preferred over participants.
+    pub min_dedicated_reputation: f32,
 }
 
 impl Default for MixerConfig {
@@ -162,6 +243,12 @@ impl Default for MixerConfig {
             rotation_interval: MIXER_ROTATION_INTERVAL,
             score_weights: ScoreWeights::default(),
             mixing_sample_rate: 48000,
+            opus: OpusConfig::default(),
+            media_mode: MediaMode::default(),
+            max_forwarded_speakers: DEFAULT_MAX_FORWARDED_SPEAKERS,
+            region: None,
+            dedicated: false,
+            min_dedicated_reputation: DEDICATED_MIXER_MIN_REPUTATION,
         }
     }
 }
@@ -175,12 +262,63 @@ pub struct MixerManager {
     mixer_start_time: Option<Instant>,
     topology_mode: TopologyMode,
     pending_rotation: bool,
+    /// An announced election was too far ahead of ours to adopt, or named a
+    /// score our stats could not confirm yet, so we may have missed rounds;
+    /// a new round lets the room bring us up to date.
+    election_behind: bool,
+    decoders: HashMap<String, OpusDecoder>,
+    /// One encoder per participant, fed every frame so each mix-minus
+    /// stream stays continuous for the stateful codec.
+    encoders: HashMap<String, OpusEncoder>,
+    forwarded_speakers: HashSet<String>,
+    local_stats: ParticipantStats,
+    joined_at: Instant,
+    election: Option<MixerElection>,
+    /// Mixers and the participants they serve when cascaded.
+    cascade: Vec<MixerGroup>,
+    handover: Option<Handover>,
+    mixer_nodes: Vec<MixerNode>,
+}
+
+/// The routing replaced by the last mixer change, honoured alongside the
+/// new one for `HANDOVER_GRACE`.
+struct Handover {
+    mixer: Option<String>,
+    cascade: Vec<MixerGroup>,
+    started: Instant,
+}
+
+/// Who mixes for whom under one election result.
+struct Routing<'a> {
+    mixer: Option<&'a str>,
+    cascade: &'a [MixerGroup],
+}
+
+impl<'a> Routing<'a> {
+    fn mixer_for(&self, peer_id: &str) -> Option<&'a str> {
+        match self.cascade.is_empty() {
+            true => self.mixer,
+            false => cascade::mixer_for(self.cascade, peer_id),
+        }
+    }
+
+    fn is_mixer(&self, peer_id: &str) -> bool {
+        match self.cascade.is_empty() {
+            true => self.mixer == Some(peer_id),
+            false => self.cascade.iter().any(|g| g.mixer == peer_id),
+        }
+    }
 }
 
 impl MixerManager {
     pub fn new(local_peer_id: String, config: Option<MixerConfig>) -> Self {
+        let config = config.unwrap_or_default();
+        let local_stats = ParticipantStats {
+            region: config.region.clone(),
+            ..ParticipantStats::new()
+        };
         Self {
-            config: config.unwrap_or_default(),
+            config,
             participants: HashMap::new(),
             local_peer_id,
             local_role: MixerRole::Peer,
@@ -188,17 +326,31 @@ impl MixerManager {
             mixer_start_time: None,
             topology_mode: TopologyMode::FullMesh,
             pending_rotation: false,
+            election_behind: false,
+            decoders: HashMap::new(),
+            encoders: HashMap::new(),
+            forwarded_speakers: HashSet::new(),
+            local_stats,
+            joined_at: Instant::now(),
+            election: None,
+            cascade: Vec::new(),
+            handover: None,
+            mixer_nodes: Vec::new(),
         }
     }
 
     pub fn add_participant(&mut self, peer_id: String) {
-        let participant = Participant::new(peer_id.clone());
+        let mut participant = Participant::new(peer_id.clone());
+        participant.calculate_score(&self.config.score_weights);
         self.participants.insert(peer_id, participant);
         self.update_topology_mode();
     }
 
     pub fn remove_participant(&mut self, peer_id: &str) {
         self.participants.remove(peer_id);
+        self.decoders.remove(peer_id);
+        self.encoders.remove(peer_id);
+        self.forwarded_speakers.remove(peer_id);
 
         // If mixer left, trigger reselection
         if self.current_mixer.as_deref() == Some(peer_id) {
@@ -212,16 +364,22 @@ impl MixerManager {
     pub fn update_participant_stats(&mut self, peer_id: &str, stats: ParticipantStats) {
         if let Some(participant) = self.participants.get_mut(peer_id) {
             participant.stats = stats;
+            participant.calculate_score(&self.config.score_weights);
         }
+        self.update_topology_mode();
     }
 
     fn update_topology_mode(&mut self) {
         let count = self.participants.len() + 1; // +1 for local peer
 
-        let new_mode = if count <= self.config.full_mesh_threshold {
+        // A dedicated node is there to mix, so even small rooms use it.
+        let mesh = count == 1 || self.preferred_mixer().is_none();
+        let new_mode = if count <= self.config.full_mesh_threshold && mesh {
             TopologyMode::FullMesh
-        } else {
+        } else if count <= self.config.max_participants {
             TopologyMode::SFU
+        } else {
+            TopologyMode::Cascade
         };
 
         if new_mode != self.topology_mode {
@@ -233,21 +391,29 @@ impl MixerManager {
             );
             self.topology_mode = new_mode;
 
-            if new_mode == TopologyMode::SFU && self.current_mixer.is_none() {
+            if new_mode != TopologyMode::FullMesh && self.current_mixer.is_none() {
                 self.select_mixer();
             }
         }
     }
 
     pub fn select_mixer(&mut self) -> Option<String> {
-        if self.topology_mode != TopologyMode::SFU {
+        if self.topology_mode == TopologyMode::FullMesh {
             self.current_mixer = None;
+            self.local_role = MixerRole::Peer;
             return None;
         }
 
+        if let Some(node) = self.preferred_mixer() {
+            tracing::info!(mixer = %node, "Dedicated mixer selected");
+            self.set_mixer(node);
+            return self.current_mixer.clone();
+        }
+
         // Calculate scores for all participants including self
         let mut local_participant = Participant::new(self.local_peer_id.clone());
         local_participant.role = self.local_role;
+        local_participant.stats = self.local_stats();
 
         let mut all_scores: Vec<(String, f64)> = self
             .participants
@@ -316,6 +482,13 @@ impl MixerManager {
                 p.role = MixerRole::Peer;
                 p.mixer_start_time = None;
             }
+            if *old_mixer != peer_id {
+                self.handover = Some(Handover {
+                    mixer: Some(old_mixer.clone()),
+                    cascade: self.cascade.clone(),
+                    started: Instant::now(),
+                });
+            }
         }
 
         self.current_mixer = Some(peer_id.clone());
@@ -323,20 +496,32 @@ impl MixerManager {
 
         if is_local {
             self.local_role = MixerRole::Mixer;
-        } else if let Some(p) = self.participants.get_mut(&peer_id) {
-            p.role = MixerRole::Mixer;
-            p.mixer_start_time = Some(Instant::now());
+        } else {
+            self.local_role = MixerRole::Peer;
+            if let Some(p) = self.participants.get_mut(&peer_id) {
+                p.role = MixerRole::Mixer;
+                p.mixer_start_time = Some(Instant::now());
+            }
         }
     }
 
     pub fn check_rotation(&mut self) -> bool {
-        if self.topology_mode != TopologyMode::SFU {
+        if !self.uses_mixers() {
             return false;
         }
 
         let Some(start_time) = self.mixer_start_time else {
             return false;
         };
+        // Rotation spreads the load between participants; dedicated nodes
+        // are there to carry it.
+        if self
+            .current_mixer
+            .as_deref()
+            .is_some_and(|mixer| self.is_dedicated(mixer))
+        {
+            return false;
+        }
 
         if start_time.elapsed() >= self.config.rotation_interval {
             self.pending_rotation = true;
@@ -352,6 +537,8 @@ impl MixerManager {
         if let Some(current) = &self.current_mixer {
             if let Some(p) = self.participants.get_mut(current) {
                 p.stats.session_duration = Duration::ZERO;
+            } else if *current == self.local_peer_id {
+                self.joined_at = Instant::now();
             }
         }
 
@@ -359,6 +546,301 @@ impl MixerManager {
         self.select_mixer()
     }
 
+    pub fn election(&self) -> Option<&MixerElection> {
+        self.election.as_ref()
+    }
+
+    /// Whether the room lacks an agreed mixer: none has been elected, an
+    /// elected one left, its term is up, or the room outgrew the plan.
+    pub fn needs_election(&self) -> bool {
+        if !self.uses_mixers() {
+            return false;
+        }
+        let Some(election) = &self.election else {
+            return true;
+        };
+        if self.pending_rotation || self.election_behind || !self.is_candidate(&election.mixer) {
+            return true;
+        }
+        match self.topology_mode {
+            TopologyMode::Cascade => {
+                election.cascade.is_empty()
+                    || election
+                        .cascade
+                        .iter()
+                        .any(|g| !self.is_candidate(&g.mixer))
+                    || self.get_participant_count()
+                        > election.cascade.len() * self.config.max_participants
+            }
+            _ => {
+                !election.cascade.is_empty()
+                    || self.current_mixer.as_deref() != Some(election.mixer.as_str())
+                    || self.preferred_mixer().is_some_and(|node| {
+                        node != election.mixer && !self.is_dedicated(&election.mixer)
+                    })
+            }
+        }
+    }
+
+    /// Runs a new election round over the stats we hold and adopts the
+    /// result, which the caller announces to the room.
+    pub fn elect(&mut self) -> Option<MixerElection> {
+        if !self.uses_mixers() {
+            return None;
+        }
+        let previous = self.snapshot();
+        let mixer = match self.pending_rotation {
+            true => self.rotate_mixer(),
+            false => self.select_mixer(),
+        }?;
+        let cascade = match self.topology_mode {
+            TopologyMode::Cascade => self.plan_cascade(),
+            _ => Vec::new(),
+        };
+        let mixer = cascade.first().map_or(mixer, |g| g.mixer.clone());
+        let election = MixerElection {
+            epoch: self
+                .election
+                .as_ref()
+                .map_or(1, |e| e.epoch.saturating_add(1)),
+            score: self.candidate_score(&mixer),
+            mixer,
+            cascade,
+        };
+        self.adopt(election.clone(), previous);
+        Some(election)
+    }
+
+    /// Adopts an election announced by another participant if it outranks
+    /// ours. Returns whether it was adopted.
+    ///
+    /// The announced score must match the one we compute from the stats we
+    /// hold, and once we have an election only the next epoch may follow
+    /// it, so no participant can claim the mixer for good.
+    pub fn apply_election(&mut self, election: MixerElection) -> bool {
+        if !self.is_candidate(&election.mixer)
+            || !election.cascade.iter().all(|g| self.is_candidate(&g.mixer))
+        {
+            return false;
+        }
+        if !election.score.is_finite() {
+            return false;
+        }
+        let score = self.candidate_score(&election.mixer);
+        if (election.score - score).abs() > ELECTION_SCORE_TOLERANCE {
+            tracing::debug!(
+                announced = election.score,
+                ours = score,
+                mixer = %election.mixer,
+                "Ignoring mixer election with a score we cannot confirm"
+            );
+            // Our stats may be the stale ones; the next round uses newer.
+            self.election_behind |= self
+                .election
+                .as_ref()
+                .is_none_or(|current| election.outranks(current));
+            return false;
+        }
+        if let Some(current) = &self.election {
+            if election.epoch > current.epoch.saturating_add(1) {
+                tracing::debug!(
+                    epoch = election.epoch,
+                    ours = current.epoch,
+                    "Ignoring mixer election from a future epoch"
+                );
+                self.election_behind = true;
+                return false;
+            }
+            if !election.outranks(current) {
+                return false;
+            }
+            if current.epoch == election.epoch {
+                tracing::warn!(
+                    epoch = election.epoch,
+                    ours = %current.mixer,
+                    theirs = %election.mixer,
+                    "Conflicting mixer elections, adopting theirs"
+                );
+            }
+        }
+
+        tracing::info!(
+            epoch = election.epoch,
+            mixer = %election.mixer,
+            mixers = election.cascade.len().max(1),
+            "Mixer election adopted"
+        );
+        let previous = self.snapshot();
+        self.adopt(election, previous);
+        true
+    }
+
+    fn snapshot(&self) -> Handover {
+        Handover {
+            mixer: self.current_mixer.clone(),
+            cascade: self.cascade.clone(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Switches to an election's routing, keeping `previous` for the
+    /// handover if anything changed.
+    fn adopt(&mut self, election: MixerElection, previous: Handover) {
+        let routing = Routing {
+            mixer: Some(&election.mixer),
+            cascade: &election.cascade,
+        };
+        let mixer = routing
+            .mixer_for(&self.local_peer_id)
+            .unwrap_or(&election.mixer)
+            .to_string();
+        self.set_mixer(mixer);
+        for (peer_id, participant) in self.participants.iter_mut() {
+            if routing.is_mixer(peer_id) {
+                participant.role = MixerRole::Mixer;
+            } else if participant.role == MixerRole::Mixer {
+                participant.role = MixerRole::Peer;
+                participant.mixer_start_time = None;
+            }
+        }
+        self.cascade = election.cascade.clone();
+        self.handover = (previous.mixer != self.current_mixer || previous.cascade != self.cascade)
+            .then_some(previous);
+        self.pending_rotation = false;
+        self.election_behind = false;
+        self.election = Some(election);
+    }
+
+    fn plan_cascade(&self) -> Vec<MixerGroup> {
+        let mut candidates: Vec<CascadeCandidate> = self
+            .participants
+            .iter()
+            .map(|(peer_id, p)| CascadeCandidate {
+                peer_id: peer_id.clone(),
+                region: p.stats.region.clone(),
+                score: p.score,
+            })
+            .collect();
+        candidates.push(CascadeCandidate {
+            peer_id: self.local_peer_id.clone(),
+            region: self.local_stats.region.clone(),
+            score: self.candidate_score(&self.local_peer_id),
+        });
+        cascade::plan_cascade(
+            &candidates,
+            &self.trusted_nodes(),
+            self.config.max_participants,
+        )
+    }
+
+    /// Dedicated nodes from node discovery. Those in the room with enough
+    /// reputation are preferred as mixers, for their region when cascaded.
+    pub fn set_mixer_nodes(&mut self, nodes: Vec<MixerNode>) {
+        self.mixer_nodes = nodes;
+        self.update_topology_mode();
+    }
+
+    fn trusted_nodes(&self) -> Vec<MixerNode> {
+        self.mixer_nodes
+            .iter()
+            .filter(|n| n.reputation >= self.config.min_dedicated_reputation)
+            .cloned()
+            .collect()
+    }
+
+    /// The dedicated node to mix for the room: ourselves when running as
+    /// one, otherwise the best trusted node among the participants.
+    fn preferred_mixer(&self) -> Option<String> {
+        if self.config.dedicated {
+            return Some(self.local_peer_id.clone());
+        }
+        self.trusted_nodes()
+            .into_iter()
+            .filter(|n| self.participants.contains_key(&n.peer_id))
+            .min_by(|a, b| {
+                b.score
+                    .partial_cmp(&a.score)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then_with(|| {
+                        a.latency_ms
+                            .unwrap_or(u32::MAX)
+                            .cmp(&b.latency_ms.unwrap_or(u32::MAX))
+                    })
+                    .then_with(|| a.peer_id.cmp(&b.peer_id))
+            })
+            .map(|n| n.peer_id)
+    }
+
+    /// Whether `peer_id` is ourselves running as a dedicated node or a
+    /// trusted one from node discovery. Elections naming one are not
+    /// contested.
+    fn is_dedicated(&self, peer_id: &str) -> bool {
+        (peer_id == self.local_peer_id && self.config.dedicated)
+            || self.trusted_nodes().iter().any(|n| n.peer_id == peer_id)
+    }
+
+    pub fn get_cascade(&self) -> &[MixerGroup] {
+        &self.cascade
+    }
+
+    fn is_candidate(&self, peer_id: &str) -> bool {
+        peer_id == self.local_peer_id || self.participants.contains_key(peer_id)
+    }
+
+    fn candidate_score(&self, peer_id: &str) -> f64 {
+        match self.participants.get(peer_id) {
+            Some(p) => p.score,
+            None => {
+                let mut local_participant = Participant::new(self.local_peer_id.clone());
+                local_participant.stats = self.local_stats();
+                local_participant.calculate_score(&self.config.score_weights)
+            }
+        }
+    }
+
+    fn uses_mixers(&self) -> bool {
+        self.topology_mode != TopologyMode::FullMesh
+    }
+
+    /// Cascaded mixers exchange forwarded streams, so cascaded rooms
+    /// forward whatever the media mode.
+    fn forwards(&self) -> bool {
+        self.config.media_mode == MediaMode::Forward || self.topology_mode == TopologyMode::Cascade
+    }
+
+    /// The current routing, then the one being handed over from.
+    fn routings(&self) -> impl Iterator<Item = Routing<'_>> {
+        let current = Routing {
+            mixer: self.current_mixer.as_deref(),
+            cascade: &self.cascade,
+        };
+        let handover = self
+            .handover
+            .as_ref()
+            .filter(|h| h.started.elapsed() < HANDOVER_GRACE)
+            .map(|h| Routing {
+                mixer: h.mixer.as_deref(),
+                cascade: &h.cascade,
+            });
+        std::iter::once(current).chain(handover)
+    }
+
+    /// Where we relay `sender`'s audio as a mixer under `routing`: to the
+    /// participants we serve and, unless another mixer relayed it to us,
+    /// to the other mixers.
+    fn relay_targets(&self, routing: &Routing, sender: &str, from_mixer: bool) -> Vec<String> {
+        let local = self.local_peer_id.as_str();
+        self.participants
+            .keys()
+            .filter(|peer_id| *peer_id != sender)
+            .filter(|peer_id| {
+                routing.mixer_for(peer_id) == Some(local)
+                    || (!from_mixer && routing.is_mixer(peer_id))
+            })
+            .cloned()
+            .collect()
+    }
+
     pub fn is_mixer(&self) -> bool {
         self.local_role == MixerRole::Mixer
     }
@@ -408,6 +890,203 @@ impl MixerManager {
         Some(mix_audio(&frame_refs, &weights))
     }
 
+    /// Queues a decoded frame from `peer_id` for the next mix.
+    pub fn push_audio(&mut self, peer_id: &str, frame: AudioFrame) {
+        let Some(participant) = self.participants.get_mut(peer_id) else {
+            return;
+        };
+        if participant.audio_buffer.len() >= MAX_BUFFERED_FRAMES {
+            participant.audio_buffer.remove(0);
+        }
+        participant.audio_buffer.push(frame);
+    }
+
+    /// Decodes an Opus frame from `peer_id` and queues it for the next mix.
+    pub fn push_encoded_audio(&mut self, peer_id: &str, data: &[u8]) -> AgoraResult<()> {
+        if !self.participants.contains_key(peer_id) {
+            return Ok(());
+        }
+        let decoder = match self.decoders.entry(peer_id.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(OpusDecoder::new(
+                self.config.opus.sample_rate,
+                self.config.opus.channels,
+            )?),
+        };
+        let frame = decoder.decode(data)?;
+        self.push_audio(peer_id, frame);
+        Ok(())
+    }
+
+    /// Mixes the oldest queued frame of every participant plus `local_audio`
+    /// and returns, per participant, an encoded mix without their own voice.
+    ///
+    /// Every participant keeps its own encoder whether it speaks or not:
+    /// sharing one encoder between silent participants would splice two
+    /// encoder states into one stream whenever someone starts talking.
+    pub fn mix_minus(
+        &mut self,
+        local_audio: Option<&AudioFrame>,
+    ) -> AgoraResult<HashMap<String, EncodedFrame>> {
+        let mut mixes = HashMap::new();
+        if !self.is_mixer() || self.participants.is_empty() {
+            return Ok(mixes);
+        }
+
+        // `None` stands for the local participant.
+        let mut speakers: Vec<(Option<String>, AudioFrame)> = Vec::new();
+        for (peer_id, participant) in self.participants.iter_mut() {
+            if participant.audio_buffer.is_empty() {
+                continue;
+            }
+            let frame = participant.audio_buffer.remove(0);
+            if calculate_rms(&frame) >= SILENCE_RMS_THRESHOLD {
+                speakers.push((Some(peer_id.clone()), frame));
+            }
+        }
+        if let Some(local) = local_audio {
+            if calculate_rms(local) >= SILENCE_RMS_THRESHOLD {
+                speakers.push((None, local.clone()));
+            }
+        }
+
+        let mix = |exclude: Option<&str>| {
+            let frames: Vec<&[f32]> = speakers
+                .iter()
+                .filter(|(peer_id, _)| exclude.is_none() || peer_id.as_deref() != exclude)
+                .map(|(_, frame)| frame.as_slice())
+                .collect();
+            if frames.is_empty() {
+                return vec![0.0; OPUS_FRAME_SIZE];
+            }
+            let weights = vec![1.0 / frames.len() as f32; frames.len()];
+            mix_audio(&frames, &weights)
+        };
+
+        let full_mix = mix(None);
+        for peer_id in self.participants.keys() {
+            let speaking = speakers
+                .iter()
+                .any(|(speaker, _)| speaker.as_ref() == Some(peer_id));
+            let encoder = match self.encoders.entry(peer_id.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(OpusEncoder::new(self.config.opus.clone())?),
+            };
+            let frame = if speaking {
+                encoder.encode_frame(&mix(Some(peer_id)))?
+            } else {
+                encoder.encode_frame(&full_mix)?
+            };
+            mixes.insert(peer_id.clone(), frame);
+        }
+
+        Ok(mixes)
+    }
+
+    pub fn media_mode(&self) -> MediaMode {
+        self.config.media_mode
+    }
+
+    /// The peers to send our own audio to when others forward for the
+    /// room, or `None` to send it to everyone. Participants send to their
+    /// mixer, and during a handover to the outgoing one too; a cascade
+    /// mixer sends to the participants it serves and the other mixers.
+    pub fn audio_targets(&self) -> Option<Vec<String>> {
+        if !self.forwards() || !self.uses_mixers() {
+            return None;
+        }
+        let local = self.local_peer_id.as_str();
+        let mut targets: Vec<String> = Vec::new();
+        for (i, routing) in self.routings().enumerate() {
+            let peers = match routing.is_mixer(local) {
+                // As the outgoing mixer our audio already reaches everyone
+                // through the new routing.
+                true if i > 0 => continue,
+                true if routing.cascade.is_empty() => return None,
+                true => self.relay_targets(&routing, local, false),
+                false => routing
+                    .mixer_for(local)
+                    .map(str::to_string)
+                    .into_iter()
+                    .collect(),
+            };
+            for peer_id in peers {
+                if !targets.contains(&peer_id) {
+                    targets.push(peer_id);
+                }
+            }
+        }
+        (!targets.is_empty()).then_some(targets)
+    }
+
+    /// Records the level of a packet relayed to us by `from` and returns the
+    /// participants to relay it to unchanged. Only the loudest
+    /// `max_forwarded_speakers` are relayed; everyone else gets nothing.
+    pub fn forward_targets(&mut self, packet: &EncryptedAudioPacket, from: &str) -> Vec<String> {
+        let local = self.local_peer_id.as_str();
+        if !self.forwards() || !self.routings().any(|routing| routing.is_mixer(local)) {
+            return Vec::new();
+        }
+        let Some(participant) = self.participants.get_mut(&packet.peer_id) else {
+            return Vec::new();
+        };
+        let level = packet.audio_level as f32;
+        if level < participant.audio_level {
+            participant.audio_level = level;
+        } else {
+            participant.audio_level += (level - participant.audio_level) * SPEAKER_LEVEL_SMOOTHING;
+        }
+        participant.last_audio = Some(Instant::now());
+
+        self.forwarded_speakers = self.active_speakers().into_iter().collect();
+        if !self.forwarded_speakers.contains(&packet.peer_id) {
+            return Vec::new();
+        }
+
+        let local = self.local_peer_id.as_str();
+        let from_mixer = from != packet.peer_id;
+        let mut targets: Vec<String> = Vec::new();
+        for routing in self.routings() {
+            // Relayed audio is only taken from the other mixers.
+            if !routing.is_mixer(local) || (from_mixer && !routing.is_mixer(from)) {
+                continue;
+            }
+            for peer_id in self.relay_targets(&routing, &packet.peer_id, from_mixer) {
+                if peer_id != from && !targets.contains(&peer_id) {
+                    targets.push(peer_id);
+                }
+            }
+        }
+        targets
+    }
+
+    /// The loudest recent speakers, at most `max_forwarded_speakers`, with
+    /// speakers already being forwarded given `SPEAKER_SWITCH_MARGIN`.
+    pub fn active_speakers(&self) -> Vec<String> {
+        let mut speakers: Vec<(&String, f32)> = self
+            .participants
+            .iter()
+            .filter(|(_, p)| p.is_speaking())
+            .map(|(peer_id, p)| {
+                let level = match self.forwarded_speakers.contains(peer_id) {
+                    true => p.audio_level - SPEAKER_SWITCH_MARGIN,
+                    false => p.audio_level,
+                };
+                (peer_id, level)
+            })
+            .collect();
+        speakers.sort_by(|a, b| {
+            a.1.partial_cmp(&b.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(b.0))
+        });
+        speakers
+            .into_iter()
+            .take(self.config.max_forwarded_speakers)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect()
+    }
+
     pub fn get_connection_targets(&self) -> Vec<String> {
         match self.topology_mode {
             TopologyMode::FullMesh => {
@@ -415,13 +1094,24 @@ impl MixerManager {
                 self.participants.keys().cloned().collect()
             }
             TopologyMode::SFU => {
-                // Only connect to mixer
-                self.current_mixer
-                    .iter()
-                    .filter(|id| *id != &self.local_peer_id)
-                    .cloned()
+                // Only connect to mixer, and the outgoing one during handover
+                self.routings()
+                    .filter_map(|routing| routing.mixer)
+                    .filter(|id| *id != self.local_peer_id)
+                    .map(str::to_string)
                     .collect()
             }
+            TopologyMode::Cascade => {
+                // Mixers connect to their participants and each other
+                let routing = Routing {
+                    mixer: self.current_mixer.as_deref(),
+                    cascade: &self.cascade,
+                };
+                match routing.is_mixer(&self.local_peer_id) {
+                    true => self.relay_targets(&routing, &self.local_peer_id, false),
+                    false => self.current_mixer.iter().cloned().collect(),
+                }
+            }
         }
     }
 
@@ -430,27 +1120,19 @@ impl MixerManager {
     }
 
     pub fn update_local_stats(&mut self, bandwidth_bps: u64, cpu_usage: f32, memory_usage: f32) {
-        let local_participant = Participant {
-            peer_id: self.local_peer_id.clone(),
-            display_name: None,
-            role: self.local_role,
-            stats: ParticipantStats {
-                bandwidth_bps,
-                cpu_usage_percent: cpu_usage,
-                memory_usage_percent: memory_usage,
-                session_duration: self
-                    .mixer_start_time
-                    .map(|t| t.elapsed())
-                    .unwrap_or(Duration::ZERO),
-                ..ParticipantStats::default()
-            },
-            audio_buffer: Vec::new(),
-            mixer_start_time: self.mixer_start_time,
-            score: 0.0,
-        };
+        self.local_stats.bandwidth_bps = bandwidth_bps;
+        self.local_stats.cpu_usage_percent = cpu_usage;
+        self.local_stats.memory_usage_percent = memory_usage;
+        self.local_stats.last_updated = Instant::now();
+    }
 
-        // This is just for score calculation, we don't store local participant
-        let _ = local_participant;
+    /// Our own stats as published to the room, with the session counted
+    /// from when we joined it.
+    pub fn local_stats(&self) -> ParticipantStats {
+        ParticipantStats {
+            session_duration: self.joined_at.elapsed(),
+            ..self.local_stats.clone()
+        }
     }
 }
 
@@ -620,6 +1302,463 @@ mod tests {
         assert_eq!(selected, "peer_a"); // Lexicographically smallest
     }
 
+    fn tone(frequency: f32) -> AudioFrame {
+        (0..OPUS_FRAME_SIZE)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * frequency * i as f32 / 48000.0).sin())
+            .collect()
+    }
+
+    fn mixing_manager() -> MixerManager {
+        let mut manager = MixerManager::new("local".to_string(), None);
+        for peer in ["speaker", "quiet1", "quiet2"] {
+            manager.add_participant(peer.to_string());
+        }
+        manager.set_mixer("local".to_string());
+        manager
+    }
+
+    #[test]
+    fn test_mix_minus_excludes_own_voice() {
+        let mut manager = mixing_manager();
+        let mut decoder = OpusDecoder::new(48000, 1).unwrap();
+        let mut shared_decoder = OpusDecoder::new(48000, 1).unwrap();
+
+        // Run a few frames so the decoders are past the codec's lookahead.
+        let (mut own, mut heard) = (Vec::new(), Vec::new());
+        for _ in 0..5 {
+            manager.push_audio("speaker", tone(440.0));
+            manager.push_audio("quiet1", vec![0.0; OPUS_FRAME_SIZE]);
+            let mixes = manager.mix_minus(None).unwrap();
+            assert_eq!(mixes.len(), 3);
+            own = decoder.decode(&mixes["speaker"].data).unwrap();
+            heard = shared_decoder.decode(&mixes["quiet1"].data).unwrap();
+        }
+
+        assert!(calculate_rms(&own) < SILENCE_RMS_THRESHOLD);
+        assert!(calculate_rms(&heard) > 0.1);
+    }
+
+    #[test]
+    fn test_mix_minus_stream_continuous_across_speech() {
+        let mut manager = mixing_manager();
+        let mut decoder = OpusDecoder::new(48000, 1).unwrap();
+
+        // "speaker" talks, falls silent and talks again while quiet1 plays a
+        // steady tone, which is all "speaker" should hear throughout.
+        let mut sequences = Vec::new();
+        for i in 0..15 {
+            let voice = if (5..10).contains(&i) {
+                vec![0.0; OPUS_FRAME_SIZE]
+            } else {
+                tone(440.0)
+            };
+            manager.push_audio("speaker", voice);
+            manager.push_audio("quiet1", tone(660.0));
+            let frame = manager.mix_minus(None).unwrap().remove("speaker").unwrap();
+            sequences.push(frame.sequence);
+
+            let decoded = decoder.decode(&frame.data).unwrap();
+            if i >= 3 {
+                let rms = calculate_rms(&decoded);
+                assert!((0.25..0.45).contains(&rms), "frame {} rms {}", i, rms);
+            }
+        }
+        assert!(sequences.windows(2).all(|w| w[1] == w[0] + 1));
+        assert_eq!(manager.encoders.len(), 3);
+    }
+
+    #[test]
+    fn test_mix_minus_requires_mixer_role() {
+        let mut manager = MixerManager::new("local".to_string(), None);
+        manager.add_participant("peer1".to_string());
+        manager.push_audio("peer1", tone(440.0));
+
+        assert!(manager.mix_minus(None).unwrap().is_empty());
+    }
+
+    fn forwarding_manager(speakers: usize, max_forwarded: usize) -> MixerManager {
+        let config = MixerConfig {
+            full_mesh_threshold: 2,
+            media_mode: MediaMode::Forward,
+            max_forwarded_speakers: max_forwarded,
+            ..Default::default()
+        };
+        let mut manager = MixerManager::new("local".to_string(), Some(config));
+        for i in 0..speakers {
+            manager.add_participant(format!("peer{}", i));
+        }
+        manager.set_mixer("local".to_string());
+        manager
+    }
+
+    fn level_packet(peer_id: &str, audio_level: u8) -> EncryptedAudioPacket {
+        let mut packet = EncryptedAudioPacket::new(1, peer_id.to_string(), vec![], [0; 12], 1);
+        packet.audio_level = audio_level;
+        packet
+    }
+
+    #[test]
+    fn test_forward_relays_to_everyone_but_sender() {
+        let mut manager = forwarding_manager(3, 3);
+
+        let mut targets = manager.forward_targets(&level_packet("peer0", 20), "peer0");
+        targets.sort();
+        assert_eq!(targets, vec!["peer1".to_string(), "peer2".to_string()]);
+
+        // Silence is never forwarded.
+        assert!(manager
+            .forward_targets(&level_packet("peer1", SILENT_AUDIO_LEVEL), "peer1")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_forward_caps_active_speakers() {
+        let mut manager = forwarding_manager(4, 2);
+
+        for _ in 0..10 {
+            for (peer, level) in [("peer0", 10), ("peer1", 20), ("peer2", 40)] {
+                manager.forward_targets(&level_packet(peer, level), peer);
+            }
+        }
+        assert_eq!(
+            manager.active_speakers(),
+            vec!["peer0".to_string(), "peer1".to_string()]
+        );
+        assert!(manager
+            .forward_targets(&level_packet("peer2", 40), "peer2")
+            .is_empty());
+
+        // A slightly louder newcomer does not take a forwarded speaker's slot.
+        for _ in 0..10 {
+            manager.forward_targets(&level_packet("peer0", 10), "peer0");
+            manager.forward_targets(&level_packet("peer1", 20), "peer1");
+            manager.forward_targets(&level_packet("peer3", 17), "peer3");
+        }
+        assert!(!manager.active_speakers().contains(&"peer3".to_string()));
+    }
+
+    #[test]
+    fn test_forwarding_mixer_routes_through_elected_peer() {
+        let mut manager = forwarding_manager(3, 3);
+        assert!(manager.audio_targets().is_none());
+        assert!(
+            manager
+                .forward_targets(&level_packet("peer0", 20), "peer0")
+                .len()
+                == 2
+        );
+
+        manager.set_mixer("peer1".to_string());
+        assert!(!manager.is_mixer());
+        assert_eq!(manager.audio_targets(), Some(vec!["peer1".to_string()]));
+        // The outgoing mixer keeps forwarding until the handover ends.
+        assert_eq!(
+            manager
+                .forward_targets(&level_packet("peer0", 20), "peer0")
+                .len(),
+            2
+        );
+
+        manager.handover = None;
+        assert!(manager
+            .forward_targets(&level_packet("peer0", 20), "peer0")
+            .is_empty());
+
+        let mut mixing = MixerManager::new(
+            "local".to_string(),
+            Some(MixerConfig {
+                media_mode: MediaMode::Mix,
+                ..Default::default()
+            }),
+        );
+        for i in 0..6 {
+            mixing.add_participant(format!("peer{}", i));
+        }
+        mixing.set_mixer("peer1".to_string());
+        assert!(mixing.audio_targets().is_none());
+    }
+
+    fn election(epoch: u64, mixer: &str, score: f64) -> MixerElection {
+        MixerElection {
+            epoch,
+            mixer: mixer.to_string(),
+            score,
+            cascade: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_election_prefers_published_stats() {
+        let mut manager = forwarding_manager(3, 3);
+        let mut stats = ParticipantStats::new();
+        stats.bandwidth_bps = 10_000_000;
+        manager.update_participant_stats("peer2", stats);
+
+        let first = manager.elect().unwrap();
+        assert_eq!(first.epoch, 1);
+        assert_eq!(first.mixer, "peer2");
+        assert!(!manager.needs_election());
+
+        // The mixer leaving calls a new round with a later epoch.
+        manager.remove_participant("peer2");
+        assert!(manager.needs_election());
+        assert_eq!(manager.elect().unwrap().epoch, 2);
+    }
+
+    #[test]
+    fn test_conflicting_elections_converge() {
+        let mut manager = forwarding_manager(3, 3);
+        assert!(manager.apply_election(election(3, "peer2", 0.45)));
+
+        // Same epoch: the higher score wins, then the smaller peer ID.
+        assert!(!manager.apply_election(election(3, "peer1", 0.42)));
+        assert!(manager.apply_election(election(3, "peer1", 0.45)));
+        assert!(!manager.apply_election(election(3, "peer2", 0.45)));
+        assert_eq!(manager.get_current_mixer(), Some("peer1"));
+
+        // Scores must match the stats we hold; one we cannot confirm yet
+        // has us hold a round once newer stats are in.
+        assert!(!manager.apply_election(election(3, "peer2", f64::INFINITY)));
+        assert!(!manager.needs_election());
+        assert!(!manager.apply_election(election(3, "peer2", 0.9)));
+        assert!(manager.needs_election());
+        let mut stats = ParticipantStats::new();
+        stats.bandwidth_bps = 10_000_000;
+        manager.update_participant_stats("peer2", stats);
+        assert!(manager.apply_election(election(3, "peer2", 0.85)));
+        assert_eq!(manager.get_current_mixer(), Some("peer2"));
+
+        // Stale rounds and unknown mixers are ignored.
+        assert!(!manager.apply_election(election(2, "peer0", 0.45)));
+        assert!(!manager.apply_election(election(4, "stranger", 0.45)));
+        assert!(manager.apply_election(election(4, "local", 0.45)));
+        assert!(manager.is_mixer());
+    }
+
+    #[test]
+    fn test_far_future_epochs_rejected() {
+        let mut manager = forwarding_manager(3, 3);
+        manager.elect().unwrap();
+        assert!(!manager.needs_election());
+
+        assert!(!manager.apply_election(election(u64::MAX, "peer0", 0.45)));
+        assert!(!manager.apply_election(election(3, "peer0", 0.45)));
+        // Having seen a later round, we ask the room to catch us up.
+        assert!(manager.needs_election());
+        assert_eq!(manager.elect().unwrap().epoch, 2);
+        assert!(!manager.needs_election());
+
+        // The epoch saturates instead of overflowing.
+        let mut joiner = forwarding_manager(3, 3);
+        assert!(joiner.apply_election(election(u64::MAX, "peer0", 0.45)));
+        assert_eq!(joiner.elect().unwrap().epoch, u64::MAX);
+    }
+
+    #[test]
+    fn test_handover_sends_to_both_mixers() {
+        let mut manager = forwarding_manager(3, 3);
+        manager.apply_election(election(1, "peer0", 0.5));
+        assert_eq!(manager.audio_targets(), Some(vec!["peer0".to_string()]));
+
+        manager.apply_election(election(2, "peer1", 0.5));
+        assert_eq!(
+            manager.audio_targets(),
+            Some(vec!["peer1".to_string(), "peer0".to_string()])
+        );
+        assert_eq!(
+            manager.get_connection_targets(),
+            vec!["peer1".to_string(), "peer0".to_string()]
+        );
+
+        manager.handover = Some(Handover {
+            mixer: Some("peer0".to_string()),
+            cascade: Vec::new(),
+            started: Instant::now() - HANDOVER_GRACE,
+        });
+        assert_eq!(manager.audio_targets(), Some(vec!["peer1".to_string()]));
+    }
+
+    fn cascade_manager(local: &str, participants: &[&str]) -> MixerManager {
+        let config = MixerConfig {
+            full_mesh_threshold: 2,
+            max_participants: 3,
+            media_mode: MediaMode::Forward,
+            ..Default::default()
+        };
+        let mut manager = MixerManager::new(local.to_string(), Some(config));
+        for peer_id in participants {
+            manager.add_participant(peer_id.to_string());
+        }
+        manager
+    }
+
+    fn cascade_election() -> MixerElection {
+        let group = |mixer: &str, members: [&str; 2]| MixerGroup {
+            mixer: mixer.to_string(),
+            members: members.iter().map(|m| m.to_string()).collect(),
+        };
+        MixerElection {
+            cascade: vec![
+                group("local", ["peer0", "peer1"]),
+                group("peer2", ["peer3", "peer4"]),
+            ],
+            ..election(1, "local", 0.5)
+        }
+    }
+
+    #[test]
+    fn test_large_rooms_elect_several_mixers() {
+        let peers: Vec<String> = (0..9).map(|i| format!("peer{}", i)).collect();
+        let peers: Vec<&str> = peers.iter().map(String::as_str).collect();
+        let mut manager = cascade_manager("local", &peers[..6]);
+        assert_eq!(manager.get_topology_mode(), TopologyMode::Cascade);
+
+        let election = manager.elect().unwrap();
+        assert_eq!(election.cascade.len(), 3);
+        assert_eq!(election.mixer, election.cascade[0].mixer);
+        assert!(!manager.needs_election());
+
+        // Outgrowing the plan calls a new round.
+        for peer_id in &peers[6..] {
+            manager.add_participant(peer_id.to_string());
+        }
+        assert!(manager.needs_election());
+    }
+
+    #[test]
+    fn test_cascade_mixer_relays_between_groups() {
+        let mut manager = cascade_manager("local", &["peer0", "peer1", "peer2", "peer3", "peer4"]);
+        assert!(manager.apply_election(cascade_election()));
+        assert!(manager.is_mixer());
+        manager.handover = None;
+
+        // Our participants are relayed to our group and the other mixer.
+        let mut targets = manager.forward_targets(&level_packet("peer0", 20), "peer0");
+        targets.sort();
+        assert_eq!(targets, vec!["peer1".to_string(), "peer2".to_string()]);
+
+        // What the other mixer relays only goes to our group.
+        let mut targets = manager.forward_targets(&level_packet("peer3", 20), "peer2");
+        targets.sort();
+        assert_eq!(targets, vec!["peer0".to_string(), "peer1".to_string()]);
+        assert!(manager
+            .forward_targets(&level_packet("peer3", 20), "peer4")
+            .is_empty());
+
+        let mut own = manager.audio_targets().unwrap();
+        own.sort();
+        assert_eq!(own, vec!["peer0", "peer1", "peer2"]);
+    }
+
+    #[test]
+    fn test_cascade_member_sends_to_its_mixer() {
+        let mut manager = cascade_manager("peer3", &["local", "peer0", "peer1", "peer2", "peer4"]);
+        assert!(manager.apply_election(cascade_election()));
+        manager.handover = None;
+
+        assert!(!manager.is_mixer());
+        assert_eq!(manager.get_current_mixer(), Some("peer2"));
+        assert_eq!(manager.audio_targets(), Some(vec!["peer2".to_string()]));
+        assert_eq!(manager.get_connection_targets(), vec!["peer2".to_string()]);
+        assert!(manager
+            .forward_targets(&level_packet("peer4", 20), "peer4")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cascade_forwards_in_mix_mode() {
+        let mut manager = cascade_manager("local", &["peer0", "peer1", "peer2", "peer3", "peer4"]);
+        manager.config.media_mode = MediaMode::Mix;
+        assert!(manager.apply_election(cascade_election()));
+        manager.handover = None;
+
+        let mut targets = manager.forward_targets(&level_packet("peer0", 20), "peer0");
+        targets.sort();
+        assert_eq!(targets, vec!["peer1".to_string(), "peer2".to_string()]);
+        assert!(manager.audio_targets().is_some());
+
+        let mut member = cascade_manager("peer3", &["local", "peer0", "peer1", "peer2", "peer4"]);
+        member.config.media_mode = MediaMode::Mix;
+        assert!(member.apply_election(cascade_election()));
+        member.handover = None;
+        assert_eq!(member.audio_targets(), Some(vec!["peer2".to_string()]));
+    }
+
+    fn dedicated_node(peer_id: &str, reputation: f32) -> MixerNode {
+        MixerNode {
+            peer_id: peer_id.to_string(),
+            region: "eu-west".to_string(),
+            latency_ms: Some(20),
+            score: 0.8,
+            reputation,
+        }
+    }
+
+    #[test]
+    fn test_trusted_dedicated_node_preferred_as_mixer() {
+        let mut manager = MixerManager::new("local".to_string(), None);
+        manager.add_participant("peer1".to_string());
+        manager.add_participant("node".to_string());
+        assert_eq!(manager.get_topology_mode(), TopologyMode::FullMesh);
+
+        manager.set_mixer_nodes(vec![dedicated_node("node", 0.3)]);
+        assert_eq!(manager.get_topology_mode(), TopologyMode::FullMesh);
+
+        // Trusted nodes mix even for rooms small enough for a full mesh.
+        manager.set_mixer_nodes(vec![dedicated_node("node", 0.9)]);
+        assert_eq!(manager.get_topology_mode(), TopologyMode::SFU);
+        assert_eq!(manager.select_mixer(), Some("node".to_string()));
+    }
+
+    #[test]
+    fn test_dedicated_node_replaces_elected_participant() {
+        let config = MixerConfig {
+            full_mesh_threshold: 2,
+            rotation_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut manager = MixerManager::new("local".to_string(), Some(config));
+        manager.add_participant("peer1".to_string());
+        manager.add_participant("peer2".to_string());
+        assert!(manager.apply_election(election(1, "peer1", 0.5)));
+        assert!(!manager.needs_election());
+
+        manager.add_participant("node".to_string());
+        manager.set_mixer_nodes(vec![dedicated_node("node", 0.9)]);
+        assert!(manager.needs_election());
+        let elected = manager.elect().unwrap();
+        assert_eq!((elected.epoch, elected.mixer.as_str()), (2, "node"));
+
+        // Dedicated nodes are not rotated out.
+        assert!(!manager.check_rotation());
+        assert!(!manager.needs_election());
+    }
+
+    #[test]
+    fn test_dedicated_node_mixes_for_small_rooms() {
+        let config = MixerConfig {
+            dedicated: true,
+            ..Default::default()
+        };
+        let mut manager = MixerManager::new("node".to_string(), Some(config));
+        assert_eq!(manager.get_topology_mode(), TopologyMode::FullMesh);
+
+        manager.add_participant("peer1".to_string());
+        assert_eq!(manager.get_topology_mode(), TopologyMode::SFU);
+        assert_eq!(manager.elect().unwrap().mixer, "node");
+        assert!(manager.is_mixer());
+
+        // Another trusted dedicated node's result is not contested; an
+        // untrusted one's is.
+        manager.add_participant("other-node".to_string());
+        manager.set_mixer_nodes(vec![dedicated_node("other-node", 0.3)]);
+        assert!(manager.apply_election(election(2, "other-node", 0.5)));
+        assert!(manager.needs_election());
+
+        manager.set_mixer_nodes(vec![dedicated_node("other-node", 0.9)]);
+        assert!(!manager.needs_election());
+    }
+
     #[test]
     fn test_participant_count() {
         let mut manager = MixerManager::new("local".to_string(), None);
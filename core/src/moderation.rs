@@ -0,0 +1,617 @@
+use crate::error::{AgoraResult, Error};
+use crate::identity::{verifying_key_from_peer_id, Identity};
+use crate::room::CreatorProof;
+use ed25519_dalek::{Signature, Verifier};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SIGNING_DOMAIN: &str = "agora moderation v1";
+
+/// Kicks are one-shot, so they are only honoured while fresh.
+pub const KICK_MAX_AGE: Duration = Duration::from_secs(300);
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModerationAction {
+    Kick {
+        target: String,
+    },
+    /// Bans until the given Unix time in seconds.
+    Ban {
+        target: String,
+        until: u64,
+    },
+    GrantModerator {
+        target: String,
+    },
+    RevokeModerator {
+        target: String,
+    },
+    ForceMute {
+        target: String,
+        muted: bool,
+    },
+}
+
+impl ModerationAction {
+    pub fn target(&self) -> &str {
+        match self {
+            ModerationAction::Kick { target }
+            | ModerationAction::Ban { target, .. }
+            | ModerationAction::GrantModerator { target }
+            | ModerationAction::RevokeModerator { target }
+            | ModerationAction::ForceMute { target, .. } => target,
+        }
+    }
+}
+
+/// A moderation action signed by the room creator or a moderator.
+///
+/// Actions are self-contained: they carry the creator's proof for the room
+/// and, when issued by a moderator, the creator-signed grant that made the
+/// issuer a moderator, so any peer can check them without prior state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedModeration {
+    pub room_id: String,
+    pub action: ModerationAction,
+    pub issuer: String,
+    /// Unix time in milliseconds.
+    pub issued_at: u64,
+    pub creator: CreatorProof,
+    pub grant: Option<Box<SignedModeration>>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct SigningPayload<'a> {
+    domain: &'static str,
+    room_id: &'a str,
+    action: &'a ModerationAction,
+    issuer: &'a str,
+    issued_at: u64,
+    creator: &'a CreatorProof,
+    grant_signature: Option<&'a [u8]>,
+}
+
+impl SignedModeration {
+    pub fn sign(
+        identity: &Identity,
+        room_id: &str,
+        action: ModerationAction,
+        creator: CreatorProof,
+        grant: Option<SignedModeration>,
+    ) -> AgoraResult<Self> {
+        let mut signed = Self {
+            room_id: room_id.to_string(),
+            action,
+            issuer: identity.peer_id(),
+            issued_at: now_millis(),
+            creator,
+            grant: grant.map(Box::new),
+            signature: Vec::new(),
+        };
+        signed.signature = identity.sign(&signed.signing_bytes()?).to_bytes().to_vec();
+        Ok(signed)
+    }
+
+    fn signing_bytes(&self) -> AgoraResult<Vec<u8>> {
+        postcard::to_allocvec(&SigningPayload {
+            domain: SIGNING_DOMAIN,
+            room_id: &self.room_id,
+            action: &self.action,
+            issuer: &self.issuer,
+            issued_at: self.issued_at,
+            creator: &self.creator,
+            grant_signature: self.grant.as_ref().map(|g| g.signature.as_slice()),
+        })
+        .map_err(|e| Error::Crypto(format!("Failed to encode moderation action: {}", e)))
+    }
+
+    pub fn issuer_peer_id(&self) -> AgoraResult<PeerId> {
+        self.issuer
+            .parse()
+            .map_err(|e| Error::Crypto(format!("Invalid issuer {}: {}", self.issuer, e)))
+    }
+
+    pub fn target_peer_id(&self) -> AgoraResult<PeerId> {
+        let target = self.action.target();
+        target
+            .parse()
+            .map_err(|e| Error::Crypto(format!("Invalid target {}: {}", target, e)))
+    }
+
+    /// Checks the signature against the key embedded in the issuer's peer ID.
+    pub fn verify_signature(&self) -> AgoraResult<()> {
+        let key = verifying_key_from_peer_id(&self.issuer_peer_id()?)?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|e| Error::Crypto(format!("Invalid signature: {}", e)))?;
+        key.verify(&self.signing_bytes()?, &signature)
+            .map_err(|_| Error::Crypto("Moderation signature mismatch".to_string()))
+    }
+}
+
+/// Moderation state of one room, built from verified actions.
+#[derive(Debug)]
+pub struct RoomModeration {
+    room_id: String,
+    creator: Option<CreatorProof>,
+    moderators: HashMap<PeerId, SignedModeration>,
+    revoked: HashMap<PeerId, u64>,
+    bans: HashMap<PeerId, SignedModeration>,
+    mutes: HashMap<PeerId, SignedModeration>,
+    seen: HashSet<Vec<u8>>,
+}
+
+impl RoomModeration {
+    pub fn new(room_id: &str) -> Self {
+        Self {
+            room_id: room_id.to_string(),
+            creator: None,
+            moderators: HashMap::new(),
+            revoked: HashMap::new(),
+            bans: HashMap::new(),
+            mutes: HashMap::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Records the creator's proof, e.g. when the local node created the room.
+    pub fn set_creator(&mut self, proof: CreatorProof) -> AgoraResult<()> {
+        if !proof.verify(&self.room_id) {
+            return Err(Error::Crypto(format!(
+                "Creator proof does not match room {}",
+                self.room_id
+            )));
+        }
+        self.creator = Some(proof);
+        Ok(())
+    }
+
+    pub fn creator_proof(&self) -> Option<&CreatorProof> {
+        self.creator.as_ref()
+    }
+
+    pub fn is_creator(&self, peer_id: &PeerId) -> bool {
+        self.creator
+            .as_ref()
+            .is_some_and(|c| c.creator_peer_id == peer_id.to_string())
+    }
+
+    pub fn is_moderator(&self, peer_id: &PeerId) -> bool {
+        self.is_creator(peer_id) || self.moderators.contains_key(peer_id)
+    }
+
+    /// The creator-signed grant making `peer_id` a moderator.
+    pub fn grant_for(&self, peer_id: &PeerId) -> Option<&SignedModeration> {
+        self.moderators.get(peer_id)
+    }
+
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.bans.get(peer_id).is_some_and(|ban| match ban.action {
+            ModerationAction::Ban { until, .. } => now_secs() < until,
+            _ => false,
+        })
+    }
+
+    pub fn is_muted(&self, peer_id: &PeerId) -> bool {
+        self.mutes.get(peer_id).is_some_and(|mute| {
+            matches!(mute.action, ModerationAction::ForceMute { muted: true, .. })
+        })
+    }
+
+    /// Actions a newly admitted peer needs to enforce the room's current state.
+    pub fn active_actions(&self) -> Vec<SignedModeration> {
+        self.moderators
+            .values()
+            .chain(self.bans.values())
+            .chain(self.mutes.values())
+            .cloned()
+            .collect()
+    }
+
+    /// Verifies `signed` and, if the issuer had the authority, applies it.
+    pub fn apply(&mut self, signed: &SignedModeration) -> AgoraResult<()> {
+        self.verify(signed)?;
+        self.seen.insert(signed.signature.clone());
+
+        let target = signed.target_peer_id()?;
+        match &signed.action {
+            ModerationAction::Kick { .. } => {}
+            ModerationAction::Ban { .. } => {
+                if self
+                    .bans
+                    .get(&target)
+                    .is_none_or(|b| b.issued_at < signed.issued_at)
+                {
+                    self.bans.insert(target, signed.clone());
+                }
+            }
+            ModerationAction::GrantModerator { .. } => {
+                let revoked_after = self.revoked.get(&target).copied().unwrap_or(0);
+                if signed.issued_at > revoked_after {
+                    self.moderators.insert(target, signed.clone());
+                }
+            }
+            ModerationAction::RevokeModerator { .. } => {
+                let revoked = self.revoked.entry(target).or_default();
+                *revoked = (*revoked).max(signed.issued_at);
+                if self
+                    .moderators
+                    .get(&target)
+                    .is_some_and(|g| g.issued_at < signed.issued_at)
+                {
+                    self.moderators.remove(&target);
+                }
+            }
+            ModerationAction::ForceMute { .. } => {
+                if self
+                    .mutes
+                    .get(&target)
+                    .is_none_or(|m| m.issued_at < signed.issued_at)
+                {
+                    self.mutes.insert(target, signed.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn verify(&mut self, signed: &SignedModeration) -> AgoraResult<()> {
+        if signed.room_id != self.room_id {
+            return Err(Error::Room(format!(
+                "Action for room {} applied to {}",
+                signed.room_id, self.room_id
+            )));
+        }
+        if self.seen.contains(&signed.signature) {
+            return Err(Error::Room("Moderation action already applied".to_string()));
+        }
+
+        let now = now_millis();
+        if signed.issued_at > now + MAX_CLOCK_SKEW.as_millis() as u64 {
+            return Err(Error::Crypto(
+                "Moderation action from the future".to_string(),
+            ));
+        }
+        if matches!(signed.action, ModerationAction::Kick { .. })
+            && now.saturating_sub(signed.issued_at) > KICK_MAX_AGE.as_millis() as u64
+        {
+            return Err(Error::Crypto("Kick is too old".to_string()));
+        }
+
+        match &self.creator {
+            Some(creator) if *creator != signed.creator => {
+                return Err(Error::Room("Action names a different creator".to_string()));
+            }
+            Some(_) => {}
+            None => {
+                if !signed.creator.verify(&self.room_id) {
+                    return Err(Error::Crypto(format!(
+                        "Creator proof does not match room {}",
+                        self.room_id
+                    )));
+                }
+                self.creator = Some(signed.creator.clone());
+            }
+        }
+
+        signed.verify_signature()?;
+
+        let issuer = signed.issuer_peer_id()?;
+        let target = signed.target_peer_id()?;
+        if self.is_creator(&issuer) {
+            return Ok(());
+        }
+
+        // Everyone else needs a live creator-signed grant and may only act
+        // on ordinary members.
+        let grant = signed
+            .grant
+            .as_deref()
+            .ok_or_else(|| Error::Room(format!("{} is not a moderator", issuer)))?;
+        let grants_issuer = matches!(
+            &grant.action,
+            ModerationAction::GrantModerator { target } if *target == signed.issuer
+        );
+        if !grants_issuer
+            || grant.room_id != self.room_id
+            || grant.creator != signed.creator
+            || grant.issuer != signed.creator.creator_peer_id
+        {
+            return Err(Error::Room(format!("{} is not a moderator", issuer)));
+        }
+        grant.verify_signature()?;
+        if self
+            .revoked
+            .get(&issuer)
+            .is_some_and(|revoked| *revoked >= grant.issued_at)
+        {
+            return Err(Error::Room(format!("{} is no longer a moderator", issuer)));
+        }
+
+        if matches!(
+            signed.action,
+            ModerationAction::GrantModerator { .. } | ModerationAction::RevokeModerator { .. }
+        ) {
+            return Err(Error::Room(
+                "Only the room creator can change moderators".to_string(),
+            ));
+        }
+        if self.is_moderator(&target) {
+            return Err(Error::Room(
+                "Moderators cannot act on the creator or other moderators".to_string(),
+            ));
+        }
+
+        self.moderators
+            .entry(issuer)
+            .or_insert_with(|| grant.clone());
+        Ok(())
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn now_secs() -> u64 {
+    now_millis() / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room::{Room, RoomConfig};
+
+    struct Setup {
+        creator: Identity,
+        room: Room,
+        moderation: RoomModeration,
+    }
+
+    fn setup() -> Setup {
+        let creator = Identity::generate().unwrap();
+        let room = Room::new(creator.peer_id(), RoomConfig::default_public());
+        let moderation = RoomModeration::new(&room.id);
+        Setup {
+            creator,
+            room,
+            moderation,
+        }
+    }
+
+    fn sign(
+        identity: &Identity,
+        room: &Room,
+        action: ModerationAction,
+        grant: Option<SignedModeration>,
+    ) -> SignedModeration {
+        SignedModeration::sign(
+            identity,
+            &room.id,
+            action,
+            room.creator_proof().unwrap(),
+            grant,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_creator_can_ban_and_mute() {
+        let mut s = setup();
+        let target = Identity::generate().unwrap();
+
+        let until = now_secs() + 3600;
+        let ban = sign(
+            &s.creator,
+            &s.room,
+            ModerationAction::Ban {
+                target: target.peer_id(),
+                until,
+            },
+            None,
+        );
+        s.moderation.apply(&ban).unwrap();
+        assert!(s.moderation.is_banned(&target.libp2p_peer_id()));
+
+        let mute = sign(
+            &s.creator,
+            &s.room,
+            ModerationAction::ForceMute {
+                target: target.peer_id(),
+                muted: true,
+            },
+            None,
+        );
+        s.moderation.apply(&mute).unwrap();
+        assert!(s.moderation.is_muted(&target.libp2p_peer_id()));
+        assert_eq!(s.moderation.active_actions().len(), 2);
+    }
+
+    #[test]
+    fn test_expired_ban_lapses() {
+        let mut s = setup();
+        let target = Identity::generate().unwrap();
+        let ban = sign(
+            &s.creator,
+            &s.room,
+            ModerationAction::Ban {
+                target: target.peer_id(),
+                until: now_secs() - 1,
+            },
+            None,
+        );
+        s.moderation.apply(&ban).unwrap();
+        assert!(!s.moderation.is_banned(&target.libp2p_peer_id()));
+    }
+
+    #[test]
+    fn test_non_moderator_rejected() {
+        let mut s = setup();
+        let mallory = Identity::generate().unwrap();
+        let kick = sign(
+            &mallory,
+            &s.room,
+            ModerationAction::Kick {
+                target: s.creator.peer_id(),
+            },
+            None,
+        );
+        assert!(s.moderation.apply(&kick).is_err());
+    }
+
+    #[test]
+    fn test_tampered_action_rejected() {
+        let mut s = setup();
+        let target = Identity::generate().unwrap();
+        let mut kick = sign(
+            &s.creator,
+            &s.room,
+            ModerationAction::Kick {
+                target: target.peer_id(),
+            },
+            None,
+        );
+        kick.action = ModerationAction::Kick {
+            target: s.creator.peer_id(),
+        };
+        assert!(s.moderation.apply(&kick).is_err());
+    }
+
+    #[test]
+    fn test_forged_creator_proof_rejected() {
+        let mut s = setup();
+        let mallory = Identity::generate().unwrap();
+        let target = Identity::generate().unwrap();
+        let mut proof = s.room.creator_proof().unwrap();
+        proof.creator_peer_id = mallory.peer_id();
+
+        let kick = SignedModeration::sign(
+            &mallory,
+            &s.room.id,
+            ModerationAction::Kick {
+                target: target.peer_id(),
+            },
+            proof,
+            None,
+        )
+        .unwrap();
+        assert!(s.moderation.apply(&kick).is_err());
+    }
+
+    #[test]
+    fn test_moderator_grant_and_revoke() {
+        let mut s = setup();
+        let moderator = Identity::generate().unwrap();
+        let target = Identity::generate().unwrap();
+
+        let grant = sign(
+            &s.creator,
+            &s.room,
+            ModerationAction::GrantModerator {
+                target: moderator.peer_id(),
+            },
+            None,
+        );
+        s.moderation.apply(&grant).unwrap();
+        assert!(s.moderation.is_moderator(&moderator.libp2p_peer_id()));
+
+        let kick = sign(
+            &moderator,
+            &s.room,
+            ModerationAction::Kick {
+                target: target.peer_id(),
+            },
+            Some(grant.clone()),
+        );
+        s.moderation.apply(&kick).unwrap();
+
+        // Moderators cannot touch the creator or hand out moderator rights.
+        let kick_creator = sign(
+            &moderator,
+            &s.room,
+            ModerationAction::Kick {
+                target: s.creator.peer_id(),
+            },
+            Some(grant.clone()),
+        );
+        assert!(s.moderation.apply(&kick_creator).is_err());
+        let promote = sign(
+            &moderator,
+            &s.room,
+            ModerationAction::GrantModerator {
+                target: target.peer_id(),
+            },
+            Some(grant.clone()),
+        );
+        assert!(s.moderation.apply(&promote).is_err());
+
+        std::thread::sleep(Duration::from_millis(2));
+        let revoke = sign(
+            &s.creator,
+            &s.room,
+            ModerationAction::RevokeModerator {
+                target: moderator.peer_id(),
+            },
+            None,
+        );
+        s.moderation.apply(&revoke).unwrap();
+        assert!(!s.moderation.is_moderator(&moderator.libp2p_peer_id()));
+
+        let late_kick = sign(
+            &moderator,
+            &s.room,
+            ModerationAction::Kick {
+                target: target.peer_id(),
+            },
+            Some(grant),
+        );
+        assert!(s.moderation.apply(&late_kick).is_err());
+    }
+
+    #[test]
+    fn test_replayed_action_rejected() {
+        let mut s = setup();
+        let target = Identity::generate().unwrap();
+        let kick = sign(
+            &s.creator,
+            &s.room,
+            ModerationAction::Kick {
+                target: target.peer_id(),
+            },
+            None,
+        );
+        s.moderation.apply(&kick).unwrap();
+        assert!(s.moderation.apply(&kick).is_err());
+    }
+
+    #[test]
+    fn test_unmute_supersedes_older_mute() {
+        let mut s = setup();
+        let target = Identity::generate().unwrap();
+        let mute = |muted| {
+            sign(
+                &s.creator,
+                &s.room,
+                ModerationAction::ForceMute {
+                    target: target.peer_id(),
+                    muted,
+                },
+                None,
+            )
+        };
+
+        let muted = mute(true);
+        std::thread::sleep(Duration::from_millis(2));
+        let unmuted = mute(false);
+
+        s.moderation.apply(&unmuted).unwrap();
+        s.moderation.apply(&muted).unwrap();
+        assert!(!s.moderation.is_muted(&target.libp2p_peer_id()));
+    }
+}
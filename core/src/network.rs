@@ -1,38 +1,109 @@
+use crate::audio::audio_level;
+use crate::audio_processor::{AudioProcessor, AudioProcessorConfig};
+use crate::audio_stream::{
+    accept_audio_streams, audio_stream_protocol, AudioStreamSender, InboundAudio, StreamMode,
+    StreamStatus,
+};
+use crate::blocklist::Blocklist;
+use crate::cascade::MixerNode;
+use crate::crypto::{KeyRotationEvent, SecureAudioChannel, SessionKey};
+use crate::dht_store::{AddressBook, PersistentStore, PEER_MAX_AGE};
+use crate::election::{MixerStats, SignedMixerElection, SignedMixerStats};
 use crate::error::{AgoraResult, Error};
-use crate::ice::{Candidate, ConnectionState as IceConnectionState, IceAgent, IceConfig};
+use crate::group_key::{GroupKeyManager, GroupRekey};
+use crate::ice::{
+    Candidate, CandidateType, ConnectionState as IceConnectionState, IceAgent, IceConfig,
+    IceCredentials, IceRole, IceTrickle, SignedIceCandidates,
+};
+use crate::ice_transport::{quic_multiaddr, IcePaths, IceTransport};
+use crate::identity::Identity;
+use crate::mixer::{MixerConfig, MixerManager, ParticipantStats};
+use crate::moderation::{ModerationAction, RoomModeration, SignedModeration};
 use crate::nat::{NatTraversal, NatType, StunConfig};
 use crate::protocol::{
-    AudioPacket, ControlMessage, ControlMessageType, MAX_FRAME_SIZE, PROTOCOL_CONTROL,
+    AudioPacket, CodecId, ControlMessage, ControlMessageType, EncodedAudioPacket,
+    EncryptedAudioPacket, JoinRejectReason, ParticipantInfo, MAX_FRAME_SIZE, PROTOCOL_CONTROL,
     PROTOCOL_NAME,
 };
+use crate::room::{CreatorProof, JoinAuthenticator, JoinRole};
+use crate::storage::BlocklistStorage;
 use async_trait::async_trait;
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
 use libp2p::{
-    autonat, dcutr, dns, identify,
+    autonat,
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, ListenerId},
+    },
+    dcutr, dns, identify,
     kad::{
-        store::MemoryStore, Behaviour as Kademlia, Event as KademliaEvent, GetProvidersOk,
-        QueryResult, RecordKey,
+        store::MemoryStoreConfig, Behaviour as Kademlia, Event as KademliaEvent, GetProvidersOk,
+        GetRecordOk, Mode as KademliaMode, PeerRecord, QueryResult, Quorum, Record, RecordKey,
     },
-    noise, ping,
+    mdns,
+    multiaddr::Protocol,
+    noise, ping, quic, relay,
     request_response::{self, Behaviour as RequestResponse, Codec, ProtocolSupport},
-    swarm::{Swarm, SwarmEvent},
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+        Swarm, SwarmEvent,
+    },
     tcp, yamux, Multiaddr, PeerId, Transport,
 };
 use libp2p_swarm_derive::NetworkBehaviour;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::io;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use tokio::sync::{broadcast, mpsc};
 
+/// How often led rooms are checked for a due periodic key rotation.
+const GROUP_KEY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a joiner has to answer a password challenge.
+const JOIN_CHALLENGE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long an ICE session may take from gathering to connection.
+const ICE_SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often sessions waiting on the remote's candidates re-query the DHT.
+const ICE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a node in a room looks for peers offering it ICE sessions.
+const ICE_INBOX_INTERVAL: Duration = Duration::from_secs(5);
+const ICE_RECORD_PREFIX: &str = "/agora/ice/";
+const ICE_INBOX_PREFIX: &str = "/agora/ice-inbox/";
+/// Dedicated nodes members may ask to mix for one room.
+const MAX_REQUESTED_MIXERS: usize = 8;
+/// How long an advertisement stays in the DHT unless published again.
+const ADVERTISEMENT_TTL: Duration = Duration::from_secs(600);
+/// Identify-observed addresses kept as dialable candidates for ICE peers.
+const MAX_EXTERNAL_ADDR_CANDIDATES: usize = 8;
+/// How often DHT records and known peer addresses are written to disk.
+const DHT_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// How often stats are published to each room, and elections held when due.
+const MIXER_STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// Published stats older than this are not scored.
+const MIXER_STATS_MAX_AGE: Duration = Duration::from_secs(30);
+const MIXER_ELECTION_MAX_AGE: Duration = Duration::from_secs(30);
+/// Records and provider keys a DHT server node holds, well above the
+/// `MemoryStore` defaults so it can anchor the DHT for others.
+const DHT_SERVER_MAX_RECORDS: usize = 16 * 1024;
+/// Relays a private node reserves a `/p2p-circuit` address on by itself.
+const MAX_AUTO_RELAYS: usize = 2;
+
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm = "AgoraBehaviourEvent")]
 pub struct AgoraBehaviour {
-    kademlia: Kademlia<MemoryStore>,
+    kademlia: Kademlia<PersistentStore>,
     identify: identify::Behaviour,
     ping: ping::Behaviour,
     autonat: autonat::Behaviour,
     dcutr: dcutr::Behaviour,
+    relay_client: Toggle<relay::client::Behaviour>,
+    relay: Toggle<relay::Behaviour>,
+    mdns: Toggle<mdns::tokio::Behaviour>,
     audio_stream: RequestResponse<AudioCodec>,
+    audio: libp2p_stream::Behaviour,
     control: RequestResponse<ControlCodec>,
 }
 
@@ -43,7 +114,10 @@ pub enum AgoraBehaviourEvent {
     Ping(ping::Event),
     Autonat(autonat::Event),
     Dcutr(dcutr::Event),
-    AudioStream(request_response::Event<AudioPacket, AudioPacket>),
+    RelayClient(relay::client::Event),
+    Relay(relay::Event),
+    Mdns(mdns::Event),
+    AudioStream(request_response::Event<EncryptedAudioPacket, EncryptedAudioPacket>),
     Control(request_response::Event<ControlMessage, ControlMessage>),
 }
 
@@ -77,8 +151,28 @@ impl From<dcutr::Event> for AgoraBehaviourEvent {
     }
 }
 
-impl From<request_response::Event<AudioPacket, AudioPacket>> for AgoraBehaviourEvent {
-    fn from(event: request_response::Event<AudioPacket, AudioPacket>) -> Self {
+impl From<relay::client::Event> for AgoraBehaviourEvent {
+    fn from(event: relay::client::Event) -> Self {
+        AgoraBehaviourEvent::RelayClient(event)
+    }
+}
+
+impl From<relay::Event> for AgoraBehaviourEvent {
+    fn from(event: relay::Event) -> Self {
+        AgoraBehaviourEvent::Relay(event)
+    }
+}
+
+impl From<mdns::Event> for AgoraBehaviourEvent {
+    fn from(event: mdns::Event) -> Self {
+        AgoraBehaviourEvent::Mdns(event)
+    }
+}
+
+impl From<request_response::Event<EncryptedAudioPacket, EncryptedAudioPacket>>
+    for AgoraBehaviourEvent
+{
+    fn from(event: request_response::Event<EncryptedAudioPacket, EncryptedAudioPacket>) -> Self {
         AgoraBehaviourEvent::AudioStream(event)
     }
 }
@@ -89,14 +183,20 @@ impl From<request_response::Event<ControlMessage, ControlMessage>> for AgoraBeha
     }
 }
 
+impl From<()> for AgoraBehaviourEvent {
+    fn from(_: ()) -> Self {
+        unreachable!("libp2p_stream::Behaviour emits no events")
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct AudioCodec;
 
 #[async_trait]
 impl Codec for AudioCodec {
     type Protocol = &'static str;
-    type Request = AudioPacket;
-    type Response = AudioPacket;
+    type Request = EncryptedAudioPacket;
+    type Response = EncryptedAudioPacket;
 
     async fn read_request<T>(&mut self, _: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
     where
@@ -116,7 +216,7 @@ impl Codec for AudioCodec {
         let mut data = vec![0u8; len];
         io.read_exact(&mut data).await?;
 
-        AudioPacket::decode(&data)
+        EncryptedAudioPacket::decode(&data)
     }
 
     async fn read_response<T>(
@@ -225,27 +325,162 @@ impl Codec for ControlCodec {
     }
 }
 
-async fn write_frame<T: AsyncWrite + Unpin + Send>(io: &mut T, data: &[u8]) -> io::Result<()> {
+pub(crate) async fn write_frame<T: AsyncWrite + Unpin + Send>(
+    io: &mut T,
+    data: &[u8],
+) -> io::Result<()> {
     io.write_all(&(data.len() as u32).to_be_bytes()).await?;
     io.write_all(data).await?;
     io.flush().await?;
     Ok(())
 }
 
+pub(crate) async fn read_frame<T: AsyncRead + Unpin + Send>(
+    io: &mut T,
+    max_len: usize,
+) -> io::Result<Vec<u8>> {
+    let mut len_bytes = [0u8; 4];
+    io.read_exact(&mut len_bytes).await?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Frame too large",
+        ));
+    }
+
+    let mut data = vec![0u8; len];
+    io.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// How outgoing audio frames are carried to peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioTransport {
+    /// One long-lived `/agora/audio/2.0.0` substream per peer and room.
+    #[default]
+    Stream,
+    /// One `/agora/audio/1.0.0` request per frame.
+    RequestResponse,
+}
+
+/// Limits for serving circuit relay v2 to other peers.
+#[derive(Debug, Clone)]
+pub struct RelayServerConfig {
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    pub reservation_duration: Duration,
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    pub max_circuit_duration: Duration,
+    /// Bytes relayed per circuit before it is closed. Relayed connections
+    /// are meant to last until DCUtR upgrades them, not to carry a call.
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for RelayServerConfig {
+    fn default() -> Self {
+        let defaults = relay::Config::default();
+        Self {
+            max_reservations: defaults.max_reservations,
+            max_reservations_per_peer: defaults.max_reservations_per_peer,
+            reservation_duration: defaults.reservation_duration,
+            max_circuits: defaults.max_circuits,
+            max_circuits_per_peer: defaults.max_circuits_per_peer,
+            max_circuit_duration: defaults.max_circuit_duration,
+            max_circuit_bytes: defaults.max_circuit_bytes,
+        }
+    }
+}
+
+impl From<&RelayServerConfig> for relay::Config {
+    fn from(config: &RelayServerConfig) -> Self {
+        relay::Config {
+            max_reservations: config.max_reservations,
+            max_reservations_per_peer: config.max_reservations_per_peer,
+            reservation_duration: config.reservation_duration,
+            max_circuits: config.max_circuits,
+            max_circuits_per_peer: config.max_circuits_per_peer,
+            max_circuit_duration: config.max_circuit_duration,
+            max_circuit_bytes: config.max_circuit_bytes,
+            ..Default::default()
+        }
+    }
+}
+
 pub struct NetworkNodeConfig {
+    /// Identity whose key authenticates the transport. A throwaway key is
+    /// generated when unset, giving the node a new peer ID on every start.
+    pub identity: Option<Identity>,
     pub listen_addr: Option<String>,
+    pub quic_listen_addr: Option<String>,
+    pub enable_tcp: bool,
+    pub enable_quic: bool,
     pub stun_servers: Vec<String>,
+    /// Dial and listen through circuit relays, which lets DCUtR upgrade
+    /// relayed connections to direct ones.
     pub enable_relay: bool,
+    /// Relays to reserve a `/p2p-circuit` address on at startup, ending in
+    /// the relay's `/p2p/` peer ID. When AutoNAT reports the node as private
+    /// it also reserves on identified relays, up to `MAX_AUTO_RELAYS`.
+    pub relays: Vec<String>,
+    /// Serve circuit relay v2 to other peers. Reservations are only handed
+    /// out once the node has an external address.
+    pub relay_server: Option<RelayServerConfig>,
+    /// Addresses the node is known to be reachable at, confirmed without
+    /// waiting for AutoNAT.
+    pub external_addrs: Vec<String>,
     pub bootstrap_peers: Vec<String>,
+    pub audio: AudioProcessorConfig,
+    pub audio_transport: AudioTransport,
+    /// Peers whose audio and control messages are dropped.
+    pub blocklist: Blocklist,
+    /// Used for ICE sessions with peers that cannot be dialed directly.
+    pub ice: IceConfig,
+    /// Serve DHT records and provider lookups before an external address is
+    /// confirmed. Meant for bootstrap and dedicated nodes with a known
+    /// public address; the node also keeps more records.
+    pub dht_server: bool,
+    /// Keeps DHT records and known peer addresses across restarts, so the
+    /// routing table is filled before bootstrap peers answer. Blocklist
+    /// changes made with `BlockPeer` and `UnblockPeer` are saved here too,
+    /// where `BlocklistStorage` finds them. Nothing is written when unset.
+    pub data_dir: Option<PathBuf>,
+    /// Find Agora peers on the local network over mDNS and ask them about
+    /// joined rooms, so a LAN without internet access needs no bootstrap
+    /// peers.
+    pub enable_mdns: bool,
+    /// Topology and media settings for every room the node joins.
+    pub mixer: MixerConfig,
+    /// Rooms a dedicated node joins at once to mix for on a
+    /// `MixerRequest`; requests are declined when this is 0.
+    pub max_hosted_rooms: usize,
 }
 
 impl Default for NetworkNodeConfig {
     fn default() -> Self {
         Self {
+            identity: None,
             listen_addr: None,
+            quic_listen_addr: None,
+            enable_tcp: true,
+            enable_quic: false,
             stun_servers: vec!["stun:stun.l.google.com:19302".to_string()],
             enable_relay: true,
+            relays: vec![],
+            relay_server: None,
+            external_addrs: vec![],
             bootstrap_peers: vec![],
+            audio: AudioProcessorConfig::default(),
+            audio_transport: AudioTransport::default(),
+            blocklist: Blocklist::new(),
+            ice: IceConfig::default(),
+            dht_server: false,
+            data_dir: None,
+            enable_mdns: false,
+            mixer: MixerConfig::default(),
+            max_hosted_rooms: 0,
         }
     }
 }
@@ -253,17 +488,125 @@ impl Default for NetworkNodeConfig {
 pub struct NetworkNode {
     swarm: Swarm<AgoraBehaviour>,
     local_peer_id: PeerId,
+    identity: Identity,
     known_peers: HashSet<PeerId>,
     nat_traversal: NatTraversal,
     ice_agent: Option<IceAgent>,
+    ice_config: IceConfig,
+    ice_sessions: HashMap<PeerId, IceSession>,
+    /// Nominated pairs libp2p runs over, and per peer the listener on our
+    /// end with the peer's end.
+    ice_paths: IcePaths,
+    ice_listeners: HashMap<PeerId, (ListenerId, SocketAddr)>,
+    ice_tx: mpsc::Sender<IceTaskResult>,
+    ice_rx: Option<mpsc::Receiver<IceTaskResult>>,
+    external_addr_candidates: Vec<Multiaddr>,
+    address_book: AddressBook,
+    /// `/p2p-circuit` listeners by relay, pending or reserved.
+    relay_listeners: HashMap<PeerId, ListenerId>,
+    /// Identified peers that offer circuit relay, with an address of theirs.
+    relay_candidates: HashMap<PeerId, Multiaddr>,
+    nat_private: bool,
+    /// Peers mDNS found on the local network.
+    lan_peers: HashSet<PeerId>,
     listen_addrs: Vec<Multiaddr>,
     room_peers: HashMap<String, HashSet<PeerId>>,
+    /// Peers we sent `JoinRoom` to that have not answered with a join yet.
+    introduced_peers: HashSet<(PeerId, String)>,
+    mixer_config: MixerConfig,
+    room_mixers: HashMap<String, MixerManager>,
+    /// The election each room's mixer holds, as announced by its elector.
+    mixer_elections: HashMap<String, SignedMixerElection>,
+    /// Our load, published to rooms for mixer elections.
+    mixer_stats: ParticipantStats,
+    /// Dedicated nodes preferred as mixers when trusted and in the room.
+    mixer_nodes: Vec<MixerNode>,
+    max_hosted_rooms: usize,
+    /// Rooms we joined on request to mix for, left once they empty.
+    hosted_rooms: HashSet<String>,
+    /// Rooms we entered on request whose requester has not admitted us yet.
+    pending_hosts: HashMap<String, (PeerId, Instant)>,
+    /// Dedicated nodes members asked to mix for each room, preferred there
+    /// on top of `mixer_nodes` and passed on to members we admit later.
+    requested_mixers: HashMap<String, Vec<MixerNode>>,
+    /// `MixerAdmitted` from peers we have not admitted yet, applied once we
+    /// do.
+    pending_mixer_admissions: HashMap<(PeerId, String), Vec<MixerNode>>,
+    /// Nodes we asked to mix for a room that have not answered yet.
+    mixer_requests: HashSet<(PeerId, String)>,
+    /// DHT directories we looked up with `Browse`.
+    browsed_directories: HashSet<String>,
+    group_keys: GroupKeyManager,
+    secure_audio: SecureAudioChannel,
+    key_mismatches: HashMap<(PeerId, String), u64>,
+    room_auth: HashMap<String, JoinAuthenticator>,
+    verified_peers: HashSet<(PeerId, String)>,
+    join_challenges: HashMap<(PeerId, String), ([u8; 32], Instant)>,
+    join_responses: HashMap<(PeerId, String), ([u8; 32], [u8; 32])>,
+    pending_group_keys: HashMap<(PeerId, String), ControlMessage>,
+    moderation: HashMap<String, RoomModeration>,
+    blocklist: Blocklist,
+    blocklist_storage: Option<BlocklistStorage>,
     peer_names: HashMap<PeerId, String>,
+    audio_config: AudioProcessorConfig,
+    audio_processor: AudioProcessor,
+    peer_decoders: HashMap<PeerId, AudioProcessor>,
+    audio_transport: AudioTransport,
+    stream_control: libp2p_stream::Control,
+    incoming_audio: Option<libp2p_stream::IncomingStreams>,
+    audio_senders: HashMap<(PeerId, String), AudioStreamSender>,
+    legacy_audio_peers: HashSet<PeerId>,
+    quic_connections: HashMap<PeerId, usize>,
+    inbound_audio_tx: mpsc::Sender<InboundAudio>,
+    inbound_audio_rx: Option<mpsc::Receiver<InboundAudio>>,
     event_tx: broadcast::Sender<NetworkEvent>,
     command_tx: mpsc::Sender<NetworkCommand>,
     command_rx: Option<mpsc::Receiver<NetworkCommand>>,
 }
 
+/// An attempt to reach a peer we could not dial directly. The agent is moved
+/// into a task while it gathers or checks, and its sockets stay open until
+/// the session ends so the peer's checks can reach us.
+struct IceSession {
+    started: Instant,
+    stage: IceStage,
+    agent: Option<IceAgent>,
+    remote: Option<SignedIceCandidates>,
+    credentials: Option<IceCredentials>,
+    /// Candidates offered so far; each trickled one is added and the whole
+    /// set re-signed, so a peer that fetches late still gets all of them.
+    local: Vec<Candidate>,
+    gathering_complete: bool,
+    trickle: Option<IceTrickle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IceStage {
+    Gathering,
+    AwaitingRemote,
+    Checking,
+    Dialing,
+}
+
+enum IceTaskResult {
+    Gathered {
+        peer_id: PeerId,
+        agent: IceAgent,
+        result: AgoraResult<mpsc::UnboundedReceiver<Candidate>>,
+    },
+    /// A candidate gathered in the background, or `None` once gathering is
+    /// complete.
+    Trickled {
+        peer_id: PeerId,
+        candidate: Option<Candidate>,
+    },
+    Checked {
+        peer_id: PeerId,
+        agent: IceAgent,
+        result: AgoraResult<()>,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub enum NetworkCommand {
     SendAudio {
@@ -280,13 +623,71 @@ pub enum NetworkCommand {
     },
     JoinRoom {
         room_id: String,
+        /// Required from joiners, and proven to them, when set.
+        password: Option<String>,
+        /// Set by the room's creator so it can moderate the room.
+        creator: Option<CreatorProof>,
     },
     LeaveRoom {
         room_id: String,
     },
+    /// Signs `action` as the room creator or a moderator and sends it to
+    /// the room.
+    Moderate {
+        room_id: String,
+        action: ModerationAction,
+    },
+    /// Drops everything but group keys from `peer_id` until unblocked.
+    BlockPeer {
+        peer_id: PeerId,
+        reason: Option<String>,
+    },
+    UnblockPeer {
+        peer_id: PeerId,
+    },
     ConnectToPeer {
         addr: Multiaddr,
     },
+    /// Reserves a `/p2p-circuit` address on the relay at `addr`, which must
+    /// end in the relay's `/p2p/` peer ID.
+    ListenViaRelay {
+        addr: Multiaddr,
+    },
+    /// Reaches `peer_id` through an ICE session instead of a known address.
+    /// Started automatically when a room peer cannot be dialed.
+    ConnectIce {
+        peer_id: PeerId,
+    },
+    /// Our load, published to joined rooms to score us as a mixer candidate.
+    UpdateMixerStats {
+        bandwidth_bps: u64,
+        cpu_usage: f32,
+        memory_usage: f32,
+    },
+    /// Dedicated nodes from node discovery, with the latency measured to
+    /// them, to prefer as mixers when a room needs several.
+    SetMixerNodes {
+        nodes: Vec<MixerNode>,
+    },
+    /// Asks a dedicated node to join a room we are in and mix for it. We
+    /// admit the node ourselves, and in a password-protected room ask the
+    /// other members to as well; it is preferred as mixer once it joins if
+    /// its reputation is high enough.
+    RequestMixer {
+        room_id: String,
+        node: MixerNode,
+    },
+    /// Publishes `value` in the DHT under `directory`, where `Browse` finds
+    /// it. Publish again before `ADVERTISEMENT_TTL` runs out to stay listed.
+    Advertise {
+        directory: String,
+        value: Vec<u8>,
+    },
+    /// Looks up everything advertised under `directory`; each entry arrives
+    /// as `NetworkEvent::AdvertisementFound`.
+    Browse {
+        directory: String,
+    },
     Stop,
 }
 
@@ -327,13 +728,110 @@ pub enum NetworkEvent {
     NatStatusChanged {
         is_public: bool,
     },
+    /// A relay accepted our reservation; peers can now reach us through it.
+    RelayReservationAccepted {
+        relay_peer_id: PeerId,
+    },
+    /// DCUtR replaced a relayed connection with a direct one.
+    DirectConnectionUpgraded {
+        peer_id: PeerId,
+    },
+    /// mDNS found an Agora peer on the local network.
+    LanPeerDiscovered {
+        peer_id: PeerId,
+    },
     BootstrapComplete,
+    /// Emitted once per candidate, as soon as it is gathered.
     IceCandidatesGathered {
         candidates: Vec<String>,
     },
+    IceGatheringComplete,
     IceConnectionStateChanged {
         state: String,
     },
+    /// Our candidates for a session with `peer_id` were gathered and
+    /// published.
+    IceCandidatesSent {
+        peer_id: PeerId,
+        candidates: Vec<String>,
+    },
+    IceCandidatesReceived {
+        peer_id: PeerId,
+        candidates: Vec<String>,
+    },
+    IcePairNominated {
+        peer_id: PeerId,
+        local: SocketAddr,
+        remote: SocketAddr,
+    },
+    /// Dialing the peer's libp2p addresses on the nominated path.
+    IceDialing {
+        peer_id: PeerId,
+        addrs: Vec<Multiaddr>,
+    },
+    IceConnected {
+        peer_id: PeerId,
+        addr: Multiaddr,
+    },
+    IceFailed {
+        peer_id: PeerId,
+        reason: String,
+    },
+    /// The key for a joined room changed, either rotated locally or
+    /// received from the room's key leader.
+    KeyRotated(KeyRotationEvent),
+    /// Audio arrived under a key id this node does not hold, usually because
+    /// a rekey has not reached it yet. Reported once per key id and sender.
+    AudioKeyMismatch {
+        peer_id: PeerId,
+        room_id: String,
+        key_id: u64,
+        current_key_id: Option<u64>,
+    },
+    /// `peer_id` refused our join, or asked for a password we do not have.
+    JoinRejected {
+        room_id: String,
+        peer_id: PeerId,
+        reason: JoinRejectReason,
+    },
+    /// The room agreed on a new mixer, by our own election or by adopting
+    /// another participant's.
+    MixerElected {
+        room_id: String,
+        mixer: PeerId,
+        epoch: u64,
+    },
+    /// A dedicated node agreed to mix for the room and is joining it.
+    MixerAccepted {
+        room_id: String,
+        peer_id: PeerId,
+    },
+    MixerDeclined {
+        room_id: String,
+        peer_id: PeerId,
+        reason: String,
+    },
+    /// We joined a room to mix for it at `requester`'s request.
+    HostingRoom {
+        room_id: String,
+        requester: PeerId,
+    },
+    /// The last participant left a room we mixed for, so we left it too.
+    HostedRoomClosed {
+        room_id: String,
+    },
+    /// An entry `peer_id` published under a directory we browsed.
+    AdvertisementFound {
+        directory: String,
+        peer_id: PeerId,
+        value: Vec<u8>,
+    },
+    /// A verified moderation action took effect in a joined room.
+    ModerationApplied {
+        room_id: String,
+        issuer: PeerId,
+        action: ModerationAction,
+    },
     Error(String),
 }
 
@@ -347,24 +845,66 @@ impl NetworkNode {
     }
 
     pub async fn with_config(config: NetworkNodeConfig) -> AgoraResult<Self> {
-        let local_keypair = libp2p::identity::Keypair::generate_ed25519();
+        let identity = match config.identity {
+            Some(identity) => identity,
+            None => Identity::generate()?,
+        };
+        let local_keypair = identity.keypair();
         let local_peer_id = PeerId::from(local_keypair.public());
 
-        let transport = dns::tokio::Transport::system(tcp::tokio::Transport::new(
-            tcp::Config::new().nodelay(true),
-        ))
-        .map_err(|e| Error::Network(format!("DNS transport error: {}", e)))?
-        .upgrade(libp2p::core::upgrade::Version::V1)
-        .authenticate(
-            noise::Config::new(&local_keypair)
-                .map_err(|e| Error::Network(format!("Noise config error: {}", e)))?,
-        )
-        .multiplex(yamux::Config::default())
-        .timeout(Duration::from_secs(20))
-        .boxed();
+        let (relay_transport, relay_client) = match config.enable_relay {
+            true => {
+                let (transport, behaviour) = relay::client::new(local_peer_id);
+                (Some(transport), Some(behaviour))
+            }
+            false => (None, None),
+        };
+        let ice_paths = IcePaths::default();
+        let transport = build_transport(
+            &local_keypair,
+            config.enable_tcp,
+            config.enable_quic,
+            relay_transport,
+            ice_paths.clone(),
+        )?;
+
+        if let Some(dir) = &config.data_dir {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| Error::Storage(format!("Failed to create data directory: {}", e)))?;
+        }
+        let blocklist_storage = config
+            .data_dir
+            .clone()
+            .map(BlocklistStorage::with_path)
+            .transpose()?;
+        let mut store_config = MemoryStoreConfig::default();
+        if config.dht_server {
+            store_config.max_records = DHT_SERVER_MAX_RECORDS;
+            store_config.max_provided_keys = DHT_SERVER_MAX_RECORDS;
+        }
+        let store = PersistentStore::new(local_peer_id, store_config, config.data_dir.as_deref());
+        let mut kademlia = Kademlia::new(local_peer_id, store);
+        if config.dht_server {
+            kademlia.set_mode(Some(KademliaMode::Server));
+        }
 
-        let store = MemoryStore::new(local_peer_id);
-        let kademlia = Kademlia::new(local_peer_id, store);
+        let address_book = AddressBook::load(config.data_dir.as_deref());
+        for (peer_id, addrs) in address_book.peers() {
+            for addr in addrs {
+                kademlia.add_address(peer_id, addr.clone());
+            }
+        }
+        for addr in &config.bootstrap_peers {
+            let addr: Multiaddr = addr
+                .parse()
+                .map_err(|e| Error::Network(format!("Invalid bootstrap address: {}", e)))?;
+            match addr.iter().last() {
+                Some(Protocol::P2p(peer_id)) => {
+                    kademlia.add_address(&peer_id, addr);
+                }
+                _ => tracing::warn!("Bootstrap address {} has no peer ID, skipping", addr),
+            }
+        }
 
         let identify = identify::Behaviour::new(
             identify::Config::new("agora/0.1.0".to_string(), local_keypair.public())
@@ -393,37 +933,98 @@ impl NetworkNode {
             request_response::Config::default().with_request_timeout(Duration::from_secs(10)),
         );
 
+        let mdns = match config.enable_mdns {
+            true => match mdns::tokio::Behaviour::new(mdns::Config::default(), local_peer_id) {
+                Ok(mdns) => Some(mdns),
+                Err(e) => {
+                    tracing::warn!("mDNS unavailable, LAN discovery disabled: {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+
+        let audio = libp2p_stream::Behaviour::new();
+        let mut stream_control = audio.new_control();
+        let incoming_audio = stream_control
+            .accept(audio_stream_protocol())
+            .map_err(|e| Error::Network(format!("Audio stream protocol error: {}", e)))?;
+
         let behaviour = AgoraBehaviour {
             kademlia,
             identify,
             ping: ping::Behaviour::default(),
             autonat,
             dcutr,
+            relay_client: relay_client.into(),
+            relay: config
+                .relay_server
+                .as_ref()
+                .map(|relay| relay::Behaviour::new(local_peer_id, relay.into()))
+                .into(),
+            mdns: mdns.into(),
             audio_stream,
+            audio,
             control,
         };
 
-        let swarm_config = libp2p::swarm::Config::with_tokio_executor();
+        let swarm_config = libp2p::swarm::Config::with_tokio_executor()
+            .with_idle_connection_timeout(Duration::from_secs(60));
         let mut swarm = Swarm::new(transport, behaviour, local_peer_id, swarm_config);
 
-        let addr = config
-            .listen_addr
-            .as_deref()
-            .unwrap_or("/ip4/0.0.0.0/tcp/0");
-        swarm
-            .listen_on(
+        let mut listen_on = Vec::new();
+        if config.enable_tcp {
+            listen_on.push(
+                config
+                    .listen_addr
+                    .as_deref()
+                    .unwrap_or("/ip4/0.0.0.0/tcp/0"),
+            );
+        }
+        if config.enable_quic {
+            listen_on.push(
+                config
+                    .quic_listen_addr
+                    .as_deref()
+                    .unwrap_or("/ip4/0.0.0.0/udp/0/quic-v1"),
+            );
+        }
+        for addr in listen_on {
+            swarm
+                .listen_on(
+                    addr.parse()
+                        .map_err(|e| Error::Network(format!("Invalid address: {}", e)))?,
+                )
+                .map_err(|e| Error::Network(format!("Listen error: {}", e)))?;
+        }
+
+        for addr in &config.external_addrs {
+            swarm.add_external_address(
                 addr.parse()
-                    .map_err(|e| Error::Network(format!("Invalid address: {}", e)))?,
-            )
-            .map_err(|e| Error::Network(format!("Listen error: {}", e)))?;
+                    .map_err(|e| Error::Network(format!("Invalid external address: {}", e)))?,
+            );
+        }
+
+        let mut relay_listeners = HashMap::new();
+        for relay in &config.relays {
+            let addr: Multiaddr = relay
+                .parse()
+                .map_err(|e| Error::Network(format!("Invalid relay address: {}", e)))?;
+            let (relay_peer_id, listener_id) = listen_via_relay(&mut swarm, addr)?;
+            relay_listeners.insert(relay_peer_id, listener_id);
+        }
 
         let stun_config = StunConfig {
             servers: config.stun_servers.clone(),
             ..Default::default()
         };
 
+        let audio_processor = AudioProcessor::new(config.audio.clone())?;
+
         let (event_tx, _) = broadcast::channel(256);
         let (command_tx, command_rx) = mpsc::channel(256);
+        let (inbound_audio_tx, inbound_audio_rx) = mpsc::channel(256);
+        let (ice_tx, ice_rx) = mpsc::channel(16);
 
         Ok(Self {
             swarm,
@@ -431,9 +1032,57 @@ impl NetworkNode {
             known_peers: HashSet::new(),
             nat_traversal: NatTraversal::new(Some(stun_config)),
             ice_agent: None,
+            ice_config: config.ice,
+            ice_sessions: HashMap::new(),
+            ice_paths,
+            ice_listeners: HashMap::new(),
+            ice_tx,
+            ice_rx: Some(ice_rx),
+            external_addr_candidates: Vec::new(),
+            relay_listeners,
+            relay_candidates: HashMap::new(),
+            address_book,
+            nat_private: false,
+            lan_peers: HashSet::new(),
             listen_addrs: vec![],
             room_peers: HashMap::new(),
+            introduced_peers: HashSet::new(),
+            mixer_config: config.mixer,
+            room_mixers: HashMap::new(),
+            mixer_elections: HashMap::new(),
+            mixer_stats: ParticipantStats::new(),
+            mixer_nodes: Vec::new(),
+            max_hosted_rooms: config.max_hosted_rooms,
+            hosted_rooms: HashSet::new(),
+            pending_hosts: HashMap::new(),
+            requested_mixers: HashMap::new(),
+            pending_mixer_admissions: HashMap::new(),
+            mixer_requests: HashSet::new(),
+            browsed_directories: HashSet::new(),
+            group_keys: GroupKeyManager::new(identity.clone()),
+            identity,
+            secure_audio: SecureAudioChannel::new(),
+            key_mismatches: HashMap::new(),
+            room_auth: HashMap::new(),
+            verified_peers: HashSet::new(),
+            join_challenges: HashMap::new(),
+            join_responses: HashMap::new(),
+            pending_group_keys: HashMap::new(),
+            moderation: HashMap::new(),
+            blocklist: config.blocklist,
+            blocklist_storage,
             peer_names: HashMap::new(),
+            audio_config: config.audio,
+            audio_processor,
+            peer_decoders: HashMap::new(),
+            audio_transport: config.audio_transport,
+            stream_control,
+            incoming_audio: Some(incoming_audio),
+            audio_senders: HashMap::new(),
+            legacy_audio_peers: HashSet::new(),
+            quic_connections: HashMap::new(),
+            inbound_audio_tx,
+            inbound_audio_rx: Some(inbound_audio_rx),
             event_tx,
             command_tx,
             command_rx: Some(command_rx),
@@ -456,6 +1105,19 @@ impl NetworkNode {
         &self.known_peers
     }
 
+    /// Current key id and key for a joined room.
+    pub fn room_key(&self, room_id: &str) -> Option<(u64, SessionKey)> {
+        self.group_keys.current_key(room_id)
+    }
+
+    pub fn blocklist(&self) -> &Blocklist {
+        &self.blocklist
+    }
+
+    pub fn room_members(&self, room_id: &str) -> Vec<PeerId> {
+        self.group_keys.members(room_id)
+    }
+
     pub fn subscribe_events(&self) -> broadcast::Receiver<NetworkEvent> {
         self.event_tx.subscribe()
     }
@@ -468,11 +1130,12 @@ impl NetworkNode {
         self.nat_traversal.detect_nat_type().await
     }
 
+    /// Returns the host candidates right away; the rest are reported through
+    /// `IceCandidatesGathered` events followed by `IceGatheringComplete`.
     pub async fn gather_ice_candidates(&mut self) -> AgoraResult<Vec<String>> {
-        let ice_config = IceConfig::default();
-        let mut agent = IceAgent::new(Some(ice_config));
+        let mut agent = IceAgent::new(Some(self.ice_config.clone()));
 
-        agent.gather_candidates().await?;
+        let mut trickled = agent.start_gathering().await?;
 
         let candidates: Vec<String> = agent
             .local_candidates()
@@ -480,16 +1143,33 @@ impl NetworkNode {
             .map(|c| c.to_sdp())
             .collect();
 
-        let _ = self.event_tx.send(NetworkEvent::IceCandidatesGathered {
-            candidates: candidates.clone(),
+        for candidate in &candidates {
+            let _ = self.event_tx.send(NetworkEvent::IceCandidatesGathered {
+                candidates: vec![candidate.clone()],
+            });
+        }
+
+        let event_tx = self.event_tx.clone();
+        tokio::spawn(async move {
+            while let Some(candidate) = trickled.recv().await {
+                let _ = event_tx.send(NetworkEvent::IceCandidatesGathered {
+                    candidates: vec![candidate.to_sdp()],
+                });
+            }
+            let _ = event_tx.send(NetworkEvent::IceGatheringComplete);
         });
 
         self.ice_agent = Some(agent);
 
-        tracing::info!("Gathered {} ICE candidates", candidates.len());
+        tracing::info!("Gathered {} host ICE candidates", candidates.len());
         Ok(candidates)
     }
 
+    /// Handle for adding remote candidates while checks are running.
+    pub fn ice_trickle(&self) -> Option<IceTrickle> {
+        self.ice_agent.as_ref().map(IceAgent::trickle)
+    }
+
     pub fn ice_candidates(&self) -> Option<Vec<&Candidate>> {
         self.ice_agent
             .as_ref()
@@ -508,7 +1188,16 @@ impl NetworkNode {
         })
     }
 
+    /// An empty candidate signals the end of the remote candidates, as it
+    /// does in browsers.
     pub fn add_remote_ice_candidate(&mut self, candidate_sdp: &str) -> AgoraResult<()> {
+        if candidate_sdp.trim().is_empty() {
+            if let Some(ref mut agent) = self.ice_agent {
+                agent.end_of_remote_candidates();
+            }
+            return Ok(());
+        }
+
         let candidate = crate::ice::parse_candidate_from_sdp(candidate_sdp)
             .ok_or_else(|| Error::Network("Invalid ICE candidate SDP".to_string()))?;
 
@@ -521,6 +1210,16 @@ impl NetworkNode {
         Ok(())
     }
 
+    pub fn ice_credentials(&self) -> Option<IceCredentials> {
+        self.ice_agent.as_ref().map(IceAgent::local_credentials)
+    }
+
+    pub fn set_remote_ice_credentials(&mut self, credentials: IceCredentials) {
+        if let Some(ref mut agent) = self.ice_agent {
+            agent.set_remote_credentials(credentials);
+        }
+    }
+
     pub async fn perform_ice_connectivity_checks(&mut self) -> AgoraResult<()> {
         if let Some(ref mut agent) = self.ice_agent {
             let state_before = agent.state();
@@ -597,6 +1296,24 @@ impl NetworkNode {
         tracing::debug!("Looking for providers of room: {}", room_id);
     }
 
+    fn save_dht(&mut self) {
+        self.address_book.prune(PEER_MAX_AGE);
+        if let Err(e) = self.address_book.save() {
+            tracing::warn!("Failed to save peer addresses: {}", e);
+        }
+        if let Err(e) = self.swarm.behaviour_mut().kademlia.store_mut().save() {
+            tracing::warn!("Failed to save DHT records: {}", e);
+        }
+    }
+
+    fn save_blocklist(&self) {
+        if let Some(storage) = &self.blocklist_storage {
+            if let Err(e) = storage.save(&self.blocklist) {
+                tracing::warn!("Failed to save blocklist: {}", e);
+            }
+        }
+    }
+
     pub fn bootstrap(&mut self) -> AgoraResult<()> {
         self.swarm
             .behaviour_mut()
@@ -615,12 +1332,47 @@ impl NetworkNode {
             }
         };
 
+        let mut inbound_audio_rx = match self.inbound_audio_rx.take() {
+            Some(rx) => rx,
+            None => {
+                tracing::error!("Inbound audio receiver already taken - run() called twice?");
+                return;
+            }
+        };
+
+        let mut ice_rx = match self.ice_rx.take() {
+            Some(rx) => rx,
+            None => {
+                tracing::error!("ICE receiver already taken - run() called twice?");
+                return;
+            }
+        };
+
+        if let Some(incoming) = self.incoming_audio.take() {
+            tokio::spawn(accept_audio_streams(
+                incoming,
+                self.inbound_audio_tx.clone(),
+            ));
+        }
+
+        // Peers remembered from the last run, or bootstrap peers.
+        if let Err(e) = self.bootstrap() {
+            tracing::debug!("Not bootstrapping: {}", e);
+        }
+
+        let mut rotation_tick = tokio::time::interval(GROUP_KEY_CHECK_INTERVAL);
+        let mut ice_tick = tokio::time::interval(ICE_POLL_INTERVAL);
+        let mut ice_inbox_tick = tokio::time::interval(ICE_INBOX_INTERVAL);
+        let mut dht_save_tick = tokio::time::interval(DHT_SAVE_INTERVAL);
+        let mut mixer_tick = tokio::time::interval(MIXER_STATS_INTERVAL);
+
         loop {
             tokio::select! {
                 Some(cmd) = command_rx.recv() => {
                     match cmd {
                         NetworkCommand::Stop => {
                             tracing::info!("Network node stopping");
+                            self.save_dht();
                             break;
                         }
                         NetworkCommand::SendAudio { peer_id, packet } => {
@@ -632,32 +1384,130 @@ impl NetworkNode {
                         NetworkCommand::SendControl { peer_id, message } => {
                             self.send_control_message(peer_id, message).await;
                         }
-                        NetworkCommand::JoinRoom { room_id } => {
-                            if let Err(e) = self.join_room(&room_id).await {
+                        NetworkCommand::JoinRoom { room_id, password, creator } => {
+                            if let Err(e) = self
+                                .join_room(&room_id, password.as_deref(), creator)
+                                .await
+                            {
                                 tracing::error!("Failed to join room: {}", e);
                             }
                         }
                         NetworkCommand::LeaveRoom { room_id } => {
-                            self.leave_room(&room_id);
+                            self.leave_room(&room_id).await;
+                        }
+                        NetworkCommand::Moderate { room_id, action } => {
+                            if let Err(e) = self.moderate(&room_id, action).await {
+                                tracing::error!("Failed to moderate room: {}", e);
+                            }
+                        }
+                        NetworkCommand::BlockPeer { peer_id, reason } => {
+                            if self.blocklist.block(&peer_id, reason) {
+                                tracing::info!("Blocked peer {}", peer_id);
+                                self.save_blocklist();
+                            }
+                        }
+                        NetworkCommand::UnblockPeer { peer_id } => {
+                            if self.blocklist.unblock(&peer_id) {
+                                tracing::info!("Unblocked peer {}", peer_id);
+                                self.save_blocklist();
+                            }
                         }
                         NetworkCommand::ConnectToPeer { addr } => {
                             if let Err(e) = self.dial(addr).await {
                                 tracing::error!("Failed to connect: {}", e);
                             }
                         }
+                        NetworkCommand::ListenViaRelay { addr } => {
+                            if let Err(e) = self.listen_via_relay(addr) {
+                                tracing::error!("Failed to listen via relay: {}", e);
+                            }
+                        }
+                        NetworkCommand::ConnectIce { peer_id } => {
+                            self.start_ice_session(peer_id, None);
+                        }
+                        NetworkCommand::UpdateMixerStats { bandwidth_bps, cpu_usage, memory_usage } => {
+                            self.mixer_stats.bandwidth_bps = bandwidth_bps;
+                            self.mixer_stats.cpu_usage_percent = cpu_usage;
+                            self.mixer_stats.memory_usage_percent = memory_usage;
+                            for mixer in self.room_mixers.values_mut() {
+                                mixer.update_local_stats(bandwidth_bps, cpu_usage, memory_usage);
+                            }
+                        }
+                        NetworkCommand::SetMixerNodes { nodes } => {
+                            self.set_mixer_nodes(nodes);
+                        }
+                        NetworkCommand::RequestMixer { room_id, node } => {
+                            self.request_mixer(&room_id, node).await;
+                        }
+                        NetworkCommand::Advertise { directory, value } => {
+                            self.advertise(&directory, value);
+                        }
+                        NetworkCommand::Browse { directory } => {
+                            self.browse(directory);
+                        }
                     }
                 }
 
+                Some(inbound) = inbound_audio_rx.recv() => {
+                    self.handle_audio_packet(inbound.peer_id, Some(&inbound.room_id), inbound.packet);
+                }
+
+                Some(result) = ice_rx.recv() => {
+                    self.handle_ice_task_result(result).await;
+                }
+
                 event = self.swarm.select_next_some() => {
                     self.handle_swarm_event(event).await;
                 }
+
+                _ = rotation_tick.tick() => {
+                    self.join_challenges
+                        .retain(|_, (_, issued)| issued.elapsed() < JOIN_CHALLENGE_TIMEOUT);
+                    self.expire_pending_hosts().await;
+                    for rekey in self.group_keys.check_rotation() {
+                        self.distribute_rekey(rekey.map(Some)).await;
+                    }
+                }
+
+                _ = ice_tick.tick() => {
+                    self.poll_ice_sessions();
+                }
+
+                _ = ice_inbox_tick.tick() => {
+                    self.poll_ice_inbox();
+                }
+
+                _ = dht_save_tick.tick() => {
+                    self.save_dht();
+                }
+
+                _ = mixer_tick.tick() => {
+                    let rooms: Vec<String> = self.room_mixers.keys().cloned().collect();
+                    for room_id in rooms {
+                        self.publish_mixer_stats(&room_id).await;
+                        if let Some(mixer) = self.room_mixers.get_mut(&room_id) {
+                            mixer.check_rotation();
+                        }
+                        self.hold_mixer_election(&room_id).await;
+                    }
+                }
             }
         }
     }
 
     async fn handle_swarm_event(&mut self, event: SwarmEvent<AgoraBehaviourEvent>) {
         match event {
-            SwarmEvent::NewListenAddr { address, .. } => {
+            SwarmEvent::NewListenAddr {
+                listener_id,
+                address,
+            } => {
+                if self
+                    .ice_listeners
+                    .values()
+                    .any(|(id, _)| *id == listener_id)
+                {
+                    return;
+                }
                 self.listen_addrs.push(address.clone());
                 tracing::info!("Listening on {}", address);
                 let _ = self.event_tx.send(NetworkEvent::Listening(address));
@@ -667,15 +1517,57 @@ impl NetworkNode {
                 peer_id, endpoint, ..
             } => {
                 self.known_peers.insert(peer_id);
+                if is_quic_addr(endpoint.get_remote_address()) {
+                    *self.quic_connections.entry(peer_id).or_default() += 1;
+                }
+                if endpoint.is_dialer()
+                    && !endpoint.is_relayed()
+                    && !self.ice_paths.serves(endpoint.get_remote_address())
+                {
+                    self.address_book
+                        .record(peer_id, [endpoint.get_remote_address().clone()]);
+                }
                 tracing::info!("Connected to {}", peer_id);
                 let _ = self.event_tx.send(NetworkEvent::PeerConnected {
                     peer_id,
                     addr: endpoint.get_remote_address().clone(),
                 });
+                self.finish_ice_session(peer_id, endpoint.get_remote_address().clone())
+                    .await;
             }
 
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
                 self.known_peers.remove(&peer_id);
+                if is_quic_addr(endpoint.get_remote_address()) {
+                    if let Entry::Occupied(mut entry) = self.quic_connections.entry(peer_id) {
+                        *entry.get_mut() -= 1;
+                        if *entry.get() == 0 {
+                            entry.remove();
+                        }
+                    }
+                }
+                if num_established == 0 {
+                    self.close_ice_path(peer_id);
+                    self.peer_decoders.remove(&peer_id);
+                    self.audio_senders.retain(|(peer, _), _| *peer != peer_id);
+                    self.legacy_audio_peers.remove(&peer_id);
+                    self.introduced_peers.retain(|(peer, _)| *peer != peer_id);
+
+                    let rooms: Vec<String> = self
+                        .room_peers
+                        .iter()
+                        .filter(|(_, peers)| peers.contains(&peer_id))
+                        .map(|(room_id, _)| room_id.clone())
+                        .collect();
+                    for room_id in rooms {
+                        self.remove_room_peer(&room_id, peer_id).await;
+                    }
+                }
                 tracing::info!("Disconnected from {}", peer_id);
                 let _ = self
                     .event_tx
@@ -684,6 +1576,28 @@ impl NetworkNode {
 
             SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
                 tracing::warn!("Failed to connect to {:?}: {}", peer_id, error);
+                if let Some(peer_id) = peer_id {
+                    let dialing = self
+                        .ice_sessions
+                        .get(&peer_id)
+                        .is_some_and(|s| s.stage == IceStage::Dialing);
+                    if dialing {
+                        self.fail_ice_session(peer_id, format!("Dial failed: {}", error));
+                    }
+                }
+            }
+
+            SwarmEvent::NewExternalAddrCandidate { address }
+                if !self.external_addr_candidates.contains(&address) =>
+            {
+                if self.external_addr_candidates.len() == MAX_EXTERNAL_ADDR_CANDIDATES {
+                    self.external_addr_candidates.remove(0);
+                }
+                self.external_addr_candidates.push(address);
+            }
+
+            SwarmEvent::ListenerClosed { listener_id, .. } => {
+                self.relay_listeners.retain(|_, id| *id != listener_id);
             }
 
             SwarmEvent::Behaviour(event) => {
@@ -700,6 +1614,24 @@ impl NetworkNode {
                 result,
                 ..
             }) => match result {
+                QueryResult::GetProviders(Ok(GetProvidersOk::FoundProviders {
+                    key,
+                    providers,
+                    ..
+                })) if key.as_ref().starts_with(ICE_INBOX_PREFIX.as_bytes()) => {
+                    self.fetch_ice_offers(providers);
+                }
+                QueryResult::GetProviders(Ok(GetProvidersOk::FoundProviders {
+                    key,
+                    providers,
+                    ..
+                })) if self
+                    .browsed_directories
+                    .contains(String::from_utf8_lossy(key.as_ref()).as_ref()) =>
+                {
+                    let directory = String::from_utf8_lossy(key.as_ref()).to_string();
+                    self.fetch_advertisements(&directory, providers);
+                }
                 QueryResult::GetProviders(Ok(GetProvidersOk::FoundProviders {
                     key,
                     providers,
@@ -708,6 +1640,7 @@ impl NetworkNode {
                     let room_id = String::from_utf8_lossy(key.as_ref()).to_string();
                     let providers: Vec<PeerId> = providers.into_iter().collect();
                     tracing::info!("Found {} providers for room {}", providers.len(), room_id);
+                    self.announce_to(&room_id, providers.iter().copied()).await;
                     let _ = self
                         .event_tx
                         .send(NetworkEvent::ProvidersFound { room_id, providers });
@@ -715,6 +1648,20 @@ impl NetworkNode {
                 QueryResult::StartProviding(Ok(_)) => {
                     tracing::debug!("Successfully started providing");
                 }
+                QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(PeerRecord {
+                    record, ..
+                }))) if record
+                    .key
+                    .as_ref()
+                    .starts_with(ICE_RECORD_PREFIX.as_bytes()) =>
+                {
+                    self.handle_ice_record(record).await;
+                }
+                QueryResult::GetRecord(Ok(GetRecordOk::FoundRecord(PeerRecord {
+                    record, ..
+                }))) => {
+                    self.handle_advertisement(record);
+                }
                 QueryResult::Bootstrap(Ok(_)) => {
                     tracing::info!("Bootstrap complete");
                     let _ = self.event_tx.send(NetworkEvent::BootstrapComplete);
@@ -734,6 +1681,21 @@ impl NetworkNode {
                         .kademlia
                         .add_address(&peer_id, addr.clone());
                 }
+                let direct_addrs: Vec<Multiaddr> = info
+                    .listen_addrs
+                    .iter()
+                    .filter(|addr| !addr.iter().any(|p| p == Protocol::P2pCircuit))
+                    .cloned()
+                    .collect();
+                self.address_book
+                    .record(peer_id, direct_addrs.iter().cloned());
+                if let (true, Some(addr)) = (
+                    info.protocols.contains(&relay::HOP_PROTOCOL_NAME),
+                    direct_addrs.first(),
+                ) {
+                    self.relay_candidates.insert(peer_id, addr.clone());
+                    self.reserve_auto_relays();
+                }
                 let _ = self.event_tx.send(NetworkEvent::PeerIdentified {
                     peer_id,
                     listen_addrs: info.listen_addrs,
@@ -745,10 +1707,7 @@ impl NetworkNode {
                 message: request_response::Message::Request { request, .. },
                 ..
             }) => {
-                let _ = self.event_tx.send(NetworkEvent::AudioReceived {
-                    peer_id: peer,
-                    packet: request,
-                });
+                self.handle_audio_packet(peer, None, request);
             }
 
             AgoraBehaviourEvent::Control(request_response::Event::Message {
@@ -756,6 +1715,14 @@ impl NetworkNode {
                 message: request_response::Message::Request { request, .. },
                 ..
             }) => {
+                // Group keys are still accepted so a blocked key leader
+                // cannot cut us off from the rest of the room.
+                if self.blocklist.is_blocked(&peer)
+                    && !matches!(request.message_type, ControlMessageType::GroupKey { .. })
+                {
+                    tracing::trace!("Dropping control message from blocked peer {}", peer);
+                    return;
+                }
                 self.handle_control_message(peer, &request).await;
                 let _ = self.event_tx.send(NetworkEvent::ControlReceived {
                     peer_id: peer,
@@ -763,12 +1730,26 @@ impl NetworkNode {
                 });
             }
 
+            // A room peer we found but cannot dial, most likely behind a NAT;
+            // try to reach it through ICE instead.
+            AgoraBehaviourEvent::Control(request_response::Event::OutboundFailure {
+                peer,
+                error: request_response::OutboundFailure::DialFailure,
+                ..
+            }) if self.room_peers.values().any(|peers| peers.contains(&peer))
+                || self.introduced_peers.iter().any(|(p, _)| *p == peer) =>
+            {
+                self.start_ice_session(peer, None);
+            }
+
             AgoraBehaviourEvent::Autonat(autonat::Event::StatusChanged { new, .. }) => {
                 let is_public = matches!(new, autonat::NatStatus::Public(_));
                 tracing::info!(
                     "NAT status: {}",
                     if is_public { "Public" } else { "Private" }
                 );
+                self.nat_private = matches!(new, autonat::NatStatus::Private);
+                self.reserve_auto_relays();
                 let _ = self
                     .event_tx
                     .send(NetworkEvent::NatStatusChanged { is_public });
@@ -783,8 +1764,73 @@ impl NetworkNode {
                 }
             },
 
-            AgoraBehaviourEvent::Dcutr(event) => {
-                tracing::debug!("DCUtR event: {:?}", event);
+            AgoraBehaviourEvent::Dcutr(dcutr::Event {
+                remote_peer_id,
+                result,
+            }) => match result {
+                Ok(_) => {
+                    tracing::info!("Upgraded relayed connection to {}", remote_peer_id);
+                    let _ = self.event_tx.send(NetworkEvent::DirectConnectionUpgraded {
+                        peer_id: remote_peer_id,
+                    });
+                }
+                Err(e) => {
+                    tracing::debug!("Hole punch to {} failed: {}", remote_peer_id, e);
+                }
+            },
+
+            AgoraBehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal: false,
+                ..
+            }) => {
+                tracing::info!("Reserved a relayed address on {}", relay_peer_id);
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::RelayReservationAccepted { relay_peer_id });
+            }
+
+            AgoraBehaviourEvent::RelayClient(event) => {
+                tracing::debug!("Relay client event: {:?}", event);
+            }
+
+            AgoraBehaviourEvent::Relay(event) => {
+                tracing::debug!("Relay event: {:?}", event);
+            }
+
+            AgoraBehaviourEvent::Mdns(mdns::Event::Discovered(found)) => {
+                let mut discovered = Vec::new();
+                for (peer_id, addr) in found {
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, addr);
+                    if self.lan_peers.insert(peer_id) {
+                        discovered.push(peer_id);
+                    }
+                }
+                let rooms: Vec<String> = self.group_keys.rooms().map(str::to_string).collect();
+                for peer_id in discovered {
+                    tracing::info!("Found {} on the local network", peer_id);
+                    let _ = self
+                        .event_tx
+                        .send(NetworkEvent::LanPeerDiscovered { peer_id });
+                    for room_id in &rooms {
+                        self.find_room_on_lan(room_id, peer_id).await;
+                    }
+                }
+            }
+
+            AgoraBehaviourEvent::Mdns(mdns::Event::Expired(expired)) => {
+                let Some(mdns) = self.swarm.behaviour().mdns.as_ref() else {
+                    return;
+                };
+                let still_present: HashSet<PeerId> = mdns.discovered_nodes().copied().collect();
+                for (peer_id, _) in expired {
+                    if !still_present.contains(&peer_id) && self.lan_peers.remove(&peer_id) {
+                        tracing::debug!("{} left the local network", peer_id);
+                    }
+                }
             }
 
             _ => {}
@@ -794,31 +1840,102 @@ impl NetworkNode {
     async fn handle_control_message(&mut self, peer_id: PeerId, message: &ControlMessage) {
         match &message.message_type {
             ControlMessageType::JoinRoom { room_id } => {
-                self.room_peers
-                    .entry(room_id.clone())
-                    .or_default()
-                    .insert(peer_id);
                 if let Some(name) = &message.display_name {
                     self.peer_names.insert(peer_id, name.clone());
                 }
-                tracing::info!("Peer {} joined room {}", peer_id, room_id);
-                let _ = self.event_tx.send(NetworkEvent::RoomJoined {
-                    room_id: room_id.clone(),
-                    peer_id,
-                });
+                if self.is_banned(room_id, &peer_id) {
+                    self.reject_join(room_id, peer_id, JoinRejectReason::Banned)
+                        .await;
+                    return;
+                }
+                if !self.is_verified(room_id, peer_id) {
+                    self.send_join_challenge(room_id, peer_id).await;
+                    return;
+                }
+                self.add_room_peer(room_id, peer_id);
+
+                if self.group_keys.has_room(room_id) {
+                    self.send_participant_list(room_id, peer_id).await;
+                    self.send_moderation_state(room_id, peer_id).await;
+                    self.send_requested_mixers(room_id, peer_id).await;
+                    if let Some(signed) = self.current_mixer_election(room_id) {
+                        self.send_mixer_election(peer_id, signed).await;
+                    }
+                    self.admit_member(room_id, peer_id).await;
+                }
             }
 
             ControlMessageType::LeaveRoom { room_id } => {
-                if let Some(peers) = self.room_peers.get_mut(room_id) {
-                    peers.remove(&peer_id);
+                self.remove_room_peer(room_id, peer_id).await;
+            }
+
+            ControlMessageType::ParticipantList { participants } => {
+                let Some(room_id) = message.room_id.as_deref() else {
+                    return;
+                };
+                if !self.group_keys.has_room(room_id) || !self.is_verified(room_id, peer_id) {
+                    return;
                 }
-                tracing::info!("Peer {} left room {}", peer_id, room_id);
-                let _ = self.event_tx.send(NetworkEvent::RoomLeft {
+                self.add_participants(room_id, peer_id, participants).await;
+            }
+
+            ControlMessageType::GroupKey { room_id, .. } => {
+                if !self.is_verified(room_id, peer_id)
+                    || !self.group_keys.is_verified(room_id, &peer_id)
+                {
+                    // Keys can overtake the JoinAccepted or ParticipantList
+                    // that admits their sender; hold the latest until then.
+                    self.pending_group_keys
+                        .insert((peer_id, room_id.clone()), message.clone());
+                    return;
+                }
+                self.install_group_key(peer_id, message);
+            }
+
+            ControlMessageType::JoinChallenge { room_id, nonce } => {
+                self.answer_join_challenge(room_id, peer_id, nonce).await;
+            }
+
+            ControlMessageType::JoinResponse {
+                room_id,
+                nonce,
+                proof,
+            } => {
+                self.check_join_response(room_id, peer_id, nonce, proof)
+                    .await;
+            }
+
+            ControlMessageType::JoinAccepted {
+                room_id,
+                proof,
+                participants,
+            } => {
+                self.check_join_accepted(room_id, peer_id, proof, participants)
+                    .await;
+            }
+
+            ControlMessageType::JoinRejected { room_id, reason } => {
+                tracing::warn!(
+                    "Peer {} rejected our join of {}: {}",
+                    peer_id,
+                    room_id,
+                    reason
+                );
+                let _ = self.event_tx.send(NetworkEvent::JoinRejected {
                     room_id: room_id.clone(),
                     peer_id,
+                    reason: *reason,
                 });
             }
 
+            ControlMessageType::Moderation(signed) => {
+                self.apply_moderation(signed).await;
+            }
+
+            ControlMessageType::IceCandidates(signed) => {
+                self.receive_ice_candidates(peer_id, signed.clone());
+            }
+
             ControlMessageType::UpdateInfo { display_name } => {
                 self.peer_names.insert(peer_id, display_name.clone());
             }
@@ -827,11 +1944,391 @@ impl NetworkNode {
                 tracing::info!("Peer {} muted: {}", peer_id, is_muted);
             }
 
+            ControlMessageType::FindRoom { room_id } => {
+                self.announce_to(room_id, [peer_id]).await;
+            }
+
+            ControlMessageType::MixerStats(signed) => {
+                self.receive_mixer_stats(peer_id, signed);
+            }
+
+            ControlMessageType::MixerElected(signed) => {
+                self.receive_mixer_election(peer_id, signed).await;
+            }
+
+            ControlMessageType::MixerRequest { room_id } => {
+                self.host_room(peer_id, room_id).await;
+            }
+
+            ControlMessageType::MixerAdmitted { room_id, node } => {
+                if !self.group_keys.has_room(room_id) {
+                    return;
+                }
+                if self.is_verified(room_id, peer_id)
+                    && self.group_keys.is_verified(room_id, &peer_id)
+                {
+                    tracing::info!(
+                        "{} admitted mixer {} to room {}",
+                        peer_id,
+                        node.peer_id,
+                        room_id
+                    );
+                    self.add_requested_mixer(room_id, node.clone());
+                    return;
+                }
+                let pending = self
+                    .pending_mixer_admissions
+                    .entry((peer_id, room_id.clone()))
+                    .or_default();
+                if pending.len() < MAX_REQUESTED_MIXERS
+                    && !pending.iter().any(|n| n.peer_id == node.peer_id)
+                {
+                    pending.push(node.clone());
+                }
+            }
+
+            ControlMessageType::MixerAccepted { room_id } => {
+                if !self.mixer_requests.remove(&(peer_id, room_id.clone())) {
+                    tracing::debug!("Ignoring unrequested mixer reply from {}", peer_id);
+                    return;
+                }
+                tracing::info!("{} will mix for room {}", peer_id, room_id);
+                let _ = self.event_tx.send(NetworkEvent::MixerAccepted {
+                    room_id: room_id.clone(),
+                    peer_id,
+                });
+            }
+
+            ControlMessageType::MixerDeclined { room_id, reason } => {
+                if !self.mixer_requests.remove(&(peer_id, room_id.clone())) {
+                    tracing::debug!("Ignoring unrequested mixer reply from {}", peer_id);
+                    return;
+                }
+                tracing::warn!(
+                    "{} declined to mix for room {}: {}",
+                    peer_id,
+                    room_id,
+                    reason
+                );
+                let peer = peer_id.to_string();
+                if let Some(nodes) = self.requested_mixers.get_mut(room_id) {
+                    nodes.retain(|n| n.peer_id != peer);
+                }
+                self.update_room_mixer_nodes(room_id);
+                let _ = self.event_tx.send(NetworkEvent::MixerDeclined {
+                    room_id: room_id.clone(),
+                    peer_id,
+                    reason: reason.clone(),
+                });
+            }
+
             _ => {}
         }
     }
 
+    /// Decrypts a frame from `peer_id`. Frames from request-response carry no
+    /// room, so every joined room shared with the peer is tried.
+    fn handle_audio_packet(
+        &mut self,
+        peer_id: PeerId,
+        room_id: Option<&str>,
+        packet: EncryptedAudioPacket,
+    ) {
+        let relayed_from_blocked = packet
+            .peer_id
+            .parse()
+            .is_ok_and(|sender| self.blocklist.is_blocked(&sender));
+        if self.blocklist.is_blocked(&peer_id) || relayed_from_blocked {
+            tracing::trace!("Dropping audio frame {} from blocked peer", packet.sequence);
+            return;
+        }
+
+        let rooms: Vec<String> = match room_id {
+            Some(room_id) => vec![room_id.to_string()],
+            None => self
+                .room_peers
+                .iter()
+                .filter(|(room, peers)| {
+                    peers.contains(&peer_id) && self.secure_audio.has_room(room)
+                })
+                .map(|(room, _)| room.clone())
+                .collect(),
+        };
+
+        let mut last_error = None;
+        for room_id in &rooms {
+            match self.decrypt_audio_packet(peer_id, room_id, &packet) {
+                Ok(decrypted) => {
+                    self.key_mismatches.remove(&(peer_id, room_id.clone()));
+                    self.forward_audio(room_id, peer_id, &packet);
+                    self.decode_audio_packet(decrypted);
+                    return;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error {
+            Some(e) => tracing::debug!(
+                "Dropping audio frame {} from {}: {}",
+                packet.sequence,
+                peer_id,
+                e
+            ),
+            None => tracing::debug!(
+                "Dropping audio frame {} from {}: no shared room key",
+                packet.sequence,
+                peer_id
+            ),
+        }
+    }
+
+    fn decrypt_audio_packet(
+        &mut self,
+        peer_id: PeerId,
+        room_id: &str,
+        packet: &EncryptedAudioPacket,
+    ) -> AgoraResult<EncodedAudioPacket> {
+        if !self.secure_audio.has_room(room_id) {
+            return Err(Error::Crypto(format!("Not in room {}", room_id)));
+        }
+
+        // Relayed frames arrive from a different peer than their sender, but
+        // the sender itself must hold the room key as a member.
+        let is_member = self
+            .group_keys
+            .members(room_id)
+            .iter()
+            .any(|m| m.to_string() == packet.peer_id);
+        if !is_member {
+            return Err(Error::Crypto(format!(
+                "Sender {} is not a member of room {}",
+                packet.peer_id, room_id
+            )));
+        }
+        if self
+            .moderation
+            .get(room_id)
+            .zip(packet.peer_id.parse::<PeerId>().ok())
+            .is_some_and(|(state, sender)| state.is_muted(&sender))
+        {
+            return Err(Error::Crypto(format!(
+                "Sender {} is muted in room {}",
+                packet.peer_id, room_id
+            )));
+        }
+
+        if !self.secure_audio.has_key_id(room_id, packet.key_id) {
+            let key = (peer_id, room_id.to_string());
+            if self.key_mismatches.insert(key, packet.key_id) != Some(packet.key_id) {
+                let current_key_id = self.secure_audio.get_current_key_id(room_id);
+                tracing::warn!(
+                    "Audio from {} in room {} uses key {}, we hold {:?}",
+                    peer_id,
+                    room_id,
+                    packet.key_id,
+                    current_key_id
+                );
+                let _ = self.event_tx.send(NetworkEvent::AudioKeyMismatch {
+                    peer_id,
+                    room_id: room_id.to_string(),
+                    key_id: packet.key_id,
+                    current_key_id,
+                });
+            }
+            return Err(Error::Crypto(format!("Unknown key ID {}", packet.key_id)));
+        }
+
+        self.secure_audio.decrypt_encoded(room_id, packet)
+    }
+
+    /// Relays a speaker's frame unchanged when we forward for the room,
+    /// whether it came from the speaker or from another mixer.
+    fn forward_audio(&mut self, room_id: &str, from: PeerId, packet: &EncryptedAudioPacket) {
+        let Some(mixer) = self.room_mixers.get_mut(room_id) else {
+            return;
+        };
+        let targets: Vec<PeerId> = mixer
+            .forward_targets(packet, &from.to_string())
+            .iter()
+            .filter_map(|peer_id| peer_id.parse().ok())
+            .collect();
+        for peer_id in targets {
+            self.send_encrypted_audio(peer_id, room_id, packet.clone());
+        }
+    }
+
+    /// Decodes with the sender's own decoder, which for forwarded frames is
+    /// not the peer that delivered them.
+    fn decode_audio_packet(&mut self, packet: EncodedAudioPacket) {
+        let Ok(peer_id) = packet.peer_id.parse::<PeerId>() else {
+            return;
+        };
+        let decoder = match self.peer_decoders.entry(peer_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let config = self.audio_config.clone().with_echo_cancellation(false);
+                match AudioProcessor::new(config) {
+                    Ok(processor) => entry.insert(processor),
+                    Err(e) => {
+                        tracing::error!("Failed to create decoder for {}: {}", peer_id, e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let frame = match packet.codec {
+            CodecId::Opus => decoder.decode_and_process(&packet.payload),
+        };
+
+        match frame {
+            Ok(frame) => {
+                let audio = &decoder.config().audio;
+                let decoded = AudioPacket {
+                    sequence: packet.sequence,
+                    timestamp: packet.timestamp,
+                    peer_id: packet.peer_id,
+                    frame,
+                    sample_rate: audio.sample_rate,
+                    channels: audio.channels,
+                };
+                let _ = self.event_tx.send(NetworkEvent::AudioReceived {
+                    peer_id,
+                    packet: decoded,
+                });
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Dropping undecodable audio frame {} from {}: {}",
+                    packet.sequence,
+                    peer_id,
+                    e
+                );
+            }
+        }
+    }
+
+    fn encode_audio_packet(&mut self, packet: AudioPacket) -> Option<EncodedAudioPacket> {
+        let mut frame = packet.frame;
+        match self.audio_processor.process_and_encode(&mut frame) {
+            // The sender id selects the per-sender key, so it must be unique
+            // to this node rather than whatever the caller put in the packet.
+            Ok(encoded) => Some(
+                EncodedAudioPacket::from_encoded_frame(
+                    packet.sequence,
+                    packet.timestamp,
+                    self.local_peer_id.to_string(),
+                    encoded,
+                    self.audio_processor.config().opus.enable_fec,
+                )
+                .with_audio_level(audio_level(&frame)),
+            ),
+            Err(e) => {
+                tracing::warn!("Failed to encode audio frame {}: {}", packet.sequence, e);
+                None
+            }
+        }
+    }
+
+    /// Sends to one peer under the key of a joined room it shares with us.
+    /// Audio is never sent in the clear, so peers without a shared room get
+    /// nothing.
     async fn send_audio_packet(&mut self, peer_id: PeerId, packet: AudioPacket) {
+        let Some(room_id) = self
+            .room_peers
+            .iter()
+            .find(|(room, peers)| peers.contains(&peer_id) && self.secure_audio.has_room(room))
+            .map(|(room, _)| room.clone())
+        else {
+            tracing::warn!("Not sending audio to {}: no shared room key", peer_id);
+            return;
+        };
+
+        if let Some(encrypted) = self.encrypt_audio_packet(&room_id, packet) {
+            self.send_encrypted_audio(peer_id, &room_id, encrypted);
+        }
+    }
+
+    fn encrypt_audio_packet(
+        &mut self,
+        room_id: &str,
+        packet: AudioPacket,
+    ) -> Option<EncryptedAudioPacket> {
+        if self.is_muted(room_id, &self.local_peer_id) {
+            tracing::trace!("Not sending audio to {}: muted by a moderator", room_id);
+            return None;
+        }
+        let encoded = self.encode_audio_packet(packet)?;
+        match self.secure_audio.encrypt_encoded(room_id, &encoded) {
+            Ok(encrypted) => Some(encrypted),
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to encrypt audio frame {} for room {}: {}",
+                    encoded.sequence,
+                    room_id,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn send_encrypted_audio(
+        &mut self,
+        peer_id: PeerId,
+        room_id: &str,
+        packet: EncryptedAudioPacket,
+    ) {
+        if self.audio_transport == AudioTransport::Stream
+            && !self.legacy_audio_peers.contains(&peer_id)
+        {
+            let key = (peer_id, room_id.to_string());
+            let mode = if self.quic_connections.contains_key(&peer_id) {
+                StreamMode::PerFrame
+            } else {
+                StreamMode::Persistent
+            };
+
+            match self.audio_senders.get(&key).map(|s| (s.status(), s.mode())) {
+                Some((StreamStatus::Unsupported, _)) => {
+                    tracing::info!(
+                        "Peer {} lacks audio stream support, using request-response",
+                        peer_id
+                    );
+                    self.audio_senders.remove(&key);
+                    self.legacy_audio_peers.insert(peer_id);
+                }
+                Some((StreamStatus::Closed, _)) => {
+                    self.audio_senders.remove(&key);
+                }
+                Some((_, current)) if current != mode => {
+                    self.audio_senders.remove(&key);
+                }
+                _ => {}
+            }
+
+            if !self.legacy_audio_peers.contains(&peer_id) {
+                let sender = self.audio_senders.entry(key).or_insert_with(|| {
+                    AudioStreamSender::spawn(
+                        self.stream_control.clone(),
+                        peer_id,
+                        room_id.to_string(),
+                        mode,
+                    )
+                });
+                if let Err(e) = sender.send(&packet) {
+                    tracing::trace!(
+                        "Audio frame {} to {} dropped: {}",
+                        packet.sequence,
+                        peer_id,
+                        e
+                    );
+                }
+                return;
+            }
+        }
+
         let request_id = self
             .swarm
             .behaviour_mut()
@@ -845,15 +2342,35 @@ impl NetworkNode {
     }
 
     async fn broadcast_audio(&mut self, room_id: &str, packet: AudioPacket) {
-        let peers: Vec<PeerId> = self
+        let mut peers: Vec<PeerId> = self
             .room_peers
             .get(room_id)
             .map(|p| p.iter().cloned().collect())
             .unwrap_or_default();
+        // With forwarding mixers elected, they relay our audio to the rest.
+        let targets: Vec<PeerId> = self
+            .room_mixers
+            .get(room_id)
+            .and_then(|m| m.audio_targets())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|peer_id| peer_id.parse().ok())
+            .filter(|peer_id| peers.contains(peer_id))
+            .collect();
+        if !targets.is_empty() {
+            peers = targets;
+        }
+
+        if peers.is_empty() {
+            return;
+        }
+
+        let Some(encrypted) = self.encrypt_audio_packet(room_id, packet) else {
+            return;
+        };
 
         for peer_id in peers {
-            let packet = packet.clone();
-            self.send_audio_packet(peer_id, packet).await;
+            self.send_encrypted_audio(peer_id, room_id, encrypted.clone());
         }
     }
 
@@ -870,26 +2387,1731 @@ impl NetworkNode {
         );
     }
 
-    async fn join_room(&mut self, room_id: &str) -> AgoraResult<()> {
+    /// Sets up the room locally without looking for its other members.
+    fn enter_room(
+        &mut self,
+        room_id: &str,
+        password: Option<&str>,
+        creator: Option<CreatorProof>,
+    ) -> AgoraResult<()> {
+        let mut moderation = RoomModeration::new(room_id);
+        if let Some(creator) = creator {
+            moderation.set_creator(creator)?;
+        }
+        self.moderation.insert(room_id.to_string(), moderation);
+
+        match password {
+            Some(password) => {
+                self.room_auth.insert(
+                    room_id.to_string(),
+                    JoinAuthenticator::new(room_id, password),
+                );
+            }
+            None => {
+                self.room_auth.remove(room_id);
+            }
+        }
+
+        let event = self.group_keys.create_room(room_id);
+        self.room_key_changed(event);
+        let mut mixer = MixerManager::new(self.peer_id_string(), Some(self.mixer_config.clone()));
+        mixer.update_local_stats(
+            self.mixer_stats.bandwidth_bps,
+            self.mixer_stats.cpu_usage_percent,
+            self.mixer_stats.memory_usage_percent,
+        );
+        mixer.set_mixer_nodes(self.mixer_nodes.clone());
+        self.room_mixers.insert(room_id.to_string(), mixer);
+        Ok(())
+    }
+
+    async fn join_room(
+        &mut self,
+        room_id: &str,
+        password: Option<&str>,
+        creator: Option<CreatorProof>,
+    ) -> AgoraResult<()> {
+        self.enter_room(room_id, password, creator)?;
         self.start_providing(room_id).await?;
         self.get_providers(room_id);
+        let lan_peers: Vec<PeerId> = self.lan_peers.iter().copied().collect();
+        for peer_id in lan_peers {
+            self.find_room_on_lan(room_id, peer_id).await;
+        }
         Ok(())
     }
 
-    fn leave_room(&mut self, room_id: &str) {
-        self.room_peers.remove(room_id);
+    async fn leave_room(&mut self, room_id: &str) {
+        if let Some(peers) = self.room_peers.remove(room_id) {
+            if self.group_keys.has_room(room_id) {
+                for peer_id in peers {
+                    let message =
+                        ControlMessage::leave_room(room_id.to_string(), self.peer_id_string());
+                    self.send_control_message(peer_id, message).await;
+                }
+            }
+        }
+        self.group_keys.leave_room(room_id);
+        self.room_auth.remove(room_id);
+        self.verified_peers.retain(|(_, room)| room != room_id);
+        self.introduced_peers.retain(|(_, room)| room != room_id);
+        self.join_challenges.retain(|(_, room), _| room != room_id);
+        self.join_responses.retain(|(_, room), _| room != room_id);
+        self.pending_group_keys
+            .retain(|(_, room), _| room != room_id);
+        self.moderation.remove(room_id);
+        self.room_mixers.remove(room_id);
+        self.mixer_elections.remove(room_id);
+        self.secure_audio.remove_room(room_id);
+        self.key_mismatches.retain(|(_, room), _| room != room_id);
+        self.audio_senders.retain(|(_, room), _| room != room_id);
+        self.hosted_rooms.remove(room_id);
+        self.pending_hosts.remove(room_id);
+        self.requested_mixers.remove(room_id);
+        self.pending_mixer_admissions
+            .retain(|(_, room), _| room != room_id);
+        self.mixer_requests.retain(|(_, room)| room != room_id);
         tracing::info!("Left room: {}", room_id);
     }
-}
 
-pub fn parse_peer_id(s: &str) -> AgoraResult<PeerId> {
-    s.parse()
-        .map_err(|e| Error::Network(format!("Invalid peer ID '{}': {}", s, e)))
-}
+    fn add_room_peer(&mut self, room_id: &str, peer_id: PeerId) {
+        self.introduced_peers
+            .remove(&(peer_id, room_id.to_string()));
+        if self
+            .room_peers
+            .entry(room_id.to_string())
+            .or_default()
+            .insert(peer_id)
+        {
+            if let Some(mixer) = self.room_mixers.get_mut(room_id) {
+                mixer.add_participant(peer_id.to_string());
+            }
+            tracing::info!("Peer {} joined room {}", peer_id, room_id);
+            let _ = self.event_tx.send(NetworkEvent::RoomJoined {
+                room_id: room_id.to_string(),
+                peer_id,
+            });
+        }
+    }
 
-pub fn parse_multiaddr(s: &str) -> AgoraResult<Multiaddr> {
-    s.parse()
-        .map_err(|e| Error::Network(format!("Invalid multiaddr '{}': {}", s, e)))
+    async fn remove_room_peer(&mut self, room_id: &str, peer_id: PeerId) {
+        if let Some(peers) = self.room_peers.get_mut(room_id) {
+            peers.remove(&peer_id);
+        }
+        if let Some(mixer) = self.room_mixers.get_mut(room_id) {
+            mixer.remove_participant(&peer_id.to_string());
+        }
+        self.hold_mixer_election(room_id).await;
+        let key = (peer_id, room_id.to_string());
+        self.verified_peers.remove(&key);
+        self.introduced_peers.remove(&key);
+        self.pending_group_keys.remove(&key);
+        self.audio_senders.remove(&(peer_id, room_id.to_string()));
+        tracing::info!("Peer {} left room {}", peer_id, room_id);
+        let _ = self.event_tx.send(NetworkEvent::RoomLeft {
+            room_id: room_id.to_string(),
+            peer_id,
+        });
+
+        let rekey = self.group_keys.remove_member(room_id, &peer_id);
+        self.distribute_rekey(rekey).await;
+
+        let empty = self.room_peers.get(room_id).is_none_or(|p| p.is_empty());
+        if empty && self.hosted_rooms.contains(room_id) {
+            self.leave_room(room_id).await;
+            let _ = self.event_tx.send(NetworkEvent::HostedRoomClosed {
+                room_id: room_id.to_string(),
+            });
+        }
+    }
+
+    /// Asks a LAN peer whether it is in `room_id`; members answer with
+    /// `JoinRoom`, so the room forms without the DHT.
+    async fn find_room_on_lan(&mut self, room_id: &str, peer_id: PeerId) {
+        if self
+            .room_peers
+            .get(room_id)
+            .is_some_and(|peers| peers.contains(&peer_id))
+            || self.is_banned(room_id, &peer_id)
+        {
+            return;
+        }
+        let message = ControlMessage::find_room(room_id.to_string(), self.peer_id_string());
+        self.send_control_message(peer_id, message).await;
+    }
+
+    /// Sends `JoinRoom` to room peers we have not yet introduced ourselves to.
+    /// They become room peers once they answer and pass the join handshake.
+    async fn announce_to(&mut self, room_id: &str, peers: impl IntoIterator<Item = PeerId>) {
+        if !self.group_keys.has_room(room_id) {
+            return;
+        }
+        let known = self.room_peers.get(room_id);
+        let new_peers: Vec<PeerId> = peers
+            .into_iter()
+            .filter(|p| {
+                *p != self.local_peer_id
+                    && !known.is_some_and(|k| k.contains(p))
+                    && !self.introduced_peers.contains(&(*p, room_id.to_string()))
+                    && !self.is_banned(room_id, p)
+            })
+            .collect();
+
+        for peer_id in new_peers {
+            self.introduced_peers.insert((peer_id, room_id.to_string()));
+            let message = ControlMessage::join_room(room_id.to_string(), self.peer_id_string());
+            self.send_control_message(peer_id, message).await;
+        }
+    }
+
+    fn listen_via_relay(&mut self, addr: Multiaddr) -> AgoraResult<()> {
+        if !self.swarm.behaviour().relay_client.is_enabled() {
+            return Err(Error::Network("Relay client is disabled".to_string()));
+        }
+        if let Some(Protocol::P2p(relay_peer_id)) = addr.iter().last() {
+            if self.relay_listeners.contains_key(&relay_peer_id) {
+                return Ok(());
+            }
+        }
+        let (relay_peer_id, listener_id) = listen_via_relay(&mut self.swarm, addr)?;
+        self.relay_listeners.insert(relay_peer_id, listener_id);
+        Ok(())
+    }
+
+    /// Reserves on identified relays while AutoNAT says we are unreachable.
+    fn reserve_auto_relays(&mut self) {
+        if !self.nat_private || !self.swarm.behaviour().relay_client.is_enabled() {
+            return;
+        }
+        let candidates: Vec<Multiaddr> = self
+            .relay_candidates
+            .iter()
+            .filter(|(peer_id, _)| !self.relay_listeners.contains_key(peer_id))
+            .map(|(peer_id, addr)| addr.clone().with(Protocol::P2p(*peer_id)))
+            .take(MAX_AUTO_RELAYS.saturating_sub(self.relay_listeners.len()))
+            .collect();
+        for addr in candidates {
+            if let Err(e) = self.listen_via_relay(addr) {
+                tracing::warn!("Failed to reserve on relay: {}", e);
+            }
+        }
+    }
+
+    /// Starts an ICE session with `peer_id`. `remote` carries the peer's
+    /// candidates when it opened the session.
+    fn start_ice_session(&mut self, peer_id: PeerId, remote: Option<SignedIceCandidates>) {
+        if peer_id == self.local_peer_id
+            || self.ice_sessions.contains_key(&peer_id)
+            || self.blocklist.is_blocked(&peer_id)
+        {
+            return;
+        }
+
+        // Roles only need to differ, so the larger peer ID controls.
+        let role = if self.local_peer_id.to_bytes() > peer_id.to_bytes() {
+            IceRole::Controlling
+        } else {
+            IceRole::Controlled
+        };
+        tracing::info!("Starting ICE session with {} as {:?}", peer_id, role);
+
+        self.ice_sessions.insert(
+            peer_id,
+            IceSession {
+                started: Instant::now(),
+                stage: IceStage::Gathering,
+                agent: None,
+                remote,
+                credentials: None,
+                local: Vec::new(),
+                gathering_complete: false,
+                trickle: None,
+            },
+        );
+
+        let mut agent = IceAgent::new(Some(self.ice_config.clone())).with_role(role);
+        let ice_tx = self.ice_tx.clone();
+        tokio::spawn(async move {
+            let result = agent.start_gathering().await;
+            let _ = ice_tx
+                .send(IceTaskResult::Gathered {
+                    peer_id,
+                    agent,
+                    result,
+                })
+                .await;
+        });
+    }
+
+    async fn handle_ice_task_result(&mut self, result: IceTaskResult) {
+        match result {
+            IceTaskResult::Gathered {
+                peer_id,
+                agent,
+                result,
+            } => {
+                if !self.ice_sessions.contains_key(&peer_id) {
+                    return;
+                }
+                let mut trickled = match result {
+                    Ok(trickled) => trickled,
+                    Err(e) => {
+                        self.fail_ice_session(peer_id, format!("Gathering failed: {}", e));
+                        return;
+                    }
+                };
+                let ice_tx = self.ice_tx.clone();
+                tokio::spawn(async move {
+                    while let Some(candidate) = trickled.recv().await {
+                        let trickle = IceTaskResult::Trickled {
+                            peer_id,
+                            candidate: Some(candidate),
+                        };
+                        if ice_tx.send(trickle).await.is_err() {
+                            return;
+                        }
+                    }
+                    let _ = ice_tx
+                        .send(IceTaskResult::Trickled {
+                            peer_id,
+                            candidate: None,
+                        })
+                        .await;
+                });
+
+                let (has_remote, local) = match self.ice_sessions.get_mut(&peer_id) {
+                    Some(session) => {
+                        session.credentials = Some(agent.local_credentials());
+                        session.local = agent.local_candidates().to_vec();
+                        session.trickle = Some(agent.trickle());
+                        session.agent = Some(agent);
+                        session.stage = IceStage::AwaitingRemote;
+                        (session.remote.is_some(), session.local.clone())
+                    }
+                    None => return,
+                };
+
+                self.publish_ice_offer(peer_id, &local).await;
+                if has_remote {
+                    self.start_ice_checks(peer_id);
+                } else {
+                    self.fetch_ice_candidates(peer_id);
+                }
+            }
+
+            IceTaskResult::Trickled { peer_id, candidate } => {
+                let Some(session) = self.ice_sessions.get_mut(&peer_id) else {
+                    return;
+                };
+                let new = match candidate {
+                    Some(candidate) => {
+                        session.local.push(candidate.clone());
+                        vec![candidate]
+                    }
+                    None => {
+                        session.gathering_complete = true;
+                        Vec::new()
+                    }
+                };
+                self.publish_ice_offer(peer_id, &new).await;
+            }
+
+            IceTaskResult::Checked {
+                peer_id,
+                agent,
+                result,
+            } => {
+                let selected = agent.selected_pair().cloned();
+                let controlling = agent.role() == IceRole::Controlling;
+                let Some(session) = self.ice_sessions.get_mut(&peer_id) else {
+                    return;
+                };
+                session.agent = Some(agent);
+
+                let pair = match (result, selected) {
+                    (Ok(()), Some(pair)) => pair,
+                    (Ok(()), None) => {
+                        self.fail_ice_session(peer_id, "No pair nominated".to_string());
+                        return;
+                    }
+                    (Err(e), _) => {
+                        self.fail_ice_session(peer_id, e.to_string());
+                        return;
+                    }
+                };
+                let (local, remote) = (pair.local.base_addr, pair.remote.connection_addr);
+                let _ = self.event_tx.send(NetworkEvent::IcePairNominated {
+                    peer_id,
+                    local,
+                    remote,
+                });
+                if pair.local.candidate_type == CandidateType::Relayed {
+                    self.fail_ice_session(peer_id, "Nominated pair is relayed".to_string());
+                    return;
+                }
+                session.stage = IceStage::Dialing;
+
+                // Both ends run QUIC on their end of the pair: the controlling
+                // peer dials, the controlled one punches and waits for it.
+                self.close_ice_path(peer_id);
+                self.ice_paths.insert(local, remote);
+                let listener_id = match self.swarm.listen_on(quic_multiaddr(local)) {
+                    Ok(listener_id) => listener_id,
+                    Err(e) => {
+                        self.ice_paths.remove(&remote);
+                        self.fail_ice_session(
+                            peer_id,
+                            format!("Listen on {} failed: {}", local, e),
+                        );
+                        return;
+                    }
+                };
+                self.ice_listeners.insert(peer_id, (listener_id, remote));
+
+                let addr = quic_multiaddr(remote).with(Protocol::P2p(peer_id));
+                tracing::info!("Dialing {} on nominated path {}", peer_id, addr);
+                let _ = self.event_tx.send(NetworkEvent::IceDialing {
+                    peer_id,
+                    addrs: vec![addr.clone()],
+                });
+                let opts = DialOpts::peer_id(peer_id)
+                    .addresses(vec![addr])
+                    .condition(PeerCondition::Always);
+                let opts = match controlling {
+                    true => opts.build(),
+                    false => opts.override_role().build(),
+                };
+                if let Err(e) = self.swarm.dial(opts) {
+                    self.fail_ice_session(peer_id, format!("Dial failed: {}", e));
+                }
+            }
+        }
+    }
+
+    /// Signs the session's candidates so far and publishes them; `new` are
+    /// the ones the peer has not been offered yet.
+    async fn publish_ice_offer(&mut self, peer_id: PeerId, new: &[Candidate]) {
+        let Some(session) = self.ice_sessions.get(&peer_id) else {
+            return;
+        };
+        let Some(credentials) = session.credentials.as_ref() else {
+            return;
+        };
+        let signed = match SignedIceCandidates::sign(
+            &self.identity,
+            &peer_id,
+            credentials,
+            &session.local,
+            &self.ice_dial_addrs(),
+            session.gathering_complete,
+        ) {
+            Ok(signed) => signed,
+            Err(e) => {
+                self.fail_ice_session(peer_id, e.to_string());
+                return;
+            }
+        };
+
+        self.publish_ice_candidates(peer_id, &signed).await;
+        if !new.is_empty() {
+            let _ = self.event_tx.send(NetworkEvent::IceCandidatesSent {
+                peer_id,
+                candidates: new.iter().map(Candidate::to_sdp).collect(),
+            });
+        }
+    }
+
+    /// Offers our candidates over the control protocol when a connection
+    /// already exists, and through a DHT record otherwise. Providing the
+    /// peer's inbox key tells it there is a record to fetch.
+    async fn publish_ice_candidates(&mut self, peer_id: PeerId, signed: &SignedIceCandidates) {
+        if self.swarm.is_connected(&peer_id) {
+            let message = ControlMessage::new(
+                ControlMessageType::IceCandidates(signed.clone()),
+                self.peer_id_string(),
+            );
+            self.send_control_message(peer_id, message).await;
+        }
+
+        let value = match signed.to_bytes() {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Failed to encode ICE candidates: {}", e);
+                return;
+            }
+        };
+        let mut record = Record::new(ice_record_key(&self.local_peer_id, &peer_id), value);
+        record.expires = Some(Instant::now() + ICE_SESSION_TIMEOUT);
+
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        if let Err(e) = kademlia.put_record(record, Quorum::One) {
+            tracing::warn!("Failed to publish ICE candidates: {:?}", e);
+        }
+        if let Err(e) = kademlia.start_providing(ice_inbox_key(&peer_id)) {
+            tracing::warn!("Failed to announce ICE offer: {:?}", e);
+        }
+    }
+
+    fn advertise(&mut self, directory: &str, value: Vec<u8>) {
+        let mut record = Record::new(advertisement_key(directory, &self.local_peer_id), value);
+        record.expires = Some(Instant::now() + ADVERTISEMENT_TTL);
+
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        if let Err(e) = kademlia.put_record(record, Quorum::One) {
+            tracing::warn!("Failed to advertise under {}: {:?}", directory, e);
+        }
+        if let Err(e) = kademlia.start_providing(RecordKey::new(&directory)) {
+            tracing::warn!("Failed to list us under {}: {:?}", directory, e);
+        }
+    }
+
+    fn browse(&mut self, directory: String) {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .get_providers(RecordKey::new(&directory));
+        self.browsed_directories.insert(directory);
+    }
+
+    fn fetch_advertisements(&mut self, directory: &str, providers: HashSet<PeerId>) {
+        for peer_id in providers {
+            if peer_id == self.local_peer_id || self.blocklist.is_blocked(&peer_id) {
+                continue;
+            }
+            self.swarm
+                .behaviour_mut()
+                .kademlia
+                .get_record(advertisement_key(directory, &peer_id));
+        }
+    }
+
+    /// Reports a record fetched by `fetch_advertisements` if it sits under
+    /// a browsed directory and was published by the peer its key names.
+    fn handle_advertisement(&mut self, record: Record) {
+        let key = String::from_utf8_lossy(record.key.as_ref()).to_string();
+        let found = self.browsed_directories.iter().find_map(|directory| {
+            let peer = key.strip_prefix(directory.as_str())?.strip_prefix('/')?;
+            Some((directory.clone(), peer.parse::<PeerId>().ok()?))
+        });
+        let Some((directory, peer_id)) = found else {
+            return;
+        };
+        if record.publisher != Some(peer_id) {
+            tracing::debug!("Ignoring advertisement {} published by another peer", key);
+            return;
+        }
+        let _ = self.event_tx.send(NetworkEvent::AdvertisementFound {
+            directory,
+            peer_id,
+            value: record.value,
+        });
+    }
+
+    fn fetch_ice_candidates(&mut self, peer_id: PeerId) {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .get_record(ice_record_key(&peer_id, &self.local_peer_id));
+    }
+
+    /// Fetches the records of peers that announced an ICE offer to us.
+    fn fetch_ice_offers(&mut self, providers: HashSet<PeerId>) {
+        for peer_id in providers {
+            if peer_id == self.local_peer_id
+                || self.ice_sessions.contains_key(&peer_id)
+                || self.swarm.is_connected(&peer_id)
+                || self.blocklist.is_blocked(&peer_id)
+            {
+                continue;
+            }
+            self.fetch_ice_candidates(peer_id);
+        }
+    }
+
+    async fn handle_ice_record(&mut self, record: Record) {
+        let signed = match SignedIceCandidates::from_bytes(&record.value) {
+            Ok(signed) => signed,
+            Err(e) => {
+                tracing::debug!("Ignoring malformed ICE record: {}", e);
+                return;
+            }
+        };
+        let Ok(from) = signed.from_peer_id() else {
+            return;
+        };
+        if record.key != ice_record_key(&from, &self.local_peer_id) {
+            return;
+        }
+        self.receive_ice_candidates(from, signed);
+    }
+
+    fn receive_ice_candidates(&mut self, peer_id: PeerId, signed: SignedIceCandidates) {
+        if let Err(e) = signed.verify(&peer_id, &self.local_peer_id) {
+            tracing::warn!("Rejected ICE candidates from {}: {}", peer_id, e);
+            return;
+        }
+        if !signed.is_fresh(ICE_SESSION_TIMEOUT) {
+            tracing::debug!("Ignoring stale ICE candidates from {}", peer_id);
+            return;
+        }
+
+        let awaiting = match self.ice_sessions.get_mut(&peer_id) {
+            Some(session) if session.remote.is_some() => {
+                self.receive_trickled_candidates(peer_id, signed);
+                return;
+            }
+            Some(session) => {
+                session.remote = Some(signed.clone());
+                session.stage == IceStage::AwaitingRemote
+            }
+            None => {
+                if self.blocklist.is_blocked(&peer_id) {
+                    return;
+                }
+                self.start_ice_session(peer_id, Some(signed.clone()));
+                false
+            }
+        };
+
+        tracing::info!(
+            "Received {} ICE candidates from {}",
+            signed.candidates.len(),
+            peer_id
+        );
+        let _ = self.event_tx.send(NetworkEvent::IceCandidatesReceived {
+            peer_id,
+            candidates: signed.candidates,
+        });
+
+        if awaiting {
+            self.start_ice_checks(peer_id);
+        }
+    }
+
+    /// Hands candidates from a newer offer by the peer to the running checks.
+    /// New credentials would mean an ICE restart, which sessions do not do.
+    fn receive_trickled_candidates(&mut self, peer_id: PeerId, signed: SignedIceCandidates) {
+        let Some(session) = self.ice_sessions.get_mut(&peer_id) else {
+            return;
+        };
+        let Some(current) = session.remote.as_ref() else {
+            return;
+        };
+        if current.credentials != signed.credentials {
+            tracing::debug!("Ignoring ICE offer with new credentials from {}", peer_id);
+            return;
+        }
+        if signed.issued_at < current.issued_at || signed == *current {
+            return;
+        }
+
+        let new: Vec<String> = signed
+            .candidates
+            .iter()
+            .filter(|c| !current.candidates.contains(c))
+            .cloned()
+            .collect();
+        if let Some(trickle) = session.trickle.as_ref() {
+            new.iter()
+                .filter_map(|sdp| crate::ice::parse_candidate_from_sdp(sdp))
+                .for_each(|candidate| trickle.add_remote_candidate(candidate));
+            if signed.end_of_candidates {
+                trickle.end_of_candidates();
+            }
+        }
+        session.remote = Some(signed);
+
+        if !new.is_empty() {
+            tracing::debug!("Trickled {} ICE candidates from {}", new.len(), peer_id);
+            let _ = self.event_tx.send(NetworkEvent::IceCandidatesReceived {
+                peer_id,
+                candidates: new,
+            });
+        }
+    }
+
+    fn start_ice_checks(&mut self, peer_id: PeerId) {
+        let Some(session) = self.ice_sessions.get_mut(&peer_id) else {
+            return;
+        };
+        let (Some(mut agent), Some(remote)) = (session.agent.take(), session.remote.as_ref())
+        else {
+            return;
+        };
+        agent.set_remote_credentials(remote.credentials.clone());
+        agent.add_remote_candidates(remote.parsed_candidates());
+        if remote.end_of_candidates {
+            agent.end_of_remote_candidates();
+        }
+        session.stage = IceStage::Checking;
+
+        let ice_tx = self.ice_tx.clone();
+        tokio::spawn(async move {
+            let result = agent.perform_connectivity_checks().await;
+            if result.is_ok() {
+                // libp2p takes over the nominated pair's local port.
+                agent.close().await;
+            }
+            let _ = ice_tx
+                .send(IceTaskResult::Checked {
+                    peer_id,
+                    agent,
+                    result,
+                })
+                .await;
+        });
+    }
+
+    /// Ends the session with `peer_id` once any connection to it is up, and
+    /// re-sends room announcements that could not be delivered before.
+    async fn finish_ice_session(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        if self.ice_sessions.remove(&peer_id).is_none() {
+            return;
+        }
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .stop_providing(&ice_inbox_key(&peer_id));
+        tracing::info!("ICE session with {} connected via {}", peer_id, addr);
+        let _ = self
+            .event_tx
+            .send(NetworkEvent::IceConnected { peer_id, addr });
+
+        let introduced = self
+            .introduced_peers
+            .iter()
+            .filter(|(p, _)| *p == peer_id)
+            .map(|(_, room_id)| room_id.clone());
+        let rooms: Vec<String> = self
+            .room_peers
+            .iter()
+            .filter(|(room_id, peers)| {
+                peers.contains(&peer_id)
+                    && self.group_keys.has_room(room_id)
+                    && !self.group_keys.members(room_id).contains(&peer_id)
+            })
+            .map(|(room_id, _)| room_id.clone())
+            .chain(introduced)
+            .collect();
+        for room_id in rooms {
+            let message = ControlMessage::join_room(room_id, self.peer_id_string());
+            self.send_control_message(peer_id, message).await;
+        }
+    }
+
+    /// Stops running libp2p over the pair nominated with `peer_id`.
+    fn close_ice_path(&mut self, peer_id: PeerId) {
+        if let Some((listener_id, remote)) = self.ice_listeners.remove(&peer_id) {
+            self.swarm.remove_listener(listener_id);
+            self.ice_paths.remove(&remote);
+        }
+    }
+
+    fn fail_ice_session(&mut self, peer_id: PeerId, reason: String) {
+        if self.ice_sessions.remove(&peer_id).is_none() {
+            return;
+        }
+        self.close_ice_path(peer_id);
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .stop_providing(&ice_inbox_key(&peer_id));
+        tracing::warn!("ICE session with {} failed: {}", peer_id, reason);
+        let _ = self
+            .event_tx
+            .send(NetworkEvent::IceFailed { peer_id, reason });
+    }
+
+    fn poll_ice_sessions(&mut self) {
+        let expired: Vec<PeerId> = self
+            .ice_sessions
+            .iter()
+            .filter(|(_, session)| session.started.elapsed() > ICE_SESSION_TIMEOUT)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in expired {
+            self.fail_ice_session(peer_id, "Timed out".to_string());
+        }
+
+        let awaiting: Vec<PeerId> = self
+            .ice_sessions
+            .iter()
+            .filter(|(_, session)| match session.stage {
+                IceStage::AwaitingRemote => true,
+                IceStage::Checking => !session
+                    .remote
+                    .as_ref()
+                    .is_some_and(|remote| remote.end_of_candidates),
+                _ => false,
+            })
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        for peer_id in awaiting {
+            self.fetch_ice_candidates(peer_id);
+        }
+    }
+
+    fn poll_ice_inbox(&mut self) {
+        if !self.group_keys.has_rooms() {
+            return;
+        }
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .get_providers(ice_inbox_key(&self.local_peer_id));
+    }
+
+    /// Addresses a peer may dial us on: our listen addresses plus external
+    /// addresses confirmed or observed by other peers.
+    fn ice_dial_addrs(&self) -> Vec<Multiaddr> {
+        let mut addrs: Vec<Multiaddr> = Vec::new();
+        let candidates = self
+            .listen_addrs
+            .iter()
+            .chain(self.swarm.external_addresses())
+            .chain(&self.external_addr_candidates);
+        for addr in candidates {
+            if !addrs.contains(addr) {
+                addrs.push(addr.clone());
+            }
+        }
+        addrs
+    }
+
+    fn participant_infos(&self, room_id: &str, except: PeerId) -> Vec<ParticipantInfo> {
+        self.group_keys
+            .members(room_id)
+            .into_iter()
+            .filter(|p| *p != except)
+            .map(|p| ParticipantInfo {
+                peer_id: p.to_string(),
+                display_name: self.peer_names.get(&p).cloned(),
+                is_mixer: false,
+                is_muted: self.is_muted(room_id, &p),
+                latency_ms: 0,
+            })
+            .collect()
+    }
+
+    async fn send_participant_list(&mut self, room_id: &str, peer_id: PeerId) {
+        let participants = self.participant_infos(room_id, peer_id);
+        let mut message = ControlMessage::new(
+            ControlMessageType::ParticipantList { participants },
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(peer_id, message).await;
+    }
+
+    /// Adds `peer_id` and introduces ourselves to the members it listed. The
+    /// listed members only share the room key once they have joined through
+    /// us.
+    async fn add_participants(
+        &mut self,
+        room_id: &str,
+        peer_id: PeerId,
+        participants: &[ParticipantInfo],
+    ) {
+        if self.is_banned(room_id, &peer_id) {
+            return;
+        }
+        self.add_room_peer(room_id, peer_id);
+        let listed: Vec<PeerId> = participants
+            .iter()
+            .filter_map(|p| p.peer_id.parse().ok())
+            .filter(|p| *p != self.local_peer_id && !self.is_banned(room_id, p))
+            .collect();
+        self.announce_to(room_id, listed).await;
+        self.admit_member(room_id, peer_id).await;
+    }
+
+    /// Adds a peer verified by this node to the room key, installing any key
+    /// it sent before it was admitted.
+    async fn admit_member(&mut self, room_id: &str, peer_id: PeerId) {
+        let rekey = self.group_keys.add_member(room_id, peer_id);
+        self.distribute_rekey(rekey).await;
+        if let Some(message) = self
+            .pending_group_keys
+            .remove(&(peer_id, room_id.to_string()))
+        {
+            self.install_group_key(peer_id, &message);
+        }
+        if let Some(nodes) = self
+            .pending_mixer_admissions
+            .remove(&(peer_id, room_id.to_string()))
+        {
+            if self.is_verified(room_id, peer_id) {
+                for node in nodes {
+                    self.add_requested_mixer(room_id, node);
+                }
+            }
+        }
+        if self
+            .pending_hosts
+            .get(room_id)
+            .is_some_and(|(requester, _)| *requester == peer_id)
+        {
+            self.confirm_host(room_id, peer_id).await;
+        }
+    }
+
+    fn install_group_key(&mut self, peer_id: PeerId, message: &ControlMessage) {
+        let ControlMessageType::GroupKey {
+            room_id,
+            key_id,
+            members,
+            sealed_key,
+        } = &message.message_type
+        else {
+            return;
+        };
+
+        let members: Vec<PeerId> = members.iter().filter_map(|m| m.parse().ok()).collect();
+        if let Some(banned) = members.iter().find(|m| self.is_banned(room_id, m)) {
+            tracing::warn!(
+                "Rejected group key from {}: roster includes banned peer {}",
+                peer_id,
+                banned
+            );
+            return;
+        }
+        match self
+            .group_keys
+            .accept_key(room_id, peer_id, *key_id, &members, sealed_key)
+        {
+            Ok(event) => {
+                let local_peer_id = self.local_peer_id;
+                for member in members.into_iter().filter(|m| *m != local_peer_id) {
+                    self.add_room_peer(room_id, member);
+                }
+                self.room_key_changed(event);
+            }
+            Err(e) => {
+                tracing::warn!("Rejected group key from {}: {}", peer_id, e);
+            }
+        }
+    }
+
+    /// Peers in rooms without a password are always verified; otherwise they
+    /// must have completed a join handshake with us.
+    fn is_verified(&self, room_id: &str, peer_id: PeerId) -> bool {
+        !self.room_auth.contains_key(room_id)
+            || self
+                .verified_peers
+                .contains(&(peer_id, room_id.to_string()))
+    }
+
+    async fn send_join_challenge(&mut self, room_id: &str, peer_id: PeerId) {
+        let nonce = JoinAuthenticator::generate_nonce();
+        self.join_challenges
+            .insert((peer_id, room_id.to_string()), (nonce, Instant::now()));
+        tracing::debug!(
+            "Challenging {} to prove the password for {}",
+            peer_id,
+            room_id
+        );
+
+        let mut message = ControlMessage::new(
+            ControlMessageType::JoinChallenge {
+                room_id: room_id.to_string(),
+                nonce,
+            },
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(peer_id, message).await;
+    }
+
+    async fn answer_join_challenge(
+        &mut self,
+        room_id: &str,
+        peer_id: PeerId,
+        challenge: &[u8; 32],
+    ) {
+        if !self.group_keys.has_room(room_id) {
+            return;
+        }
+        let Some(auth) = self.room_auth.get(room_id) else {
+            tracing::warn!("Room {} requires a password we do not have", room_id);
+            let _ = self.event_tx.send(NetworkEvent::JoinRejected {
+                room_id: room_id.to_string(),
+                peer_id,
+                reason: JoinRejectReason::PasswordRequired,
+            });
+            return;
+        };
+
+        let nonce = JoinAuthenticator::generate_nonce();
+        let proof = auth.proof(
+            JoinRole::Joiner,
+            room_id,
+            challenge,
+            &nonce,
+            &self.local_peer_id.to_string(),
+            &peer_id.to_string(),
+        );
+        self.join_responses
+            .insert((peer_id, room_id.to_string()), (*challenge, nonce));
+
+        let mut message = ControlMessage::new(
+            ControlMessageType::JoinResponse {
+                room_id: room_id.to_string(),
+                nonce,
+                proof,
+            },
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(peer_id, message).await;
+    }
+
+    async fn check_join_response(
+        &mut self,
+        room_id: &str,
+        peer_id: PeerId,
+        nonce: &[u8; 32],
+        proof: &[u8; 32],
+    ) {
+        let key = (peer_id, room_id.to_string());
+        if self.is_banned(room_id, &peer_id) {
+            self.join_challenges.remove(&key);
+            self.reject_join(room_id, peer_id, JoinRejectReason::Banned)
+                .await;
+            return;
+        }
+        let challenge = self
+            .join_challenges
+            .remove(&key)
+            .filter(|(_, issued)| issued.elapsed() < JOIN_CHALLENGE_TIMEOUT)
+            .map(|(challenge, _)| challenge);
+        let (Some(challenge), Some(auth)) = (challenge, self.room_auth.get(room_id)) else {
+            self.reject_join(room_id, peer_id, JoinRejectReason::ChallengeExpired)
+                .await;
+            return;
+        };
+
+        let local = self.local_peer_id.to_string();
+        let joiner = peer_id.to_string();
+        if !auth.verify(
+            JoinRole::Joiner,
+            room_id,
+            &challenge,
+            nonce,
+            &joiner,
+            &local,
+            proof,
+        ) {
+            self.reject_join(room_id, peer_id, JoinRejectReason::InvalidPassword)
+                .await;
+            return;
+        }
+
+        let member_proof = auth.proof(
+            JoinRole::Member,
+            room_id,
+            &challenge,
+            nonce,
+            &joiner,
+            &local,
+        );
+        self.verified_peers.insert(key);
+        tracing::info!("Peer {} proved the password for {}", peer_id, room_id);
+
+        let mut message = ControlMessage::new(
+            ControlMessageType::JoinAccepted {
+                room_id: room_id.to_string(),
+                proof: member_proof,
+                participants: self.participant_infos(room_id, peer_id),
+            },
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(peer_id, message).await;
+        self.send_moderation_state(room_id, peer_id).await;
+        self.send_requested_mixers(room_id, peer_id).await;
+
+        self.add_room_peer(room_id, peer_id);
+        self.admit_member(room_id, peer_id).await;
+    }
+
+    async fn check_join_accepted(
+        &mut self,
+        room_id: &str,
+        peer_id: PeerId,
+        proof: &[u8; 32],
+        participants: &[ParticipantInfo],
+    ) {
+        let key = (peer_id, room_id.to_string());
+        let (Some((challenge, nonce)), Some(auth)) = (
+            self.join_responses.remove(&key),
+            self.room_auth.get(room_id),
+        ) else {
+            return;
+        };
+
+        if !auth.verify(
+            JoinRole::Member,
+            room_id,
+            &challenge,
+            &nonce,
+            &self.local_peer_id.to_string(),
+            &peer_id.to_string(),
+            proof,
+        ) {
+            tracing::warn!(
+                "Peer {} could not prove the password for {}",
+                peer_id,
+                room_id
+            );
+            return;
+        }
+
+        self.verified_peers.insert(key);
+        self.add_participants(room_id, peer_id, participants).await;
+    }
+
+    async fn reject_join(&mut self, room_id: &str, peer_id: PeerId, reason: JoinRejectReason) {
+        tracing::warn!("Rejecting {} from room {}: {}", peer_id, room_id, reason);
+        let mut message = ControlMessage::new(
+            ControlMessageType::JoinRejected {
+                room_id: room_id.to_string(),
+                reason,
+            },
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(peer_id, message).await;
+    }
+
+    fn is_banned(&self, room_id: &str, peer_id: &PeerId) -> bool {
+        self.moderation
+            .get(room_id)
+            .is_some_and(|state| state.is_banned(peer_id))
+    }
+
+    fn is_muted(&self, room_id: &str, peer_id: &PeerId) -> bool {
+        self.moderation
+            .get(room_id)
+            .is_some_and(|state| state.is_muted(peer_id))
+    }
+
+    async fn moderate(&mut self, room_id: &str, action: ModerationAction) -> AgoraResult<()> {
+        let state = self
+            .moderation
+            .get(room_id)
+            .ok_or_else(|| Error::Room(format!("Not in room {}", room_id)))?;
+        let creator = state
+            .creator_proof()
+            .cloned()
+            .ok_or_else(|| Error::Room(format!("Creator of room {} is unknown", room_id)))?;
+        let grant = if state.is_creator(&self.local_peer_id) {
+            None
+        } else {
+            let grant = state
+                .grant_for(&self.local_peer_id)
+                .cloned()
+                .ok_or_else(|| Error::Room(format!("Not a moderator of room {}", room_id)))?;
+            Some(grant)
+        };
+
+        let signed = SignedModeration::sign(&self.identity, room_id, action, creator, grant)?;
+        self.apply_moderation(&signed).await;
+        Ok(())
+    }
+
+    async fn publish_mixer_stats(&mut self, room_id: &str) {
+        let Some(mixer) = self.room_mixers.get(room_id) else {
+            return;
+        };
+        let stats = MixerStats::from(&mixer.local_stats());
+        let signed = match SignedMixerStats::sign(&self.identity, room_id, stats) {
+            Ok(signed) => signed,
+            Err(e) => {
+                tracing::error!("Failed to sign mixer stats: {}", e);
+                return;
+            }
+        };
+        let peers: Vec<PeerId> = self
+            .room_peers
+            .get(room_id)
+            .map(|p| p.iter().copied().collect())
+            .unwrap_or_default();
+        for peer_id in peers {
+            let mut message = ControlMessage::new(
+                ControlMessageType::MixerStats(signed.clone()),
+                self.peer_id_string(),
+            );
+            message.room_id = Some(room_id.to_string());
+            self.send_control_message(peer_id, message).await;
+        }
+    }
+
+    fn receive_mixer_stats(&mut self, peer_id: PeerId, signed: &SignedMixerStats) {
+        let room_id = signed.room_id.as_str();
+        if !self
+            .room_peers
+            .get(room_id)
+            .is_some_and(|peers| peers.contains(&peer_id))
+            || !self.is_verified(room_id, peer_id)
+        {
+            return;
+        }
+        if let Err(e) = signed.verify(&peer_id) {
+            tracing::debug!("Ignoring mixer stats from {}: {}", peer_id, e);
+            return;
+        }
+        if !signed.is_fresh(MIXER_STATS_MAX_AGE) {
+            tracing::debug!("Ignoring stale mixer stats from {}", peer_id);
+            return;
+        }
+        if let Some(mixer) = self.room_mixers.get_mut(room_id) {
+            mixer.update_participant_stats(&signed.peer_id, (&signed.stats).into());
+        }
+    }
+
+    /// Runs an election round if the room has no agreed mixer and announces
+    /// the result to the room.
+    async fn hold_mixer_election(&mut self, room_id: &str) {
+        let Some(election) = self
+            .room_mixers
+            .get_mut(room_id)
+            .filter(|mixer| mixer.needs_election())
+            .and_then(|mixer| mixer.elect())
+        else {
+            return;
+        };
+        let signed = match SignedMixerElection::sign(&self.identity, room_id, election) {
+            Ok(signed) => signed,
+            Err(e) => {
+                tracing::error!("Failed to sign mixer election: {}", e);
+                return;
+            }
+        };
+        self.mixer_elected(&signed);
+
+        let peers: Vec<PeerId> = self
+            .room_peers
+            .get(room_id)
+            .map(|p| p.iter().copied().collect())
+            .unwrap_or_default();
+        for peer_id in peers {
+            self.send_mixer_election(peer_id, signed.clone()).await;
+        }
+    }
+
+    /// Adopts an announced election that outranks ours. If ours wins, for
+    /// example after a split brain, the sender is sent ours instead.
+    async fn receive_mixer_election(&mut self, peer_id: PeerId, signed: &SignedMixerElection) {
+        let room_id = signed.room_id.as_str();
+        if !self
+            .room_peers
+            .get(room_id)
+            .is_some_and(|peers| peers.contains(&peer_id))
+            || !self.is_verified(room_id, peer_id)
+        {
+            return;
+        }
+        let is_member = signed
+            .elector_peer_id()
+            .is_ok_and(|elector| self.group_keys.members(room_id).contains(&elector));
+        if !is_member {
+            tracing::debug!("Ignoring mixer election by non-member {}", signed.elector);
+            return;
+        }
+        if let Err(e) = signed.verify() {
+            tracing::debug!("Ignoring mixer election from {}: {}", peer_id, e);
+            return;
+        }
+        if !signed.is_fresh(MIXER_ELECTION_MAX_AGE) {
+            tracing::debug!("Ignoring stale mixer election from {}", peer_id);
+            return;
+        }
+
+        let Some(mixer) = self.room_mixers.get_mut(room_id) else {
+            return;
+        };
+        if mixer.apply_election(signed.election.clone()) {
+            self.mixer_elected(signed);
+            return;
+        }
+        let outranked = self
+            .mixer_elections
+            .get(room_id)
+            .is_some_and(|ours| ours.election.outranks(&signed.election));
+        if !outranked {
+            return;
+        }
+        if let Some(ours) = self.current_mixer_election(room_id) {
+            self.send_mixer_election(peer_id, ours).await;
+        }
+    }
+
+    /// The room's election for passing on, signed again by us once it is
+    /// half way to going stale.
+    fn current_mixer_election(&mut self, room_id: &str) -> Option<SignedMixerElection> {
+        let signed = self.mixer_elections.get(room_id)?;
+        if signed.is_fresh(MIXER_ELECTION_MAX_AGE / 2) {
+            return Some(signed.clone());
+        }
+        match SignedMixerElection::sign(&self.identity, room_id, signed.election.clone()) {
+            Ok(signed) => {
+                self.mixer_elections
+                    .insert(room_id.to_string(), signed.clone());
+                Some(signed)
+            }
+            Err(e) => {
+                tracing::error!("Failed to sign mixer election: {}", e);
+                None
+            }
+        }
+    }
+
+    fn mixer_elected(&mut self, signed: &SignedMixerElection) {
+        self.mixer_elections
+            .insert(signed.room_id.clone(), signed.clone());
+        let Ok(mixer) = signed.election.mixer.parse() else {
+            return;
+        };
+        let _ = self.event_tx.send(NetworkEvent::MixerElected {
+            room_id: signed.room_id.clone(),
+            mixer,
+            epoch: signed.election.epoch,
+        });
+    }
+
+    fn set_mixer_nodes(&mut self, nodes: Vec<MixerNode>) {
+        self.mixer_nodes = nodes;
+        let rooms: Vec<String> = self.room_mixers.keys().cloned().collect();
+        for room_id in rooms {
+            self.update_room_mixer_nodes(&room_id);
+        }
+    }
+
+    /// `mixer_nodes` with the nodes members asked to mix for the room in
+    /// place of any discovered entry for the same node.
+    fn mixer_nodes_for(&self, room_id: &str) -> Vec<MixerNode> {
+        let requested = self
+            .requested_mixers
+            .get(room_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        self.mixer_nodes
+            .iter()
+            .filter(|n| !requested.iter().any(|r| r.peer_id == n.peer_id))
+            .chain(requested)
+            .cloned()
+            .collect()
+    }
+
+    fn update_room_mixer_nodes(&mut self, room_id: &str) {
+        let nodes = self.mixer_nodes_for(room_id);
+        if let Some(mixer) = self.room_mixers.get_mut(room_id) {
+            mixer.set_mixer_nodes(nodes);
+        }
+    }
+
+    /// Prefers `node` as the room's mixer on a member's word and admits it
+    /// without a challenge, since it cannot answer one.
+    fn add_requested_mixer(&mut self, room_id: &str, node: MixerNode) -> Option<PeerId> {
+        let peer_id = match parse_peer_id(&node.peer_id) {
+            Ok(peer_id) if !self.is_banned(room_id, &peer_id) => peer_id,
+            Ok(peer_id) => {
+                tracing::warn!("Not admitting banned mixer {} to {}", peer_id, room_id);
+                return None;
+            }
+            Err(e) => {
+                tracing::warn!("Invalid mixer node: {}", e);
+                return None;
+            }
+        };
+        let nodes = self
+            .requested_mixers
+            .entry(room_id.to_string())
+            .or_default();
+        nodes.retain(|n| n.peer_id != node.peer_id);
+        if nodes.len() >= MAX_REQUESTED_MIXERS {
+            tracing::warn!("Too many mixers requested for {}", room_id);
+            return None;
+        }
+        nodes.push(node);
+        self.verified_peers.insert((peer_id, room_id.to_string()));
+        self.update_room_mixer_nodes(room_id);
+        Some(peer_id)
+    }
+
+    async fn request_mixer(&mut self, room_id: &str, node: MixerNode) {
+        if !self.group_keys.has_room(room_id) {
+            tracing::warn!("Cannot request a mixer for {}: not in the room", room_id);
+            return;
+        }
+        let Some(peer_id) = self.add_requested_mixer(room_id, node.clone()) else {
+            return;
+        };
+
+        // Everyone in the room needs to prefer the node for the election
+        // to settle on it, and to admit it without a challenge.
+        let members: Vec<PeerId> = self
+            .room_peers
+            .get(room_id)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|p| *p != peer_id && self.group_keys.is_verified(room_id, p))
+            .collect();
+        for member in members {
+            self.send_mixer_admission(room_id, node.clone(), member)
+                .await;
+        }
+
+        self.mixer_requests.insert((peer_id, room_id.to_string()));
+        let mut message = ControlMessage::new(
+            ControlMessageType::MixerRequest {
+                room_id: room_id.to_string(),
+            },
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(peer_id, message).await;
+    }
+
+    async fn send_mixer_admission(&mut self, room_id: &str, node: MixerNode, to: PeerId) {
+        let mut message = ControlMessage::new(
+            ControlMessageType::MixerAdmitted {
+                room_id: room_id.to_string(),
+                node,
+            },
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(to, message).await;
+    }
+
+    async fn send_requested_mixers(&mut self, room_id: &str, peer_id: PeerId) {
+        let peer = peer_id.to_string();
+        let nodes: Vec<MixerNode> = self
+            .requested_mixers
+            .get(room_id)
+            .into_iter()
+            .flatten()
+            .filter(|n| n.peer_id != peer)
+            .cloned()
+            .collect();
+        for node in nodes {
+            self.send_mixer_admission(room_id, node, peer_id).await;
+        }
+    }
+
+    /// Enters a room to mix for it on `requester`'s request, within
+    /// `max_hosted_rooms`. We only contact the requester; it introduces us
+    /// to the rest of the room once it admits us, and we leave again if it
+    /// does not within `JOIN_CHALLENGE_TIMEOUT`.
+    async fn host_room(&mut self, requester: PeerId, room_id: &str) {
+        if self.group_keys.has_room(room_id) {
+            if self.hosted_rooms.contains(room_id) {
+                self.send_mixer_reply(requester, room_id, Ok(())).await;
+            }
+            return;
+        }
+
+        let result = if self.hosted_rooms.len() + self.pending_hosts.len() >= self.max_hosted_rooms
+        {
+            Err("No mixer capacity left".to_string())
+        } else {
+            self.enter_room(room_id, None, None)
+                .map_err(|e| e.to_string())
+        };
+        match result {
+            Ok(()) => {
+                self.pending_hosts
+                    .insert(room_id.to_string(), (requester, Instant::now()));
+                self.announce_to(room_id, [requester]).await;
+            }
+            Err(reason) => {
+                tracing::info!("Declined to mix for room {}: {}", room_id, reason);
+                self.send_mixer_reply(requester, room_id, Err(reason)).await;
+            }
+        }
+    }
+
+    async fn confirm_host(&mut self, room_id: &str, requester: PeerId) {
+        self.pending_hosts.remove(room_id);
+        self.hosted_rooms.insert(room_id.to_string());
+        tracing::info!("Mixing for room {} at {}'s request", room_id, requester);
+        let _ = self.event_tx.send(NetworkEvent::HostingRoom {
+            room_id: room_id.to_string(),
+            requester,
+        });
+        self.send_mixer_reply(requester, room_id, Ok(())).await;
+    }
+
+    async fn expire_pending_hosts(&mut self) {
+        let expired: Vec<(String, PeerId)> = self
+            .pending_hosts
+            .iter()
+            .filter(|(_, (_, requested))| requested.elapsed() >= JOIN_CHALLENGE_TIMEOUT)
+            .map(|(room_id, (requester, _))| (room_id.clone(), *requester))
+            .collect();
+        for (room_id, requester) in expired {
+            tracing::info!("{} never admitted us to room {}", requester, room_id);
+            self.leave_room(&room_id).await;
+            let reason = "Not admitted to the room".to_string();
+            self.send_mixer_reply(requester, &room_id, Err(reason))
+                .await;
+        }
+    }
+
+    async fn send_mixer_reply(
+        &mut self,
+        requester: PeerId,
+        room_id: &str,
+        result: Result<(), String>,
+    ) {
+        let room_id = room_id.to_string();
+        let reply = match result {
+            Ok(()) => ControlMessageType::MixerAccepted {
+                room_id: room_id.clone(),
+            },
+            Err(reason) => ControlMessageType::MixerDeclined {
+                room_id: room_id.clone(),
+                reason,
+            },
+        };
+        let mut message = ControlMessage::new(reply, self.peer_id_string());
+        message.room_id = Some(room_id);
+        self.send_control_message(requester, message).await;
+    }
+
+    async fn send_mixer_election(&mut self, peer_id: PeerId, signed: SignedMixerElection) {
+        let room_id = signed.room_id.clone();
+        let mut message = ControlMessage::new(
+            ControlMessageType::MixerElected(signed),
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id);
+        self.send_control_message(peer_id, message).await;
+    }
+
+    /// Verifies and applies an action, passes it on to the room, then
+    /// enforces it. The target hears about it before it is removed.
+    async fn apply_moderation(&mut self, signed: &SignedModeration) {
+        let room_id = signed.room_id.as_str();
+        let Some(state) = self.moderation.get_mut(room_id) else {
+            return;
+        };
+        if let Err(e) = state.apply(signed) {
+            tracing::debug!("Ignoring moderation action in {}: {}", room_id, e);
+            return;
+        }
+        let (Ok(issuer), Ok(target)) = (signed.issuer_peer_id(), signed.target_peer_id()) else {
+            return;
+        };
+        tracing::info!("{} applied {:?} in room {}", issuer, signed.action, room_id);
+
+        let peers: Vec<PeerId> = self
+            .room_peers
+            .get(room_id)
+            .map(|p| p.iter().copied().collect())
+            .unwrap_or_default();
+        for peer_id in peers {
+            self.send_moderation(room_id, peer_id, signed.clone()).await;
+        }
+
+        let _ = self.event_tx.send(NetworkEvent::ModerationApplied {
+            room_id: room_id.to_string(),
+            issuer,
+            action: signed.action.clone(),
+        });
+
+        if matches!(
+            signed.action,
+            ModerationAction::Kick { .. } | ModerationAction::Ban { .. }
+        ) {
+            if target == self.local_peer_id {
+                self.leave_room(room_id).await;
+            } else if self.group_keys.members(room_id).contains(&target)
+                || self
+                    .room_peers
+                    .get(room_id)
+                    .is_some_and(|p| p.contains(&target))
+            {
+                self.remove_room_peer(room_id, target).await;
+            }
+        }
+    }
+
+    async fn send_moderation(&mut self, room_id: &str, peer_id: PeerId, signed: SignedModeration) {
+        let mut message = ControlMessage::new(
+            ControlMessageType::Moderation(signed),
+            self.peer_id_string(),
+        );
+        message.room_id = Some(room_id.to_string());
+        self.send_control_message(peer_id, message).await;
+    }
+
+    /// Brings a newly admitted peer up to date on bans, mutes and moderators.
+    async fn send_moderation_state(&mut self, room_id: &str, peer_id: PeerId) {
+        let actions = self
+            .moderation
+            .get(room_id)
+            .map(|state| state.active_actions())
+            .unwrap_or_default();
+        for signed in actions {
+            self.send_moderation(room_id, peer_id, signed).await;
+        }
+    }
+
+    async fn distribute_rekey(&mut self, rekey: AgoraResult<Option<GroupRekey>>) {
+        let rekey = match rekey {
+            Ok(Some(rekey)) => rekey,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::error!("Group rekey failed: {}", e);
+                return;
+            }
+        };
+
+        let members: Vec<String> = rekey.members.iter().map(|m| m.to_string()).collect();
+        for (peer_id, sealed_key) in rekey.sealed {
+            let mut message = ControlMessage::new(
+                ControlMessageType::GroupKey {
+                    room_id: rekey.event.room_id.clone(),
+                    key_id: rekey.event.new_key_id,
+                    members: members.clone(),
+                    sealed_key,
+                },
+                self.peer_id_string(),
+            );
+            message.room_id = Some(rekey.event.room_id.clone());
+            self.send_control_message(peer_id, message).await;
+        }
+        self.room_key_changed(rekey.event);
+    }
+
+    /// Hands the room's current group key to the audio channel and reports it.
+    fn room_key_changed(&mut self, event: KeyRotationEvent) {
+        if let Some((key_id, key)) = self.group_keys.current_key(&event.room_id) {
+            self.secure_audio.install_key(&event.room_id, key_id, key);
+        }
+        let _ = self.event_tx.send(NetworkEvent::KeyRotated(event));
+    }
+}
+
+pub fn parse_peer_id(s: &str) -> AgoraResult<PeerId> {
+    s.parse()
+        .map_err(|e| Error::Network(format!("Invalid peer ID '{}': {}", s, e)))
+}
+
+pub fn parse_multiaddr(s: &str) -> AgoraResult<Multiaddr> {
+    s.parse()
+        .map_err(|e| Error::Network(format!("Invalid multiaddr '{}': {}", s, e)))
+}
+
+/// Listens on `/p2p-circuit` through the relay at `addr`.
+fn listen_via_relay(
+    swarm: &mut Swarm<AgoraBehaviour>,
+    addr: Multiaddr,
+) -> AgoraResult<(PeerId, ListenerId)> {
+    let Some(Protocol::P2p(relay_peer_id)) = addr.iter().last() else {
+        return Err(Error::Network(format!(
+            "Relay address {} does not end in a peer ID",
+            addr
+        )));
+    };
+    let listener_id = swarm
+        .listen_on(addr.with(Protocol::P2pCircuit))
+        .map_err(|e| Error::Network(format!("Relay listen error: {}", e)))?;
+    Ok((relay_peer_id, listener_id))
+}
+
+fn build_transport(
+    keypair: &libp2p::identity::Keypair,
+    enable_tcp: bool,
+    enable_quic: bool,
+    relay: Option<relay::client::Transport>,
+    ice_paths: IcePaths,
+) -> AgoraResult<Boxed<(PeerId, StreamMuxerBox)>> {
+    let tcp = if enable_tcp {
+        Some(
+            dns::tokio::Transport::system(tcp::tokio::Transport::new(
+                tcp::Config::new().nodelay(true),
+            ))
+            .map_err(|e| Error::Network(format!("DNS transport error: {}", e)))?
+            .upgrade(libp2p::core::upgrade::Version::V1)
+            .authenticate(
+                noise::Config::new(keypair)
+                    .map_err(|e| Error::Network(format!("Noise config error: {}", e)))?,
+            )
+            .multiplex(yamux::Config::default())
+            .timeout(Duration::from_secs(20))
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed(),
+        )
+    } else {
+        None
+    };
+
+    let quic = enable_quic.then(|| {
+        quic::tokio::Transport::new(quic::Config::new(keypair))
+            .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)))
+            .boxed()
+    });
+
+    let direct = match (quic, tcp) {
+        (Some(quic), Some(tcp)) => quic
+            .or_transport(tcp)
+            .map(|output, _| output.into_inner())
+            .boxed(),
+        (Some(quic), None) => quic,
+        (None, Some(tcp)) => tcp,
+        (None, None) => {
+            return Err(Error::Network(
+                "At least one of TCP or QUIC must be enabled".to_string(),
+            ))
+        }
+    };
+
+    let direct = IceTransport::new(quic::Config::new(keypair), ice_paths)
+        .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)))
+        .or_transport(direct)
+        .map(|output, _| output.into_inner())
+        .boxed();
+
+    let Some(relay) = relay else {
+        return Ok(direct);
+    };
+    let relayed = relay
+        .upgrade(libp2p::core::upgrade::Version::V1)
+        .authenticate(
+            noise::Config::new(keypair)
+                .map_err(|e| Error::Network(format!("Noise config error: {}", e)))?,
+        )
+        .multiplex(yamux::Config::default())
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .boxed();
+    Ok(relayed
+        .or_transport(direct)
+        .map(|output, _| output.into_inner())
+        .boxed())
+}
+
+fn is_quic_addr(addr: &Multiaddr) -> bool {
+    addr.iter()
+        .any(|p| matches!(p, Protocol::QuicV1 | Protocol::Quic))
+}
+
+/// Where `from` publishes its candidates for a session with `to`.
+fn ice_record_key(from: &PeerId, to: &PeerId) -> RecordKey {
+    RecordKey::new(&format!("{}{}/{}", ICE_RECORD_PREFIX, from, to))
+}
+
+/// Provided by every peer that has published candidates for `to`.
+fn ice_inbox_key(to: &PeerId) -> RecordKey {
+    RecordKey::new(&format!("{}{}", ICE_INBOX_PREFIX, to))
+}
+
+/// Where `peer_id` keeps its entry in `directory`, which it provides.
+fn advertisement_key(directory: &str, peer_id: &PeerId) -> RecordKey {
+    RecordKey::new(&format!("{}/{}", directory, peer_id))
 }
 
 fn candidate_to_multiaddr(candidate: &Candidate) -> AgoraResult<Multiaddr> {
@@ -1,11 +1,22 @@
+use crate::audio::SILENT_AUDIO_LEVEL;
+use crate::cascade::MixerNode;
+use crate::codec::EncodedFrame;
+use crate::election::{SignedMixerElection, SignedMixerStats};
+use crate::group_key::SealedGroupKey;
+use crate::ice::SignedIceCandidates;
+use crate::moderation::SignedModeration;
 use serde::{Deserialize, Serialize};
 use std::io;
 
 pub const PROTOCOL_NAME: &str = "/agora/audio/1.0.0";
+pub const AUDIO_STREAM_PROTOCOL: &str = "/agora/audio/2.0.0";
 pub const PROTOCOL_CONTROL: &str = "/agora/control/1.0.0";
 
 pub const MAX_FRAME_SIZE: usize = 4096;
 pub const AUDIO_FRAME_SIZE: usize = 960;
+/// Version 2 wraps every frame in an [`EncryptedAudioPacket`]; version 3
+/// adds the audio level.
+pub const AUDIO_WIRE_VERSION: u8 = 3;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioPacket {
@@ -41,6 +52,96 @@ impl AudioPacket {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CodecId {
+    Opus,
+}
+
+/// Compressed audio frame as sent on the wire.
+///
+/// Encoded as a single [`AUDIO_WIRE_VERSION`] byte followed by the postcard body,
+/// so peers can reject frames from incompatible versions before parsing them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncodedAudioPacket {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub peer_id: String,
+    pub codec: CodecId,
+    pub bitrate: i32,
+    pub fec: bool,
+    pub payload: Vec<u8>,
+    /// Level of the frame before encoding, in -dBov (see
+    /// [`crate::audio::audio_level`]).
+    pub audio_level: u8,
+}
+
+impl EncodedAudioPacket {
+    pub fn from_encoded_frame(
+        sequence: u64,
+        timestamp: u64,
+        peer_id: String,
+        frame: EncodedFrame,
+        fec: bool,
+    ) -> Self {
+        Self {
+            sequence,
+            timestamp,
+            peer_id,
+            codec: CodecId::Opus,
+            bitrate: frame.bitrate,
+            fec,
+            payload: frame.data,
+            audio_level: SILENT_AUDIO_LEVEL,
+        }
+    }
+
+    pub fn with_audio_level(mut self, audio_level: u8) -> Self {
+        self.audio_level = audio_level;
+        self
+    }
+
+    pub fn to_encoded_frame(&self) -> EncodedFrame {
+        EncodedFrame {
+            data: self.payload.clone(),
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            bitrate: self.bitrate,
+        }
+    }
+
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        encode_versioned(self)
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        decode_versioned(data)
+    }
+}
+
+fn encode_versioned<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    postcard::to_extend(value, vec![AUDIO_WIRE_VERSION])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn decode_versioned<T: for<'de> Deserialize<'de>>(data: &[u8]) -> io::Result<T> {
+    match data.split_first() {
+        Some((&AUDIO_WIRE_VERSION, body)) => {
+            postcard::from_bytes(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+        Some((version, _)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported audio wire version: {}", version),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Empty audio packet",
+        )),
+    }
+}
+
+/// End-to-end encrypted [`EncodedAudioPacket`], the unit every audio
+/// transport carries. Relays see only the sender, sequence, key id and audio
+/// level, the last so a forwarding node can pick active speakers.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedAudioPacket {
     pub sequence: u64,
@@ -49,6 +150,7 @@ pub struct EncryptedAudioPacket {
     pub encrypted_frame: Vec<u8>,
     pub nonce: [u8; 12],
     pub key_id: u64,
+    pub audio_level: u8,
 }
 
 impl EncryptedAudioPacket {
@@ -69,6 +171,7 @@ impl EncryptedAudioPacket {
             encrypted_frame,
             nonce,
             key_id,
+            audio_level: SILENT_AUDIO_LEVEL,
         }
     }
 
@@ -88,6 +191,7 @@ impl EncryptedAudioPacket {
             encrypted_frame: encrypted_msg.ciphertext,
             nonce: encrypted_msg.nonce,
             key_id,
+            audio_level: SILENT_AUDIO_LEVEL,
         }
     }
 
@@ -96,11 +200,11 @@ impl EncryptedAudioPacket {
     }
 
     pub fn encode(&self) -> io::Result<Vec<u8>> {
-        postcard::to_allocvec(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        encode_versioned(self)
     }
 
     pub fn decode(data: &[u8]) -> io::Result<Self> {
-        postcard::from_bytes(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        decode_versioned(data)
     }
 }
 
@@ -115,13 +219,110 @@ pub struct ControlMessage {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ControlMessageType {
-    JoinRoom { room_id: String },
-    LeaveRoom { room_id: String },
-    UpdateInfo { display_name: String },
-    MuteChanged { is_muted: bool },
-    ParticipantList { participants: Vec<ParticipantInfo> },
+    JoinRoom {
+        room_id: String,
+    },
+    LeaveRoom {
+        room_id: String,
+    },
+    UpdateInfo {
+        display_name: String,
+    },
+    MuteChanged {
+        is_muted: bool,
+    },
+    ParticipantList {
+        participants: Vec<ParticipantInfo>,
+    },
     Ping,
     Pong,
+    /// A room key sealed to the recipient, with the roster it was issued for.
+    GroupKey {
+        room_id: String,
+        key_id: u64,
+        members: Vec<String>,
+        sealed_key: SealedGroupKey,
+    },
+    /// Sent in reply to `JoinRoom` for a password-protected room.
+    JoinChallenge {
+        room_id: String,
+        nonce: [u8; 32],
+    },
+    /// The joiner's nonce and its proof over both nonces.
+    JoinResponse {
+        room_id: String,
+        nonce: [u8; 32],
+        proof: [u8; 32],
+    },
+    /// The member's own proof, so the joiner knows it is talking to someone
+    /// who holds the password, plus the members the joiner should contact.
+    JoinAccepted {
+        room_id: String,
+        proof: [u8; 32],
+        participants: Vec<ParticipantInfo>,
+    },
+    JoinRejected {
+        room_id: String,
+        reason: JoinRejectReason,
+    },
+    Moderation(SignedModeration),
+    /// ICE candidates for a session with the recipient, used when a
+    /// connection already exists (for example to upgrade a relayed path).
+    IceCandidates(SignedIceCandidates),
+    /// Asks a peer found on the local network whether it is in a room.
+    /// Members answer with `JoinRoom`, standing in for a DHT provider lookup
+    /// when there is no internet to reach bootstrap peers.
+    FindRoom {
+        room_id: String,
+    },
+    /// The sender's stats, published periodically so every participant
+    /// scores mixer candidates from the same numbers.
+    MixerStats(SignedMixerStats),
+    /// The result of a mixer election round, announced by its elector and
+    /// passed on to participants that hold an older or losing result.
+    MixerElected(SignedMixerElection),
+    /// Asks a dedicated node to join a room we are in and mix for it. The
+    /// node joins through us and only stays once we admit it.
+    MixerRequest {
+        room_id: String,
+    },
+    /// Tells the other members that the sender asked `node` to mix for the
+    /// room, so they prefer it as mixer too and, in a password-protected
+    /// room, admit it without a challenge.
+    MixerAdmitted {
+        room_id: String,
+        node: MixerNode,
+    },
+    /// The requester admitted the node and it will mix once elected.
+    MixerAccepted {
+        room_id: String,
+    },
+    MixerDeclined {
+        room_id: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinRejectReason {
+    /// The room has a password and the joiner did not supply one.
+    PasswordRequired,
+    InvalidPassword,
+    /// No outstanding challenge matched the response, or it timed out.
+    ChallengeExpired,
+    /// The joiner is banned from the room.
+    Banned,
+}
+
+impl std::fmt::Display for JoinRejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinRejectReason::PasswordRequired => write!(f, "password required"),
+            JoinRejectReason::InvalidPassword => write!(f, "invalid password"),
+            JoinRejectReason::ChallengeExpired => write!(f, "challenge expired"),
+            JoinRejectReason::Banned => write!(f, "banned"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -155,6 +356,10 @@ impl ControlMessage {
         Self::new(ControlMessageType::LeaveRoom { room_id }, peer_id)
     }
 
+    pub fn find_room(room_id: String, peer_id: String) -> Self {
+        Self::new(ControlMessageType::FindRoom { room_id }, peer_id)
+    }
+
     pub fn mute_changed(peer_id: String, is_muted: bool) -> Self {
         Self::new(ControlMessageType::MuteChanged { is_muted }, peer_id)
     }
@@ -266,6 +471,65 @@ mod tests {
         assert_eq!(packet.frame.len(), decoded.frame.len());
     }
 
+    #[test]
+    fn test_encoded_audio_packet_encode_decode() {
+        let frame = EncodedFrame {
+            data: vec![0xfc, 0x01, 0x02, 0x03],
+            sequence: 7,
+            timestamp: 6720,
+            bitrate: 24000,
+        };
+        let packet =
+            EncodedAudioPacket::from_encoded_frame(7, 1234, "peer123".to_string(), frame, true);
+
+        let encoded = packet.encode().unwrap();
+        assert_eq!(encoded[0], AUDIO_WIRE_VERSION);
+
+        let decoded = EncodedAudioPacket::decode(&encoded).unwrap();
+        assert_eq!(decoded.sequence, 7);
+        assert_eq!(decoded.peer_id, "peer123");
+        assert_eq!(decoded.codec, CodecId::Opus);
+        assert_eq!(decoded.bitrate, 24000);
+        assert!(decoded.fec);
+        assert_eq!(decoded.payload, vec![0xfc, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn test_encoded_audio_packet_rejects_unknown_version() {
+        let frame = EncodedFrame {
+            data: vec![1, 2, 3],
+            sequence: 1,
+            timestamp: 960,
+            bitrate: 32000,
+        };
+        let packet = EncodedAudioPacket::from_encoded_frame(1, 0, "peer".to_string(), frame, false);
+
+        let mut encoded = packet.encode().unwrap();
+        encoded[0] = AUDIO_WIRE_VERSION + 1;
+        assert!(EncodedAudioPacket::decode(&encoded).is_err());
+        assert!(EncodedAudioPacket::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn test_encoded_audio_packet_smaller_than_pcm() {
+        let pcm = AudioPacket::new(1, "peer123".to_string(), vec![0.5; AUDIO_FRAME_SIZE]);
+        let frame = EncodedFrame {
+            data: vec![0u8; 80],
+            sequence: 1,
+            timestamp: 960,
+            bitrate: 32000,
+        };
+        let encoded = EncodedAudioPacket::from_encoded_frame(
+            1,
+            pcm.timestamp,
+            pcm.peer_id.clone(),
+            frame,
+            true,
+        );
+
+        assert!(encoded.encode().unwrap().len() * 10 < pcm.encode().unwrap().len());
+    }
+
     #[test]
     fn test_control_message_encode_decode() {
         let msg = ControlMessage::join_room("room456".to_string(), "peer123".to_string());
@@ -22,6 +22,10 @@ pub struct ReputationScore {
 
     pub vouches_received: u32,
     pub vouches_given: u32,
+
+    /// Independent blocks from vouched peers' shared blocklists.
+    #[serde(default)]
+    pub blocks_received: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -30,9 +34,13 @@ pub struct ScoreComponents {
     pub performance: f32,
     pub reliability: f32,
     pub challenge: f32,
+    /// Penalty subtracted from the weighted score.
+    #[serde(default)]
+    pub blocklist: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ReputationConfig {
     pub uptime_weight: f32,
     pub performance_weight: f32,
@@ -46,6 +54,9 @@ pub struct ReputationConfig {
 
     pub max_latency_samples: usize,
 
+    pub blocklist_penalty_per_block: f32,
+    pub blocklist_penalty_max: f32,
+
     pub initial_score: f32,
     pub min_score: f32,
     pub max_score: f32,
@@ -66,6 +77,9 @@ impl Default for ReputationConfig {
 
             max_latency_samples: 100,
 
+            blocklist_penalty_per_block: 0.1,
+            blocklist_penalty_max: 0.5,
+
             initial_score: 0.5,
             min_score: 0.0,
             max_score: 1.0,
@@ -92,6 +106,7 @@ impl ReputationScore {
             last_updated: now,
             vouches_received: 0,
             vouches_given: 0,
+            blocks_received: 0,
         }
     }
 
@@ -100,11 +115,13 @@ impl ReputationScore {
         self.components.performance = self.calculate_performance_score(config);
         self.components.reliability = self.calculate_reliability_score(config);
         self.components.challenge = self.calculate_challenge_score();
+        self.components.blocklist = self.calculate_blocklist_penalty(config);
 
         let raw_score = self.components.uptime * config.uptime_weight
             + self.components.performance * config.performance_weight
             + self.components.reliability * config.reliability_weight
-            + self.components.challenge * config.challenge_weight;
+            + self.components.challenge * config.challenge_weight
+            - self.components.blocklist;
 
         self.overall = raw_score.clamp(config.min_score, config.max_score);
         self.last_updated = current_timestamp();
@@ -153,6 +170,11 @@ impl ReputationScore {
         self.challenges_passed as f32 / self.challenges_total as f32
     }
 
+    fn calculate_blocklist_penalty(&self, config: &ReputationConfig) -> f32 {
+        (self.blocks_received as f32 * config.blocklist_penalty_per_block)
+            .min(config.blocklist_penalty_max)
+    }
+
     pub fn record_uptime(&mut self, seconds: u64) {
         self.uptime_seconds += seconds;
     }
@@ -195,6 +217,11 @@ impl ReputationScore {
         self.vouches_given += 1;
     }
 
+    /// Sets the count from [`crate::blocklist::Blocklist::independent_blocks`].
+    pub fn record_blocks(&mut self, independent_blocks: u32) {
+        self.blocks_received = independent_blocks;
+    }
+
     pub fn uptime_days(&self) -> f32 {
         self.uptime_seconds as f32 / 86400.0
     }
@@ -359,6 +386,24 @@ mod tests {
         assert!(score.overall > 0.9);
     }
 
+    #[test]
+    fn test_blocklist_penalty() {
+        let config = ReputationConfig::default();
+        let mut score = ReputationScore::new(&config);
+        score.recalculate(&config);
+        let unblocked = score.overall;
+
+        score.record_blocks(2);
+        score.recalculate(&config);
+        assert!((score.components.blocklist - 0.2).abs() < 0.001);
+        assert!((unblocked - score.overall - 0.2).abs() < 0.001);
+
+        score.record_blocks(50);
+        score.recalculate(&config);
+        assert!((score.components.blocklist - config.blocklist_penalty_max).abs() < 0.001);
+        assert!(score.overall >= config.min_score);
+    }
+
     #[test]
     fn test_serialization() {
         let config = ReputationConfig::default();
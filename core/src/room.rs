@@ -1,9 +1,12 @@
 use base64::Engine;
+use hmac::{Hmac, Mac};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
 const SALT_LENGTH: usize = 16;
+const JOIN_KEY_INFO: &[u8] = b"agora room join v1";
+const ROOM_ID_DOMAIN: &[u8] = b"agora room id v1";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Room {
@@ -13,6 +16,24 @@ pub struct Room {
     pub password_hash: Option<String>,
     pub max_participants: usize,
     pub created_at: u64,
+    /// Salt the room id was derived from together with the creator's peer
+    /// ID; see [`CreatorProof`].
+    #[serde(default)]
+    pub creator_salt: Option<String>,
+}
+
+/// Shows that a peer created a room: the room id is a hash of the
+/// creator's peer ID and this salt, so nobody else can claim it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatorProof {
+    pub creator_peer_id: String,
+    pub salt: String,
+}
+
+impl CreatorProof {
+    pub fn verify(&self, room_id: &str) -> bool {
+        room_id_for_creator(&self.creator_peer_id, &self.salt) == room_id
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,7 +45,8 @@ pub struct RoomConfig {
 
 impl Room {
     pub fn new(creator_peer_id: String, config: RoomConfig) -> Self {
-        let id = generate_room_id();
+        let salt = hex::encode(rand::thread_rng().gen::<[u8; SALT_LENGTH]>());
+        let id = room_id_for_creator(&creator_peer_id, &salt);
         let password_hash = config.password.map(|p| hash_password(&p));
 
         Self {
@@ -34,9 +56,19 @@ impl Room {
             password_hash,
             max_participants: config.max_participants.unwrap_or(20),
             created_at: current_timestamp(),
+            creator_salt: Some(salt),
         }
     }
 
+    /// Proof of creatorship to hand to the network node, if this room was
+    /// created with a creator-bound id.
+    pub fn creator_proof(&self) -> Option<CreatorProof> {
+        self.creator_salt.as_ref().map(|salt| CreatorProof {
+            creator_peer_id: self.creator_peer_id.clone(),
+            salt: salt.clone(),
+        })
+    }
+
     pub fn verify_password(&self, password: &str) -> bool {
         match &self.password_hash {
             Some(hash) => verify_password_hash(password, hash),
@@ -85,6 +117,96 @@ impl RoomConfig {
     }
 }
 
+/// Which side of a join handshake a proof comes from. Proofs are
+/// domain-separated so a joiner's proof cannot be reflected back as a
+/// member's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinRole {
+    Joiner,
+    Member,
+}
+
+/// Challenge-response proofs of room password knowledge.
+///
+/// Both sides derive a key from the room id and password and MAC the
+/// member's challenge, the joiner's nonce and both peer IDs, so the password
+/// never crosses the wire and a proof is useless in any other handshake.
+/// A peer that takes part in a handshake can still guess weak passwords
+/// offline against the transcript.
+pub struct JoinAuthenticator {
+    key: [u8; 32],
+}
+
+impl JoinAuthenticator {
+    pub fn new(room_id: &str, password: &str) -> Self {
+        let mut key = [0u8; 32];
+        hkdf::Hkdf::<Sha256>::new(Some(room_id.as_bytes()), password.as_bytes())
+            .expand(JOIN_KEY_INFO, &mut key)
+            .expect("HKDF expand should never fail");
+        Self { key }
+    }
+
+    pub fn generate_nonce() -> [u8; 32] {
+        rand::thread_rng().gen()
+    }
+
+    fn mac(
+        &self,
+        role: JoinRole,
+        room_id: &str,
+        challenge: &[u8; 32],
+        nonce: &[u8; 32],
+        joiner: &str,
+        member: &str,
+    ) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(match role {
+            JoinRole::Joiner => b"joiner",
+            JoinRole::Member => b"member",
+        });
+        for field in [room_id.as_bytes(), joiner.as_bytes(), member.as_bytes()] {
+            mac.update(&(field.len() as u32).to_be_bytes());
+            mac.update(field);
+        }
+        mac.update(challenge);
+        mac.update(nonce);
+        mac
+    }
+
+    pub fn proof(
+        &self,
+        role: JoinRole,
+        room_id: &str,
+        challenge: &[u8; 32],
+        nonce: &[u8; 32],
+        joiner: &str,
+        member: &str,
+    ) -> [u8; 32] {
+        self.mac(role, room_id, challenge, nonce, joiner, member)
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    /// Checks `proof` in constant time.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify(
+        &self,
+        role: JoinRole,
+        room_id: &str,
+        challenge: &[u8; 32],
+        nonce: &[u8; 32],
+        joiner: &str,
+        member: &str,
+        proof: &[u8; 32],
+    ) -> bool {
+        self.mac(role, room_id, challenge, nonce, joiner, member)
+            .verify_slice(proof)
+            .is_ok()
+    }
+}
+
 pub fn generate_room_id() -> String {
     let random_bytes: [u8; 16] = rand::thread_rng().gen();
     let mut hasher = Sha256::new();
@@ -94,6 +216,15 @@ pub fn generate_room_id() -> String {
     hex::encode(&hash[..8])
 }
 
+fn room_id_for_creator(creator_peer_id: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(ROOM_ID_DOMAIN);
+    hasher.update((creator_peer_id.len() as u32).to_be_bytes());
+    hasher.update(creator_peer_id.as_bytes());
+    hasher.update(salt.as_bytes());
+    hex::encode(&hasher.finalize()[..8])
+}
+
 fn hash_password(password: &str) -> String {
     let salt: [u8; SALT_LENGTH] = rand::thread_rng().gen();
     let mut hash = [0u8; 32];
@@ -200,6 +331,101 @@ mod tests {
         assert!(verify_password_hash("same_password", &hash2));
     }
 
+    #[test]
+    fn test_join_proof_roundtrip() {
+        let member = JoinAuthenticator::new("room", "secret");
+        let joiner = JoinAuthenticator::new("room", "secret");
+        let challenge = JoinAuthenticator::generate_nonce();
+        let nonce = JoinAuthenticator::generate_nonce();
+
+        let proof = joiner.proof(JoinRole::Joiner, "room", &challenge, &nonce, "j", "m");
+        assert!(member.verify(
+            JoinRole::Joiner,
+            "room",
+            &challenge,
+            &nonce,
+            "j",
+            "m",
+            &proof
+        ));
+
+        // A joiner proof is not valid as a member proof, nor for other peers.
+        assert!(!member.verify(
+            JoinRole::Member,
+            "room",
+            &challenge,
+            &nonce,
+            "j",
+            "m",
+            &proof
+        ));
+        assert!(!member.verify(
+            JoinRole::Joiner,
+            "room",
+            &challenge,
+            &nonce,
+            "x",
+            "m",
+            &proof
+        ));
+        assert!(!member.verify(
+            JoinRole::Joiner,
+            "room",
+            &nonce,
+            &challenge,
+            "j",
+            "m",
+            &proof
+        ));
+    }
+
+    #[test]
+    fn test_join_proof_wrong_password() {
+        let member = JoinAuthenticator::new("room", "secret");
+        let joiner = JoinAuthenticator::new("room", "guess");
+        let other_room = JoinAuthenticator::new("other", "secret");
+        let challenge = JoinAuthenticator::generate_nonce();
+        let nonce = JoinAuthenticator::generate_nonce();
+
+        let proof = joiner.proof(JoinRole::Joiner, "room", &challenge, &nonce, "j", "m");
+        assert!(!member.verify(
+            JoinRole::Joiner,
+            "room",
+            &challenge,
+            &nonce,
+            "j",
+            "m",
+            &proof
+        ));
+
+        let proof = other_room.proof(JoinRole::Joiner, "room", &challenge, &nonce, "j", "m");
+        assert!(!member.verify(
+            JoinRole::Joiner,
+            "room",
+            &challenge,
+            &nonce,
+            "j",
+            "m",
+            &proof
+        ));
+    }
+
+    #[test]
+    fn test_creator_proof() {
+        let room = Room::new("peer123".to_string(), RoomConfig::default_public());
+        let proof = room.creator_proof().unwrap();
+        assert!(proof.verify(&room.id));
+
+        let forged = CreatorProof {
+            creator_peer_id: "mallory".to_string(),
+            ..proof.clone()
+        };
+        assert!(!forged.verify(&room.id));
+
+        let other = Room::new("peer123".to_string(), RoomConfig::default_public());
+        assert!(!proof.verify(&other.id));
+    }
+
     #[test]
     fn test_share_link() {
         let room = Room::new("peer123".to_string(), RoomConfig::default_public());
@@ -1,9 +1,11 @@
+use crate::blocklist::{Blocklist, SignedBlocklist};
 use crate::error::{AgoraResult, Error};
 use crate::identity::Identity;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
 const IDENTITY_FILE: &str = "identity.bin";
+const BLOCKLIST_FILE: &str = "blocklist.json";
 
 #[derive(Serialize, Deserialize)]
 struct StoredIdentity {
@@ -152,6 +154,90 @@ impl IdentityStorage {
     }
 }
 
+/// Persists the user's [`Blocklist`] next to the identity.
+pub struct BlocklistStorage {
+    config_dir: PathBuf,
+}
+
+impl BlocklistStorage {
+    pub fn new() -> AgoraResult<Self> {
+        let config_dir = dirs::config_dir()
+            .ok_or_else(|| Error::Storage("Cannot determine config directory".to_string()))?
+            .join("agora");
+
+        Self::with_path(config_dir)
+    }
+
+    pub fn with_path(config_dir: PathBuf) -> AgoraResult<Self> {
+        std::fs::create_dir_all(&config_dir)
+            .map_err(|e| Error::Storage(format!("Failed to create config directory: {}", e)))?;
+
+        Ok(Self { config_dir })
+    }
+
+    fn blocklist_path(&self) -> PathBuf {
+        self.config_dir.join(BLOCKLIST_FILE)
+    }
+
+    pub fn save(&self, blocklist: &Blocklist) -> AgoraResult<()> {
+        let json = serde_json::to_string_pretty(blocklist)
+            .map_err(|e| Error::Storage(format!("Failed to serialize blocklist: {}", e)))?;
+
+        std::fs::write(self.blocklist_path(), json)
+            .map_err(|e| Error::Storage(format!("Failed to write blocklist: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Returns an empty blocklist if none has been saved yet.
+    pub fn load(&self) -> AgoraResult<Blocklist> {
+        let path = self.blocklist_path();
+
+        if !path.exists() {
+            return Ok(Blocklist::new());
+        }
+
+        let json = std::fs::read_to_string(&path)
+            .map_err(|e| Error::Storage(format!("Failed to read blocklist: {}", e)))?;
+
+        serde_json::from_str(&json)
+            .map_err(|e| Error::Storage(format!("Failed to parse blocklist: {}", e)))
+    }
+
+    /// Writes a signed copy of the blocklist for sharing with contacts.
+    pub fn export_to_file(
+        &self,
+        blocklist: &Blocklist,
+        identity: &Identity,
+        path: &Path,
+    ) -> AgoraResult<()> {
+        let signed = blocklist.export_signed(identity)?;
+
+        let json = serde_json::to_string_pretty(&signed)
+            .map_err(|e| Error::Storage(format!("Failed to serialize: {}", e)))?;
+
+        std::fs::write(path, json)
+            .map_err(|e| Error::Storage(format!("Failed to write file: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// Reads a contact's signed blocklist and adds it to the stored one.
+    pub fn import_from_file(&self, path: &Path) -> AgoraResult<SignedBlocklist> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| Error::Storage(format!("Failed to read file: {}", e)))?;
+
+        let signed: SignedBlocklist = serde_json::from_str(&json)
+            .map_err(|e| Error::Storage(format!("Failed to parse: {}", e)))?;
+
+        let mut blocklist = self.load()?;
+        blocklist.import_shared(signed.clone())?;
+        self.save(&blocklist)?;
+
+        Ok(signed)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,4 +312,36 @@ mod tests {
         assert_eq!(identity.peer_id(), imported.peer_id());
         assert_eq!(identity.display_name(), imported.display_name());
     }
+
+    #[test]
+    fn test_blocklist_persistence_and_sharing() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let storage =
+            BlocklistStorage::with_path(dir.path().join("ours")).expect("Failed to create storage");
+        assert!(storage.load().expect("Failed to load").is_empty());
+
+        let peer = Identity::generate().unwrap().libp2p_peer_id();
+        let mut blocklist = Blocklist::new();
+        blocklist.block(&peer, Some("spam".to_string()));
+        storage.save(&blocklist).expect("Failed to save");
+        assert!(storage.load().expect("Failed to load").is_blocked(&peer));
+
+        let contact = Identity::generate().unwrap();
+        let contact_storage = BlocklistStorage::with_path(dir.path().join("theirs"))
+            .expect("Failed to create storage");
+        let export_path = dir.path().join("shared.json");
+        contact_storage
+            .export_to_file(&blocklist, &contact, &export_path)
+            .expect("Failed to export");
+
+        let imported = storage
+            .import_from_file(&export_path)
+            .expect("Failed to import");
+        assert_eq!(imported.owner, contact.peer_id());
+        let reloaded = storage.load().expect("Failed to load");
+        assert_eq!(
+            reloaded.shared_owners().collect::<Vec<_>>(),
+            vec![contact.peer_id()]
+        );
+    }
 }
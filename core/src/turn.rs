@@ -1,11 +1,46 @@
 use crate::error::{AgoraResult, Error};
+use crate::identity::{verifying_key_from_peer_id, Identity};
+use ed25519_dalek::{Signature, Verifier};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stun::agent::TransactionId;
+use stun::attributes::{
+    ATTR_CHANNEL_NUMBER, ATTR_DATA, ATTR_LIFETIME, ATTR_MESSAGE_INTEGRITY, ATTR_NONCE, ATTR_REALM,
+    ATTR_REQUESTED_TRANSPORT, ATTR_USERNAME, ATTR_XORMAPPED_ADDRESS, ATTR_XOR_PEER_ADDRESS,
+    ATTR_XOR_RELAYED_ADDRESS,
+};
+use stun::error_code::{ErrorCodeAttribute, CODE_STALE_NONCE, CODE_UNAUTHORIZED};
+use stun::integrity::MessageIntegrity;
+use stun::message::{
+    Getter, Message, MessageType, Method, Setter, CLASS_ERROR_RESPONSE, CLASS_INDICATION,
+    CLASS_REQUEST, CLASS_SUCCESS_RESPONSE, METHOD_ALLOCATE, METHOD_CHANNEL_BIND,
+    METHOD_CREATE_PERMISSION, METHOD_DATA, METHOD_REFRESH, METHOD_SEND,
+};
+use stun::textattrs::TextAttribute;
+use stun::xoraddr::XorMappedAddress;
+use tokio::net::UdpSocket;
 
 pub const DEFAULT_TURN_PORT: u16 = 3478;
 pub const DEFAULT_TURN_TLS_PORT: u16 = 5349;
 pub const TURN_LIFETIME_SECONDS: u32 = 600;
 pub const TURN_REFRESH_MARGIN_SECONDS: u32 = 60;
+/// Permissions last five minutes on the server (RFC 8656 section 9).
+pub const TURN_PERMISSION_LIFETIME_SECONDS: u64 = 300;
+/// Channel bindings last ten minutes on the server (RFC 8656 section 12).
+pub const TURN_CHANNEL_LIFETIME_SECONDS: u64 = 600;
+
+const CREDENTIAL_SIGNING_DOMAIN: &str = "agora turn credentials v1";
+const CREDENTIAL_REQUEST_MAX_SKEW_SECONDS: u64 = 60;
+const CHANNEL_NUMBER_MIN: u16 = 0x4000;
+const CHANNEL_NUMBER_MAX: u16 = 0x4FFF;
+const CHANNEL_DATA_HEADER_SIZE: usize = 4;
+const PROTOCOL_UDP: u8 = 17;
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+const MAX_TRANSMISSIONS: u32 = 5;
+const MAX_DATAGRAM_SIZE: usize = 65536;
 
 #[derive(Debug, Clone)]
 pub struct TurnConfig {
@@ -26,6 +61,14 @@ impl Default for TurnConfig {
     }
 }
 
+impl TurnConfig {
+    pub fn with_credentials(mut self, credentials: &TurnCredentials) -> Self {
+        self.username = Some(credentials.username.clone());
+        self.password = Some(credentials.password.clone());
+        self
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TurnServer {
     pub address: SocketAddr,
@@ -75,6 +118,8 @@ impl TurnServer {
 pub struct TurnAllocation {
     pub server: SocketAddr,
     pub relayed_addr: SocketAddr,
+    /// Our address as seen by the server, from XOR-MAPPED-ADDRESS.
+    pub mapped_addr: Option<SocketAddr>,
     pub lifetime: Duration,
     pub created_at: std::time::Instant,
     pub username: String,
@@ -92,6 +137,7 @@ impl TurnAllocation {
         Self {
             server,
             relayed_addr,
+            mapped_addr: None,
             lifetime,
             created_at: std::time::Instant::now(),
             username,
@@ -126,7 +172,7 @@ impl TurnPermission {
         Self {
             peer_addr,
             created_at: std::time::Instant::now(),
-            lifetime: Duration::from_secs(300),
+            lifetime: Duration::from_secs(TURN_PERMISSION_LIFETIME_SECONDS),
         }
     }
 
@@ -135,18 +181,109 @@ impl TurnPermission {
     }
 }
 
+/// One allocation and the socket whose 5-tuple identifies it on the server.
+struct TurnSession {
+    socket: UdpSocket,
+    allocation: TurnAllocation,
+    realm: String,
+    nonce: String,
+    integrity: MessageIntegrity,
+    permissions: Vec<TurnPermission>,
+    channels: HashMap<SocketAddr, u16>,
+    next_channel: u16,
+    /// Peer data that arrived while waiting for a transaction response.
+    pending: VecDeque<(SocketAddr, Vec<u8>)>,
+}
+
+impl TurnSession {
+    fn server(&self) -> SocketAddr {
+        self.allocation.server
+    }
+
+    /// Sends an authenticated request, retrying once with a fresh nonce if
+    /// the server reports the current one as stale.
+    async fn request(
+        &mut self,
+        method: Method,
+        attributes: impl Fn(&mut Message) -> AgoraResult<()>,
+    ) -> AgoraResult<Message> {
+        for _ in 0..2 {
+            let mut request = new_message(method, CLASS_REQUEST)?;
+            attributes(&mut request)?;
+            add_credentials(
+                &mut request,
+                &self.allocation.username,
+                &self.realm,
+                &self.nonce,
+                &self.integrity,
+            )?;
+
+            let mut response = self.transact(&request).await?;
+            if response.typ.class == CLASS_SUCCESS_RESPONSE {
+                if response.contains(ATTR_MESSAGE_INTEGRITY) {
+                    self.integrity.check(&mut response).map_err(|e| {
+                        Error::Network(format!("TURN response failed integrity check: {}", e))
+                    })?;
+                }
+                return Ok(response);
+            }
+
+            let error = error_code(&response);
+            if error.code == CODE_STALE_NONCE {
+                self.nonce = text_attribute(&response, ATTR_NONCE)?;
+                tracing::debug!("TURN nonce from {} went stale, retrying", self.server());
+                continue;
+            }
+            return Err(turn_error(method, &error));
+        }
+        Err(Error::Network(format!(
+            "TURN {} kept failing with a stale nonce",
+            method
+        )))
+    }
+
+    async fn transact(&mut self, request: &Message) -> AgoraResult<Message> {
+        let server = self.server();
+        let mut pending = std::mem::take(&mut self.pending);
+        let channels = self.channels.clone();
+        let result = transact(&self.socket, server, request, |packet| {
+            if let Some(data) = parse_peer_data(packet, &channels) {
+                pending.push_back(data);
+            }
+        })
+        .await;
+        self.pending = pending;
+        result
+    }
+
+    fn has_permission(&self, peer_addr: SocketAddr) -> bool {
+        self.permissions
+            .iter()
+            .any(|p| p.peer_addr.ip() == peer_addr.ip() && !p.is_expired())
+    }
+
+    fn record_permission(&mut self, peer_addr: SocketAddr) {
+        self.permissions
+            .retain(|p| p.peer_addr.ip() != peer_addr.ip());
+        self.permissions.push(TurnPermission::new(peer_addr));
+    }
+}
+
+/// TURN client (RFC 8656) using long-term credentials over UDP.
+///
+/// Each allocation owns a UDP socket; data to and from peers goes through
+/// [`TurnClient::send_to`] and [`TurnClient::recv_from`]. Permission and
+/// data methods act on the first unexpired allocation.
 pub struct TurnClient {
     config: TurnConfig,
-    allocations: Vec<TurnAllocation>,
-    permissions: Vec<TurnPermission>,
+    sessions: Vec<TurnSession>,
 }
 
 impl TurnClient {
     pub fn new(config: TurnConfig) -> Self {
         Self {
             config,
-            allocations: vec![],
-            permissions: vec![],
+            sessions: vec![],
         }
     }
 
@@ -176,94 +313,453 @@ impl TurnClient {
         self.config.username.is_some() && self.config.password.is_some()
     }
 
+    /// Allocates a relayed address on `server`. The first Allocate is sent
+    /// without credentials to learn the realm and nonce from the server's
+    /// 401 response, then repeated with MESSAGE-INTEGRITY.
     pub async fn create_allocation(&mut self, server: &TurnServer) -> AgoraResult<TurnAllocation> {
-        if !self.has_credentials() {
+        let (Some(username), Some(password)) =
+            (self.config.username.clone(), self.config.password.clone())
+        else {
             return Err(Error::Network("TURN credentials required".to_string()));
+        };
+        if server.use_tls {
+            return Err(Error::Network(
+                "TURN over TLS is not supported yet".to_string(),
+            ));
         }
 
-        let username = self.config.username.clone().unwrap();
-        let realm = server.realm.clone();
+        let bind_addr: SocketAddr = if server.address.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|e| Error::Network(format!("Failed to bind TURN socket: {}", e)))?;
 
-        let allocation = TurnAllocation::new(
-            server.address,
-            self.simulate_relay_address()?,
-            self.config.lifetime,
-            username,
+        let lifetime = self.config.lifetime.as_secs() as u32;
+        let add_allocate_attributes = |m: &mut Message| -> AgoraResult<()> {
+            m.add(ATTR_REQUESTED_TRANSPORT, &[PROTOCOL_UDP, 0, 0, 0]);
+            m.add(ATTR_LIFETIME, &lifetime.to_be_bytes());
+            Ok(())
+        };
+
+        let mut probe = new_message(METHOD_ALLOCATE, CLASS_REQUEST)?;
+        add_allocate_attributes(&mut probe)?;
+        let challenge = transact(&socket, server.address, &probe, |_| {}).await?;
+        let error = error_code(&challenge);
+        if challenge.typ.class != CLASS_ERROR_RESPONSE || error.code != CODE_UNAUTHORIZED {
+            return Err(Error::Network(format!(
+                "TURN server {} did not ask for credentials",
+                server.address
+            )));
+        }
+        let realm = text_attribute(&challenge, ATTR_REALM)?;
+        let nonce = text_attribute(&challenge, ATTR_NONCE)?;
+        let integrity =
+            MessageIntegrity::new_long_term_integrity(username.clone(), realm.clone(), password);
+
+        let mut session = TurnSession {
+            socket,
+            allocation: TurnAllocation::new(
+                server.address,
+                server.address,
+                self.config.lifetime,
+                username,
+                Some(realm.clone()),
+            ),
             realm,
-        );
+            nonce,
+            integrity,
+            permissions: vec![],
+            channels: HashMap::new(),
+            next_channel: CHANNEL_NUMBER_MIN,
+            pending: VecDeque::new(),
+        };
+
+        let response = session
+            .request(METHOD_ALLOCATE, add_allocate_attributes)
+            .await?;
+        session.allocation.relayed_addr = xor_address(&response, ATTR_XOR_RELAYED_ADDRESS)?;
+        session.allocation.mapped_addr = xor_address(&response, ATTR_XORMAPPED_ADDRESS).ok();
+        if let Some(lifetime) = lifetime_attribute(&response) {
+            session.allocation.lifetime = lifetime;
+        }
 
+        let allocation = session.allocation.clone();
         tracing::info!(
             "Created TURN allocation on {} -> {}",
             server.address,
             allocation.relayed_addr
         );
 
-        self.allocations.push(allocation.clone());
+        self.sessions.retain(|s| s.server() != server.address);
+        self.sessions.push(session);
         Ok(allocation)
     }
 
-    fn simulate_relay_address(&self) -> AgoraResult<SocketAddr> {
-        let port: u16 = 50000 + (rand::random::<u16>() % 10000);
-        Ok(SocketAddr::new(
-            std::net::IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 1)),
-            port,
-        ))
-    }
-
     pub fn get_allocation(&self, server: SocketAddr) -> Option<&TurnAllocation> {
-        self.allocations.iter().find(|a| a.server == server)
+        self.sessions
+            .iter()
+            .map(|s| &s.allocation)
+            .find(|a| a.server == server)
     }
 
     pub fn get_active_allocation(&self) -> Option<&TurnAllocation> {
-        self.allocations.iter().find(|a| !a.is_expired())
+        self.sessions
+            .iter()
+            .map(|s| &s.allocation)
+            .find(|a| !a.is_expired())
+    }
+
+    fn session_mut(&mut self, server: SocketAddr) -> AgoraResult<&mut TurnSession> {
+        self.sessions
+            .iter_mut()
+            .find(|s| s.server() == server)
+            .ok_or_else(|| Error::Network(format!("No TURN allocation on {}", server)))
+    }
+
+    fn active_session_mut(&mut self) -> AgoraResult<&mut TurnSession> {
+        self.sessions
+            .iter_mut()
+            .find(|s| !s.allocation.is_expired())
+            .ok_or_else(|| Error::Network("No active TURN allocation".to_string()))
     }
 
     pub async fn refresh_allocation(&mut self, server: SocketAddr) -> AgoraResult<()> {
-        if let Some(idx) = self.allocations.iter().position(|a| a.server == server) {
-            let allocation = &mut self.allocations[idx];
-            allocation.lifetime = self.config.lifetime;
-            allocation.created_at = std::time::Instant::now();
-            tracing::debug!("Refreshed TURN allocation for {}", server);
-        }
+        let lifetime = self.config.lifetime.as_secs() as u32;
+        let session = self.session_mut(server)?;
+        let response = session
+            .request(METHOD_REFRESH, |m| {
+                m.add(ATTR_LIFETIME, &lifetime.to_be_bytes());
+                Ok(())
+            })
+            .await?;
+
+        let allocation = &mut session.allocation;
+        allocation.lifetime = lifetime_attribute(&response).unwrap_or(allocation.lifetime);
+        allocation.created_at = std::time::Instant::now();
+        tracing::debug!("Refreshed TURN allocation for {}", server);
         Ok(())
     }
 
+    /// Deletes the allocation on the server with a zero-lifetime Refresh.
+    pub async fn release_allocation(&mut self, server: SocketAddr) -> AgoraResult<()> {
+        let session = self.session_mut(server)?;
+        let result = session
+            .request(METHOD_REFRESH, |m| {
+                m.add(ATTR_LIFETIME, &0u32.to_be_bytes());
+                Ok(())
+            })
+            .await;
+        self.sessions.retain(|s| s.server() != server);
+        result.map(|_| ())
+    }
+
+    /// Lets `peer_addr`'s IP send to the active allocation's relayed address.
     pub async fn create_permission(&mut self, peer_addr: SocketAddr) -> AgoraResult<()> {
-        let permission = TurnPermission::new(peer_addr);
+        let session = self.active_session_mut()?;
+        session
+            .request(METHOD_CREATE_PERMISSION, |m| {
+                add_xor_address(m, ATTR_XOR_PEER_ADDRESS, peer_addr)
+            })
+            .await?;
+        session.record_permission(peer_addr);
         tracing::debug!("Created TURN permission for {}", peer_addr);
-        self.permissions.push(permission);
         Ok(())
     }
 
     pub fn has_permission(&self, peer_addr: SocketAddr) -> bool {
-        self.permissions
+        self.sessions.iter().any(|s| s.has_permission(peer_addr))
+    }
+
+    /// Binds a channel to `peer_addr` so data travels in 4-byte-header
+    /// ChannelData frames instead of Send/Data indications. Rebinding an
+    /// existing peer refreshes its binding.
+    pub async fn bind_channel(&mut self, peer_addr: SocketAddr) -> AgoraResult<u16> {
+        let session = self.active_session_mut()?;
+        let channel = match session.channels.get(&peer_addr) {
+            Some(channel) => *channel,
+            None if session.next_channel <= CHANNEL_NUMBER_MAX => session.next_channel,
+            None => return Err(Error::Network("No TURN channel numbers left".to_string())),
+        };
+
+        session
+            .request(METHOD_CHANNEL_BIND, |m| {
+                m.add(
+                    ATTR_CHANNEL_NUMBER,
+                    &[(channel >> 8) as u8, channel as u8, 0, 0],
+                );
+                add_xor_address(m, ATTR_XOR_PEER_ADDRESS, peer_addr)
+            })
+            .await?;
+
+        if session.channels.insert(peer_addr, channel).is_none() {
+            session.next_channel += 1;
+        }
+        session.record_permission(peer_addr);
+        tracing::debug!("Bound TURN channel {:#x} to {}", channel, peer_addr);
+        Ok(channel)
+    }
+
+    pub fn channel_for(&self, peer_addr: SocketAddr) -> Option<u16> {
+        self.sessions
             .iter()
-            .any(|p| p.peer_addr == peer_addr && !p.is_expired())
+            .find_map(|s| s.channels.get(&peer_addr).copied())
+    }
+
+    /// Relays `data` to `peer_addr`, over a channel if one is bound.
+    pub async fn send_to(&mut self, peer_addr: SocketAddr, data: &[u8]) -> AgoraResult<()> {
+        let session = self.active_session_mut()?;
+        if !session.has_permission(peer_addr) {
+            return Err(Error::Network(format!(
+                "No TURN permission for {}",
+                peer_addr
+            )));
+        }
+
+        let packet = match session.channels.get(&peer_addr) {
+            Some(channel) => encode_channel_data(*channel, data),
+            None => {
+                let mut indication = new_message(METHOD_SEND, CLASS_INDICATION)?;
+                add_xor_address(&mut indication, ATTR_XOR_PEER_ADDRESS, peer_addr)?;
+                indication.add(ATTR_DATA, data);
+                indication.raw
+            }
+        };
+
+        session
+            .socket
+            .send_to(&packet, session.server())
+            .await
+            .map_err(|e| Error::Network(format!("Failed to send to TURN server: {}", e)))?;
+        Ok(())
+    }
+
+    /// Waits for data relayed from a peer, returning the peer's address.
+    pub async fn recv_from(&mut self) -> AgoraResult<(SocketAddr, Vec<u8>)> {
+        let session = self.active_session_mut()?;
+        if let Some(data) = session.pending.pop_front() {
+            return Ok(data);
+        }
+
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            let (n, from) = session
+                .socket
+                .recv_from(&mut buf)
+                .await
+                .map_err(|e| Error::Network(format!("Failed to receive from TURN: {}", e)))?;
+            if from != session.server() {
+                continue;
+            }
+            if let Some(data) = parse_peer_data(&buf[..n], &session.channels) {
+                return Ok(data);
+            }
+        }
     }
 
     pub fn cleanup_expired(&mut self) {
-        self.allocations.retain(|a| !a.is_expired());
-        self.permissions.retain(|p| !p.is_expired());
+        self.sessions.retain(|s| !s.allocation.is_expired());
+        for session in &mut self.sessions {
+            session.permissions.retain(|p| !p.is_expired());
+        }
         tracing::debug!(
             "Cleaned up expired TURN resources: {} allocations, {} permissions remaining",
-            self.allocations.len(),
-            self.permissions.len()
+            self.allocation_count(),
+            self.permission_count()
         );
     }
 
     pub fn allocation_count(&self) -> usize {
-        self.allocations.len()
+        self.sessions.len()
     }
 
     pub fn permission_count(&self) -> usize {
-        self.permissions.len()
+        self.sessions.iter().map(|s| s.permissions.len()).sum()
+    }
+}
+
+fn new_message(method: Method, class: stun::message::MessageClass) -> AgoraResult<Message> {
+    let mut message = Message::new();
+    message
+        .build(&[
+            Box::new(MessageType::new(method, class)),
+            Box::new(TransactionId::new()),
+        ])
+        .map_err(|e| Error::Network(format!("Failed to build TURN {}: {}", method, e)))?;
+    Ok(message)
+}
+
+fn add_credentials(
+    message: &mut Message,
+    username: &str,
+    realm: &str,
+    nonce: &str,
+    integrity: &MessageIntegrity,
+) -> AgoraResult<()> {
+    let attributes: [&dyn Setter; 4] = [
+        &TextAttribute::new(ATTR_USERNAME, username.to_string()),
+        &TextAttribute::new(ATTR_REALM, realm.to_string()),
+        &TextAttribute::new(ATTR_NONCE, nonce.to_string()),
+        integrity,
+    ];
+    for attribute in attributes {
+        attribute
+            .add_to(message)
+            .map_err(|e| Error::Network(format!("Failed to add TURN credentials: {}", e)))?;
+    }
+    Ok(())
+}
+
+fn add_xor_address(
+    message: &mut Message,
+    attribute: stun::attributes::AttrType,
+    addr: SocketAddr,
+) -> AgoraResult<()> {
+    XorMappedAddress {
+        ip: addr.ip(),
+        port: addr.port(),
+    }
+    .add_to_as(message, attribute)
+    .map_err(|e| Error::Network(format!("Failed to encode {}: {}", attribute, e)))
+}
+
+fn xor_address(
+    message: &Message,
+    attribute: stun::attributes::AttrType,
+) -> AgoraResult<SocketAddr> {
+    let mut addr = XorMappedAddress::default();
+    addr.get_from_as(message, attribute)
+        .map_err(|e| Error::Network(format!("Missing {}: {}", attribute, e)))?;
+    Ok(SocketAddr::new(addr.ip, addr.port))
+}
+
+fn text_attribute(message: &Message, attribute: stun::attributes::AttrType) -> AgoraResult<String> {
+    TextAttribute::get_from_as(message, attribute)
+        .map(|a| a.text)
+        .map_err(|e| Error::Network(format!("Missing {}: {}", attribute, e)))
+}
+
+fn lifetime_attribute(message: &Message) -> Option<Duration> {
+    let value: [u8; 4] = message.get(ATTR_LIFETIME).ok()?.try_into().ok()?;
+    Some(Duration::from_secs(u32::from_be_bytes(value) as u64))
+}
+
+fn error_code(message: &Message) -> ErrorCodeAttribute {
+    let mut error = ErrorCodeAttribute::default();
+    let _ = error.get_from(message);
+    error
+}
+
+fn turn_error(method: Method, error: &ErrorCodeAttribute) -> Error {
+    Error::Network(format!(
+        "TURN {} failed: {} {}",
+        method,
+        error.code.0,
+        String::from_utf8_lossy(&error.reason)
+    ))
+}
+
+fn encode_channel_data(channel: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(CHANNEL_DATA_HEADER_SIZE + data.len());
+    packet.extend_from_slice(&channel.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+fn decode_channel_data(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < CHANNEL_DATA_HEADER_SIZE {
+        return None;
     }
+    let channel = u16::from_be_bytes([packet[0], packet[1]]);
+    let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if !(CHANNEL_NUMBER_MIN..=CHANNEL_NUMBER_MAX).contains(&channel)
+        || packet.len() < CHANNEL_DATA_HEADER_SIZE + len
+    {
+        return None;
+    }
+    Some((
+        channel,
+        &packet[CHANNEL_DATA_HEADER_SIZE..CHANNEL_DATA_HEADER_SIZE + len],
+    ))
+}
+
+/// Extracts peer data from a ChannelData frame or Data indication.
+fn parse_peer_data(
+    packet: &[u8],
+    channels: &HashMap<SocketAddr, u16>,
+) -> Option<(SocketAddr, Vec<u8>)> {
+    if let Some((channel, data)) = decode_channel_data(packet) {
+        let peer = channels
+            .iter()
+            .find(|(_, c)| **c == channel)
+            .map(|(peer, _)| *peer)?;
+        return Some((peer, data.to_vec()));
+    }
+
+    let mut message = Message::new();
+    message.unmarshal_binary(packet).ok()?;
+    if message.typ != MessageType::new(METHOD_DATA, CLASS_INDICATION) {
+        return None;
+    }
+    let peer = xor_address(&message, ATTR_XOR_PEER_ADDRESS).ok()?;
+    let data = message.get(ATTR_DATA).ok()?;
+    Some((peer, data))
+}
+
+/// Sends `request` with exponential-backoff retransmission until a response
+/// with its transaction ID arrives. Other packets go to `on_other`.
+async fn transact(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    request: &Message,
+    mut on_other: impl FnMut(&[u8]),
+) -> AgoraResult<Message> {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    let mut rto = INITIAL_RTO;
+
+    for _ in 0..MAX_TRANSMISSIONS {
+        socket
+            .send_to(&request.raw, server)
+            .await
+            .map_err(|e| Error::Network(format!("Failed to send to TURN server: {}", e)))?;
+
+        let deadline = tokio::time::Instant::now() + rto;
+        loop {
+            let received = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await;
+            let Ok(received) = received else {
+                break;
+            };
+            let (n, from) = received
+                .map_err(|e| Error::Network(format!("Failed to receive from TURN: {}", e)))?;
+            if from != server {
+                continue;
+            }
+
+            let mut response = Message::new();
+            if response.unmarshal_binary(&buf[..n]).is_ok()
+                && response.transaction_id == request.transaction_id
+            {
+                return Ok(response);
+            }
+            on_other(&buf[..n]);
+        }
+        rto *= 2;
+    }
+
+    Err(Error::Network(format!(
+        "TURN server {} did not respond to {}",
+        server, request.typ
+    )))
 }
 
 #[derive(Debug, Clone)]
 pub struct TurnCandidate {
     pub relayed_addr: SocketAddr,
     pub server: SocketAddr,
+    pub mapped_addr: Option<SocketAddr>,
     pub priority: u32,
 }
 
@@ -277,13 +773,110 @@ impl TurnCandidate {
         Self {
             relayed_addr,
             server,
+            mapped_addr: None,
             priority,
         }
     }
 
+    pub fn from_allocation(allocation: &TurnAllocation, local_preference: u16) -> Self {
+        Self {
+            mapped_addr: allocation.mapped_addr,
+            ..Self::new(allocation.relayed_addr, allocation.server, local_preference)
+        }
+    }
+
+    /// The related address is our server-reflexive address when the server
+    /// reported one, as RFC 8445 asks for relayed candidates.
     pub fn to_ice_candidate(&self) -> crate::ice::Candidate {
-        crate::ice::Candidate::new_relayed(self.relayed_addr, self.server, 1)
+        let mut candidate = crate::ice::Candidate::new_relayed(self.relayed_addr, self.server, 1);
+        if let Some(mapped) = self.mapped_addr {
+            candidate.related_addr = Some(mapped);
+        }
+        candidate
+    }
+}
+
+/// Asks a relay node for TURN credentials, proving control of an Agora
+/// identity. Signed for one relay so it cannot be replayed against another.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnCredentialRequest {
+    pub peer_id: String,
+    pub relay: String,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct CredentialSigningPayload<'a> {
+    domain: &'static str,
+    peer_id: &'a str,
+    relay: &'a str,
+    timestamp: u64,
+}
+
+impl TurnCredentialRequest {
+    pub fn sign(identity: &Identity, relay: &str) -> AgoraResult<Self> {
+        let mut request = Self {
+            peer_id: identity.peer_id(),
+            relay: relay.to_string(),
+            timestamp: now_secs(),
+            signature: Vec::new(),
+        };
+        request.signature = identity.sign(&request.signing_bytes()?).to_bytes().to_vec();
+        Ok(request)
     }
+
+    fn signing_bytes(&self) -> AgoraResult<Vec<u8>> {
+        postcard::to_allocvec(&CredentialSigningPayload {
+            domain: CREDENTIAL_SIGNING_DOMAIN,
+            peer_id: &self.peer_id,
+            relay: &self.relay,
+            timestamp: self.timestamp,
+        })
+        .map_err(|e| Error::Crypto(format!("Failed to encode credential request: {}", e)))
+    }
+
+    /// Checks the signature, that the request is for `relay` and that it was
+    /// signed within the last minute. Returns the requesting peer.
+    pub fn verify(&self, relay: &str) -> AgoraResult<PeerId> {
+        if self.relay != relay {
+            return Err(Error::Crypto(format!(
+                "Credential request is for relay {}",
+                self.relay
+            )));
+        }
+        if now_secs().abs_diff(self.timestamp) > CREDENTIAL_REQUEST_MAX_SKEW_SECONDS {
+            return Err(Error::Crypto("Credential request expired".to_string()));
+        }
+
+        let peer_id: PeerId = self
+            .peer_id
+            .parse()
+            .map_err(|e| Error::Crypto(format!("Invalid peer ID {}: {}", self.peer_id, e)))?;
+        let key = verifying_key_from_peer_id(&peer_id)?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|e| Error::Crypto(format!("Invalid signature: {}", e)))?;
+        key.verify(&self.signing_bytes()?, &signature)
+            .map_err(|_| Error::Crypto("Credential request signature mismatch".to_string()))?;
+        Ok(peer_id)
+    }
+}
+
+/// Time-limited TURN credentials issued by a relay node. The username
+/// embeds the expiry and the peer ID they were issued to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnCredentials {
+    pub username: String,
+    pub password: String,
+    pub ttl: u64,
+    pub uris: Vec<String>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
 #[cfg(test)]
@@ -400,4 +993,335 @@ mod tests {
             "203.0.113.1:50000".parse().unwrap()
         );
     }
+
+    /// Just enough of a TURN server for the client: long-term auth with a
+    /// fixed nonce, one relay socket, permissions and channels.
+    mod stand_in {
+        use super::*;
+        use std::sync::Arc;
+        use tokio::sync::Mutex;
+
+        pub const REALM: &str = "agora.test";
+        pub const USER: &str = "alice";
+        pub const PASS: &str = "secret";
+
+        #[derive(Default)]
+        struct State {
+            nonce: String,
+            stale_nonces: u32,
+            relay: Option<Arc<UdpSocket>>,
+            permissions: Vec<std::net::IpAddr>,
+            channels: HashMap<u16, SocketAddr>,
+        }
+
+        pub struct Server {
+            pub addr: SocketAddr,
+            state: Arc<Mutex<State>>,
+        }
+
+        impl Server {
+            pub async fn start() -> Self {
+                let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+                let addr = socket.local_addr().unwrap();
+                let state = Arc::new(Mutex::new(State {
+                    nonce: "nonce-1".to_string(),
+                    ..Default::default()
+                }));
+                tokio::spawn(serve(socket, state.clone()));
+                Self { addr, state }
+            }
+
+            /// Rejects the next `count` authenticated requests as stale.
+            pub async fn expire_nonces(&self, count: u32) {
+                self.state.lock().await.stale_nonces = count;
+            }
+        }
+
+        fn reply(request: &Message, class: stun::message::MessageClass) -> Message {
+            let mut response = Message::new();
+            response
+                .build(&[
+                    Box::new(request.clone()),
+                    Box::new(MessageType::new(request.typ.method, class)),
+                ])
+                .unwrap();
+            response
+        }
+
+        fn error(request: &Message, code: stun::error_code::ErrorCode, nonce: &str) -> Message {
+            let mut response = reply(request, CLASS_ERROR_RESPONSE);
+            ErrorCodeAttribute {
+                code,
+                reason: vec![],
+            }
+            .add_to(&mut response)
+            .unwrap();
+            TextAttribute::new(ATTR_REALM, REALM.to_string())
+                .add_to(&mut response)
+                .unwrap();
+            TextAttribute::new(ATTR_NONCE, nonce.to_string())
+                .add_to(&mut response)
+                .unwrap();
+            response
+        }
+
+        async fn serve(socket: Arc<UdpSocket>, shared: Arc<Mutex<State>>) {
+            let integrity = MessageIntegrity::new_long_term_integrity(
+                USER.to_string(),
+                REALM.to_string(),
+                PASS.to_string(),
+            );
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            loop {
+                let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+                let mut state = shared.lock().await;
+
+                if let Some((channel, data)) = decode_channel_data(&buf[..n]) {
+                    if let (Some(peer), Some(relay)) = (state.channels.get(&channel), &state.relay)
+                    {
+                        relay.send_to(data, peer).await.unwrap();
+                    }
+                    continue;
+                }
+
+                let mut request = Message::new();
+                if request.unmarshal_binary(&buf[..n]).is_err() {
+                    continue;
+                }
+
+                if request.typ == MessageType::new(METHOD_SEND, CLASS_INDICATION) {
+                    let peer = xor_address(&request, ATTR_XOR_PEER_ADDRESS).unwrap();
+                    let data = request.get(ATTR_DATA).unwrap();
+                    if state.permissions.contains(&peer.ip()) {
+                        if let Some(relay) = &state.relay {
+                            relay.send_to(&data, peer).await.unwrap();
+                        }
+                    }
+                    continue;
+                }
+
+                if !request.contains(ATTR_MESSAGE_INTEGRITY) {
+                    let response = error(&request, CODE_UNAUTHORIZED, &state.nonce);
+                    socket.send_to(&response.raw, from).await.unwrap();
+                    continue;
+                }
+                if integrity.check(&mut request).is_err() {
+                    let response = error(
+                        &request,
+                        stun::error_code::CODE_WRONG_CREDENTIALS,
+                        &state.nonce,
+                    );
+                    socket.send_to(&response.raw, from).await.unwrap();
+                    continue;
+                }
+                let nonce = text_attribute(&request, ATTR_NONCE).unwrap();
+                if state.stale_nonces > 0 || nonce != state.nonce {
+                    if state.stale_nonces > 0 {
+                        state.stale_nonces -= 1;
+                        state.nonce = format!("{}+", state.nonce);
+                    }
+                    let response = error(&request, CODE_STALE_NONCE, &state.nonce);
+                    socket.send_to(&response.raw, from).await.unwrap();
+                    continue;
+                }
+
+                let mut response = reply(&request, CLASS_SUCCESS_RESPONSE);
+                match request.typ.method {
+                    METHOD_ALLOCATE => {
+                        let relay = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+                        add_xor_address(
+                            &mut response,
+                            ATTR_XOR_RELAYED_ADDRESS,
+                            relay.local_addr().unwrap(),
+                        )
+                        .unwrap();
+                        add_xor_address(&mut response, ATTR_XORMAPPED_ADDRESS, from).unwrap();
+                        response.add(ATTR_LIFETIME, &600u32.to_be_bytes());
+                        tokio::spawn(forward(relay.clone(), socket.clone(), from, shared.clone()));
+                        state.relay = Some(relay);
+                    }
+                    METHOD_REFRESH => {
+                        let lifetime = request.get(ATTR_LIFETIME).unwrap();
+                        response.add(ATTR_LIFETIME, &lifetime);
+                        if lifetime == [0, 0, 0, 0] {
+                            state.relay = None;
+                        }
+                    }
+                    METHOD_CREATE_PERMISSION => {
+                        let peer = xor_address(&request, ATTR_XOR_PEER_ADDRESS).unwrap();
+                        state.permissions.push(peer.ip());
+                    }
+                    METHOD_CHANNEL_BIND => {
+                        let peer = xor_address(&request, ATTR_XOR_PEER_ADDRESS).unwrap();
+                        let number = request.get(ATTR_CHANNEL_NUMBER).unwrap();
+                        let channel = u16::from_be_bytes([number[0], number[1]]);
+                        state.channels.insert(channel, peer);
+                        state.permissions.push(peer.ip());
+                    }
+                    _ => continue,
+                }
+                integrity.add_to(&mut response).unwrap();
+                socket.send_to(&response.raw, from).await.unwrap();
+            }
+        }
+
+        /// Relays peer datagrams to the client over a bound channel when
+        /// there is one, otherwise as Data indications.
+        async fn forward(
+            relay: Arc<UdpSocket>,
+            server: Arc<UdpSocket>,
+            client: SocketAddr,
+            shared: Arc<Mutex<State>>,
+        ) {
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            while let Ok((n, peer)) = relay.recv_from(&mut buf).await {
+                let state = shared.lock().await;
+                if !state.permissions.contains(&peer.ip()) {
+                    continue;
+                }
+                let channel = state
+                    .channels
+                    .iter()
+                    .find(|(_, p)| **p == peer)
+                    .map(|(c, _)| *c);
+                let packet = match channel {
+                    Some(channel) => encode_channel_data(channel, &buf[..n]),
+                    None => {
+                        let mut indication = new_message(METHOD_DATA, CLASS_INDICATION).unwrap();
+                        add_xor_address(&mut indication, ATTR_XOR_PEER_ADDRESS, peer).unwrap();
+                        indication.add(ATTR_DATA, &buf[..n]);
+                        indication.raw
+                    }
+                };
+                server.send_to(&packet, client).await.unwrap();
+            }
+        }
+    }
+
+    async fn allocate(server: &stand_in::Server) -> (TurnClient, TurnAllocation) {
+        let turn_server = TurnServer::new(server.addr);
+        let mut client = TurnClient::with_servers(vec![turn_server.clone()])
+            .with_credentials(stand_in::USER.to_string(), stand_in::PASS.to_string());
+        let allocation = client.create_allocation(&turn_server).await.unwrap();
+        (client, allocation)
+    }
+
+    #[tokio::test]
+    async fn test_allocate_with_long_term_credentials() {
+        let server = stand_in::Server::start().await;
+        let (client, allocation) = allocate(&server).await;
+
+        assert_eq!(allocation.realm.as_deref(), Some(stand_in::REALM));
+        assert_eq!(allocation.lifetime, Duration::from_secs(600));
+        assert_ne!(allocation.relayed_addr, server.addr);
+        assert!(allocation.mapped_addr.is_some());
+        assert_eq!(client.allocation_count(), 1);
+
+        let candidate = TurnCandidate::from_allocation(&allocation, 0).to_ice_candidate();
+        assert_eq!(candidate.connection_addr, allocation.relayed_addr);
+        assert_eq!(candidate.related_addr, allocation.mapped_addr);
+    }
+
+    #[tokio::test]
+    async fn test_allocate_with_wrong_password_fails() {
+        let server = stand_in::Server::start().await;
+        let turn_server = TurnServer::new(server.addr);
+        let mut client = TurnClient::with_servers(vec![turn_server.clone()])
+            .with_credentials(stand_in::USER.to_string(), "wrong".to_string());
+
+        assert!(client.create_allocation(&turn_server).await.is_err());
+        assert_eq!(client.allocation_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_nonce_is_retried() {
+        let server = stand_in::Server::start().await;
+        let (mut client, allocation) = allocate(&server).await;
+
+        server.expire_nonces(1).await;
+        client.refresh_allocation(allocation.server).await.unwrap();
+
+        server.expire_nonces(2).await;
+        assert!(client.refresh_allocation(allocation.server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_relay_with_send_and_data_indications() {
+        let server = stand_in::Server::start().await;
+        let (mut client, allocation) = allocate(&server).await;
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        assert!(client.send_to(peer_addr, b"early").await.is_err());
+        client.create_permission(peer_addr).await.unwrap();
+        assert!(client.has_permission(peer_addr));
+
+        client.send_to(peer_addr, b"hello peer").await.unwrap();
+        let mut buf = [0u8; 64];
+        let (n, from) = peer.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello peer");
+        assert_eq!(from, allocation.relayed_addr);
+
+        peer.send_to(b"hello client", from).await.unwrap();
+        let (from, data) = client.recv_from().await.unwrap();
+        assert_eq!(from, peer_addr);
+        assert_eq!(data, b"hello client");
+    }
+
+    #[tokio::test]
+    async fn test_relay_over_channel() {
+        let server = stand_in::Server::start().await;
+        let (mut client, allocation) = allocate(&server).await;
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        let channel = client.bind_channel(peer_addr).await.unwrap();
+        assert_eq!(channel, CHANNEL_NUMBER_MIN);
+        assert_eq!(client.bind_channel(peer_addr).await.unwrap(), channel);
+        assert_eq!(client.channel_for(peer_addr), Some(channel));
+
+        client.send_to(peer_addr, b"over channel").await.unwrap();
+        let mut buf = [0u8; 64];
+        let (n, from) = peer.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"over channel");
+        assert_eq!(from, allocation.relayed_addr);
+
+        peer.send_to(b"channel reply", from).await.unwrap();
+        let (from, data) = client.recv_from().await.unwrap();
+        assert_eq!(from, peer_addr);
+        assert_eq!(data, b"channel reply");
+
+        client.release_allocation(allocation.server).await.unwrap();
+        assert_eq!(client.allocation_count(), 0);
+    }
+
+    #[test]
+    fn test_channel_data_roundtrip() {
+        let packet = encode_channel_data(0x4001, b"abc");
+        assert_eq!(decode_channel_data(&packet), Some((0x4001, &b"abc"[..])));
+        assert_eq!(
+            decode_channel_data(&encode_channel_data(0x3fff, b"x")),
+            None
+        );
+        assert_eq!(decode_channel_data(&packet[..5]), None);
+    }
+
+    #[test]
+    fn test_credential_request_verification() {
+        let identity = Identity::generate().unwrap();
+        let request = TurnCredentialRequest::sign(&identity, "relay-a").unwrap();
+        assert_eq!(
+            request.verify("relay-a").unwrap(),
+            identity.libp2p_peer_id()
+        );
+        assert!(request.verify("relay-b").is_err());
+
+        let mut forged = request.clone();
+        forged.peer_id = Identity::generate().unwrap().peer_id();
+        assert!(forged.verify("relay-a").is_err());
+
+        let mut stale = request;
+        stale.timestamp -= 3600;
+        assert!(stale.verify("relay-a").is_err());
+    }
 }
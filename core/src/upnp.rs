@@ -1,10 +1,40 @@
 use crate::error::{AgoraResult, Error};
-use std::net::{IpAddr, Ipv4Addr};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use xmltree::Element;
 
 pub const UPNP_DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 pub const UPNP_DEFAULT_LEASE: Duration = Duration::from_secs(3600);
 pub const NAT_PMP_DEFAULT_PORT: u16 = 5351;
+pub const SSDP_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+pub const SSDP_PORT: u16 = 1900;
+
+const SSDP_MX_SECONDS: u32 = 2;
+const SSDP_SETTLE_TIME: Duration = Duration::from_millis(300);
+const SSDP_SEARCH_TARGETS: [&str; 3] = [
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:2",
+    WAN_IP_CONNECTION_1,
+];
+const WAN_IP_CONNECTION_1: &str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+const WAN_CONNECTION_SERVICES: [&str; 3] = [
+    WAN_IP_CONNECTION_1,
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+const NAT_PMP_VERSION: u8 = 0;
+const PCP_VERSION: u8 = 2;
+const PCP_HEADER_LEN: usize = 24;
+const PCP_OPCODE_ANNOUNCE: u8 = 0;
+const PCP_OPCODE_MAP: u8 = 1;
+const PCP_OPCODE_PEER: u8 = 2;
+const PORT_MAPPING_INITIAL_RTO: Duration = Duration::from_millis(250);
+const SPECIFIED_ARRAY_INDEX_INVALID: u16 = 713;
+const ONLY_PERMANENT_LEASES_SUPPORTED: u16 = 725;
+const MAX_MAPPING_ENTRIES: u32 = 256;
 
 #[derive(Debug, Clone)]
 pub struct PortMapping {
@@ -44,7 +74,7 @@ impl PortMapping {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Protocol {
     Tcp,
     Udp,
@@ -77,7 +107,7 @@ impl UpnpDevice {
             manufacturer: String::new(),
             model_name: String::new(),
             control_url: String::new(),
-            service_type: "urn:schemas-upnp-org:service:WANIPConnection:1".to_string(),
+            service_type: WAN_IP_CONNECTION_1.to_string(),
         }
     }
 
@@ -90,6 +120,34 @@ impl UpnpDevice {
         self.control_url = url;
         self
     }
+
+    /// Builds a device from its description XML, picking the first
+    /// WANIPConnection service, or WANPPPConnection if there is none.
+    pub fn from_description(location: &str, xml: &str) -> AgoraResult<Self> {
+        let root = Element::parse(xml.as_bytes())
+            .map_err(|e| Error::Network(format!("Invalid UPnP description: {}", e)))?;
+        let device = root
+            .get_child("device")
+            .ok_or_else(|| Error::Network("UPnP description has no device".to_string()))?;
+
+        let mut services = Vec::new();
+        collect_services(device, &mut services);
+        let (service_type, control_url) = WAN_CONNECTION_SERVICES
+            .iter()
+            .find_map(|wanted| services.iter().find(|(typ, _)| typ == wanted))
+            .cloned()
+            .ok_or_else(|| Error::Network(format!("{} has no WAN connection service", location)))?;
+
+        let base = child_text(&root, "URLBase").unwrap_or_else(|| location.to_string());
+        Ok(Self {
+            location: location.to_string(),
+            friendly_name: child_text(device, "friendlyName").unwrap_or_default(),
+            manufacturer: child_text(device, "manufacturer").unwrap_or_default(),
+            model_name: child_text(device, "modelName").unwrap_or_default(),
+            control_url: resolve_url(&base, &control_url),
+            service_type,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -97,6 +155,8 @@ pub struct UpnpConfig {
     pub search_timeout: Duration,
     pub lease_duration: Duration,
     pub description: String,
+    /// Where M-SEARCH requests go; the SSDP multicast group by default.
+    pub ssdp_addr: SocketAddr,
 }
 
 impl Default for UpnpConfig {
@@ -105,10 +165,12 @@ impl Default for UpnpConfig {
             search_timeout: UPNP_DEFAULT_TIMEOUT,
             lease_duration: UPNP_DEFAULT_LEASE,
             description: "Agora P2P Voice".to_string(),
+            ssdp_addr: SocketAddr::new(IpAddr::V4(SSDP_MULTICAST_ADDR), SSDP_PORT),
         }
     }
 }
 
+/// UPnP IGD client: SSDP discovery plus the WANIPConnection SOAP actions.
 pub struct UpnpClient {
     config: UpnpConfig,
     devices: Vec<UpnpDevice>,
@@ -144,30 +206,118 @@ impl UpnpClient {
         self.external_ip
     }
 
+    /// Sends SSDP M-SEARCH requests and fetches the description of every
+    /// gateway that answers. Fails if no usable gateway is found.
     pub async fn discover(&mut self) -> AgoraResult<Vec<UpnpDevice>> {
-        tracing::info!("Starting UPnP device discovery");
+        tracing::info!(
+            "Starting UPnP device discovery via {}",
+            self.config.ssdp_addr
+        );
 
-        let simulated_device = UpnpDevice::new("http://192.168.1.1:49152/rootDesc.xml".to_string())
-            .with_friendly_name("Simulated Router".to_string())
-            .with_control_url("/upnp/control/WANIPConn1".to_string());
+        let bind_addr: SocketAddr = if self.config.ssdp_addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|e| Error::Network(format!("Failed to bind SSDP socket: {}", e)))?;
+
+        for target in SSDP_SEARCH_TARGETS {
+            let request = format!(
+                "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+                self.config.ssdp_addr,
+                SSDP_MX_SECONDS,
+                target
+            );
+            socket
+                .send_to(request.as_bytes(), self.config.ssdp_addr)
+                .await
+                .map_err(|e| Error::Network(format!("Failed to send M-SEARCH: {}", e)))?;
+        }
 
-        self.devices.push(simulated_device.clone());
-        self.external_ip = Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 50)));
+        let mut locations = Vec::new();
+        let mut buf = vec![0u8; 2048];
+        let mut deadline = tokio::time::Instant::now() + self.config.search_timeout;
+        while let Ok(Ok((n, from))) =
+            tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await
+        {
+            let response = String::from_utf8_lossy(&buf[..n]);
+            let Some(location) = header_value(&response, "location") else {
+                continue;
+            };
+            tracing::debug!("SSDP response from {}: {}", from, location);
+            if !locations.contains(&location) {
+                locations.push(location);
+            }
+            // Gateways answer together; don't sit out the whole timeout.
+            deadline = deadline.min(tokio::time::Instant::now() + SSDP_SETTLE_TIME);
+        }
 
+        let mut devices = Vec::new();
+        for location in locations {
+            match self.fetch_description(&location).await {
+                Ok(device) => devices.push(device),
+                Err(e) => tracing::debug!("Skipping UPnP device at {}: {}", location, e),
+            }
+        }
+        if devices.is_empty() {
+            return Err(Error::Network("No UPnP gateway found".to_string()));
+        }
+
+        self.devices = devices;
         tracing::info!("Found {} UPnP device(s)", self.devices.len());
+
+        match query_external_ip(&self.devices[0], self.config.search_timeout).await {
+            Ok(ip) => self.external_ip = Some(ip),
+            Err(e) => tracing::debug!("GetExternalIPAddress failed: {}", e),
+        }
         Ok(self.devices.clone())
     }
 
-    pub async fn add_port_mapping(&mut self, mapping: &PortMapping) -> AgoraResult<()> {
+    async fn fetch_description(&self, location: &str) -> AgoraResult<UpnpDevice> {
+        let (status, body) =
+            http_request("GET", location, &[], "", self.config.search_timeout).await?;
+        if status != 200 {
+            return Err(Error::Network(format!(
+                "Description request returned {}",
+                status
+            )));
+        }
+        UpnpDevice::from_description(location, &body)
+    }
+
+    async fn device(&mut self) -> AgoraResult<UpnpDevice> {
         if self.devices.is_empty() {
             self.discover().await?;
         }
-
-        let device = self
-            .devices
+        self.devices
             .first()
-            .ok_or_else(|| Error::Network("No UPnP device found".to_string()))?;
+            .cloned()
+            .ok_or_else(|| Error::Network("No UPnP device found".to_string()))
+    }
 
+    /// The local address the gateway sees us as, for NewInternalClient.
+    pub async fn local_ip(&mut self) -> AgoraResult<IpAddr> {
+        let device = self.device().await?;
+        let (host, port, _) = split_url(&device.control_url)?;
+        let socket = std::net::UdpSocket::bind(if host.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        })
+        .map_err(|e| Error::Network(format!("Failed to bind socket: {}", e)))?;
+        socket
+            .connect(format!("{}:{}", host, port))
+            .map_err(|e| Error::Network(format!("Failed to route to gateway: {}", e)))?;
+        socket
+            .local_addr()
+            .map(|addr| addr.ip())
+            .map_err(|e| Error::Network(format!("Failed to get local address: {}", e)))
+    }
+
+    pub async fn add_port_mapping(&mut self, mapping: &PortMapping) -> AgoraResult<()> {
+        let device = self.device().await?;
         let lease = mapping.lease_duration.min(self.config.lease_duration);
 
         tracing::info!(
@@ -181,13 +331,16 @@ impl UpnpClient {
             lease
         );
 
-        tracing::debug!(
-            "UPnP AddPortMapping to device {} at {}",
-            device.friendly_name,
-            device.control_url
-        );
-
-        Ok(())
+        let result = add_port_mapping(&device, mapping, lease, self.config.search_timeout).await;
+        match result {
+            Err(UpnpError::Soap(code, _)) if code == ONLY_PERMANENT_LEASES_SUPPORTED => {
+                tracing::debug!("{} only supports permanent leases", device.friendly_name);
+                add_port_mapping(&device, mapping, Duration::ZERO, self.config.search_timeout)
+                    .await
+                    .map_err(Error::from)
+            }
+            result => result.map_err(Error::from),
+        }
     }
 
     pub async fn remove_port_mapping(
@@ -195,23 +348,20 @@ impl UpnpClient {
         external_port: u16,
         protocol: Protocol,
     ) -> AgoraResult<()> {
-        if self.devices.is_empty() {
-            self.discover().await?;
-        }
-
-        let device = self
-            .devices
-            .first()
-            .ok_or_else(|| Error::Network("No UPnP device found".to_string()))?;
-
+        let device = self.device().await?;
         tracing::info!("Removing UPnP port mapping: {} {}", external_port, protocol);
 
-        tracing::debug!(
-            "UPnP DeletePortMapping from device {} at {}",
-            device.friendly_name,
-            device.control_url
-        );
-
+        soap_request(
+            &device,
+            "DeletePortMapping",
+            &[
+                ("NewRemoteHost", String::new()),
+                ("NewExternalPort", external_port.to_string()),
+                ("NewProtocol", protocol.to_string()),
+            ],
+            self.config.search_timeout,
+        )
+        .await?;
         Ok(())
     }
 
@@ -220,14 +370,41 @@ impl UpnpClient {
             return Ok(ip);
         }
 
-        self.discover().await?;
-
-        self.external_ip
-            .ok_or_else(|| Error::Network("Could not determine external IP via UPnP".to_string()))
+        let device = self.device().await?;
+        let ip = query_external_ip(&device, self.config.search_timeout).await?;
+        self.external_ip = Some(ip);
+        Ok(ip)
     }
 
+    /// Lists the gateway's mappings with GetGenericPortMappingEntry until
+    /// it reports the index as out of range.
     pub async fn get_mappings(&self) -> AgoraResult<Vec<PortMapping>> {
-        Ok(Vec::new())
+        let device = self
+            .devices
+            .first()
+            .ok_or_else(|| Error::Network("No UPnP device found".to_string()))?;
+
+        let mut mappings = Vec::new();
+        for index in 0..MAX_MAPPING_ENTRIES {
+            let response = match soap_request(
+                device,
+                "GetGenericPortMappingEntry",
+                &[("NewPortMappingIndex", index.to_string())],
+                self.config.search_timeout,
+            )
+            .await
+            {
+                Ok(response) => response,
+                Err(UpnpError::Soap(code, _)) if code == SPECIFIED_ARRAY_INDEX_INVALID => break,
+                Err(e) => return Err(e.into()),
+            };
+
+            match mapping_from_entry(&response) {
+                Some(mapping) => mappings.push(mapping),
+                None => tracing::debug!("Skipping malformed port mapping entry {}", index),
+            }
+        }
+        Ok(mappings)
     }
 }
 
@@ -237,9 +414,317 @@ impl Default for UpnpClient {
     }
 }
 
+#[derive(Debug)]
+enum UpnpError {
+    /// A UPnPError fault from the gateway: errorCode and errorDescription.
+    Soap(u16, String),
+    Other(Error),
+}
+
+impl From<Error> for UpnpError {
+    fn from(error: Error) -> Self {
+        UpnpError::Other(error)
+    }
+}
+
+impl From<UpnpError> for Error {
+    fn from(error: UpnpError) -> Self {
+        match error {
+            UpnpError::Soap(code, description) => {
+                Error::Network(format!("UPnP error {}: {}", code, description))
+            }
+            UpnpError::Other(error) => error,
+        }
+    }
+}
+
+async fn query_external_ip(device: &UpnpDevice, timeout: Duration) -> AgoraResult<IpAddr> {
+    let response = soap_request(device, "GetExternalIPAddress", &[], timeout).await?;
+    child_text(&response, "NewExternalIPAddress")
+        .and_then(|ip| ip.parse().ok())
+        .ok_or_else(|| Error::Network("Could not determine external IP via UPnP".to_string()))
+}
+
+async fn add_port_mapping(
+    device: &UpnpDevice,
+    mapping: &PortMapping,
+    lease: Duration,
+    timeout: Duration,
+) -> Result<(), UpnpError> {
+    soap_request(
+        device,
+        "AddPortMapping",
+        &[
+            ("NewRemoteHost", String::new()),
+            ("NewExternalPort", mapping.external_port.to_string()),
+            ("NewProtocol", mapping.protocol.to_string()),
+            ("NewInternalPort", mapping.internal_port.to_string()),
+            ("NewInternalClient", mapping.internal_ip.to_string()),
+            ("NewEnabled", "1".to_string()),
+            ("NewPortMappingDescription", mapping.description.clone()),
+            ("NewLeaseDuration", lease.as_secs().to_string()),
+        ],
+        timeout,
+    )
+    .await
+    .map(|_| ())
+}
+
+fn mapping_from_entry(entry: &Element) -> Option<PortMapping> {
+    let protocol = match child_text(entry, "NewProtocol")?.to_uppercase().as_str() {
+        "TCP" => Protocol::Tcp,
+        "UDP" => Protocol::Udp,
+        _ => return None,
+    };
+    let mapping = PortMapping::new(
+        child_text(entry, "NewExternalPort")?.parse().ok()?,
+        child_text(entry, "NewInternalPort")?.parse().ok()?,
+        child_text(entry, "NewInternalClient")?.parse().ok()?,
+        protocol,
+    )
+    .with_description(&child_text(entry, "NewPortMappingDescription").unwrap_or_default())
+    .with_lease_duration(Duration::from_secs(
+        child_text(entry, "NewLeaseDuration")
+            .and_then(|lease| lease.parse().ok())
+            .unwrap_or(0),
+    ));
+    Some(mapping)
+}
+
+/// Calls `action` on the device's WAN connection service and returns the
+/// `<action>Response` element.
+async fn soap_request(
+    device: &UpnpDevice,
+    action: &str,
+    args: &[(&str, String)],
+    timeout: Duration,
+) -> Result<Element, UpnpError> {
+    let mut body = format!(
+        "<?xml version=\"1.0\"?>\r\n<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{} xmlns:u=\"{}\">",
+        action, device.service_type
+    );
+    for (name, value) in args {
+        body.push_str(&format!("<{0}>{1}</{0}>", name, xml_escape(value)));
+    }
+    body.push_str(&format!("</u:{}></s:Body></s:Envelope>", action));
+
+    let soap_action = format!("\"{}#{}\"", device.service_type, action);
+    let (status, response) = http_request(
+        "POST",
+        &device.control_url,
+        &[
+            ("Content-Type", "text/xml; charset=\"utf-8\""),
+            ("SOAPAction", &soap_action),
+        ],
+        &body,
+        timeout,
+    )
+    .await?;
+
+    let envelope = Element::parse(response.as_bytes())
+        .map_err(|e| Error::Network(format!("Invalid SOAP response to {}: {}", action, e)))?;
+    if status != 200 {
+        let fault = find_descendant(&envelope, "UPnPError");
+        let code = fault
+            .and_then(|f| child_text(f, "errorCode"))
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(0);
+        let description = fault
+            .and_then(|f| child_text(f, "errorDescription"))
+            .unwrap_or_else(|| format!("HTTP {}", status));
+        return Err(UpnpError::Soap(code, description));
+    }
+
+    find_descendant(&envelope, &format!("{}Response", action))
+        .cloned()
+        .ok_or_else(|| {
+            UpnpError::Other(Error::Network(format!(
+                "SOAP response has no {}Response",
+                action
+            )))
+        })
+}
+
+/// Minimal HTTP/1.1 client for talking to gateways on the LAN.
+async fn http_request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+    timeout: Duration,
+) -> AgoraResult<(u16, String)> {
+    let (host, port, path) = split_url(url)?;
+    let exchange = async {
+        let mut stream = TcpStream::connect((host.trim_matches(['[', ']']), port)).await?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            host,
+            port,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+
+    let response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| Error::Network(format!("HTTP request to {} timed out", url)))?
+        .map_err(|e| Error::Network(format!("HTTP request to {} failed: {}", url, e)))?;
+    let response = String::from_utf8_lossy(&response);
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| Error::Network(format!("Malformed HTTP response from {}", url)))?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| Error::Network(format!("Malformed HTTP status from {}", url)))?;
+
+    let chunked =
+        header_value(head, "transfer-encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked"));
+    let body = if chunked {
+        decode_chunked(body)
+    } else {
+        body.to_string()
+    };
+    Ok((status, body))
+}
+
+fn decode_chunked(mut body: &str) -> String {
+    let mut decoded = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size = size.split(';').next().unwrap_or("").trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            break;
+        };
+        if size == 0 || rest.len() < size {
+            break;
+        }
+        decoded.push_str(&rest[..size]);
+        body = rest[size..].trim_start_matches("\r\n");
+    }
+    decoded
+}
+
+/// Splits an `http://` URL into host, port and path.
+fn split_url(url: &str) -> AgoraResult<(String, u16, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| Error::Network(format!("Unsupported URL {}", url)))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rfind(':') {
+        Some(i) if !authority[i..].contains(']') => {
+            let port = authority[i + 1..]
+                .parse()
+                .map_err(|_| Error::Network(format!("Invalid port in {}", url)))?;
+            (&authority[..i], port)
+        }
+        _ => (authority, 80),
+    };
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+fn resolve_url(base: &str, url: &str) -> String {
+    if url.starts_with("http://") {
+        return url.to_string();
+    }
+    let origin_end = base
+        .strip_prefix("http://")
+        .and_then(|rest| rest.find('/'))
+        .map_or(base.len(), |i| i + "http://".len());
+    if url.starts_with('/') {
+        format!("{}{}", &base[..origin_end], url)
+    } else {
+        let dir_end = base[origin_end..]
+            .rfind('/')
+            .map_or(base.len(), |i| origin_end + i);
+        format!("{}/{}", &base[..dir_end], url)
+    }
+}
+
+fn header_value(message: &str, name: &str) -> Option<String> {
+    message.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+fn collect_services(device: &Element, services: &mut Vec<(String, String)>) {
+    if let Some(list) = device.get_child("serviceList") {
+        for service in child_elements(list, "service") {
+            if let (Some(typ), Some(url)) = (
+                child_text(service, "serviceType"),
+                child_text(service, "controlURL"),
+            ) {
+                services.push((typ, url));
+            }
+        }
+    }
+    if let Some(list) = device.get_child("deviceList") {
+        for child in child_elements(list, "device") {
+            collect_services(child, services);
+        }
+    }
+}
+
+fn child_elements<'a>(element: &'a Element, name: &'a str) -> impl Iterator<Item = &'a Element> {
+    element
+        .children
+        .iter()
+        .filter_map(|node| node.as_element())
+        .filter(move |e| e.name == name)
+}
+
+fn child_text(element: &Element, name: &str) -> Option<String> {
+    element
+        .get_child(name)
+        .and_then(|e| e.get_text())
+        .map(|text| text.trim().to_string())
+}
+
+fn find_descendant<'a>(element: &'a Element, name: &str) -> Option<&'a Element> {
+    element
+        .children
+        .iter()
+        .filter_map(|node| node.as_element())
+        .find_map(|child| {
+            if child.name == name {
+                Some(child)
+            } else {
+                find_descendant(child, name)
+            }
+        })
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 #[derive(Debug, Clone)]
 pub struct NatPmpConfig {
     pub gateway: Option<Ipv4Addr>,
+    pub port: u16,
     pub timeout: Duration,
     pub retry_count: u32,
 }
@@ -248,24 +733,34 @@ impl Default for NatPmpConfig {
     fn default() -> Self {
         Self {
             gateway: None,
+            port: NAT_PMP_DEFAULT_PORT,
             timeout: Duration::from_secs(5),
             retry_count: 3,
         }
     }
 }
 
+/// RFC 6886 NAT-PMP client.
 pub struct NatPmpClient {
     config: NatPmpConfig,
     gateway: Option<Ipv4Addr>,
     external_ip: Option<Ipv4Addr>,
+    epoch: EpochTracker,
+    mappings: HashMap<(u16, Protocol), PortMapping>,
 }
 
 impl NatPmpClient {
     pub fn new() -> Self {
+        Self::with_config(NatPmpConfig::default())
+    }
+
+    pub fn with_config(config: NatPmpConfig) -> Self {
         Self {
-            config: NatPmpConfig::default(),
-            gateway: None,
+            gateway: config.gateway,
+            config,
             external_ip: None,
+            epoch: EpochTracker::default(),
+            mappings: HashMap::new(),
         }
     }
 
@@ -283,62 +778,162 @@ impl NatPmpClient {
         self.external_ip
     }
 
-    pub async fn discover_gateway(&mut self) -> AgoraResult<Ipv4Addr> {
-        tracing::info!("Starting NAT-PMP gateway discovery");
+    /// True once if the gateway's epoch showed it lost its mapping state
+    /// (e.g. a reboot); all mappings must then be requested again.
+    pub fn take_epoch_reset(&mut self) -> bool {
+        self.epoch.take_reset()
+    }
 
-        let gateway = self.config.gateway.unwrap_or(Ipv4Addr::new(192, 168, 1, 1));
+    /// Uses the configured gateway, or the IPv4 default route.
+    pub async fn discover_gateway(&mut self) -> AgoraResult<Ipv4Addr> {
+        let gateway = match self.config.gateway {
+            Some(gateway) => gateway,
+            None => default_gateway_v4()?,
+        };
         self.gateway = Some(gateway);
 
         tracing::info!("Discovered NAT-PMP gateway: {}", gateway);
         Ok(gateway)
     }
 
+    async fn server(&mut self) -> AgoraResult<SocketAddr> {
+        let gateway = match self.gateway {
+            Some(gateway) => gateway,
+            None => self.discover_gateway().await?,
+        };
+        Ok(SocketAddr::new(IpAddr::V4(gateway), self.config.port))
+    }
+
+    pub async fn local_ip(&mut self) -> AgoraResult<IpAddr> {
+        let server = self.server().await?;
+        route_local_ip(server)
+    }
+
+    async fn request(&mut self, request: &[u8], opcode: u8) -> AgoraResult<Vec<u8>> {
+        let server = self.server().await?;
+        let response = udp_transact(
+            server,
+            request,
+            self.config.timeout,
+            self.config.retry_count,
+            |response| response.len() >= 8 && response[1] == opcode | 0x80,
+        )
+        .await?;
+
+        if response[0] != NAT_PMP_VERSION {
+            return Err(Error::Network(format!(
+                "Gateway answered NAT-PMP with version {}",
+                response[0]
+            )));
+        }
+        let result = u16::from_be_bytes([response[2], response[3]]);
+        if result != 0 {
+            return Err(Error::Network(format!(
+                "NAT-PMP error {}: {}",
+                result,
+                nat_pmp_result_text(result)
+            )));
+        }
+        self.epoch.observe(u32::from_be_bytes([
+            response[4],
+            response[5],
+            response[6],
+            response[7],
+        ]));
+        Ok(response)
+    }
+
     pub async fn get_external_address(&mut self) -> AgoraResult<Ipv4Addr> {
-        if self.gateway.is_none() {
-            self.discover_gateway().await?;
+        let response = self.request(&[NAT_PMP_VERSION, 0], 0).await?;
+        if response.len() < 12 {
+            return Err(Error::Network("Short NAT-PMP response".to_string()));
         }
 
-        let external = Ipv4Addr::new(203, 0, 113, 50);
+        let external = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
         self.external_ip = Some(external);
 
         tracing::info!("External address via NAT-PMP: {}", external);
         Ok(external)
     }
 
+    /// Requests a mapping and returns what the gateway granted, which may
+    /// use a different external port or a shorter lifetime.
     pub async fn map_port(
         &mut self,
         internal_port: u16,
         external_port: u16,
         protocol: Protocol,
         lifetime: Duration,
-    ) -> AgoraResult<()> {
-        if self.gateway.is_none() {
-            self.discover_gateway().await?;
-        }
-
-        let gateway = self.gateway.unwrap();
+    ) -> AgoraResult<PortMapping> {
+        let (granted_port, granted_lifetime) = self
+            .send_mapping(internal_port, external_port, protocol, lifetime)
+            .await?;
 
         tracing::info!(
-            "NAT-PMP mapping {}:{} -> {}:{} ({}, lifetime: {:?})",
+            "NAT-PMP mapped {}:{} -> {} ({}, lifetime: {:?})",
             self.external_ip.unwrap_or(Ipv4Addr::UNSPECIFIED),
-            external_port,
-            gateway,
+            granted_port,
             internal_port,
             protocol,
-            lifetime
+            granted_lifetime
         );
 
-        Ok(())
+        let mapping = PortMapping::new(
+            granted_port,
+            internal_port,
+            self.local_ip().await?,
+            protocol,
+        )
+        .with_lease_duration(granted_lifetime);
+        self.mappings
+            .insert((granted_port, protocol), mapping.clone());
+        Ok(mapping)
     }
 
     pub async fn unmap_port(&mut self, external_port: u16, protocol: Protocol) -> AgoraResult<()> {
-        if self.gateway.is_none() {
-            self.discover_gateway().await?;
-        }
+        let internal_port = self
+            .mappings
+            .get(&(external_port, protocol))
+            .map(|m| m.internal_port)
+            .ok_or_else(|| {
+                Error::Network(format!(
+                    "No NAT-PMP mapping for {} {}",
+                    external_port, protocol
+                ))
+            })?;
 
         tracing::info!("NAT-PMP unmapping port {} {}", external_port, protocol);
+        self.send_mapping(internal_port, 0, protocol, Duration::ZERO)
+            .await?;
+        self.mappings.remove(&(external_port, protocol));
         Ok(())
     }
+
+    async fn send_mapping(
+        &mut self,
+        internal_port: u16,
+        external_port: u16,
+        protocol: Protocol,
+        lifetime: Duration,
+    ) -> AgoraResult<(u16, Duration)> {
+        let opcode = match protocol {
+            Protocol::Udp => 1,
+            Protocol::Tcp => 2,
+        };
+        let mut request = vec![NAT_PMP_VERSION, opcode, 0, 0];
+        request.extend_from_slice(&internal_port.to_be_bytes());
+        request.extend_from_slice(&external_port.to_be_bytes());
+        request.extend_from_slice(&lifetime_secs(lifetime).to_be_bytes());
+
+        let response = self.request(&request, opcode).await?;
+        if response.len() < 16 {
+            return Err(Error::Network("Short NAT-PMP response".to_string()));
+        }
+        let granted_port = u16::from_be_bytes([response[10], response[11]]);
+        let granted_lifetime =
+            u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+        Ok((granted_port, Duration::from_secs(granted_lifetime as u64)))
+    }
 }
 
 impl Default for NatPmpClient {
@@ -347,25 +942,563 @@ impl Default for NatPmpClient {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct PcpConfig {
+    /// PCP server address; the default gateway on port 5351 when unset.
+    pub server: Option<SocketAddr>,
+    pub timeout: Duration,
+    pub retry_count: u32,
+}
+
+impl Default for PcpConfig {
+    fn default() -> Self {
+        Self {
+            server: None,
+            timeout: Duration::from_secs(5),
+            retry_count: 3,
+        }
+    }
+}
+
+struct PcpMapping {
+    nonce: [u8; 12],
+    mapping: PortMapping,
+}
+
+/// RFC 6887 PCP client for MAP and PEER mappings over IPv4 or IPv6.
+pub struct PcpClient {
+    config: PcpConfig,
+    server: Option<SocketAddr>,
+    external_ip: Option<IpAddr>,
+    epoch: EpochTracker,
+    mappings: HashMap<(u16, Protocol), PcpMapping>,
+}
+
+impl PcpClient {
+    pub fn new() -> Self {
+        Self::with_config(PcpConfig::default())
+    }
+
+    pub fn with_config(config: PcpConfig) -> Self {
+        Self {
+            server: config.server,
+            config,
+            external_ip: None,
+            epoch: EpochTracker::default(),
+            mappings: HashMap::new(),
+        }
+    }
+
+    pub fn server(&self) -> Option<SocketAddr> {
+        self.server
+    }
+
+    /// Learned from MAP and PEER responses; PCP has no dedicated query.
+    pub fn external_ip(&self) -> Option<IpAddr> {
+        self.external_ip
+    }
+
+    pub fn take_epoch_reset(&mut self) -> bool {
+        self.epoch.take_reset()
+    }
+
+    /// Uses the configured server, or the default gateway (IPv4 first).
+    pub fn discover_server(&mut self) -> AgoraResult<SocketAddr> {
+        let server = match self.config.server {
+            Some(server) => server,
+            None => match default_gateway_v4() {
+                Ok(gateway) => SocketAddr::new(IpAddr::V4(gateway), NAT_PMP_DEFAULT_PORT),
+                Err(_) => default_gateway_v6()?,
+            },
+        };
+        self.server = Some(server);
+        Ok(server)
+    }
+
+    fn resolve_server(&mut self) -> AgoraResult<SocketAddr> {
+        match self.server {
+            Some(server) => Ok(server),
+            None => self.discover_server(),
+        }
+    }
+
+    pub fn local_ip(&mut self) -> AgoraResult<IpAddr> {
+        let server = self.resolve_server()?;
+        route_local_ip(server)
+    }
+
+    /// Sends an ANNOUNCE to check that the server speaks PCP.
+    pub async fn probe(&mut self) -> AgoraResult<()> {
+        self.request(PCP_OPCODE_ANNOUNCE, Duration::ZERO, &[], |_| true)
+            .await
+            .map(|_| ())
+    }
+
+    /// MAP request for inbound traffic to `internal_port`.
+    pub async fn map_port(
+        &mut self,
+        internal_port: u16,
+        external_port: u16,
+        protocol: Protocol,
+        lifetime: Duration,
+    ) -> AgoraResult<PortMapping> {
+        // Refreshes must reuse the nonce of the original request.
+        let existing = self
+            .mappings
+            .values()
+            .find(|m| m.mapping.internal_port == internal_port && m.mapping.protocol == protocol);
+        let nonce = existing.map_or_else(rand::random, |m| m.nonce);
+        let suggested_ip = existing.and(self.external_ip);
+
+        let payload = pcp_map_payload(&nonce, protocol, internal_port, external_port, suggested_ip);
+        let response = self
+            .request(PCP_OPCODE_MAP, lifetime, &payload, |p| p[..12] == nonce)
+            .await?;
+        let (granted_lifetime, external) = parse_pcp_assignment(&response);
+
+        tracing::info!(
+            "PCP mapped {} -> {} ({}, lifetime: {:?})",
+            external,
+            internal_port,
+            protocol,
+            granted_lifetime
+        );
+
+        self.external_ip = Some(external.ip());
+        let mapping = PortMapping::new(external.port(), internal_port, self.local_ip()?, protocol)
+            .with_lease_duration(granted_lifetime);
+        self.mappings.retain(|_, m| {
+            !(m.mapping.internal_port == internal_port && m.mapping.protocol == protocol)
+        });
+        self.mappings.insert(
+            (external.port(), protocol),
+            PcpMapping {
+                nonce,
+                mapping: mapping.clone(),
+            },
+        );
+        Ok(mapping)
+    }
+
+    /// PEER request: creates or extends the mapping used for outbound
+    /// traffic to `remote` and returns its external address.
+    pub async fn peer(
+        &mut self,
+        internal_port: u16,
+        protocol: Protocol,
+        remote: SocketAddr,
+        lifetime: Duration,
+    ) -> AgoraResult<SocketAddr> {
+        let nonce: [u8; 12] = rand::random();
+        let mut payload = pcp_map_payload(&nonce, protocol, internal_port, 0, None);
+        payload.extend_from_slice(&remote.port().to_be_bytes());
+        payload.extend_from_slice(&[0, 0]);
+        payload.extend_from_slice(&pcp_address(remote.ip()));
+
+        let response = self
+            .request(PCP_OPCODE_PEER, lifetime, &payload, |p| p[..12] == nonce)
+            .await?;
+        let (_, external) = parse_pcp_assignment(&response);
+        self.external_ip = Some(external.ip());
+        Ok(external)
+    }
+
+    pub async fn unmap_port(&mut self, external_port: u16, protocol: Protocol) -> AgoraResult<()> {
+        let existing = self
+            .mappings
+            .get(&(external_port, protocol))
+            .ok_or_else(|| {
+                Error::Network(format!("No PCP mapping for {} {}", external_port, protocol))
+            })?;
+        let nonce = existing.nonce;
+        let payload = pcp_map_payload(&nonce, protocol, existing.mapping.internal_port, 0, None);
+
+        tracing::info!("PCP unmapping port {} {}", external_port, protocol);
+        self.request(PCP_OPCODE_MAP, Duration::ZERO, &payload, |p| {
+            p[..12] == nonce
+        })
+        .await?;
+        self.mappings.remove(&(external_port, protocol));
+        Ok(())
+    }
+
+    async fn request(
+        &mut self,
+        opcode: u8,
+        lifetime: Duration,
+        payload: &[u8],
+        matches: impl Fn(&[u8]) -> bool,
+    ) -> AgoraResult<Vec<u8>> {
+        let server = self.resolve_server()?;
+        let client_ip = route_local_ip(server)?;
+
+        let mut request = vec![PCP_VERSION, opcode, 0, 0];
+        request.extend_from_slice(&lifetime_secs(lifetime).to_be_bytes());
+        request.extend_from_slice(&pcp_address(client_ip));
+        request.extend_from_slice(payload);
+
+        let response = udp_transact(
+            server,
+            &request,
+            self.config.timeout,
+            self.config.retry_count,
+            |response| {
+                // A NAT-PMP-only gateway answers with version 0.
+                response.len() >= 4 && response[0] != PCP_VERSION
+                    || response.len() >= PCP_HEADER_LEN + payload.len()
+                        && response[1] == opcode | 0x80
+                        && matches(&response[PCP_HEADER_LEN..])
+            },
+        )
+        .await?;
+
+        if response[0] != PCP_VERSION {
+            return Err(Error::Network(format!(
+                "Gateway answered PCP with version {}",
+                response[0]
+            )));
+        }
+        let result = response[3];
+        if result != 0 {
+            return Err(Error::Network(format!(
+                "PCP error {}: {}",
+                result,
+                pcp_result_text(result)
+            )));
+        }
+        self.epoch.observe(u32::from_be_bytes([
+            response[8],
+            response[9],
+            response[10],
+            response[11],
+        ]));
+        Ok(response)
+    }
+}
+
+impl Default for PcpClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detects gateway state loss from the epoch field (RFC 6887 section 8.5).
+#[derive(Default)]
+struct EpochTracker {
+    last: Option<(u32, Instant)>,
+    reset: bool,
+}
+
+impl EpochTracker {
+    fn observe(&mut self, epoch: u32) {
+        let now = Instant::now();
+        if let Some((previous, at)) = self.last {
+            let client_delta = now.duration_since(at).as_secs();
+            let server_delta = epoch.saturating_sub(previous) as u64;
+            if (epoch as u64) + 1 < previous as u64
+                || server_delta + 2 < client_delta - client_delta / 16
+            {
+                tracing::info!(
+                    "Gateway epoch went from {} to {}, mappings were lost",
+                    previous,
+                    epoch
+                );
+                self.reset = true;
+            }
+        }
+        self.last = Some((epoch, now));
+    }
+
+    fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+}
+
+/// Sends `request` with exponential backoff until a datagram from `server`
+/// satisfies `accept`.
+async fn udp_transact(
+    server: SocketAddr,
+    request: &[u8],
+    timeout: Duration,
+    retry_count: u32,
+    accept: impl Fn(&[u8]) -> bool,
+) -> AgoraResult<Vec<u8>> {
+    let bind_addr: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind_addr)
+        .await
+        .map_err(|e| Error::Network(format!("Failed to bind socket: {}", e)))?;
+    socket
+        .connect(server)
+        .await
+        .map_err(|e| Error::Network(format!("Failed to reach gateway {}: {}", server, e)))?;
+    let overall = tokio::time::Instant::now() + timeout;
+    let mut wait = PORT_MAPPING_INITIAL_RTO;
+    let mut buf = [0u8; 1100];
+    for _ in 0..=retry_count {
+        // A closed port shows up as ECONNREFUSED on the next send or recv.
+        if socket.send(request).await.is_err() {
+            break;
+        }
+        let deadline = overall.min(tokio::time::Instant::now() + wait);
+        while let Ok(Ok(n)) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+            if accept(&buf[..n]) {
+                return Ok(buf[..n].to_vec());
+            }
+        }
+        if tokio::time::Instant::now() >= overall {
+            break;
+        }
+        wait *= 2;
+    }
+
+    Err(Error::Network(format!(
+        "No response from gateway {}",
+        server
+    )))
+}
+
+fn route_local_ip(server: SocketAddr) -> AgoraResult<IpAddr> {
+    let socket = std::net::UdpSocket::bind(if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })
+    .map_err(|e| Error::Network(format!("Failed to bind socket: {}", e)))?;
+    socket
+        .connect(server)
+        .map_err(|e| Error::Network(format!("Failed to route to gateway: {}", e)))?;
+    socket
+        .local_addr()
+        .map(|addr| addr.ip())
+        .map_err(|e| Error::Network(format!("Failed to get local address: {}", e)))
+}
+
+fn lifetime_secs(lifetime: Duration) -> u32 {
+    lifetime.as_secs().min(u32::MAX as u64) as u32
+}
+
+/// PCP carries every address as 16 bytes, IPv4 as IPv4-mapped IPv6.
+fn pcp_address(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn pcp_map_payload(
+    nonce: &[u8; 12],
+    protocol: Protocol,
+    internal_port: u16,
+    external_port: u16,
+    external_ip: Option<IpAddr>,
+) -> Vec<u8> {
+    let protocol = match protocol {
+        Protocol::Tcp => 6,
+        Protocol::Udp => 17,
+    };
+    let mut payload = nonce.to_vec();
+    payload.extend_from_slice(&[protocol, 0, 0, 0]);
+    payload.extend_from_slice(&internal_port.to_be_bytes());
+    payload.extend_from_slice(&external_port.to_be_bytes());
+    payload.extend_from_slice(&match external_ip {
+        Some(ip) => pcp_address(ip),
+        None => [0; 16],
+    });
+    payload
+}
+
+/// Lifetime and assigned external address from a MAP or PEER response.
+fn parse_pcp_assignment(response: &[u8]) -> (Duration, SocketAddr) {
+    let lifetime = u32::from_be_bytes([response[4], response[5], response[6], response[7]]);
+    let payload = &response[PCP_HEADER_LEN..];
+    let port = u16::from_be_bytes([payload[18], payload[19]]);
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&payload[20..36]);
+    let ip = Ipv6Addr::from(octets);
+    let ip = match ip.to_ipv4_mapped() {
+        Some(ip) => IpAddr::V4(ip),
+        None => IpAddr::V6(ip),
+    };
+    (
+        Duration::from_secs(lifetime as u64),
+        SocketAddr::new(ip, port),
+    )
+}
+
+fn nat_pmp_result_text(code: u16) -> &'static str {
+    match code {
+        1 => "unsupported version",
+        2 => "not authorized",
+        3 => "network failure",
+        4 => "out of resources",
+        5 => "unsupported opcode",
+        _ => "unknown error",
+    }
+}
+
+fn pcp_result_text(code: u8) -> &'static str {
+    match code {
+        1 => "unsupported version",
+        2 => "not authorized",
+        3 => "malformed request",
+        4 => "unsupported opcode",
+        5 => "unsupported option",
+        6 => "malformed option",
+        7 => "network failure",
+        8 => "no resources",
+        9 => "unsupported protocol",
+        10 => "user quota exceeded",
+        11 => "cannot provide external address",
+        12 => "address mismatch",
+        13 => "excessive remote peers",
+        _ => "unknown error",
+    }
+}
+
+fn default_gateway_v4() -> AgoraResult<Ipv4Addr> {
+    #[cfg(target_os = "linux")]
+    let gateway = std::fs::read_to_string("/proc/net/route")
+        .ok()
+        .and_then(|table| parse_proc_route(&table));
+
+    #[cfg(not(target_os = "linux"))]
+    let gateway = route_command_output().and_then(|output| parse_route_output(&output));
+
+    gateway.ok_or_else(|| Error::Network("No IPv4 default gateway found".to_string()))
+}
+
+fn default_gateway_v6() -> AgoraResult<SocketAddr> {
+    #[cfg(target_os = "linux")]
+    if let Some((ip, interface)) = std::fs::read_to_string("/proc/net/ipv6_route")
+        .ok()
+        .and_then(|table| parse_proc_ipv6_route(&table))
+    {
+        // Default routes usually point at a link-local router address.
+        let scope_id = std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", interface))
+            .ok()
+            .and_then(|index| index.trim().parse().ok())
+            .unwrap_or(0);
+        return Ok(SocketAddr::V6(std::net::SocketAddrV6::new(
+            ip,
+            NAT_PMP_DEFAULT_PORT,
+            0,
+            scope_id,
+        )));
+    }
+
+    Err(Error::Network("No IPv6 default gateway found".to_string()))
+}
+
+/// Default route gateway from `/proc/net/route`, where addresses are
+/// little-endian hex.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_route(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 || fields[1] != "00000000" {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+        (gateway != 0).then(|| Ipv4Addr::from(gateway.to_le_bytes()))
+    })
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_ipv6_route(table: &str) -> Option<(Ipv6Addr, String)> {
+    table.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 || fields[0] != "0".repeat(32) || fields[1] != "00" {
+            return None;
+        }
+        let gateway = Ipv6Addr::from(u128::from_str_radix(fields[4], 16).ok()?);
+        (!gateway.is_unspecified()).then(|| (gateway, fields[9].to_string()))
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn route_command_output() -> Option<String> {
+    #[cfg(windows)]
+    let output = std::process::Command::new("route")
+        .args(["print", "-4", "0.0.0.0"])
+        .output();
+    #[cfg(not(windows))]
+    let output = std::process::Command::new("route")
+        .args(["-n", "get", "default"])
+        .output();
+    output
+        .ok()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Gateway from `route -n get default` (BSD/macOS) or `route print`
+/// (Windows) output.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn parse_route_output(output: &str) -> Option<Ipv4Addr> {
+    output.lines().find_map(|line| {
+        let line = line.trim();
+        if let Some(gateway) = line.strip_prefix("gateway:") {
+            return gateway.trim().parse().ok();
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Maps ports with whichever of UPnP, PCP or NAT-PMP the gateway supports
+/// and keeps the leases alive.
 pub struct PortForwarder {
     upnp: Option<UpnpClient>,
+    pcp: Option<PcpClient>,
     nat_pmp: Option<NatPmpClient>,
     mappings: Vec<PortMapping>,
+    upnp_config: UpnpConfig,
+    pcp_config: PcpConfig,
+    nat_pmp_config: NatPmpConfig,
+    renewals: HashMap<(u16, Protocol), Instant>,
 }
 
 impl PortForwarder {
     pub fn new() -> Self {
         Self {
             upnp: None,
+            pcp: None,
             nat_pmp: None,
             mappings: Vec::new(),
+            upnp_config: UpnpConfig::default(),
+            pcp_config: PcpConfig::default(),
+            nat_pmp_config: NatPmpConfig::default(),
+            renewals: HashMap::new(),
         }
     }
 
+    pub fn with_upnp_config(mut self, config: UpnpConfig) -> Self {
+        self.upnp_config = config;
+        self
+    }
+
+    pub fn with_pcp_config(mut self, config: PcpConfig) -> Self {
+        self.pcp_config = config;
+        self
+    }
+
+    pub fn with_nat_pmp_config(mut self, config: NatPmpConfig) -> Self {
+        self.nat_pmp_config = config;
+        self
+    }
+
+    /// Tries UPnP, then PCP, then NAT-PMP. PCP and NAT-PMP share port 5351;
+    /// a NAT-PMP-only gateway rejects the PCP probe with a version error.
     pub async fn setup(&mut self) -> AgoraResult<()> {
         tracing::info!("Setting up port forwarding");
 
-        let mut upnp = UpnpClient::new();
+        let mut upnp = UpnpClient::with_config(self.upnp_config.clone());
         match upnp.discover().await {
             Ok(_) => {
                 if upnp.has_devices() {
@@ -379,10 +1512,26 @@ impl PortForwarder {
             }
         }
 
-        let mut nat_pmp = NatPmpClient::new();
-        match nat_pmp.discover_gateway().await {
-            Ok(gateway) => {
-                tracing::info!("NAT-PMP available via gateway {}", gateway);
+        let mut pcp = PcpClient::with_config(self.pcp_config.clone());
+        match pcp.probe().await {
+            Ok(()) => {
+                tracing::info!("PCP available via {}", pcp.server().unwrap());
+                self.pcp = Some(pcp);
+                return Ok(());
+            }
+            Err(e) => {
+                tracing::debug!("PCP probe failed: {}", e);
+            }
+        }
+
+        let mut nat_pmp = NatPmpClient::with_config(self.nat_pmp_config.clone());
+        match nat_pmp.get_external_address().await {
+            Ok(external) => {
+                tracing::info!(
+                    "NAT-PMP available via gateway {}, external address {}",
+                    nat_pmp.gateway().unwrap(),
+                    external
+                );
                 self.nat_pmp = Some(nat_pmp);
                 return Ok(());
             }
@@ -392,7 +1541,7 @@ impl PortForwarder {
         }
 
         Err(Error::Network(
-            "No UPnP or NAT-PMP support available".to_string(),
+            "No UPnP, PCP or NAT-PMP support available".to_string(),
         ))
     }
 
@@ -402,22 +1551,51 @@ impl PortForwarder {
         external_port: u16,
         protocol: Protocol,
     ) -> AgoraResult<PortMapping> {
-        let internal_ip = self.get_local_ip()?;
+        let requested = PortMapping::new(
+            external_port,
+            internal_port,
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            protocol,
+        )
+        .with_description(&self.upnp_config.description)
+        .with_lease_duration(self.upnp_config.lease_duration);
+
+        let mapping = self.map(&requested).await?;
+        self.track(mapping.clone());
+        Ok(mapping)
+    }
 
+    /// Requests `mapping` and returns what the gateway granted.
+    async fn map(&mut self, mapping: &PortMapping) -> AgoraResult<PortMapping> {
         if let Some(ref mut upnp) = self.upnp {
-            let mapping = PortMapping::new(external_port, internal_port, internal_ip, protocol);
+            let mut mapping = mapping.clone();
+            mapping.internal_ip = upnp.local_ip().await?;
             upnp.add_port_mapping(&mapping).await?;
-            self.mappings.push(mapping.clone());
             return Ok(mapping);
         }
 
+        if let Some(ref mut pcp) = self.pcp {
+            return pcp
+                .map_port(
+                    mapping.internal_port,
+                    mapping.external_port,
+                    mapping.protocol,
+                    mapping.lease_duration,
+                )
+                .await
+                .map(|granted| granted.with_description(&mapping.description));
+        }
+
         if let Some(ref mut nat_pmp) = self.nat_pmp {
-            nat_pmp
-                .map_port(internal_port, external_port, protocol, UPNP_DEFAULT_LEASE)
-                .await?;
-            let mapping = PortMapping::new(external_port, internal_port, internal_ip, protocol);
-            self.mappings.push(mapping.clone());
-            return Ok(mapping);
+            return nat_pmp
+                .map_port(
+                    mapping.internal_port,
+                    mapping.external_port,
+                    mapping.protocol,
+                    mapping.lease_duration,
+                )
+                .await
+                .map(|granted| granted.with_description(&mapping.description));
         }
 
         Err(Error::Network(
@@ -425,6 +1603,79 @@ impl PortForwarder {
         ))
     }
 
+    fn track(&mut self, mapping: PortMapping) {
+        self.forget(mapping.external_port, mapping.protocol);
+
+        // Mappings are refreshed at half their lease; a zero lease is
+        // permanent and never renewed.
+        if !mapping.lease_duration.is_zero() {
+            self.renewals.insert(
+                (mapping.external_port, mapping.protocol),
+                Instant::now() + mapping.lease_duration / 2,
+            );
+        }
+        self.mappings.push(mapping);
+    }
+
+    fn forget(&mut self, external_port: u16, protocol: Protocol) {
+        self.mappings
+            .retain(|m| !(m.external_port == external_port && m.protocol == protocol));
+        self.renewals.remove(&(external_port, protocol));
+    }
+
+    fn take_epoch_reset(&mut self) -> bool {
+        if let Some(ref mut pcp) = self.pcp {
+            return pcp.take_epoch_reset();
+        }
+        if let Some(ref mut nat_pmp) = self.nat_pmp {
+            return nat_pmp.take_epoch_reset();
+        }
+        false
+    }
+
+    /// Re-requests every mapping whose renewal is due, or all of them if the
+    /// gateway lost its state, and returns how many were renewed. Call this
+    /// periodically, e.g. after `next_renewal` elapses.
+    pub async fn renew_mappings(&mut self) -> AgoraResult<usize> {
+        let now = Instant::now();
+        let reset = self.take_epoch_reset();
+        let due: Vec<PortMapping> = self
+            .mappings
+            .iter()
+            .filter(|m| {
+                reset
+                    || self
+                        .renewals
+                        .get(&(m.external_port, m.protocol))
+                        .is_some_and(|at| *at <= now)
+            })
+            .cloned()
+            .collect();
+
+        for mapping in &due {
+            tracing::debug!(
+                "Renewing port mapping {} {}",
+                mapping.external_port,
+                mapping.protocol
+            );
+            let requested = mapping
+                .clone()
+                .with_lease_duration(self.upnp_config.lease_duration);
+            let granted = self.map(&requested).await?;
+            // NAT-PMP and PCP may move the mapping to another port.
+            self.forget(mapping.external_port, mapping.protocol);
+            self.track(granted);
+        }
+        Ok(due.len())
+    }
+
+    pub fn next_renewal(&self) -> Option<Duration> {
+        self.renewals
+            .values()
+            .min()
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
     pub async fn remove_mapping(
         &mut self,
         external_port: u16,
@@ -432,21 +1683,18 @@ impl PortForwarder {
     ) -> AgoraResult<()> {
         if let Some(ref mut upnp) = self.upnp {
             upnp.remove_port_mapping(external_port, protocol).await?;
-            self.mappings
-                .retain(|m| !(m.external_port == external_port && m.protocol == protocol));
-            return Ok(());
-        }
-
-        if let Some(ref mut nat_pmp) = self.nat_pmp {
+        } else if let Some(ref mut pcp) = self.pcp {
+            pcp.unmap_port(external_port, protocol).await?;
+        } else if let Some(ref mut nat_pmp) = self.nat_pmp {
             nat_pmp.unmap_port(external_port, protocol).await?;
-            self.mappings
-                .retain(|m| !(m.external_port == external_port && m.protocol == protocol));
-            return Ok(());
+        } else {
+            return Err(Error::Network(
+                "No port forwarding method available".to_string(),
+            ));
         }
 
-        Err(Error::Network(
-            "No port forwarding method available".to_string(),
-        ))
+        self.forget(external_port, protocol);
+        Ok(())
     }
 
     pub fn get_local_ip(&self) -> AgoraResult<IpAddr> {
@@ -469,6 +1717,12 @@ impl PortForwarder {
             return upnp.get_external_ip().await;
         }
 
+        if let Some(ref pcp) = self.pcp {
+            return pcp.external_ip().ok_or_else(|| {
+                Error::Network("PCP reports the external address only with a mapping".to_string())
+            });
+        }
+
         if let Some(ref mut nat_pmp) = self.nat_pmp {
             let ip = nat_pmp.get_external_address().await?;
             return Ok(IpAddr::V4(ip));
@@ -483,6 +1737,10 @@ impl PortForwarder {
         self.upnp.is_some()
     }
 
+    pub fn has_pcp(&self) -> bool {
+        self.pcp.is_some()
+    }
+
     pub fn has_nat_pmp(&self) -> bool {
         self.nat_pmp.is_some()
     }
@@ -594,100 +1852,864 @@ mod tests {
     fn test_port_forwarder_creation() {
         let forwarder = PortForwarder::new();
         assert!(!forwarder.has_upnp());
+        assert!(!forwarder.has_pcp());
         assert!(!forwarder.has_nat_pmp());
         assert!(forwarder.mappings().is_empty());
     }
 
+    #[test]
+    fn test_device_from_description() {
+        let xml = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <URLBase>http://10.0.0.1:5000/igd/</URLBase>
+  <device>
+    <friendlyName>DSL Modem</friendlyName>
+    <manufacturer>Acme</manufacturer>
+    <deviceList><device><deviceList><device>
+      <serviceList><service>
+        <serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+        <controlURL>ppp/ctl</controlURL>
+      </service></serviceList>
+    </device></deviceList></device></deviceList>
+  </device>
+</root>"#;
+
+        let device = UpnpDevice::from_description("http://10.0.0.1:5000/desc.xml", xml).unwrap();
+        assert_eq!(device.friendly_name, "DSL Modem");
+        assert_eq!(device.manufacturer, "Acme");
+        assert_eq!(
+            device.service_type,
+            "urn:schemas-upnp-org:service:WANPPPConnection:1"
+        );
+        assert_eq!(device.control_url, "http://10.0.0.1:5000/igd/ppp/ctl");
+
+        let no_wan = xml.replace("WANPPPConnection", "Layer3Forwarding");
+        assert!(UpnpDevice::from_description("http://10.0.0.1/", &no_wan).is_err());
+    }
+
+    #[test]
+    fn test_url_helpers() {
+        assert_eq!(
+            resolve_url("http://192.168.1.1:49152/rootDesc.xml", "/ctl/IPConn"),
+            "http://192.168.1.1:49152/ctl/IPConn"
+        );
+        assert_eq!(
+            resolve_url("http://192.168.1.1", "ctl"),
+            "http://192.168.1.1/ctl"
+        );
+        assert_eq!(
+            split_url("http://192.168.1.1:49152/ctl/IPConn").unwrap(),
+            ("192.168.1.1".to_string(), 49152, "/ctl/IPConn".to_string())
+        );
+        assert_eq!(
+            split_url("http://gateway").unwrap(),
+            ("gateway".to_string(), 80, "/".to_string())
+        );
+        assert!(split_url("https://gateway/").is_err());
+        assert_eq!(
+            decode_chunked("5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"),
+            "hello world"
+        );
+    }
+
+    mod mock_igd {
+        use super::*;
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+        use tokio::net::TcpListener;
+        use tokio::task::JoinHandle;
+
+        pub const EXTERNAL_IP: &str = "198.51.100.7";
+
+        const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <friendlyName>Mock IGD</friendlyName>
+    <manufacturer>Agora</manufacturer>
+    <modelName>mock</modelName>
+    <serviceList><service>
+      <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+      <controlURL>/ctl/L3F</controlURL>
+    </service></serviceList>
+    <deviceList><device>
+      <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+      <deviceList><device>
+        <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+        <serviceList><service>
+          <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+          <controlURL>/ctl/IPConn</controlURL>
+        </service></serviceList>
+      </device></deviceList>
+    </device></deviceList>
+  </device>
+</root>"#;
+
+        #[derive(Default)]
+        pub struct State {
+            pub entries: Mutex<Vec<PortMapping>>,
+            pub add_requests: AtomicUsize,
+            pub permanent_only: AtomicBool,
+        }
+
+        pub struct MockIgd {
+            pub ssdp_addr: SocketAddr,
+            pub state: Arc<State>,
+            tasks: Vec<JoinHandle<()>>,
+        }
+
+        impl MockIgd {
+            pub async fn start() -> Self {
+                let state = Arc::new(State::default());
+                let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let location = format!("http://{}/rootDesc.xml", http.local_addr().unwrap());
+                let ssdp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let ssdp_addr = ssdp.local_addr().unwrap();
+
+                let ssdp_task = tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    while let Ok((n, from)) = ssdp.recv_from(&mut buf).await {
+                        let request = String::from_utf8_lossy(&buf[..n]);
+                        if !request.starts_with("M-SEARCH") {
+                            continue;
+                        }
+                        let st = header_value(&request, "st").unwrap_or_default();
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: {}\r\nUSN: uuid:mock::{}\r\nLOCATION: {}\r\n\r\n",
+                            st, st, location
+                        );
+                        let _ = ssdp.send_to(response.as_bytes(), from).await;
+                    }
+                });
+
+                let http_state = state.clone();
+                let http_task = tokio::spawn(async move {
+                    while let Ok((stream, _)) = http.accept().await {
+                        tokio::spawn(serve(stream, http_state.clone()));
+                    }
+                });
+
+                Self {
+                    ssdp_addr,
+                    state,
+                    tasks: vec![ssdp_task, http_task],
+                }
+            }
+
+            pub fn config(&self) -> UpnpConfig {
+                UpnpConfig {
+                    search_timeout: Duration::from_secs(2),
+                    ssdp_addr: self.ssdp_addr,
+                    ..Default::default()
+                }
+            }
+        }
+
+        impl Drop for MockIgd {
+            fn drop(&mut self) {
+                for task in &self.tasks {
+                    task.abort();
+                }
+            }
+        }
+
+        async fn serve(mut stream: TcpStream, state: Arc<State>) {
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            let (head, body) = loop {
+                let Ok(n) = stream.read(&mut buf).await else {
+                    return;
+                };
+                if n == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = header_value(head, "content-length")
+                        .and_then(|l| l.parse().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break (head.to_string(), body.to_string());
+                    }
+                }
+            };
+
+            let path = head.split_whitespace().nth(1).unwrap_or("").to_string();
+            let (status, body) = match path.as_str() {
+                "/rootDesc.xml" => (200, DESCRIPTION.to_string()),
+                "/ctl/IPConn" => {
+                    let action = header_value(&head, "soapaction")
+                        .and_then(|a| a.trim_matches('"').split('#').nth(1).map(str::to_string))
+                        .unwrap_or_default();
+                    let envelope = Element::parse(body.as_bytes()).unwrap();
+                    let args = find_descendant(&envelope, &action).unwrap().clone();
+                    handle_action(&state, &action, &args)
+                }
+                _ => (404, String::new()),
+            };
+
+            let reason = if status == 200 { "OK" } else { "Error" };
+            let response = format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                reason,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+
+        fn handle_action(state: &State, action: &str, args: &Element) -> (u16, String) {
+            let arg = |name: &str| child_text(args, name).unwrap_or_default();
+            let mut entries = state.entries.lock().unwrap();
+            let result = match action {
+                "GetExternalIPAddress" => Ok(format!(
+                    "<NewExternalIPAddress>{}</NewExternalIPAddress>",
+                    EXTERNAL_IP
+                )),
+                "AddPortMapping" => {
+                    state.add_requests.fetch_add(1, Ordering::SeqCst);
+                    let lease: u64 = arg("NewLeaseDuration").parse().unwrap();
+                    if lease != 0 && state.permanent_only.load(Ordering::SeqCst) {
+                        Err((725, "OnlyPermanentLeasesSupported"))
+                    } else {
+                        let mapping = mapping_from_entry(args).unwrap();
+                        entries.retain(|m| {
+                            !(m.external_port == mapping.external_port
+                                && m.protocol == mapping.protocol)
+                        });
+                        entries.push(mapping);
+                        Ok(String::new())
+                    }
+                }
+                "DeletePortMapping" => {
+                    let port: u16 = arg("NewExternalPort").parse().unwrap();
+                    let protocol = arg("NewProtocol");
+                    let before = entries.len();
+                    entries.retain(|m| {
+                        !(m.external_port == port && m.protocol.to_string() == protocol)
+                    });
+                    if entries.len() == before {
+                        Err((714, "NoSuchEntryInArray"))
+                    } else {
+                        Ok(String::new())
+                    }
+                }
+                "GetGenericPortMappingEntry" => {
+                    let index: usize = arg("NewPortMappingIndex").parse().unwrap();
+                    match entries.get(index) {
+                        Some(m) => Ok(format!(
+                            "<NewRemoteHost></NewRemoteHost><NewExternalPort>{}</NewExternalPort><NewProtocol>{}</NewProtocol><NewInternalPort>{}</NewInternalPort><NewInternalClient>{}</NewInternalClient><NewEnabled>1</NewEnabled><NewPortMappingDescription>{}</NewPortMappingDescription><NewLeaseDuration>{}</NewLeaseDuration>",
+                            m.external_port,
+                            m.protocol,
+                            m.internal_port,
+                            m.internal_ip,
+                            xml_escape(&m.description),
+                            m.lease_duration.as_secs()
+                        )),
+                        None => Err((713, "SpecifiedArrayIndexInvalid")),
+                    }
+                }
+                _ => Err((401, "Invalid Action")),
+            };
+
+            let envelope = |inner: String| {
+                format!(
+                    "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>{}</s:Body></s:Envelope>",
+                    inner
+                )
+            };
+            match result {
+                Ok(fields) => (
+                    200,
+                    envelope(format!(
+                        "<u:{0}Response xmlns:u=\"{1}\">{2}</u:{0}Response>",
+                        action, WAN_IP_CONNECTION_1, fields
+                    )),
+                ),
+                Err((code, description)) => (
+                    500,
+                    envelope(format!(
+                        "<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\"><errorCode>{}</errorCode><errorDescription>{}</errorDescription></UPnPError></detail></s:Fault>",
+                        code, description
+                    )),
+                ),
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_upnp_client_discover() {
-        let mut client = UpnpClient::new();
+        let igd = mock_igd::MockIgd::start().await;
+        let mut client = UpnpClient::with_config(igd.config());
         let devices = client.discover().await.unwrap();
 
-        assert!(!devices.is_empty());
-        assert!(client.has_devices());
-        assert!(client.external_ip().is_some());
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].friendly_name, "Mock IGD");
+        assert_eq!(devices[0].service_type, WAN_IP_CONNECTION_1);
+        assert!(devices[0].control_url.ends_with("/ctl/IPConn"));
+        assert_eq!(
+            client.external_ip(),
+            Some(mock_igd::EXTERNAL_IP.parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upnp_discover_without_gateway() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut client = UpnpClient::with_config(UpnpConfig {
+            search_timeout: Duration::from_millis(200),
+            ssdp_addr: silent.local_addr().unwrap(),
+            ..Default::default()
+        });
+
+        assert!(client.discover().await.is_err());
+        assert!(!client.has_devices());
     }
 
     #[tokio::test]
-    async fn test_upnp_add_port_mapping() {
-        let mut client = UpnpClient::new();
+    async fn test_upnp_port_mappings() {
+        let igd = mock_igd::MockIgd::start().await;
+        let mut client = UpnpClient::with_config(igd.config());
+        client.discover().await.unwrap();
 
-        let mapping = PortMapping::new(
-            7001,
-            7001,
-            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)),
-            Protocol::Udp,
-        );
+        let local_ip = client.local_ip().await.unwrap();
+        assert_eq!(local_ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let udp = PortMapping::new(7001, 7001, local_ip, Protocol::Udp)
+            .with_description("Agora & friends");
+        let tcp = PortMapping::new(7002, 7000, local_ip, Protocol::Tcp);
+        client.add_port_mapping(&udp).await.unwrap();
+        client.add_port_mapping(&tcp).await.unwrap();
+
+        let mappings = client.get_mappings().await.unwrap();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].external_port, 7001);
+        assert_eq!(mappings[0].protocol, Protocol::Udp);
+        assert_eq!(mappings[0].description, "Agora & friends");
+        assert_eq!(mappings[0].lease_duration, UPNP_DEFAULT_LEASE);
+        assert_eq!(mappings[1].internal_port, 7000);
+        assert_eq!(mappings[1].protocol, Protocol::Tcp);
+
+        client
+            .remove_port_mapping(7001, Protocol::Udp)
+            .await
+            .unwrap();
+        let mappings = client.get_mappings().await.unwrap();
+        assert_eq!(mappings.len(), 1);
+        assert_eq!(mappings[0].external_port, 7002);
+
+        let err = client
+            .remove_port_mapping(7001, Protocol::Udp)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("714"));
+    }
 
-        let result = client.add_port_mapping(&mapping).await;
-        assert!(result.is_ok());
+    #[tokio::test]
+    async fn test_upnp_permanent_lease_fallback() {
+        let igd = mock_igd::MockIgd::start().await;
+        igd.state
+            .permanent_only
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        let mut client = UpnpClient::with_config(igd.config());
+
+        let mapping = PortMapping::new(7001, 7001, IpAddr::V4(Ipv4Addr::LOCALHOST), Protocol::Udp);
+        client.add_port_mapping(&mapping).await.unwrap();
+
+        let mappings = client.get_mappings().await.unwrap();
+        assert_eq!(mappings.len(), 1);
+        assert_eq!(mappings[0].lease_duration, Duration::ZERO);
     }
 
     #[tokio::test]
-    async fn test_upnp_remove_port_mapping() {
-        let mut client = UpnpClient::new();
-        client.discover().await.unwrap();
+    async fn test_port_forwarder_setup() {
+        let igd = mock_igd::MockIgd::start().await;
+        let mut forwarder = PortForwarder::new().with_upnp_config(igd.config());
+        forwarder.setup().await.unwrap();
 
-        let result = client.remove_port_mapping(7001, Protocol::Udp).await;
-        assert!(result.is_ok());
+        assert!(forwarder.has_upnp());
+        assert!(!forwarder.has_nat_pmp());
     }
 
     #[tokio::test]
-    async fn test_nat_pmp_discover_gateway() {
-        let mut client = NatPmpClient::new();
-        let gateway = client.discover_gateway().await.unwrap();
+    async fn test_port_forwarder_add_mapping() {
+        let igd = mock_igd::MockIgd::start().await;
+        let mut forwarder = PortForwarder::new().with_upnp_config(igd.config());
+        forwarder.setup().await.unwrap();
+
+        let mapping = forwarder
+            .add_mapping(7001, 7001, Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(mapping.external_port, 7001);
+        assert_eq!(mapping.internal_ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(forwarder.mappings().len(), 1);
+        assert_eq!(igd.state.entries.lock().unwrap().len(), 1);
 
-        assert!(client.gateway().is_some());
-        assert_eq!(client.gateway(), Some(gateway));
+        forwarder.cleanup().await.unwrap();
+        assert!(forwarder.mappings().is_empty());
+        assert!(forwarder.next_renewal().is_none());
+        assert!(igd.state.entries.lock().unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_nat_pmp_get_external_address() {
-        let mut client = NatPmpClient::new();
-        let addr = client.get_external_address().await.unwrap();
+    async fn test_port_forwarder_renews_leases() {
+        let igd = mock_igd::MockIgd::start().await;
+        let mut forwarder = PortForwarder::new().with_upnp_config(UpnpConfig {
+            lease_duration: Duration::from_secs(2),
+            ..igd.config()
+        });
+        forwarder.setup().await.unwrap();
+        forwarder
+            .add_mapping(7001, 7001, Protocol::Udp)
+            .await
+            .unwrap();
+
+        assert_eq!(forwarder.renew_mappings().await.unwrap(), 0);
+        let next = forwarder.next_renewal().unwrap();
+        assert!(next <= Duration::from_secs(1));
+
+        tokio::time::sleep(next + Duration::from_millis(50)).await;
+        assert_eq!(forwarder.renew_mappings().await.unwrap(), 1);
+        assert_eq!(
+            igd.state
+                .add_requests
+                .load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+        assert!(forwarder.next_renewal().unwrap() > Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_port_forwarder_get_external_ip() {
+        let igd = mock_igd::MockIgd::start().await;
+        let mut forwarder = PortForwarder::new().with_upnp_config(igd.config());
+        forwarder.setup().await.unwrap();
+
+        let ip = forwarder.get_external_ip().await.unwrap();
+        assert_eq!(ip, mock_igd::EXTERNAL_IP.parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_parse_routing_tables() {
+        let route = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                     eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+                     eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n";
+        assert_eq!(parse_proc_route(route), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(parse_proc_route("Iface\tDestination\tGateway\n"), None);
+
+        let ipv6_route = "fe800000000000000000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001 eth0\n\
+                          00000000000000000000000000000000 00 00000000000000000000000000000000 00 fe800000000000000000000000000001 00000400 00000001 00000000 00000003 eth0\n";
+        assert_eq!(
+            parse_proc_ipv6_route(ipv6_route),
+            Some(("fe80::1".parse().unwrap(), "eth0".to_string()))
+        );
+
+        let bsd =
+            "   route to: default\ndestination: default\n    gateway: 10.0.0.1\n  interface: en0\n";
+        assert_eq!(parse_route_output(bsd), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        let windows = "Network Destination        Netmask          Gateway       Interface  Metric\n          0.0.0.0          0.0.0.0      192.168.0.1    192.168.0.20     25\n";
+        assert_eq!(
+            parse_route_output(windows),
+            Some(Ipv4Addr::new(192, 168, 0, 1))
+        );
+    }
+
+    /// Loopback gateway speaking NAT-PMP and, when enabled, PCP on one port.
+    mod fake_gateway {
+        use super::*;
+        use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+        use std::sync::{Arc, Mutex};
+        use tokio::task::JoinHandle;
+
+        pub const EXTERNAL_V4: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 7);
+        pub const EXTERNAL_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7);
+
+        pub struct State {
+            pub pcp: AtomicBool,
+            pub epoch: AtomicU32,
+            pub max_lifetime: AtomicU32,
+            /// (protocol, internal port) -> external port
+            pub mappings: Mutex<HashMap<(u8, u16), u16>>,
+            pub map_requests: AtomicU32,
+        }
+
+        pub struct FakeGateway {
+            pub addr: SocketAddr,
+            pub state: Arc<State>,
+            task: JoinHandle<()>,
+        }
+
+        impl FakeGateway {
+            pub async fn start(bind: &str, pcp: bool) -> std::io::Result<Self> {
+                let socket = UdpSocket::bind(bind).await?;
+                let addr = socket.local_addr()?;
+                let state = Arc::new(State {
+                    pcp: AtomicBool::new(pcp),
+                    epoch: AtomicU32::new(1000),
+                    max_lifetime: AtomicU32::new(7200),
+                    mappings: Mutex::new(HashMap::new()),
+                    map_requests: AtomicU32::new(0),
+                });
+
+                let task_state = state.clone();
+                let task = tokio::spawn(async move {
+                    let mut buf = [0u8; 1100];
+                    while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+                        if let Some(response) = handle(&task_state, &buf[..n], from) {
+                            let _ = socket.send_to(&response, from).await;
+                        }
+                    }
+                });
+
+                Ok(Self { addr, state, task })
+            }
+
+            pub fn nat_pmp_config(&self) -> NatPmpConfig {
+                let IpAddr::V4(gateway) = self.addr.ip() else {
+                    unreachable!()
+                };
+                NatPmpConfig {
+                    gateway: Some(gateway),
+                    port: self.addr.port(),
+                    timeout: Duration::from_secs(2),
+                    retry_count: 2,
+                }
+            }
+
+            pub fn pcp_config(&self) -> PcpConfig {
+                PcpConfig {
+                    server: Some(self.addr),
+                    timeout: Duration::from_secs(2),
+                    retry_count: 2,
+                }
+            }
+
+            pub fn mapping_count(&self) -> usize {
+                self.state.mappings.lock().unwrap().len()
+            }
+        }
+
+        impl Drop for FakeGateway {
+            fn drop(&mut self) {
+                self.task.abort();
+            }
+        }
+
+        fn assign(
+            state: &State,
+            protocol: u8,
+            internal: u16,
+            suggested: u16,
+            lifetime: u32,
+        ) -> (u16, u32) {
+            state.map_requests.fetch_add(1, Ordering::SeqCst);
+            let mut mappings = state.mappings.lock().unwrap();
+            if lifetime == 0 {
+                mappings.remove(&(protocol, internal));
+                return (0, 0);
+            }
+            let taken = |port: u16| {
+                mappings
+                    .iter()
+                    .any(|(key, ext)| *ext == port && *key != (protocol, internal))
+            };
+            let external = match mappings.get(&(protocol, internal)) {
+                Some(external) => *external,
+                None if suggested != 0 && !taken(suggested) => suggested,
+                None => (40000..).find(|port| !taken(*port)).unwrap(),
+            };
+            mappings.insert((protocol, internal), external);
+            (
+                external,
+                lifetime.min(state.max_lifetime.load(Ordering::SeqCst)),
+            )
+        }
+
+        fn handle(state: &State, request: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+            let epoch = state.epoch.load(Ordering::SeqCst).to_be_bytes();
+            if request.len() < 2 {
+                return None;
+            }
+            let opcode = request[1];
+
+            if request[0] != NAT_PMP_VERSION && !state.pcp.load(Ordering::SeqCst) {
+                let mut response = vec![NAT_PMP_VERSION, opcode | 0x80, 0, 1];
+                response.extend_from_slice(&epoch);
+                return Some(response);
+            }
+
+            if request[0] == NAT_PMP_VERSION {
+                let mut response = vec![NAT_PMP_VERSION, opcode | 0x80, 0, 0];
+                response.extend_from_slice(&epoch);
+                match opcode {
+                    0 => response.extend_from_slice(&EXTERNAL_V4.octets()),
+                    1 | 2 if request.len() >= 12 => {
+                        let internal = u16::from_be_bytes([request[4], request[5]]);
+                        let suggested = u16::from_be_bytes([request[6], request[7]]);
+                        let lifetime = u32::from_be_bytes(request[8..12].try_into().unwrap());
+                        let (external, lifetime) =
+                            assign(state, opcode, internal, suggested, lifetime);
+                        response.extend_from_slice(&internal.to_be_bytes());
+                        response.extend_from_slice(&external.to_be_bytes());
+                        response.extend_from_slice(&lifetime.to_be_bytes());
+                    }
+                    _ => response[3] = 5,
+                }
+                return Some(response);
+            }
 
-        assert!(client.external_ip().is_some());
-        assert_eq!(client.external_ip(), Some(addr));
+            let mut header = vec![PCP_VERSION, opcode | 0x80, 0, 0];
+            let requested_lifetime = u32::from_be_bytes(request[4..8].try_into().unwrap());
+            if request[8..24] != pcp_address(from.ip()) {
+                header[3] = 12;
+            }
+            let payload = &request[PCP_HEADER_LEN..];
+            let mut response_payload = payload.to_vec();
+            let mut lifetime = 0;
+            if header[3] == 0 && (opcode == PCP_OPCODE_MAP || opcode == PCP_OPCODE_PEER) {
+                let protocol = payload[12];
+                let internal = u16::from_be_bytes([payload[16], payload[17]]);
+                let suggested = u16::from_be_bytes([payload[18], payload[19]]);
+                let (external, granted) =
+                    assign(state, protocol, internal, suggested, requested_lifetime);
+                lifetime = granted;
+                let external_ip = match from.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(EXTERNAL_V4),
+                    IpAddr::V6(_) => IpAddr::V6(EXTERNAL_V6),
+                };
+                response_payload[18..20].copy_from_slice(&external.to_be_bytes());
+                response_payload[20..36].copy_from_slice(&pcp_address(external_ip));
+            }
+            header.extend_from_slice(&lifetime.to_be_bytes());
+            header.extend_from_slice(&epoch);
+            header.extend_from_slice(&[0; 12]);
+            header.extend_from_slice(&response_payload);
+            Some(header)
+        }
+    }
+
+    async fn silent_upnp() -> (UdpSocket, UpnpConfig) {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = UpnpConfig {
+            search_timeout: Duration::from_millis(100),
+            ssdp_addr: silent.local_addr().unwrap(),
+            ..Default::default()
+        };
+        (silent, config)
     }
 
     #[tokio::test]
-    async fn test_nat_pmp_map_port() {
-        let mut client = NatPmpClient::new();
+    async fn test_nat_pmp_external_address_and_mappings() {
+        let gateway = fake_gateway::FakeGateway::start("127.0.0.1:0", false)
+            .await
+            .unwrap();
+        let mut client = NatPmpClient::with_config(gateway.nat_pmp_config());
+
+        let external = client.get_external_address().await.unwrap();
+        assert_eq!(external, fake_gateway::EXTERNAL_V4);
+        assert_eq!(client.external_ip(), Some(external));
 
-        let result = client
+        let mapping = client
             .map_port(7001, 7001, Protocol::Udp, Duration::from_secs(3600))
-            .await;
-        assert!(result.is_ok());
+            .await
+            .unwrap();
+        assert_eq!(mapping.external_port, 7001);
+        assert_eq!(mapping.internal_ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(mapping.lease_duration, Duration::from_secs(3600));
+
+        // The gateway picks another port when the suggested one is taken.
+        let other = client
+            .map_port(7002, 7001, Protocol::Udp, Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_ne!(other.external_port, 7001);
+        assert_eq!(gateway.mapping_count(), 2);
+
+        client.unmap_port(7001, Protocol::Udp).await.unwrap();
+        assert_eq!(gateway.mapping_count(), 1);
+        assert!(client.unmap_port(7001, Protocol::Udp).await.is_err());
     }
 
     #[tokio::test]
-    async fn test_port_forwarder_setup() {
-        let mut forwarder = PortForwarder::new();
-        let result = forwarder.setup().await;
-
-        assert!(result.is_ok());
-        assert!(forwarder.has_upnp() || forwarder.has_nat_pmp());
+    async fn test_nat_pmp_detects_epoch_reset() {
+        let gateway = fake_gateway::FakeGateway::start("127.0.0.1:0", false)
+            .await
+            .unwrap();
+        let mut client = NatPmpClient::with_config(gateway.nat_pmp_config());
+
+        client.get_external_address().await.unwrap();
+        client.get_external_address().await.unwrap();
+        assert!(!client.take_epoch_reset());
+
+        gateway
+            .state
+            .epoch
+            .store(3, std::sync::atomic::Ordering::SeqCst);
+        client.get_external_address().await.unwrap();
+        assert!(client.take_epoch_reset());
+        assert!(!client.take_epoch_reset());
     }
 
     #[tokio::test]
-    async fn test_port_forwarder_add_mapping() {
-        let mut forwarder = PortForwarder::new();
-        forwarder.setup().await.unwrap();
+    async fn test_nat_pmp_without_gateway_times_out() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut client = NatPmpClient::with_config(NatPmpConfig {
+            gateway: Some(Ipv4Addr::LOCALHOST),
+            port: silent.local_addr().unwrap().port(),
+            timeout: Duration::from_millis(300),
+            retry_count: 1,
+        });
+
+        assert!(client.get_external_address().await.is_err());
+    }
 
-        let result = forwarder.add_mapping(7001, 7001, Protocol::Udp).await;
-        assert!(result.is_ok());
+    #[tokio::test]
+    async fn test_pcp_rejected_by_nat_pmp_gateway() {
+        let gateway = fake_gateway::FakeGateway::start("127.0.0.1:0", false)
+            .await
+            .unwrap();
+        let mut client = PcpClient::with_config(gateway.pcp_config());
+
+        let err = client.probe().await.unwrap_err();
+        assert!(err.to_string().contains("version 0"));
+    }
 
-        let mapping = result.unwrap();
+    #[tokio::test]
+    async fn test_pcp_map_and_peer() {
+        let gateway = fake_gateway::FakeGateway::start("127.0.0.1:0", true)
+            .await
+            .unwrap();
+        let mut client = PcpClient::with_config(gateway.pcp_config());
+        client.probe().await.unwrap();
+
+        let mapping = client
+            .map_port(7001, 7001, Protocol::Udp, Duration::from_secs(3600))
+            .await
+            .unwrap();
         assert_eq!(mapping.external_port, 7001);
-        assert_eq!(forwarder.mappings().len(), 1);
+        assert_eq!(mapping.internal_ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(
+            client.external_ip(),
+            Some(IpAddr::V4(fake_gateway::EXTERNAL_V4))
+        );
+
+        // Refreshing reuses the mapping rather than creating a second one.
+        let refreshed = client
+            .map_port(7001, 7001, Protocol::Udp, Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(refreshed.external_port, 7001);
+        assert_eq!(gateway.mapping_count(), 1);
+
+        let remote: SocketAddr = "203.0.113.9:9000".parse().unwrap();
+        let external = client
+            .peer(7002, Protocol::Tcp, remote, Duration::from_secs(120))
+            .await
+            .unwrap();
+        assert_eq!(external.ip(), IpAddr::V4(fake_gateway::EXTERNAL_V4));
+        assert_eq!(gateway.mapping_count(), 2);
+
+        client.unmap_port(7001, Protocol::Udp).await.unwrap();
+        assert_eq!(gateway.mapping_count(), 1);
     }
 
     #[tokio::test]
-    async fn test_port_forwarder_get_external_ip() {
-        let mut forwarder = PortForwarder::new();
+    async fn test_pcp_ipv6() {
+        let Ok(gateway) = fake_gateway::FakeGateway::start("[::1]:0", true).await else {
+            return;
+        };
+        let mut client = PcpClient::with_config(gateway.pcp_config());
+
+        let mapping = client
+            .map_port(7001, 7001, Protocol::Tcp, Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(mapping.internal_ip, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(
+            client.external_ip(),
+            Some(IpAddr::V6(fake_gateway::EXTERNAL_V6))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_port_forwarder_chooses_protocol() {
+        let (_silent, upnp_config) = silent_upnp().await;
+
+        let pcp_gateway = fake_gateway::FakeGateway::start("127.0.0.1:0", true)
+            .await
+            .unwrap();
+        let mut forwarder = PortForwarder::new()
+            .with_upnp_config(upnp_config.clone())
+            .with_pcp_config(pcp_gateway.pcp_config())
+            .with_nat_pmp_config(pcp_gateway.nat_pmp_config());
+        forwarder.setup().await.unwrap();
+        assert!(!forwarder.has_upnp());
+        assert!(forwarder.has_pcp());
+        assert!(!forwarder.has_nat_pmp());
+
+        let nat_pmp_gateway = fake_gateway::FakeGateway::start("127.0.0.1:0", false)
+            .await
+            .unwrap();
+        let mut forwarder = PortForwarder::new()
+            .with_upnp_config(upnp_config)
+            .with_pcp_config(nat_pmp_gateway.pcp_config())
+            .with_nat_pmp_config(nat_pmp_gateway.nat_pmp_config());
         forwarder.setup().await.unwrap();
+        assert!(!forwarder.has_pcp());
+        assert!(forwarder.has_nat_pmp());
 
         let ip = forwarder.get_external_ip().await.unwrap();
-        assert!(!ip.is_unspecified());
+        assert_eq!(ip, IpAddr::V4(fake_gateway::EXTERNAL_V4));
+    }
+
+    #[tokio::test]
+    async fn test_port_forwarder_refreshes_nat_pmp_leases() {
+        let (_silent, upnp_config) = silent_upnp().await;
+        let gateway = fake_gateway::FakeGateway::start("127.0.0.1:0", false)
+            .await
+            .unwrap();
+        gateway
+            .state
+            .max_lifetime
+            .store(2, std::sync::atomic::Ordering::SeqCst);
+
+        let mut forwarder = PortForwarder::new()
+            .with_upnp_config(upnp_config)
+            .with_pcp_config(gateway.pcp_config())
+            .with_nat_pmp_config(gateway.nat_pmp_config());
+        forwarder.setup().await.unwrap();
+
+        let mapping = forwarder
+            .add_mapping(7001, 7001, Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(mapping.lease_duration, Duration::from_secs(2));
+        assert_eq!(forwarder.renew_mappings().await.unwrap(), 0);
+
+        let next = forwarder.next_renewal().unwrap();
+        tokio::time::sleep(next + Duration::from_millis(50)).await;
+        assert_eq!(forwarder.renew_mappings().await.unwrap(), 1);
+        assert_eq!(forwarder.mappings().len(), 1);
+
+        // A gateway reboot resets its epoch and forces an immediate refresh.
+        gateway
+            .state
+            .epoch
+            .store(0, std::sync::atomic::Ordering::SeqCst);
+        forwarder.get_external_ip().await.unwrap();
+        assert_eq!(forwarder.renew_mappings().await.unwrap(), 1);
+        assert_eq!(
+            gateway
+                .state
+                .map_requests
+                .load(std::sync::atomic::Ordering::SeqCst),
+            3
+        );
+
+        forwarder.cleanup().await.unwrap();
+        assert_eq!(gateway.mapping_count(), 0);
     }
 }
@@ -140,6 +140,7 @@ async fn test_mixer_score_calculation() {
         memory_usage_percent: 20.0,
         session_duration: Duration::from_secs(3600),
         packet_loss_percent: 0.0,
+        region: None,
         last_updated: std::time::Instant::now(),
     };
     manager.update_participant_stats("peer_0", stats1);
@@ -152,6 +153,7 @@ async fn test_mixer_score_calculation() {
         memory_usage_percent: 90.0,
         session_duration: Duration::from_secs(60),
         packet_loss_percent: 5.0,
+        region: None,
         last_updated: std::time::Instant::now(),
     };
     manager.update_participant_stats("peer_1", stats2);
@@ -346,6 +348,487 @@ async fn test_network_node_creation() {
     assert!(known_peers.is_empty());
 }
 
+#[tokio::test]
+async fn test_network_node_uses_identity_key() {
+    use agora_core::network::NetworkNodeConfig;
+
+    let identity = Identity::generate().expect("Failed to generate identity");
+    let config = || NetworkNodeConfig {
+        identity: Some(identity.clone()),
+        listen_addr: Some("/ip4/127.0.0.1/tcp/0".to_string()),
+        ..Default::default()
+    };
+
+    let first = NetworkNode::with_config(config())
+        .await
+        .expect("Failed to create network node");
+    assert_eq!(first.peer_id_string(), identity.peer_id());
+    drop(first);
+
+    let restarted = NetworkNode::with_config(config())
+        .await
+        .expect("Failed to create network node");
+    assert_eq!(restarted.local_peer_id(), identity.libp2p_peer_id());
+}
+
+struct RoomPair {
+    host_id: libp2p::PeerId,
+    joiner_id: libp2p::PeerId,
+    host_events: tokio::sync::broadcast::Receiver<agora_core::NetworkEvent>,
+    joiner_events: tokio::sync::broadcast::Receiver<agora_core::NetworkEvent>,
+    host_commands: tokio::sync::mpsc::Sender<agora_core::NetworkCommand>,
+    joiner_commands: tokio::sync::mpsc::Sender<agora_core::NetworkCommand>,
+}
+
+/// Runs both nodes, has `joiner` dial `host`, joins both to `room_id` with
+/// the given passwords and sends the joiner's `JoinRoom` to the host.
+async fn connect_and_join(
+    host: NetworkNode,
+    joiner: NetworkNode,
+    room_id: &str,
+    host_password: Option<&str>,
+    joiner_password: Option<&str>,
+    host_creator: Option<agora_core::room::CreatorProof>,
+) -> RoomPair {
+    use agora_core::{NetworkCommand, NetworkEvent};
+
+    let (mut host, mut joiner) = (host, joiner);
+    let host_id = host.local_peer_id();
+    let joiner_id = joiner.local_peer_id();
+    let mut host_events = host.subscribe_events();
+    let mut joiner_events = joiner.subscribe_events();
+    let host_commands = host.command_sender();
+    let joiner_commands = joiner.command_sender();
+
+    tokio::spawn(async move { host.run().await });
+    tokio::spawn(async move { joiner.run().await });
+
+    let addr = loop {
+        if let Ok(NetworkEvent::Listening(addr)) = host_events.recv().await {
+            break addr;
+        }
+    };
+
+    for (commands, password, creator) in [
+        (&host_commands, host_password, host_creator),
+        (&joiner_commands, joiner_password, None),
+    ] {
+        commands
+            .send(NetworkCommand::JoinRoom {
+                room_id: room_id.to_string(),
+                password: password.map(str::to_string),
+                creator,
+            })
+            .await
+            .unwrap();
+    }
+    joiner_commands
+        .send(NetworkCommand::ConnectToPeer { addr })
+        .await
+        .unwrap();
+    loop {
+        if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = joiner_events.recv().await {
+            if peer_id == host_id {
+                break;
+            }
+        }
+    }
+
+    joiner_commands
+        .send(NetworkCommand::SendControl {
+            peer_id: host_id,
+            message: ControlMessage::join_room(room_id.to_string(), joiner_id.to_string()),
+        })
+        .await
+        .unwrap();
+
+    RoomPair {
+        host_id,
+        joiner_id,
+        host_events,
+        joiner_events,
+        host_commands,
+        joiner_commands,
+    }
+}
+
+/// Waits until both sides report the same rekeyed group key.
+async fn wait_for_shared_key(pair: &mut RoomPair, room_id: &str) -> u64 {
+    use agora_core::NetworkEvent;
+
+    let host_rotation = async {
+        loop {
+            if let Ok(NetworkEvent::KeyRotated(event)) = pair.host_events.recv().await {
+                if event.new_key_id > 1 {
+                    return event;
+                }
+            }
+        }
+    };
+    let joiner_rotation = async {
+        loop {
+            if let Ok(NetworkEvent::KeyRotated(event)) = pair.joiner_events.recv().await {
+                if event.new_key_id > 1 {
+                    return event;
+                }
+            }
+        }
+    };
+    let (host_event, joiner_event) = tokio::join!(host_rotation, joiner_rotation);
+    assert_eq!(host_event.room_id, room_id);
+    assert_eq!(host_event.new_key_id, joiner_event.new_key_id);
+    host_event.new_key_id
+}
+
+async fn start_room_pair(host: NetworkNode, joiner: NetworkNode, room_id: &str) -> RoomPair {
+    let mut pair = connect_and_join(host, joiner, room_id, None, None, None).await;
+    wait_for_shared_key(&mut pair, room_id).await;
+    pair
+}
+
+fn local_node_config() -> agora_core::network::NetworkNodeConfig {
+    agora_core::network::NetworkNodeConfig {
+        listen_addr: Some("/ip4/127.0.0.1/tcp/0".to_string()),
+        stun_servers: vec![],
+        // Rooms in these tests are joined explicitly; keep parallel tests
+        // from finding each other on the loopback network.
+        enable_mdns: false,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_room_join_distributes_group_key() {
+    let host = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create host");
+    let joiner = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create joiner");
+
+    let key_id = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut pair = connect_and_join(host, joiner, "group-key-room", None, None, None).await;
+        wait_for_shared_key(&mut pair, "group-key-room").await
+    })
+    .await
+    .expect("Group key was not distributed");
+    assert_eq!(key_id, 2);
+}
+
+#[tokio::test]
+async fn test_password_room_admits_joiner_with_password() {
+    let host = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create host");
+    let joiner = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create joiner");
+
+    let key_id = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut pair = connect_and_join(
+            host,
+            joiner,
+            "private-room",
+            Some("hunter2"),
+            Some("hunter2"),
+            None,
+        )
+        .await;
+        wait_for_shared_key(&mut pair, "private-room").await
+    })
+    .await
+    .expect("Joiner with the right password was not admitted");
+    assert_eq!(key_id, 2);
+}
+
+#[tokio::test]
+async fn test_password_room_rejects_wrong_password() {
+    use agora_core::protocol::JoinRejectReason;
+    use agora_core::NetworkEvent;
+
+    let host = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create host");
+    let joiner = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create joiner");
+
+    let result = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut pair = connect_and_join(
+            host,
+            joiner,
+            "private-room",
+            Some("hunter2"),
+            Some("hunter3"),
+            None,
+        )
+        .await;
+
+        loop {
+            match pair.joiner_events.recv().await {
+                Ok(NetworkEvent::JoinRejected {
+                    room_id,
+                    peer_id,
+                    reason,
+                }) => break (pair, room_id, peer_id, reason),
+                Ok(NetworkEvent::KeyRotated(event)) if event.new_key_id > 1 => {
+                    panic!("Joiner received a room key despite the wrong password")
+                }
+                _ => {}
+            }
+        }
+    })
+    .await;
+
+    let (mut pair, room_id, peer_id, reason) = result.expect("Join was not rejected");
+    assert_eq!(room_id, "private-room");
+    assert_eq!(peer_id, pair.host_id);
+    assert_eq!(reason, JoinRejectReason::InvalidPassword);
+
+    // The host never released a key to the joiner.
+    while let Ok(event) = pair.host_events.try_recv() {
+        if let NetworkEvent::KeyRotated(event) = event {
+            assert_eq!(event.new_key_id, 1);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_password_room_requires_password() {
+    use agora_core::protocol::JoinRejectReason;
+    use agora_core::NetworkEvent;
+
+    let host = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create host");
+    let joiner = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create joiner");
+
+    let reason = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut pair =
+            connect_and_join(host, joiner, "private-room", Some("hunter2"), None, None).await;
+        loop {
+            if let Ok(NetworkEvent::JoinRejected { reason, .. }) = pair.joiner_events.recv().await {
+                break reason;
+            }
+        }
+    })
+    .await
+    .expect("Missing password was not reported");
+    assert_eq!(reason, JoinRejectReason::PasswordRequired);
+}
+
+#[tokio::test]
+async fn test_blocked_peer_control_messages_dropped() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{Blocklist, NetworkCommand, NetworkEvent};
+
+    let joiner = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create joiner");
+    let mut blocklist = Blocklist::new();
+    blocklist.block(&joiner.local_peer_id(), None);
+    let host = NetworkNode::with_config(NetworkNodeConfig {
+        blocklist,
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to create host");
+
+    let mut pair = connect_and_join(host, joiner, "blocked-room", None, None, None).await;
+    let joiner_id = pair.joiner_id;
+
+    let dropped = tokio::time::timeout(Duration::from_millis(500), async {
+        loop {
+            if let Ok(NetworkEvent::ControlReceived { peer_id, .. }) = pair.host_events.recv().await
+            {
+                if peer_id == joiner_id {
+                    return;
+                }
+            }
+        }
+    })
+    .await;
+    assert!(
+        dropped.is_err(),
+        "Control message from a blocked peer got through"
+    );
+
+    pair.host_commands
+        .send(NetworkCommand::UnblockPeer { peer_id: joiner_id })
+        .await
+        .unwrap();
+    let update = ControlMessage::new(
+        ControlMessageType::UpdateInfo {
+            display_name: "unblocked".to_string(),
+        },
+        joiner_id.to_string(),
+    );
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            pair.joiner_commands
+                .send(NetworkCommand::SendControl {
+                    peer_id: pair.host_id,
+                    message: update.clone(),
+                })
+                .await
+                .unwrap();
+            let received = tokio::time::timeout(Duration::from_millis(200), async {
+                loop {
+                    if let Ok(NetworkEvent::ControlReceived { peer_id, .. }) =
+                        pair.host_events.recv().await
+                    {
+                        if peer_id == joiner_id {
+                            return;
+                        }
+                    }
+                }
+            })
+            .await;
+            if received.is_ok() {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("Control messages still dropped after unblocking");
+}
+
+#[tokio::test]
+async fn test_creator_ban_removes_and_blocks_joiner() {
+    use agora_core::moderation::ModerationAction;
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::protocol::JoinRejectReason;
+    use agora_core::{NetworkCommand, NetworkEvent};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let creator = Identity::generate().unwrap();
+    let room = Room::new(creator.peer_id(), RoomConfig::default_public());
+    let host = NetworkNode::with_config(NetworkNodeConfig {
+        identity: Some(creator),
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to create host");
+    let joiner = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create joiner");
+
+    let reason = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut pair =
+            connect_and_join(host, joiner, &room.id, None, None, room.creator_proof()).await;
+        let key_id = wait_for_shared_key(&mut pair, &room.id).await;
+
+        let until = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 3600;
+        pair.host_commands
+            .send(NetworkCommand::Moderate {
+                room_id: room.id.clone(),
+                action: ModerationAction::Ban {
+                    target: pair.joiner_id.to_string(),
+                    until,
+                },
+            })
+            .await
+            .unwrap();
+
+        // The host drops the joiner and moves on to a key it never sees.
+        loop {
+            if let Ok(NetworkEvent::KeyRotated(event)) = pair.host_events.recv().await {
+                if event.new_key_id > key_id {
+                    break;
+                }
+            }
+        }
+        loop {
+            if let Ok(NetworkEvent::ModerationApplied { issuer, .. }) =
+                pair.joiner_events.recv().await
+            {
+                assert_eq!(issuer, pair.host_id);
+                break;
+            }
+        }
+
+        pair.joiner_commands
+            .send(NetworkCommand::JoinRoom {
+                room_id: room.id.clone(),
+                password: None,
+                creator: None,
+            })
+            .await
+            .unwrap();
+        pair.joiner_commands
+            .send(NetworkCommand::SendControl {
+                peer_id: pair.host_id,
+                message: ControlMessage::join_room(room.id.clone(), pair.joiner_id.to_string()),
+            })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::JoinRejected { reason, .. }) = pair.joiner_events.recv().await {
+                break reason;
+            }
+        }
+    })
+    .await
+    .expect("Banned joiner was not removed and rejected");
+    assert_eq!(reason, JoinRejectReason::Banned);
+}
+
+#[tokio::test]
+async fn test_room_audio_is_end_to_end_encrypted() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{AudioProcessorConfig, NetworkCommand, NetworkEvent};
+
+    let config = || NetworkNodeConfig {
+        listen_addr: Some("/ip4/127.0.0.1/tcp/0".to_string()),
+        stun_servers: vec![],
+        audio: AudioProcessorConfig::default()
+            .with_denoising(false)
+            .with_echo_cancellation(false),
+        enable_mdns: false,
+        ..Default::default()
+    };
+
+    let host = NetworkNode::with_config(config())
+        .await
+        .expect("Failed to create host");
+    let joiner = NetworkNode::with_config(config())
+        .await
+        .expect("Failed to create joiner");
+
+    let result = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut pair = start_room_pair(host, joiner, "e2ee-room").await;
+
+        for sequence in 1..=3 {
+            pair.joiner_commands
+                .send(NetworkCommand::BroadcastAudio {
+                    room_id: "e2ee-room".to_string(),
+                    packet: AudioPacket::new(sequence, "spoofed".to_string(), vec![0.1; 960]),
+                })
+                .await
+                .unwrap();
+        }
+
+        let packet = loop {
+            if let Ok(NetworkEvent::AudioReceived { packet, .. }) = pair.host_events.recv().await {
+                break packet;
+            }
+        };
+        (pair.joiner_id, packet)
+    })
+    .await;
+
+    let (joiner_id, packet) = result.expect("No encrypted audio received");
+    assert_eq!(packet.frame.len(), 960);
+    // The sender id is bound to the transport identity, not caller-supplied.
+    assert_eq!(packet.peer_id, joiner_id.to_string());
+}
+
 #[tokio::test]
 async fn test_network_event_subscription() {
     let node = NetworkNode::new(None)
@@ -360,6 +843,1339 @@ async fn test_network_event_subscription() {
     drop(event_rx);
 }
 
+#[tokio::test]
+async fn test_quic_audio_delivery() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{AudioProcessorConfig, NetworkCommand, NetworkEvent};
+
+    let quic_config = || NetworkNodeConfig {
+        quic_listen_addr: Some("/ip4/127.0.0.1/udp/0/quic-v1".to_string()),
+        enable_tcp: false,
+        enable_quic: true,
+        stun_servers: vec![],
+        audio: AudioProcessorConfig::default()
+            .with_denoising(false)
+            .with_echo_cancellation(false),
+        enable_mdns: false,
+        ..Default::default()
+    };
+
+    let receiver = NetworkNode::with_config(quic_config())
+        .await
+        .expect("Failed to create receiver");
+    let sender = NetworkNode::with_config(quic_config())
+        .await
+        .expect("Failed to create sender");
+
+    let result = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut pair = start_room_pair(receiver, sender, "quic-room").await;
+
+        for sequence in 1..=3 {
+            pair.joiner_commands
+                .send(NetworkCommand::SendAudio {
+                    peer_id: pair.host_id,
+                    packet: AudioPacket::new(sequence, "sender".to_string(), vec![0.1; 960]),
+                })
+                .await
+                .unwrap();
+        }
+
+        loop {
+            if let Ok(NetworkEvent::AudioReceived { packet, .. }) = pair.host_events.recv().await {
+                break packet;
+            }
+        }
+    })
+    .await;
+
+    let packet = result.expect("No audio received over QUIC");
+    assert_eq!(packet.frame.len(), 960);
+}
+
+#[tokio::test]
+async fn test_forwarding_mixer_relays_audio() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{AudioProcessorConfig, MediaMode, NetworkCommand, NetworkEvent};
+
+    // Three participants are enough for SFU topology with this threshold.
+    let config = || NetworkNodeConfig {
+        audio: AudioProcessorConfig::default()
+            .with_denoising(false)
+            .with_echo_cancellation(false),
+        mixer: MixerConfig {
+            full_mesh_threshold: 2,
+            media_mode: MediaMode::Forward,
+            ..Default::default()
+        },
+        ..local_node_config()
+    };
+    let mut nodes = Vec::new();
+    for _ in 0..3 {
+        nodes.push(
+            NetworkNode::with_config(config())
+                .await
+                .expect("Failed to create node"),
+        );
+    }
+    let ids: Vec<libp2p::PeerId> = nodes.iter().map(|n| n.local_peer_id()).collect();
+    let mut events: Vec<_> = nodes.iter().map(|n| n.subscribe_events()).collect();
+    let commands: Vec<_> = nodes.iter().map(|n| n.command_sender()).collect();
+    for mut node in nodes {
+        tokio::spawn(async move { node.run().await });
+    }
+
+    // Every node agrees on the lowest peer id when scores tie.
+    let mixer = (0..3).min_by_key(|&i| ids[i].to_string()).unwrap();
+    let mut others = (0..3).filter(|&i| i != mixer);
+    let (sender, receiver) = (others.next().unwrap(), others.next().unwrap());
+
+    let result = tokio::time::timeout(Duration::from_secs(15), async {
+        let addr = loop {
+            if let Ok(NetworkEvent::Listening(addr)) = events[mixer].recv().await {
+                break addr;
+            }
+        };
+        for command in &commands {
+            command
+                .send(NetworkCommand::JoinRoom {
+                    room_id: "forward-room".to_string(),
+                    password: None,
+                    creator: None,
+                })
+                .await
+                .unwrap();
+        }
+        for i in [sender, receiver] {
+            commands[i]
+                .send(NetworkCommand::ConnectToPeer { addr: addr.clone() })
+                .await
+                .unwrap();
+            loop {
+                if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = events[i].recv().await {
+                    if peer_id == ids[mixer] {
+                        break;
+                    }
+                }
+            }
+            commands[i]
+                .send(NetworkCommand::SendControl {
+                    peer_id: ids[mixer],
+                    message: ControlMessage::join_room(
+                        "forward-room".to_string(),
+                        ids[i].to_string(),
+                    ),
+                })
+                .await
+                .unwrap();
+        }
+
+        let mut sequence = 0;
+        loop {
+            sequence += 1;
+            commands[sender]
+                .send(NetworkCommand::BroadcastAudio {
+                    room_id: "forward-room".to_string(),
+                    packet: AudioPacket::new(sequence, String::new(), vec![0.1; 960]),
+                })
+                .await
+                .unwrap();
+            let received = tokio::time::timeout(Duration::from_millis(200), async {
+                loop {
+                    if let Ok(NetworkEvent::AudioReceived { peer_id, packet }) =
+                        events[receiver].recv().await
+                    {
+                        if peer_id == ids[sender] {
+                            return packet;
+                        }
+                    }
+                }
+            })
+            .await;
+            if let Ok(packet) = received {
+                break packet;
+            }
+        }
+    })
+    .await;
+
+    let packet = result.expect("Forwarded audio never reached the receiver");
+    assert_eq!(packet.peer_id, ids[sender].to_string());
+}
+
+/// Reads events until every listed node's latest `MixerElected` names the
+/// same mixer for the same epoch, and returns that result.
+async fn wait_for_agreed_mixer(
+    events: &mut [tokio::sync::broadcast::Receiver<agora_core::NetworkEvent>],
+    nodes: &[usize],
+    min_epoch: u64,
+) -> (libp2p::PeerId, u64) {
+    let mut latest = vec![None; events.len()];
+    loop {
+        for &i in nodes {
+            while let Ok(event) = events[i].try_recv() {
+                if let agora_core::NetworkEvent::MixerElected { mixer, epoch, .. } = event {
+                    latest[i] = Some((mixer, epoch));
+                }
+            }
+        }
+        let first = latest[nodes[0]];
+        if let Some((mixer, epoch)) = first {
+            if epoch >= min_epoch && nodes.iter().all(|&i| latest[i] == first) {
+                return (mixer, epoch);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_mixer_election_agreed_and_rerun_when_mixer_leaves() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{NetworkCommand, NetworkEvent};
+
+    // Two participants are already enough for SFU topology, so the room
+    // still needs a mixer after one of the three leaves.
+    let config = || NetworkNodeConfig {
+        mixer: MixerConfig {
+            full_mesh_threshold: 1,
+            ..Default::default()
+        },
+        ..local_node_config()
+    };
+    let mut nodes = Vec::new();
+    for _ in 0..3 {
+        nodes.push(
+            NetworkNode::with_config(config())
+                .await
+                .expect("Failed to create node"),
+        );
+    }
+    let ids: Vec<libp2p::PeerId> = nodes.iter().map(|n| n.local_peer_id()).collect();
+    let mut events: Vec<_> = nodes.iter().map(|n| n.subscribe_events()).collect();
+    let commands: Vec<_> = nodes.iter().map(|n| n.command_sender()).collect();
+    for mut node in nodes {
+        tokio::spawn(async move { node.run().await });
+    }
+
+    // Everyone joins through the hub and then connects to the others it
+    // lists, so the rest stay connected whichever mixer leaves.
+    let hub = 0;
+
+    let result = tokio::time::timeout(Duration::from_secs(30), async {
+        let addr = loop {
+            if let Ok(NetworkEvent::Listening(addr)) = events[hub].recv().await {
+                break addr;
+            }
+        };
+        for command in &commands {
+            command
+                .send(NetworkCommand::JoinRoom {
+                    room_id: "election-room".to_string(),
+                    password: None,
+                    creator: None,
+                })
+                .await
+                .unwrap();
+        }
+        for i in [1, 2] {
+            commands[i]
+                .send(NetworkCommand::ConnectToPeer { addr: addr.clone() })
+                .await
+                .unwrap();
+            loop {
+                if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = events[i].recv().await {
+                    if peer_id == ids[hub] {
+                        break;
+                    }
+                }
+            }
+            commands[i]
+                .send(NetworkCommand::SendControl {
+                    peer_id: ids[hub],
+                    message: ControlMessage::join_room(
+                        "election-room".to_string(),
+                        ids[i].to_string(),
+                    ),
+                })
+                .await
+                .unwrap();
+        }
+
+        let (elected, epoch) = wait_for_agreed_mixer(&mut events, &[0, 1, 2], 1).await;
+        let mixer = ids.iter().position(|id| *id == elected).unwrap();
+        let rest: Vec<usize> = (0..3).filter(|&i| i != mixer).collect();
+
+        commands[mixer]
+            .send(NetworkCommand::LeaveRoom {
+                room_id: "election-room".to_string(),
+            })
+            .await
+            .unwrap();
+        let (elected, _) = wait_for_agreed_mixer(&mut events, &rest, epoch + 1).await;
+        (elected, rest)
+    })
+    .await;
+
+    let (elected, rest) = result.expect("Remaining participants never agreed on a new mixer");
+    assert!(rest.iter().any(|&i| ids[i] == elected));
+}
+
+#[tokio::test]
+async fn test_cascaded_mixers_relay_audio_across_groups() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{AudioProcessorConfig, MediaMode, NetworkCommand, NetworkEvent};
+
+    // Four participants with room for two per mixer: two mixers, each
+    // serving one other participant.
+    let config = || NetworkNodeConfig {
+        audio: AudioProcessorConfig::default()
+            .with_denoising(false)
+            .with_echo_cancellation(false),
+        mixer: MixerConfig {
+            full_mesh_threshold: 1,
+            max_participants: 2,
+            media_mode: MediaMode::Forward,
+            ..Default::default()
+        },
+        ..local_node_config()
+    };
+    let mut nodes = Vec::new();
+    for _ in 0..4 {
+        nodes.push(
+            NetworkNode::with_config(config())
+                .await
+                .expect("Failed to create node"),
+        );
+    }
+    // Joining in peer ID order keeps the first participant leading the room
+    // key, so no rekey races a newcomer's.
+    nodes.sort_by_key(|n| n.local_peer_id());
+    let ids: Vec<libp2p::PeerId> = nodes.iter().map(|n| n.local_peer_id()).collect();
+    let mut events: Vec<_> = nodes.iter().map(|n| n.subscribe_events()).collect();
+    let commands: Vec<_> = nodes.iter().map(|n| n.command_sender()).collect();
+    for mut node in nodes {
+        tokio::spawn(async move { node.run().await });
+    }
+
+    let result = tokio::time::timeout(Duration::from_secs(30), async {
+        let mut addrs = Vec::new();
+        for receiver in events.iter_mut() {
+            addrs.push(loop {
+                if let Ok(NetworkEvent::Listening(addr)) = receiver.recv().await {
+                    break addr;
+                }
+            });
+        }
+        for command in &commands {
+            command
+                .send(NetworkCommand::JoinRoom {
+                    room_id: "cascade-room".to_string(),
+                    password: None,
+                    creator: None,
+                })
+                .await
+                .unwrap();
+        }
+        // Mixers relay to each other directly, so connect everyone.
+        for i in 1..4 {
+            for j in 0..i {
+                commands[i]
+                    .send(NetworkCommand::ConnectToPeer {
+                        addr: addrs[j].clone(),
+                    })
+                    .await
+                    .unwrap();
+                loop {
+                    if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = events[i].recv().await
+                    {
+                        if peer_id == ids[j] {
+                            break;
+                        }
+                    }
+                }
+                commands[i]
+                    .send(NetworkCommand::SendControl {
+                        peer_id: ids[j],
+                        message: ControlMessage::join_room(
+                            "cascade-room".to_string(),
+                            ids[i].to_string(),
+                        ),
+                    })
+                    .await
+                    .unwrap();
+            }
+        }
+        wait_for_agreed_mixer(&mut events, &[0, 1, 2, 3], 1).await;
+
+        // Whichever group the speaker is in, its audio has to cross to the
+        // other one through both mixers.
+        let mut heard = [false; 4];
+        heard[3] = true;
+        let mut sequence = 0;
+        while !heard.iter().all(|&h| h) {
+            sequence += 1;
+            commands[3]
+                .send(NetworkCommand::BroadcastAudio {
+                    room_id: "cascade-room".to_string(),
+                    packet: AudioPacket::new(sequence, String::new(), vec![0.1; 960]),
+                })
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            for i in 0..3 {
+                while let Ok(event) = events[i].try_recv() {
+                    if let NetworkEvent::AudioReceived { peer_id, .. } = event {
+                        heard[i] |= peer_id == ids[3];
+                    }
+                }
+            }
+        }
+    })
+    .await;
+
+    result.expect("Audio never reached every group of the cascade");
+}
+
+#[tokio::test]
+async fn test_dedicated_node_mixes_on_request() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{
+        AudioProcessorConfig, Identity, MediaMode, MixerNode, NetworkCommand, NetworkEvent,
+    };
+
+    // The first participant has the lowest peer ID, so it keeps leading the
+    // room key while the others join.
+    let mut identities: Vec<Identity> = (0..3).map(|_| Identity::generate().unwrap()).collect();
+    identities.sort_by_key(|i| i.libp2p_peer_id());
+    let config = |identity: Identity, dedicated: bool| NetworkNodeConfig {
+        identity: Some(identity),
+        audio: AudioProcessorConfig::default()
+            .with_denoising(false)
+            .with_echo_cancellation(false),
+        mixer: MixerConfig {
+            media_mode: MediaMode::Forward,
+            dedicated,
+            ..Default::default()
+        },
+        max_hosted_rooms: if dedicated { 1 } else { 0 },
+        ..local_node_config()
+    };
+    let (host, guest, node) = (0, 1, 2);
+    let mut nodes = Vec::new();
+    for (i, identity) in identities.into_iter().enumerate() {
+        nodes.push(
+            NetworkNode::with_config(config(identity, i == node))
+                .await
+                .expect("Failed to create node"),
+        );
+    }
+    let ids: Vec<libp2p::PeerId> = nodes.iter().map(|n| n.local_peer_id()).collect();
+    let mut events: Vec<_> = nodes.iter().map(|n| n.subscribe_events()).collect();
+    let mut node_events = nodes[node].subscribe_events();
+    let commands: Vec<_> = nodes.iter().map(|n| n.command_sender()).collect();
+    for mut node in nodes {
+        tokio::spawn(async move { node.run().await });
+    }
+
+    let result = tokio::time::timeout(Duration::from_secs(30), async {
+        let mut addrs = Vec::new();
+        for receiver in events.iter_mut() {
+            addrs.push(loop {
+                if let Ok(NetworkEvent::Listening(addr)) = receiver.recv().await {
+                    break addr;
+                }
+            });
+        }
+        // The node never learns the password; the host admits it and asks
+        // the guest to, so neither challenges it.
+        for i in [host, guest] {
+            commands[i]
+                .send(NetworkCommand::JoinRoom {
+                    room_id: "dedicated-room".to_string(),
+                    password: Some("secret".to_string()),
+                    creator: None,
+                })
+                .await
+                .unwrap();
+        }
+        for (i, j) in [(guest, host), (host, node), (guest, node)] {
+            commands[i]
+                .send(NetworkCommand::ConnectToPeer {
+                    addr: addrs[j].clone(),
+                })
+                .await
+                .unwrap();
+            loop {
+                if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = events[i].recv().await {
+                    if peer_id == ids[j] {
+                        break;
+                    }
+                }
+            }
+        }
+        commands[guest]
+            .send(NetworkCommand::SendControl {
+                peer_id: ids[host],
+                message: ControlMessage::join_room(
+                    "dedicated-room".to_string(),
+                    ids[guest].to_string(),
+                ),
+            })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::RoomJoined { peer_id, .. }) = events[host].recv().await {
+                if peer_id == ids[guest] {
+                    break;
+                }
+            }
+        }
+
+        commands[host]
+            .send(NetworkCommand::RequestMixer {
+                room_id: "dedicated-room".to_string(),
+                node: MixerNode {
+                    peer_id: ids[node].to_string(),
+                    region: "local".to_string(),
+                    latency_ms: Some(1),
+                    score: 0.9,
+                    reputation: 0.9,
+                },
+            })
+            .await
+            .unwrap();
+        // Only the node we asked can answer for it.
+        let mut forged = ControlMessage::new(
+            ControlMessageType::MixerDeclined {
+                room_id: "dedicated-room".to_string(),
+                reason: "forged".to_string(),
+            },
+            ids[guest].to_string(),
+        );
+        forged.room_id = Some("dedicated-room".to_string());
+        commands[guest]
+            .send(NetworkCommand::SendControl {
+                peer_id: ids[host],
+                message: forged,
+            })
+            .await
+            .unwrap();
+        loop {
+            match events[host].recv().await {
+                Ok(NetworkEvent::MixerAccepted { peer_id, .. }) => {
+                    assert_eq!(peer_id, ids[node]);
+                    break;
+                }
+                Ok(NetworkEvent::MixerDeclined { peer_id, .. }) => {
+                    panic!("Took a mixer reply from {}", peer_id);
+                }
+                _ => {}
+            }
+        }
+
+        // The room may briefly agree on a participant before the node's
+        // stats reach everyone.
+        let mut min_epoch = 1;
+        loop {
+            let (mixer, epoch) = wait_for_agreed_mixer(&mut events, &[0, 1, 2], min_epoch).await;
+            if mixer == ids[node] {
+                break;
+            }
+            min_epoch = epoch + 1;
+        }
+
+        let mut sequence = 0;
+        loop {
+            sequence += 1;
+            commands[guest]
+                .send(NetworkCommand::BroadcastAudio {
+                    room_id: "dedicated-room".to_string(),
+                    packet: AudioPacket::new(sequence, String::new(), vec![0.1; 960]),
+                })
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let mut heard = false;
+            while let Ok(event) = events[host].try_recv() {
+                if let NetworkEvent::AudioReceived { peer_id, .. } = event {
+                    heard |= peer_id == ids[guest];
+                }
+            }
+            if heard {
+                break;
+            }
+        }
+
+        // One hosted room is all the node takes.
+        commands[guest]
+            .send(NetworkCommand::JoinRoom {
+                room_id: "second-room".to_string(),
+                password: None,
+                creator: None,
+            })
+            .await
+            .unwrap();
+        commands[guest]
+            .send(NetworkCommand::RequestMixer {
+                room_id: "second-room".to_string(),
+                node: MixerNode {
+                    peer_id: ids[node].to_string(),
+                    region: "local".to_string(),
+                    latency_ms: Some(1),
+                    score: 0.9,
+                    reputation: 0.9,
+                },
+            })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::MixerDeclined { room_id, .. }) = events[guest].recv().await {
+                break room_id;
+            }
+        }
+    })
+    .await;
+
+    let declined = result.expect("Dedicated node never mixed for the room");
+    assert_eq!(declined, "second-room");
+    loop {
+        match node_events.try_recv() {
+            Ok(NetworkEvent::JoinRejected { peer_id, .. }) => {
+                panic!("{} challenged the admitted node", peer_id)
+            }
+            Ok(_) | Err(tokio::sync::broadcast::error::TryRecvError::Lagged(_)) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_requested_mixer_kept_by_other_members() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{AudioProcessorConfig, MediaMode, MixerNode, NetworkCommand, NetworkEvent};
+
+    // Every room uses a mixer, and a short term makes members that do not
+    // know the node is dedicated rotate it away at once.
+    let mut identities: Vec<Identity> = (0..4).map(|_| Identity::generate().unwrap()).collect();
+    identities.sort_by_key(|i| i.libp2p_peer_id());
+    let config = |identity: Identity, dedicated: bool| NetworkNodeConfig {
+        identity: Some(identity),
+        audio: AudioProcessorConfig::default()
+            .with_denoising(false)
+            .with_echo_cancellation(false),
+        mixer: MixerConfig {
+            media_mode: MediaMode::Forward,
+            full_mesh_threshold: 1,
+            rotation_interval: Duration::from_secs(1),
+            dedicated,
+            ..Default::default()
+        },
+        max_hosted_rooms: if dedicated { 1 } else { 0 },
+        ..local_node_config()
+    };
+    let (host, guest, late, node) = (0, 1, 2, 3);
+    let mut nodes = Vec::new();
+    for (i, identity) in identities.into_iter().enumerate() {
+        nodes.push(
+            NetworkNode::with_config(config(identity, i == node))
+                .await
+                .expect("Failed to create node"),
+        );
+    }
+    let ids: Vec<libp2p::PeerId> = nodes.iter().map(|n| n.local_peer_id()).collect();
+    let mut events: Vec<_> = nodes.iter().map(|n| n.subscribe_events()).collect();
+    let commands: Vec<_> = nodes.iter().map(|n| n.command_sender()).collect();
+    for mut node in nodes {
+        tokio::spawn(async move { node.run().await });
+    }
+
+    let result = tokio::time::timeout(Duration::from_secs(60), async {
+        let mut addrs = Vec::new();
+        for receiver in events.iter_mut() {
+            addrs.push(loop {
+                if let Ok(NetworkEvent::Listening(addr)) = receiver.recv().await {
+                    break addr;
+                }
+            });
+        }
+        for i in [host, guest, late] {
+            commands[i]
+                .send(NetworkCommand::JoinRoom {
+                    room_id: "shared-mixer-room".to_string(),
+                    password: None,
+                    creator: None,
+                })
+                .await
+                .unwrap();
+        }
+        for (i, j) in [
+            (guest, host),
+            (host, node),
+            (guest, node),
+            (late, host),
+            (late, guest),
+            (late, node),
+        ] {
+            commands[i]
+                .send(NetworkCommand::ConnectToPeer {
+                    addr: addrs[j].clone(),
+                })
+                .await
+                .unwrap();
+            loop {
+                if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = events[i].recv().await {
+                    if peer_id == ids[j] {
+                        break;
+                    }
+                }
+            }
+        }
+        let join = |i: usize, via: usize| NetworkCommand::SendControl {
+            peer_id: ids[via],
+            message: ControlMessage::join_room("shared-mixer-room".to_string(), ids[i].to_string()),
+        };
+        commands[guest].send(join(guest, host)).await.unwrap();
+        loop {
+            if let Ok(NetworkEvent::RoomJoined { peer_id, .. }) = events[host].recv().await {
+                if peer_id == ids[guest] {
+                    break;
+                }
+            }
+        }
+
+        commands[host]
+            .send(NetworkCommand::RequestMixer {
+                room_id: "shared-mixer-room".to_string(),
+                node: MixerNode {
+                    peer_id: ids[node].to_string(),
+                    region: "local".to_string(),
+                    latency_ms: Some(1),
+                    score: 0.9,
+                    reputation: 0.9,
+                },
+            })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::MixerAccepted { .. }) = events[host].recv().await {
+                break;
+            }
+        }
+
+        // The late member only hears of the node from the members.
+        commands[late].send(join(late, guest)).await.unwrap();
+        loop {
+            if let Ok(NetworkEvent::RoomJoined { peer_id, .. }) = events[guest].recv().await {
+                if peer_id == ids[late] {
+                    break;
+                }
+            }
+        }
+
+        let members = [host, guest, late, node];
+        let mut min_epoch = 1;
+        loop {
+            let (mixer, epoch) = wait_for_agreed_mixer(&mut events, &members, min_epoch).await;
+            if mixer == ids[node] {
+                break;
+            }
+            min_epoch = epoch + 1;
+        }
+
+        // Nobody rotates the dedicated node away once the room agreed on it.
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(11);
+        while tokio::time::Instant::now() < deadline {
+            for &i in &members {
+                while let Ok(event) = events[i].try_recv() {
+                    if let NetworkEvent::MixerElected { mixer, .. } = event {
+                        assert_eq!(mixer, ids[node], "member {} elected another mixer", i);
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    result.expect("The room never agreed on the requested node");
+}
+
+#[tokio::test]
+async fn test_peer_reachable_through_circuit_relay() {
+    use agora_core::network::{NetworkNodeConfig, RelayServerConfig};
+    use agora_core::{NetworkCommand, NetworkEvent};
+    use libp2p::multiaddr::Protocol;
+
+    // The relay only hands out reservations for a known external address.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let relay_addr: libp2p::Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap();
+    let mut relay = NetworkNode::with_config(NetworkNodeConfig {
+        listen_addr: Some(relay_addr.to_string()),
+        relay_server: Some(RelayServerConfig::default()),
+        external_addrs: vec![relay_addr.to_string()],
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to create relay");
+    let mut listener = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create listener");
+    let mut dialer = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create dialer");
+
+    let relay_id = relay.local_peer_id();
+    let listener_id = listener.local_peer_id();
+    let mut listener_events = listener.subscribe_events();
+    let mut dialer_events = dialer.subscribe_events();
+    let listener_commands = listener.command_sender();
+    let dialer_commands = dialer.command_sender();
+
+    tokio::spawn(async move { relay.run().await });
+    tokio::spawn(async move { listener.run().await });
+    tokio::spawn(async move { dialer.run().await });
+
+    let relay_addr = relay_addr.with(Protocol::P2p(relay_id));
+    let result = tokio::time::timeout(Duration::from_secs(20), async {
+        listener_commands
+            .send(NetworkCommand::ListenViaRelay {
+                addr: relay_addr.clone(),
+            })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::RelayReservationAccepted { relay_peer_id }) =
+                listener_events.recv().await
+            {
+                assert_eq!(relay_peer_id, relay_id);
+                break;
+            }
+        }
+
+        let circuit = relay_addr
+            .with(Protocol::P2pCircuit)
+            .with(Protocol::P2p(listener_id));
+        dialer_commands
+            .send(NetworkCommand::ConnectToPeer { addr: circuit })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::PeerConnected { peer_id, addr }) = dialer_events.recv().await {
+                if peer_id == listener_id {
+                    return addr;
+                }
+            }
+        }
+    })
+    .await;
+
+    let addr = result.expect("Peer was not reachable through the relay");
+    assert!(addr.iter().any(|p| p == Protocol::P2pCircuit));
+}
+
+#[tokio::test]
+async fn test_known_peers_survive_restart() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{NetworkCommand, NetworkEvent};
+
+    let data_dir = tempdir().expect("Failed to create temp dir");
+    let persistent_config = || NetworkNodeConfig {
+        data_dir: Some(data_dir.path().to_path_buf()),
+        ..local_node_config()
+    };
+
+    let mut anchor = NetworkNode::with_config(NetworkNodeConfig {
+        dht_server: true,
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to create anchor");
+    let mut node = NetworkNode::with_config(persistent_config())
+        .await
+        .expect("Failed to create node");
+    let anchor_id = anchor.local_peer_id();
+    let mut anchor_events = anchor.subscribe_events();
+    let mut node_events = node.subscribe_events();
+    let node_commands = node.command_sender();
+
+    tokio::spawn(async move { anchor.run().await });
+    let node_task = tokio::spawn(async move { node.run().await });
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        let addr = loop {
+            if let Ok(NetworkEvent::Listening(addr)) = anchor_events.recv().await {
+                break addr;
+            }
+        };
+        node_commands
+            .send(NetworkCommand::ConnectToPeer { addr })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::PeerIdentified { peer_id, .. }) = node_events.recv().await {
+                if peer_id == anchor_id {
+                    break;
+                }
+            }
+        }
+        node_commands.send(NetworkCommand::Stop).await.unwrap();
+        node_task.await.unwrap();
+    })
+    .await
+    .expect("Node did not reach the anchor");
+
+    // Nothing tells the restarted node about the anchor but its data dir.
+    let mut restarted = NetworkNode::with_config(persistent_config())
+        .await
+        .expect("Failed to restart node");
+    let mut restarted_events = restarted.subscribe_events();
+    tokio::spawn(async move { restarted.run().await });
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = restarted_events.recv().await {
+                if peer_id == anchor_id {
+                    break;
+                }
+            }
+        }
+    })
+    .await
+    .expect("Restarted node did not reconnect to the anchor");
+}
+
+#[tokio::test]
+async fn test_blocklist_changes_survive_restart() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{BlocklistStorage, NetworkCommand};
+
+    let data_dir = tempdir().expect("Failed to create temp dir");
+    let mut node = NetworkNode::with_config(NetworkNodeConfig {
+        data_dir: Some(data_dir.path().to_path_buf()),
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to create node");
+    let commands = node.command_sender();
+    let node_task = tokio::spawn(async move { node.run().await });
+
+    let blocked = libp2p::PeerId::random();
+    let unblocked = libp2p::PeerId::random();
+    for peer_id in [blocked, unblocked] {
+        commands
+            .send(NetworkCommand::BlockPeer {
+                peer_id,
+                reason: Some("spam".to_string()),
+            })
+            .await
+            .unwrap();
+    }
+    commands
+        .send(NetworkCommand::UnblockPeer { peer_id: unblocked })
+        .await
+        .unwrap();
+    commands.send(NetworkCommand::Stop).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(10), node_task)
+        .await
+        .expect("Node did not stop")
+        .unwrap();
+
+    // The restarted node gets its blocklist the way the CLI loads it.
+    let blocklist = BlocklistStorage::with_path(data_dir.path().to_path_buf())
+        .and_then(|storage| storage.load())
+        .expect("Failed to load blocklist");
+    let restarted = NetworkNode::with_config(NetworkNodeConfig {
+        blocklist,
+        data_dir: Some(data_dir.path().to_path_buf()),
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to restart node");
+    assert!(restarted.blocklist().is_blocked(&blocked));
+    assert!(!restarted.blocklist().is_blocked(&unblocked));
+}
+
+#[tokio::test]
+async fn test_lan_peers_form_room_without_bootstrap() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{NetworkCommand, NetworkEvent};
+
+    // mDNS skips loopback interfaces, so listen on all of them.
+    let lan_config = || NetworkNodeConfig {
+        listen_addr: Some("/ip4/0.0.0.0/tcp/0".to_string()),
+        enable_mdns: true,
+        ..local_node_config()
+    };
+    let mut first = NetworkNode::with_config(lan_config())
+        .await
+        .expect("Failed to create first node");
+    let mut second = NetworkNode::with_config(lan_config())
+        .await
+        .expect("Failed to create second node");
+    let first_id = first.local_peer_id();
+    let second_id = second.local_peer_id();
+    let room_id = format!("lan-room-{}", first_id);
+    let first_events = first.subscribe_events();
+    let second_events = second.subscribe_events();
+
+    for commands in [first.command_sender(), second.command_sender()] {
+        commands
+            .send(NetworkCommand::JoinRoom {
+                room_id: room_id.clone(),
+                password: None,
+                creator: None,
+            })
+            .await
+            .unwrap();
+    }
+    tokio::spawn(async move { first.run().await });
+    tokio::spawn(async move { second.run().await });
+
+    async fn room_joined(
+        mut events: tokio::sync::broadcast::Receiver<NetworkEvent>,
+        room_id: &str,
+        peer: libp2p::PeerId,
+    ) {
+        loop {
+            if let Ok(NetworkEvent::RoomJoined {
+                room_id: joined,
+                peer_id,
+            }) = events.recv().await
+            {
+                if joined == room_id && peer_id == peer {
+                    break;
+                }
+            }
+        }
+    }
+
+    tokio::time::timeout(Duration::from_secs(15), async {
+        tokio::join!(
+            room_joined(first_events, &room_id, second_id),
+            room_joined(second_events, &room_id, first_id)
+        )
+    })
+    .await
+    .expect("LAN peers did not find each other's room");
+}
+
+fn ice_node_config() -> agora_core::network::NetworkNodeConfig {
+    agora_core::network::NetworkNodeConfig {
+        ice: agora_core::IceConfig {
+            stun_servers: vec![],
+            host_addresses: vec!["127.0.0.1".parse().unwrap()],
+            ..Default::default()
+        },
+        ..local_node_config()
+    }
+}
+
+/// Waits until `events` report every ICE stage for a session with `peer`.
+async fn wait_for_ice_connection(
+    events: &mut tokio::sync::broadcast::Receiver<agora_core::NetworkEvent>,
+    peer: libp2p::PeerId,
+) -> Vec<&'static str> {
+    use agora_core::NetworkEvent;
+
+    let mut stages = Vec::new();
+    loop {
+        let stage = match events.recv().await {
+            Ok(NetworkEvent::IceCandidatesSent { peer_id, .. }) if peer_id == peer => "sent",
+            Ok(NetworkEvent::IceCandidatesReceived { peer_id, .. }) if peer_id == peer => {
+                "received"
+            }
+            Ok(NetworkEvent::IcePairNominated { peer_id, .. }) if peer_id == peer => "nominated",
+            Ok(NetworkEvent::IceDialing { peer_id, .. }) if peer_id == peer => "dialing",
+            Ok(NetworkEvent::IceConnected { peer_id, .. }) if peer_id == peer => "connected",
+            Ok(NetworkEvent::IceFailed { peer_id, reason }) if peer_id == peer => {
+                panic!("ICE session failed: {}", reason)
+            }
+            _ => continue,
+        };
+        stages.push(stage);
+        if stage == "connected" {
+            return stages;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_ice_session_over_control_protocol() {
+    use agora_core::{NetworkCommand, NetworkEvent};
+
+    let mut answerer = NetworkNode::with_config(ice_node_config())
+        .await
+        .expect("Failed to create answerer");
+    let mut offerer = NetworkNode::with_config(ice_node_config())
+        .await
+        .expect("Failed to create offerer");
+    let answerer_id = answerer.local_peer_id();
+    let offerer_id = offerer.local_peer_id();
+    let mut answerer_events = answerer.subscribe_events();
+    let mut offerer_events = offerer.subscribe_events();
+    let mut answerer_paths = answerer.subscribe_events();
+    let mut offerer_paths = offerer.subscribe_events();
+    let offerer_commands = offerer.command_sender();
+
+    tokio::spawn(async move { answerer.run().await });
+    tokio::spawn(async move { offerer.run().await });
+
+    let result = tokio::time::timeout(Duration::from_secs(20), async {
+        let addr = loop {
+            if let Ok(NetworkEvent::Listening(addr)) = answerer_events.recv().await {
+                break addr;
+            }
+        };
+        offerer_commands
+            .send(NetworkCommand::ConnectToPeer { addr })
+            .await
+            .unwrap();
+        loop {
+            if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) = offerer_events.recv().await {
+                if peer_id == answerer_id {
+                    break;
+                }
+            }
+        }
+
+        offerer_commands
+            .send(NetworkCommand::ConnectIce {
+                peer_id: answerer_id,
+            })
+            .await
+            .unwrap();
+        tokio::join!(
+            wait_for_ice_connection(&mut offerer_events, answerer_id),
+            wait_for_ice_connection(&mut answerer_events, offerer_id)
+        )
+    })
+    .await;
+
+    let (offerer_stages, answerer_stages) = result.expect("ICE session did not connect");
+    assert_eq!(offerer_stages.first(), Some(&"sent"));
+    assert_eq!(answerer_stages.first(), Some(&"received"));
+    for stage in ["nominated", "dialing"] {
+        assert!(
+            offerer_stages.contains(&stage) || answerer_stages.contains(&stage),
+            "no side reported {}",
+            stage
+        );
+    }
+
+    // Neither node listens on QUIC, so a QUIC connection to the nominated
+    // remote end can only run over the pair the checks opened.
+    for (events, peer) in [
+        (&mut offerer_paths, answerer_id),
+        (&mut answerer_paths, offerer_id),
+    ] {
+        let (mut nominated, mut connected) = (None, None);
+        while let Ok(event) = events.try_recv() {
+            match event {
+                NetworkEvent::IcePairNominated {
+                    peer_id, remote, ..
+                } if peer_id == peer => nominated = Some(remote),
+                NetworkEvent::IceConnected { peer_id, addr } if peer_id == peer => {
+                    connected = Some(addr)
+                }
+                _ => {}
+            }
+        }
+        let remote = nominated.expect("No pair nominated");
+        let path = format!("/ip4/{}/udp/{}/quic-v1", remote.ip(), remote.port());
+        let addr = connected.expect("ICE session did not connect").to_string();
+        assert!(
+            addr.starts_with(&path),
+            "connected via {} instead of {}",
+            addr,
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_ice_session_reaches_peer_through_dht() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{NetworkCommand, NetworkEvent};
+
+    // Neither peer knows the other's address. The offer goes through the
+    // bootstrap node's DHT; on loopback the DHT lookups may connect the two
+    // before the checks finish, which also completes the session.
+    let bootstrap = NetworkNode::with_config(NetworkNodeConfig {
+        dht_server: true,
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to create bootstrap node");
+    let answerer = NetworkNode::with_config(ice_node_config())
+        .await
+        .expect("Failed to create answerer");
+    let offerer = NetworkNode::with_config(ice_node_config())
+        .await
+        .expect("Failed to create offerer");
+
+    let bootstrap_id = bootstrap.local_peer_id();
+    let answerer_id = answerer.local_peer_id();
+    let offerer_id = offerer.local_peer_id();
+    let mut bootstrap_events = bootstrap.subscribe_events();
+    let mut answerer_events = answerer.subscribe_events();
+    let mut offerer_events = offerer.subscribe_events();
+    let answerer_commands = answerer.command_sender();
+    let offerer_commands = offerer.command_sender();
+
+    for mut node in [bootstrap, answerer, offerer] {
+        tokio::spawn(async move { node.run().await });
+    }
+
+    let result = tokio::time::timeout(Duration::from_secs(30), async {
+        let addr = loop {
+            if let Ok(NetworkEvent::Listening(addr)) = bootstrap_events.recv().await {
+                break addr;
+            }
+        };
+        for (commands, events) in [
+            (&answerer_commands, &mut answerer_events),
+            (&offerer_commands, &mut offerer_events),
+        ] {
+            commands
+                .send(NetworkCommand::ConnectToPeer { addr: addr.clone() })
+                .await
+                .unwrap();
+            loop {
+                if let Ok(NetworkEvent::PeerIdentified { peer_id, .. }) = events.recv().await {
+                    if peer_id == bootstrap_id {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // The ICE inbox is only polled while in a room.
+        answerer_commands
+            .send(NetworkCommand::JoinRoom {
+                room_id: "lobby".to_string(),
+                password: None,
+                creator: None,
+            })
+            .await
+            .unwrap();
+        offerer_commands
+            .send(NetworkCommand::ConnectIce {
+                peer_id: answerer_id,
+            })
+            .await
+            .unwrap();
+        let answerer_connected = async {
+            loop {
+                if let Ok(NetworkEvent::PeerConnected { peer_id, .. }) =
+                    answerer_events.recv().await
+                {
+                    if peer_id == offerer_id {
+                        break;
+                    }
+                }
+            }
+        };
+        tokio::join!(
+            wait_for_ice_connection(&mut offerer_events, answerer_id),
+            answerer_connected
+        );
+    })
+    .await;
+
+    result.expect("ICE session did not connect");
+}
+
+#[tokio::test]
+async fn test_advertisement_found_by_browsing_directory() {
+    use agora_core::network::NetworkNodeConfig;
+    use agora_core::{NetworkCommand, NetworkEvent};
+
+    let bootstrap = NetworkNode::with_config(NetworkNodeConfig {
+        dht_server: true,
+        ..local_node_config()
+    })
+    .await
+    .expect("Failed to create bootstrap node");
+    let advertiser = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create advertiser");
+    let browser = NetworkNode::with_config(local_node_config())
+        .await
+        .expect("Failed to create browser");
+
+    let bootstrap_id = bootstrap.local_peer_id();
+    let advertiser_id = advertiser.local_peer_id();
+    let mut bootstrap_events = bootstrap.subscribe_events();
+    let mut advertiser_events = advertiser.subscribe_events();
+    let mut browser_events = browser.subscribe_events();
+    let advertiser_commands = advertiser.command_sender();
+    let browser_commands = browser.command_sender();
+
+    for mut node in [bootstrap, advertiser, browser] {
+        tokio::spawn(async move { node.run().await });
+    }
+
+    let result = tokio::time::timeout(Duration::from_secs(30), async {
+        let addr = loop {
+            if let Ok(NetworkEvent::Listening(addr)) = bootstrap_events.recv().await {
+                break addr;
+            }
+        };
+        for (commands, events) in [
+            (&advertiser_commands, &mut advertiser_events),
+            (&browser_commands, &mut browser_events),
+        ] {
+            commands
+                .send(NetworkCommand::ConnectToPeer { addr: addr.clone() })
+                .await
+                .unwrap();
+            loop {
+                if let Ok(NetworkEvent::PeerIdentified { peer_id, .. }) = events.recv().await {
+                    if peer_id == bootstrap_id {
+                        break;
+                    }
+                }
+            }
+        }
+
+        advertiser_commands
+            .send(NetworkCommand::Advertise {
+                directory: "/agora/nodes".to_string(),
+                value: b"mixer".to_vec(),
+            })
+            .await
+            .unwrap();
+
+        let mut retry = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = retry.tick() => {
+                    browser_commands
+                        .send(NetworkCommand::Browse {
+                            directory: "/agora/nodes".to_string(),
+                        })
+                        .await
+                        .unwrap();
+                }
+                event = browser_events.recv() => {
+                    if let Ok(NetworkEvent::AdvertisementFound { directory, peer_id, value }) = event {
+                        assert_eq!(directory, "/agora/nodes");
+                        assert_eq!(peer_id, advertiser_id);
+                        assert_eq!(value, b"mixer");
+                        break;
+                    }
+                }
+            }
+        }
+    })
+    .await;
+
+    result.expect("Advertisement was not found");
+}
+
 #[tokio::test]
 async fn test_e2e_room_flow() {
     let identity1 = Identity::generate().expect("Failed to generate identity 1");
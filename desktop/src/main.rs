@@ -1,8 +1,8 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use agora_core::{
-    protocol::ControlMessage, AudioConfig, AudioPipeline, MixerConfig, MixerManager,
-    NetworkCommand, NetworkEvent, NetworkNode,
+    network::NetworkNodeConfig, protocol::ControlMessage, AudioConfig, AudioPipeline, MixerConfig,
+    MixerManager, NetworkCommand, NetworkEvent, NetworkNode,
 };
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -219,7 +219,7 @@ async fn create_room(
 
     let config = agora_core::RoomConfig {
         name,
-        password,
+        password: password.clone(),
         max_participants: Some(20),
     };
     let room = agora_core::Room::new(peer_id, config);
@@ -230,6 +230,7 @@ async fn create_room(
         has_password: room.has_password(),
     };
     let room_id = room.id.clone();
+    let creator = room.creator_proof();
     let link = info.link.clone();
     let name_clone = room.name.clone();
 
@@ -244,7 +245,13 @@ async fn create_room(
     {
         let cmd_lock = state.network_command.lock().await;
         if let Some(cmd_tx) = cmd_lock.as_ref() {
-            let _ = cmd_tx.send(NetworkCommand::JoinRoom { room_id }).await;
+            let _ = cmd_tx
+                .send(NetworkCommand::JoinRoom {
+                    room_id,
+                    password,
+                    creator,
+                })
+                .await;
         }
     }
 
@@ -253,7 +260,7 @@ async fn create_room(
 
 #[tauri::command(rename_all = "snake_case")]
 async fn join_room(state: tauri::State<'_, AppState>, room_link: String) -> Result<String, String> {
-    let (room_id, _) =
+    let (room_id, password) =
         agora_core::room::parse_room_link(&room_link).ok_or_else(|| "Invalid link".to_string())?;
     {
         let mut room_lock = state.current_room.lock().await;
@@ -269,6 +276,8 @@ async fn join_room(state: tauri::State<'_, AppState>, room_link: String) -> Resu
             let _ = cmd_tx
                 .send(NetworkCommand::JoinRoom {
                     room_id: room_id.clone(),
+                    password,
+                    creator: None,
                 })
                 .await;
         }
@@ -285,7 +294,21 @@ async fn start_network(
     let listen_addr = listen_port
         .map(|p| format!("/ip4/0.0.0.0/tcp/{}", p))
         .unwrap_or_else(|| "/ip4/0.0.0.0/tcp/0".to_string());
-    let mut network = NetworkNode::new(Some(&listen_addr))
+    let identity = state.identity.lock().await.clone();
+    let blocklist = agora_core::BlocklistStorage::new()
+        .and_then(|storage| storage.load())
+        .unwrap_or_default();
+    let config = NetworkNodeConfig {
+        identity,
+        blocklist,
+        listen_addr: Some(listen_addr),
+        enable_mdns: true,
+        data_dir: agora_core::IdentityStorage::new()
+            .ok()
+            .map(|storage| storage.config_dir().to_path_buf()),
+        ..Default::default()
+    };
+    let mut network = NetworkNode::with_config(config)
         .await
         .map_err(|e| format!("Failed: {}", e))?;
     let peer_id = network.peer_id_string();
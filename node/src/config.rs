@@ -1,12 +1,15 @@
 use crate::error::NodeError;
+use agora_core::network::RelayServerConfig;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub const DEFAULT_LISTEN_PORT: u16 = 7001;
 pub const DEFAULT_DASHBOARD_PORT: u16 = 8080;
 pub const DEFAULT_METRICS_PORT: u16 = 9090;
+pub const DEFAULT_TURN_PORT: u16 = 3478;
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -51,8 +54,12 @@ pub struct NodeConfig {
     #[serde(default)]
     pub turn: TurnSection,
     #[serde(default)]
+    pub relay: RelaySection,
+    #[serde(default)]
     pub dashboard: DashboardSection,
     #[serde(default)]
+    pub signaling: SignalingSection,
+    #[serde(default)]
     pub metrics: MetricsSection,
     #[serde(default)]
     pub logging: LoggingSection,
@@ -70,6 +77,10 @@ pub struct NodeSection {
     pub max_connections: usize,
     #[serde(default = "default_max_mixers")]
     pub max_mixers: usize,
+    /// Where DHT records and known peers are kept across restarts.
+    /// Defaults to the directory of `identity.key_file`.
+    #[serde(default)]
+    pub data_dir: Option<PathBuf>,
 }
 
 fn default_mode() -> NodeMode {
@@ -96,6 +107,7 @@ impl Default for NodeSection {
             listen_port: default_listen_port(),
             max_connections: default_max_connections(),
             max_mixers: default_max_mixers(),
+            data_dir: None,
         }
     }
 }
@@ -131,6 +143,19 @@ pub struct NetworkSection {
     pub stun_servers: Vec<String>,
     #[serde(default)]
     pub region: Option<String>,
+    #[serde(default = "default_true")]
+    pub enable_tcp: bool,
+    #[serde(default)]
+    pub enable_quic: bool,
+    #[serde(default)]
+    pub quic_port: Option<u16>,
+    /// Multiaddrs the node is publicly reachable at. Relay nodes need one
+    /// before they accept circuit relay reservations.
+    #[serde(default)]
+    pub external_addrs: Vec<String>,
+    /// Discover peers on the local network over mDNS.
+    #[serde(default)]
+    pub enable_mdns: bool,
 }
 
 fn default_true() -> bool {
@@ -150,6 +175,11 @@ impl Default for NetworkSection {
             enable_upnp: default_true(),
             stun_servers: default_stun_servers(),
             region: None,
+            enable_tcp: default_true(),
+            enable_quic: false,
+            quic_port: None,
+            external_addrs: Vec::new(),
+            enable_mdns: false,
         }
     }
 }
@@ -164,6 +194,136 @@ pub struct TurnSection {
     pub username: String,
     #[serde(default)]
     pub password: String,
+    #[serde(default)]
+    pub server: TurnServerSection,
+}
+
+/// The embedded TURN server, run only in relay mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnServerSection {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_turn_port")]
+    pub port: u16,
+    #[serde(default = "default_true")]
+    pub enable_tcp: bool,
+    #[serde(default = "default_realm")]
+    pub realm: String,
+    /// Shared secret for REST-style credentials. A random one is generated
+    /// at startup when empty, so issued credentials die with the process.
+    #[serde(default)]
+    pub secret: String,
+    /// Public address advertised for relayed transport addresses.
+    #[serde(default)]
+    pub external_ip: Option<IpAddr>,
+    #[serde(default = "default_credential_ttl")]
+    pub credential_ttl_secs: u64,
+    #[serde(default = "default_max_allocations")]
+    pub max_allocations: usize,
+    #[serde(default = "default_max_allocations_per_user")]
+    pub max_allocations_per_user: usize,
+    /// Bytes per second relayed for one user, both directions combined.
+    #[serde(default = "default_max_bandwidth_per_user")]
+    pub max_bandwidth_per_user: u64,
+    /// Loopback, private, link-local and other non-public addresses clients
+    /// may still relay to. Everything else of that kind is refused.
+    #[serde(default)]
+    pub allowed_peer_ips: Vec<IpAddr>,
+}
+
+fn default_turn_port() -> u16 {
+    DEFAULT_TURN_PORT
+}
+fn default_realm() -> String {
+    "agora".to_string()
+}
+fn default_credential_ttl() -> u64 {
+    24 * 60 * 60
+}
+fn default_max_allocations() -> usize {
+    1000
+}
+fn default_max_allocations_per_user() -> usize {
+    4
+}
+fn default_max_bandwidth_per_user() -> u64 {
+    64 * 1024
+}
+
+impl Default for TurnServerSection {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            port: default_turn_port(),
+            enable_tcp: default_true(),
+            realm: default_realm(),
+            secret: String::new(),
+            external_ip: None,
+            credential_ttl_secs: default_credential_ttl(),
+            max_allocations: default_max_allocations(),
+            max_allocations_per_user: default_max_allocations_per_user(),
+            max_bandwidth_per_user: default_max_bandwidth_per_user(),
+            allowed_peer_ips: Vec::new(),
+        }
+    }
+}
+
+/// The libp2p circuit relay v2 server, run only in relay mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelaySection {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_max_reservations")]
+    pub max_reservations: usize,
+    #[serde(default = "default_max_reservations_per_peer")]
+    pub max_reservations_per_peer: usize,
+    #[serde(default = "default_reservation_duration")]
+    pub reservation_duration_secs: u64,
+    #[serde(default = "default_max_circuits")]
+    pub max_circuits: usize,
+    #[serde(default = "default_max_circuits_per_peer")]
+    pub max_circuits_per_peer: usize,
+    #[serde(default = "default_max_circuit_duration")]
+    pub max_circuit_duration_secs: u64,
+    #[serde(default = "default_max_circuit_bytes")]
+    pub max_circuit_bytes: u64,
+}
+
+fn default_max_reservations() -> usize {
+    128
+}
+fn default_max_reservations_per_peer() -> usize {
+    4
+}
+fn default_reservation_duration() -> u64 {
+    60 * 60
+}
+fn default_max_circuits() -> usize {
+    16
+}
+fn default_max_circuits_per_peer() -> usize {
+    4
+}
+fn default_max_circuit_duration() -> u64 {
+    2 * 60
+}
+fn default_max_circuit_bytes() -> u64 {
+    128 * 1024
+}
+
+impl Default for RelaySection {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            max_reservations: default_max_reservations(),
+            max_reservations_per_peer: default_max_reservations_per_peer(),
+            reservation_duration_secs: default_reservation_duration(),
+            max_circuits: default_max_circuits(),
+            max_circuits_per_peer: default_max_circuits_per_peer(),
+            max_circuit_duration_secs: default_max_circuit_duration(),
+            max_circuit_bytes: default_max_circuit_bytes(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -193,6 +353,22 @@ impl Default for DashboardSection {
     }
 }
 
+/// WebSocket signaling for WebRTC clients, served under `/signaling` on the
+/// dashboard listener.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalingSection {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+impl Default for SignalingSection {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetricsSection {
     #[serde(default = "default_true")]
@@ -277,6 +453,41 @@ impl NodeConfig {
             return Err(NodeError::Config("max_mixers must be > 0".to_string()));
         }
 
+        if !self.network.enable_tcp && !self.network.enable_quic {
+            return Err(NodeError::Config(
+                "at least one of enable_tcp or enable_quic must be true".to_string(),
+            ));
+        }
+
+        let turn = &self.turn.server;
+        if self.node.mode == NodeMode::Relay && turn.enabled {
+            if turn.max_allocations == 0 || turn.max_allocations_per_user == 0 {
+                return Err(NodeError::Config(
+                    "turn.server allocation limits must be > 0".to_string(),
+                ));
+            }
+            if turn.max_bandwidth_per_user == 0 || turn.credential_ttl_secs == 0 {
+                return Err(NodeError::Config(
+                    "turn.server max_bandwidth_per_user and credential_ttl_secs must be > 0"
+                        .to_string(),
+                ));
+            }
+        }
+
+        let relay = &self.relay;
+        if self.node.mode == NodeMode::Relay && relay.enabled {
+            if relay.max_reservations == 0 || relay.max_reservations_per_peer == 0 {
+                return Err(NodeError::Config(
+                    "relay reservation limits must be > 0".to_string(),
+                ));
+            }
+            if relay.max_circuits == 0 || relay.max_circuits_per_peer == 0 {
+                return Err(NodeError::Config(
+                    "relay circuit limits must be > 0".to_string(),
+                ));
+            }
+        }
+
         Ok(())
     }
 
@@ -289,16 +500,71 @@ impl NodeConfig {
         SocketAddr::new(self.node.listen_addr, self.node.listen_port)
     }
 
+    pub fn tcp_multiaddr(&self) -> String {
+        format!(
+            "/{}/{}/tcp/{}",
+            ip_protocol(self.node.listen_addr),
+            self.node.listen_addr,
+            self.node.listen_port
+        )
+    }
+
+    pub fn quic_multiaddr(&self) -> String {
+        format!(
+            "/{}/{}/udp/{}/quic-v1",
+            ip_protocol(self.node.listen_addr),
+            self.node.listen_addr,
+            self.network.quic_port.unwrap_or(self.node.listen_port)
+        )
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.node.data_dir.clone().unwrap_or_else(|| {
+            Path::new(&self.identity.key_file)
+                .parent()
+                .unwrap_or(Path::new("."))
+                .to_path_buf()
+        })
+    }
+
     pub fn dashboard_socket(&self) -> SocketAddr {
         SocketAddr::new(self.dashboard.listen_addr, self.dashboard.port)
     }
 
+    /// Circuit relay limits, when this node serves as a relay.
+    pub fn relay_server(&self) -> Option<RelayServerConfig> {
+        if self.node.mode != NodeMode::Relay || !self.relay.enabled {
+            return None;
+        }
+        let relay = &self.relay;
+        Some(RelayServerConfig {
+            max_reservations: relay.max_reservations,
+            max_reservations_per_peer: relay.max_reservations_per_peer,
+            reservation_duration: Duration::from_secs(relay.reservation_duration_secs),
+            max_circuits: relay.max_circuits,
+            max_circuits_per_peer: relay.max_circuits_per_peer,
+            max_circuit_duration: Duration::from_secs(relay.max_circuit_duration_secs),
+            max_circuit_bytes: relay.max_circuit_bytes,
+        })
+    }
+
+    pub fn turn_socket(&self) -> SocketAddr {
+        SocketAddr::new(self.node.listen_addr, self.turn.server.port)
+    }
+
     #[allow(dead_code)]
     pub fn metrics_socket(&self) -> SocketAddr {
         SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), self.metrics.port)
     }
 }
 
+fn ip_protocol(addr: IpAddr) -> &'static str {
+    match addr {
+        IpAddr::V4(_) => "ip4",
+        IpAddr::V6(_) => "ip6",
+    }
+}
+
 pub fn generate_default<P: AsRef<Path>>(output: P) -> Result<(), NodeError> {
     let config = NodeConfig::default();
     let toml = config.to_toml()?;
@@ -320,6 +586,8 @@ mod tests {
         assert_eq!(config.node.mode, NodeMode::Dedicated);
         assert_eq!(config.node.listen_port, DEFAULT_LISTEN_PORT);
         assert!(config.dashboard.enabled);
+        assert!(config.signaling.enabled);
+        assert_eq!(config.data_dir(), Path::new("/var/lib/agora"));
     }
 
     #[test]
@@ -336,10 +604,83 @@ mod tests {
         assert!(toml.contains("mode = \"dedicated\""));
     }
 
+    #[test]
+    fn test_transport_config() {
+        let mut config: NodeConfig = toml::from_str(
+            r#"
+            [node]
+            listen_port = 7001
+
+            [network]
+            enable_quic = true
+            quic_port = 7002
+            "#,
+        )
+        .unwrap();
+        assert!(config.network.enable_tcp);
+        assert!(config.network.enable_quic);
+        assert_eq!(config.tcp_multiaddr(), "/ip4/0.0.0.0/tcp/7001");
+        assert_eq!(config.quic_multiaddr(), "/ip4/0.0.0.0/udp/7002/quic-v1");
+
+        config.network.enable_tcp = false;
+        config.network.enable_quic = false;
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_listen_socket() {
         let config = NodeConfig::default();
         let socket = config.listen_socket();
         assert_eq!(socket.port(), DEFAULT_LISTEN_PORT);
     }
+
+    #[test]
+    fn test_turn_server_config() {
+        let mut config: NodeConfig = toml::from_str(
+            r#"
+            [node]
+            mode = "relay"
+
+            [turn.server]
+            port = 3479
+            max_allocations_per_user = 2
+            "#,
+        )
+        .unwrap();
+        assert!(config.turn.server.enabled);
+        assert_eq!(config.turn_socket().port(), 3479);
+        assert_eq!(config.turn.server.max_allocations_per_user, 2);
+        assert_eq!(config.turn.server.realm, "agora");
+        assert!(config.validate().is_ok());
+
+        config.turn.server.max_allocations_per_user = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_relay_config() {
+        let mut config: NodeConfig = toml::from_str(
+            r#"
+            [node]
+            mode = "relay"
+
+            [relay]
+            max_reservations = 64
+            max_circuit_duration_secs = 300
+            "#,
+        )
+        .unwrap();
+        let relay = config.relay_server().unwrap();
+        assert_eq!(relay.max_reservations, 64);
+        assert_eq!(relay.max_reservations_per_peer, 4);
+        assert_eq!(relay.max_circuit_duration, Duration::from_secs(300));
+        assert!(config.validate().is_ok());
+
+        config.relay.max_circuits = 0;
+        assert!(config.validate().is_err());
+
+        config.node.mode = NodeMode::Dedicated;
+        assert!(config.relay_server().is_none());
+        assert!(config.validate().is_ok());
+    }
 }
@@ -1,8 +1,9 @@
+use agora_core::{TurnCredentialRequest, TurnCredentials};
 use axum::{
     extract::{Query, State},
     http::{header, StatusCode},
     response::{Html, IntoResponse, Json},
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
@@ -15,6 +16,8 @@ use crate::config::NodeConfig;
 use crate::discovery::{NodeAdvertisement, NodeCapability};
 use crate::error::NodeError;
 use crate::metrics::NodeMetrics;
+use crate::signaling::{signaling_router, SignalingState};
+use crate::turn_server::CredentialIssuer;
 
 pub type DashboardState = Arc<RwLock<DashboardData>>;
 
@@ -59,6 +62,8 @@ pub struct AudioStatus {
 pub struct Dashboard {
     state: DashboardState,
     addr: SocketAddr,
+    turn_credentials: Option<Arc<CredentialIssuer>>,
+    signaling: Option<Arc<SignalingState>>,
 }
 
 impl Dashboard {
@@ -73,24 +78,50 @@ impl Dashboard {
         Self {
             state,
             addr: config.dashboard_socket(),
+            turn_credentials: None,
+            signaling: config
+                .signaling
+                .enabled
+                .then(|| Arc::new(SignalingState::new())),
         }
     }
 
+    pub fn with_turn_credentials(mut self, issuer: CredentialIssuer) -> Self {
+        self.turn_credentials = Some(Arc::new(issuer));
+        self
+    }
+
     pub fn state(&self) -> DashboardState {
         self.state.clone()
     }
 
     pub async fn start(self) -> Result<(), NodeError> {
-        let app = Router::new()
+        let mut app = Router::new()
             .route("/", get(index))
             .route("/api/status", get(api_status))
             .route("/api/peers", get(api_peers))
             .route("/api/discover", get(api_discover))
             .route("/health", get(health))
             .route("/metrics", get(metrics))
-            .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any))
             .with_state(self.state);
 
+        if let Some(issuer) = self.turn_credentials {
+            app = app.merge(
+                Router::new()
+                    .route("/api/turn/credentials", post(turn_credentials))
+                    .with_state(issuer),
+            );
+        }
+        if let Some(signaling) = self.signaling {
+            app = app.nest("/signaling", signaling_router().with_state(signaling));
+        }
+        let app = app.layer(
+            CorsLayer::new()
+                .allow_origin(Any)
+                .allow_methods(Any)
+                .allow_headers(Any),
+        );
+
         tracing::info!("Dashboard listening on {}", self.addr);
 
         let listener = tokio::net::TcpListener::bind(self.addr)
@@ -140,6 +171,16 @@ async fn api_discover(Query(query): Query<DiscoverQuery>) -> Json<Vec<NodeAdvert
     Json(vec![])
 }
 
+async fn turn_credentials(
+    State(issuer): State<Arc<CredentialIssuer>>,
+    Json(request): Json<TurnCredentialRequest>,
+) -> Result<Json<TurnCredentials>, (StatusCode, String)> {
+    issuer
+        .issue(&request)
+        .map(Json)
+        .map_err(|e| (StatusCode::FORBIDDEN, e.to_string()))
+}
+
 async fn health() -> impl IntoResponse {
     (StatusCode::OK, "OK")
 }
@@ -1,3 +1,4 @@
+use agora_core::{ReputationConfig, ReputationScore};
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -78,12 +79,10 @@ impl NodeAdvertisement {
         format!("{}/{}/{}", DHT_KEY_PREFIX, self.region, self.peer_id)
     }
 
-    #[allow(dead_code)]
     pub fn dht_key_for_region(region: &str) -> String {
         format!("{}/{}", DHT_KEY_PREFIX, region)
     }
 
-    #[allow(dead_code)]
     pub fn global_dht_key() -> String {
         DHT_KEY_PREFIX.to_string()
     }
@@ -93,7 +92,6 @@ impl NodeAdvertisement {
         self.last_seen = current_timestamp();
     }
 
-    #[allow(dead_code)]
     pub fn update_load(&mut self, current: u32) {
         self.current_load = current;
     }
@@ -124,7 +122,6 @@ impl NodeAdvertisement {
         serde_json::to_vec(self)
     }
 
-    #[allow(dead_code)]
     pub fn deserialize(data: &[u8]) -> Result<Self, serde_json::Error> {
         serde_json::from_slice(data)
     }
@@ -190,6 +187,10 @@ impl DiscoveredNode {
 pub struct NodeDiscovery {
     nodes: HashMap<PeerId, DiscoveredNode>,
     max_age: Duration,
+    reputation_config: ReputationConfig,
+    /// What we observed of each node ourselves. Kept after a node goes
+    /// stale, so it does not start over when it comes back.
+    reputations: HashMap<PeerId, ReputationScore>,
 }
 
 impl NodeDiscovery {
@@ -197,13 +198,30 @@ impl NodeDiscovery {
         Self {
             nodes: HashMap::new(),
             max_age: Duration::from_secs(300),
+            reputation_config: ReputationConfig::default(),
+            reputations: HashMap::new(),
         }
     }
 
-    #[allow(dead_code)]
-    pub fn add_node(&mut self, advertisement: NodeAdvertisement) {
+    /// Adds or refreshes a node. The advertised reputation is the node's
+    /// own claim, so it is replaced with our own score for it, which grows
+    /// with the time we keep seeing it.
+    pub fn add_node(&mut self, mut advertisement: NodeAdvertisement) {
         if let Ok(peer_id) = advertisement.peer_id.parse::<PeerId>() {
-            let discovered = DiscoveredNode::new(advertisement);
+            let config = &self.reputation_config;
+            let reputation = self
+                .reputations
+                .entry(peer_id)
+                .or_insert_with(|| ReputationScore::new(config));
+            let previous = self.nodes.get(&peer_id);
+            if let Some(previous) = previous.filter(|n| n.age() < self.max_age) {
+                reputation.record_uptime(previous.age().as_secs());
+                reputation.recalculate(config);
+            }
+            advertisement.reputation = reputation.overall;
+
+            let mut discovered = DiscoveredNode::new(advertisement);
+            discovered.latency_ms = previous.and_then(|n| n.latency_ms);
             self.nodes.insert(peer_id, discovered);
         }
     }
@@ -222,10 +240,15 @@ impl NodeDiscovery {
     pub fn update_latency(&mut self, peer_id: &PeerId, latency_ms: u64) {
         if let Some(node) = self.nodes.get_mut(peer_id) {
             node.latency_ms = Some(latency_ms);
+            if let Some(reputation) = self.reputations.get_mut(peer_id) {
+                let sample = latency_ms.min(u32::MAX as u64) as u32;
+                reputation.record_latency(sample, &self.reputation_config);
+                reputation.recalculate(&self.reputation_config);
+                node.advertisement.reputation = reputation.overall;
+            }
         }
     }
 
-    #[allow(dead_code)]
     pub fn prune_stale(&mut self) {
         self.nodes.retain(|_, node| node.age() < self.max_age);
     }
@@ -243,7 +266,6 @@ impl NodeDiscovery {
             .collect()
     }
 
-    #[allow(dead_code)]
     pub fn get_by_capability(&self, capability: NodeCapability) -> Vec<&DiscoveredNode> {
         self.nodes
             .values()
@@ -273,6 +295,21 @@ impl NodeDiscovery {
         nodes.into_iter().take(limit).collect()
     }
 
+    /// Mixer-capable nodes as cascade mixer hints for `NetworkNode`, with
+    /// our own reputation for each.
+    pub fn mixer_nodes(&self) -> Vec<agora_core::MixerNode> {
+        self.get_by_capability(NodeCapability::Mixer)
+            .into_iter()
+            .map(|node| agora_core::MixerNode {
+                peer_id: node.advertisement.peer_id.clone(),
+                region: node.advertisement.region.clone(),
+                latency_ms: node.latency_ms.map(|ms| ms.min(u32::MAX as u64) as u32),
+                score: node.final_score(),
+                reputation: node.advertisement.reputation,
+            })
+            .collect()
+    }
+
     #[allow(dead_code)]
     pub fn count(&self) -> usize {
         self.nodes.len()
@@ -371,6 +408,35 @@ mod tests {
 
         let mixer_nodes = discovery.get_by_capability(NodeCapability::Mixer);
         assert_eq!(mixer_nodes.len(), 1);
+
+        discovery.update_latency(&peer_id1, 42);
+        let hints = discovery.mixer_nodes();
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].peer_id, peer_id1.to_string());
+        assert_eq!(hints[0].region, "eu-west");
+        assert_eq!(hints[0].latency_ms, Some(42));
+    }
+
+    #[test]
+    fn test_advertised_reputation_not_trusted() {
+        use agora_core::mixer::{MixerManager, TopologyMode};
+
+        let mut discovery = NodeDiscovery::new();
+        let peer_id = create_test_peer_id();
+        let mut ad = NodeAdvertisement::new(peer_id, NodeMode::Dedicated);
+        ad.reputation = 1.0;
+        discovery.add_node(ad.clone());
+        discovery.add_node(ad);
+
+        let nodes = discovery.mixer_nodes();
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].reputation < 0.7);
+
+        // Without a trusted dedicated node a small room stays full mesh.
+        let mut manager = MixerManager::new("client".to_string(), None);
+        manager.add_participant(peer_id.to_string());
+        manager.set_mixer_nodes(nodes);
+        assert_eq!(manager.get_topology_mode(), TopologyMode::FullMesh);
     }
 
     #[test]
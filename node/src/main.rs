@@ -5,6 +5,7 @@ mod error;
 mod metrics;
 mod node;
 mod signaling;
+mod turn_server;
 
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
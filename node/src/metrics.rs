@@ -57,6 +57,41 @@ lazy_static! {
         "Current mixer role (0=none, 1=participant, 2=mixer)"
     )
     .unwrap();
+    pub static ref TURN_ALLOCATIONS_ACTIVE: IntGauge = register_int_gauge!(
+        "agora_turn_allocations_active",
+        "Number of active TURN allocations"
+    )
+    .unwrap();
+    pub static ref TURN_ALLOCATIONS_TOTAL: Counter = register_counter!(
+        "agora_turn_allocations_total",
+        "Total TURN allocations created"
+    )
+    .unwrap();
+    pub static ref TURN_AUTH_FAILURES: Counter = register_counter!(
+        "agora_turn_auth_failures_total",
+        "TURN requests rejected for bad or expired credentials"
+    )
+    .unwrap();
+    pub static ref TURN_QUOTA_REJECTIONS: Counter = register_counter!(
+        "agora_turn_quota_rejections_total",
+        "TURN allocations refused by per-user or global quotas"
+    )
+    .unwrap();
+    pub static ref TURN_RELAYED_PACKETS: Counter = register_counter!(
+        "agora_turn_relayed_packets_total",
+        "Total packets relayed by the TURN server"
+    )
+    .unwrap();
+    pub static ref TURN_RELAYED_BYTES: Counter = register_counter!(
+        "agora_turn_relayed_bytes_total",
+        "Total payload bytes relayed by the TURN server"
+    )
+    .unwrap();
+    pub static ref TURN_DROPPED_PACKETS: Counter = register_counter!(
+        "agora_turn_dropped_packets_total",
+        "Packets dropped for exceeding a user's bandwidth quota"
+    )
+    .unwrap();
 }
 
 pub struct NodeMetrics {
@@ -119,6 +154,31 @@ impl NodeMetrics {
         MIXER_ROLE.set(role as f64);
     }
 
+    pub fn set_turn_allocations(&self, count: i64) {
+        TURN_ALLOCATIONS_ACTIVE.set(count);
+    }
+
+    pub fn inc_turn_allocations(&self) {
+        TURN_ALLOCATIONS_TOTAL.inc();
+    }
+
+    pub fn inc_turn_auth_failures(&self) {
+        TURN_AUTH_FAILURES.inc();
+    }
+
+    pub fn inc_turn_quota_rejections(&self) {
+        TURN_QUOTA_REJECTIONS.inc();
+    }
+
+    pub fn inc_turn_relayed(&self, bytes: usize) {
+        TURN_RELAYED_PACKETS.inc();
+        TURN_RELAYED_BYTES.inc_by(bytes as f64);
+    }
+
+    pub fn inc_turn_dropped(&self) {
+        TURN_DROPPED_PACKETS.inc();
+    }
+
     pub fn gather() -> Vec<u8> {
         let metric_families = prometheus::gather();
         let mut buffer = Vec::new();
@@ -1,15 +1,23 @@
 use crate::config::NodeConfig;
 use crate::dashboard::{Dashboard, DashboardData};
-use crate::discovery::{NodeAdvertisement, NodeDiscovery, NodeMode};
+use crate::discovery::{DiscoveryConfig, NodeAdvertisement, NodeDiscovery, NodeMode};
 use crate::error::NodeError;
 use crate::metrics::NodeMetrics;
-use agora_core::{Identity, IdentityStorage, NetworkNode};
+use crate::turn_server::{CredentialIssuer, RelayHandle, RelaySettings, TurnRelay};
+use agora_core::network::NetworkNodeConfig;
+use agora_core::{
+    Identity, IdentityStorage, MediaMode, MixerConfig, NetworkCommand, NetworkEvent, NetworkNode,
+};
 use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::signal;
 
+/// How long the network gets to stop and save the DHT before it is aborted.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub async fn run(config_path: impl AsRef<Path>, _foreground: bool) -> Result<(), NodeError> {
     tracing::info!("Starting Agora Node...");
 
@@ -21,13 +29,21 @@ pub async fn run(config_path: impl AsRef<Path>, _foreground: bool) -> Result<(),
     tracing::info!("Identity loaded: {}", identity.peer_id());
 
     let metrics = Arc::new(NodeMetrics::new());
-    let dashboard = Dashboard::new(&config);
+    let mut dashboard = Dashboard::new(&config);
     let dashboard_state = dashboard.state();
 
+    // Held until shutdown; dropping the handle stops the relay.
+    let mut turn_relay = None;
+    if config.node.mode == crate::config::NodeMode::Relay && config.turn.server.enabled {
+        let (relay, issuer) = start_turn_relay(&config, &identity, metrics.clone()).await?;
+        dashboard = dashboard.with_turn_credentials(issuer);
+        turn_relay = Some(relay);
+    }
+
     let shutdown_token = tokio_util::sync::CancellationToken::new();
     let shutdown_token_clone = shutdown_token.clone();
 
-    let network_handle = tokio::spawn(async move {
+    let mut network_handle = tokio::spawn(async move {
         run_network(
             config,
             identity,
@@ -43,20 +59,22 @@ pub async fn run(config_path: impl AsRef<Path>, _foreground: bool) -> Result<(),
     tracing::info!("Node started. Press Ctrl+C to stop.");
 
     match signal::ctrl_c().await {
-        Ok(()) => {
-            tracing::info!("Shutdown signal received...");
-            shutdown_token.cancel();
-        }
-        Err(err) => {
-            tracing::error!("Error waiting for shutdown signal: {}", err);
-        }
+        Ok(()) => tracing::info!("Shutdown signal received..."),
+        Err(err) => tracing::error!("Error waiting for shutdown signal: {}", err),
     }
+    shutdown_token.cancel();
 
-    network_handle.abort();
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut network_handle)
+        .await
+        .is_err()
+    {
+        tracing::warn!("Network did not stop in time, aborting");
+        network_handle.abort();
+        let _ = network_handle.await;
+    }
     dashboard_handle.abort();
-
-    let _ = network_handle.await;
     let _ = dashboard_handle.await;
+    drop(turn_relay);
 
     tracing::info!("Node stopped.");
     Ok(())
@@ -71,8 +89,36 @@ async fn run_network(
 ) -> Result<(), NodeError> {
     tracing::info!("Initializing network node...");
 
-    let listen_addr = Some(config.listen_socket().to_string());
-    let _network = NetworkNode::new(listen_addr.as_deref())
+    let dedicated = config.node.mode == crate::config::NodeMode::Dedicated;
+    let network_config = NetworkNodeConfig {
+        identity: Some(identity.clone()),
+        listen_addr: Some(config.tcp_multiaddr()),
+        quic_listen_addr: Some(config.quic_multiaddr()),
+        enable_tcp: config.network.enable_tcp,
+        enable_quic: config.network.enable_quic,
+        bootstrap_peers: config.network.bootstrap_peers.clone(),
+        relay_server: config.relay_server(),
+        external_addrs: config.network.external_addrs.clone(),
+        enable_mdns: config.network.enable_mdns,
+        dht_server: true,
+        data_dir: Some(config.data_dir()),
+        mixer: MixerConfig {
+            region: config.network.region.clone(),
+            dedicated,
+            // Hosted rooms are relayed, not mixed: the node only forwards
+            // each speaker's packets.
+            media_mode: MediaMode::Forward,
+            ..Default::default()
+        },
+        max_hosted_rooms: if dedicated { config.node.max_mixers } else { 0 },
+        ..Default::default()
+    };
+    if network_config.relay_server.is_some() && network_config.external_addrs.is_empty() {
+        tracing::warn!(
+            "network.external_addrs is not set, circuit relay reservations wait for AutoNAT"
+        );
+    }
+    let mut network = NetworkNode::with_config(network_config)
         .await
         .map_err(|e| NodeError::Network(format!("Failed to create network node: {}", e)))?;
 
@@ -82,17 +128,18 @@ async fn run_network(
         crate::config::NodeMode::Bootstrap => NodeMode::Bootstrap,
     };
 
-    let peer_id = identity
-        .peer_id()
-        .parse::<PeerId>()
-        .map_err(|e| NodeError::Identity(format!("Invalid peer ID: {}", e)))?;
+    let peer_id = network.local_peer_id();
+    let mut events = network.subscribe_events();
+    let commands = network.command_sender();
+    let network_task = tokio::spawn(async move { network.run().await });
 
     let mut advertisement = NodeAdvertisement::new(peer_id, node_mode);
     if let Some(ref region) = config.network.region {
         advertisement.region = region.clone();
     }
 
-    let _discovery = Arc::new(tokio::sync::RwLock::new(NodeDiscovery::new()));
+    let discovery_config = DiscoveryConfig::default();
+    let mut discovery = NodeDiscovery::new();
 
     {
         let mut data = dashboard_state.write().await;
@@ -100,8 +147,11 @@ async fn run_network(
     }
 
     let mut interval = tokio::time::interval(Duration::from_secs(5));
-    let mut advertise_interval = tokio::time::interval(Duration::from_secs(300));
+    let mut advertise_interval = tokio::time::interval(discovery_config.advertise_interval);
+    let mut refresh_interval = tokio::time::interval(discovery_config.refresh_interval);
     let mut uptime_secs: u64 = 0;
+    // Rooms this node mixes for, with the participants it knows in each.
+    let mut hosted: HashMap<String, HashSet<PeerId>> = HashMap::new();
 
     loop {
         tokio::select! {
@@ -110,28 +160,80 @@ async fn run_network(
                 break;
             }
 
+            event = events.recv() => match event {
+                Ok(NetworkEvent::HostingRoom { room_id, requester }) => {
+                    tracing::info!("Mixing room {} for {}", room_id, requester);
+                    hosted.entry(room_id).or_default().insert(requester);
+                }
+                Ok(NetworkEvent::HostedRoomClosed { room_id }) => {
+                    tracing::info!("Room {} closed, no longer mixing", room_id);
+                    hosted.remove(&room_id);
+                }
+                Ok(NetworkEvent::RoomJoined { room_id, peer_id }) => {
+                    if let Some(peers) = hosted.get_mut(&room_id) {
+                        peers.insert(peer_id);
+                    }
+                }
+                Ok(NetworkEvent::RoomLeft { room_id, peer_id }) => {
+                    if let Some(peers) = hosted.get_mut(&room_id) {
+                        peers.remove(&peer_id);
+                    }
+                }
+                Ok(NetworkEvent::AdvertisementFound { peer_id, value, .. }) => {
+                    match NodeAdvertisement::deserialize(&value) {
+                        Ok(found) if found.peer_id == peer_id.to_string() => {
+                            discovery.add_node(found);
+                            let nodes = discovery.mixer_nodes();
+                            let _ = commands.send(NetworkCommand::SetMixerNodes { nodes }).await;
+                        }
+                        Ok(_) => tracing::warn!("Advertisement from {} names another node", peer_id),
+                        Err(e) => tracing::debug!("Invalid advertisement from {}: {}", peer_id, e),
+                    }
+                }
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Dropped {} network events", skipped);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    tracing::error!("Network node stopped");
+                    break;
+                }
+            },
+
             _ = interval.tick() => {
                 uptime_secs += 5;
                 metrics.update_uptime();
 
                 advertisement.update_uptime(uptime_secs);
+                advertisement.update_load(hosted.len() as u32);
 
                 let mut data = dashboard_state.write().await;
                 data.status.uptime_seconds = uptime_secs;
                 data.status.connections.total = 0;
-                data.status.rooms.active = 0;
+                data.status.rooms.active = hosted.len() as i64;
+                data.status.rooms.participants =
+                    hosted.values().map(|peers| peers.len() as i64).sum();
             }
 
             _ = advertise_interval.tick() => {
                 tracing::debug!("Advertising node in DHT: {}", advertisement.dht_key());
 
-                let _serialized = match advertisement.serialize() {
+                let serialized = match advertisement.serialize() {
                     Ok(s) => s,
                     Err(e) => {
                         tracing::error!("Failed to serialize advertisement: {}", e);
                         continue;
                     }
                 };
+                // Listed for region lookups and for the global one other
+                // nodes browse.
+                for directory in [
+                    NodeAdvertisement::dht_key_for_region(&advertisement.region),
+                    NodeAdvertisement::global_dht_key(),
+                ] {
+                    let value = serialized.clone();
+                    let _ = commands.send(NetworkCommand::Advertise { directory, value }).await;
+                }
 
                 tracing::info!(
                     "Node advertisement: region={}, mode={}, load={}/{}",
@@ -141,12 +243,53 @@ async fn run_network(
                     advertisement.max_clients
                 );
             }
+
+            _ = refresh_interval.tick() => {
+                discovery.prune_stale();
+                let nodes = discovery.mixer_nodes();
+                let _ = commands.send(NetworkCommand::SetMixerNodes { nodes }).await;
+                let directory = NodeAdvertisement::global_dht_key();
+                let _ = commands.send(NetworkCommand::Browse { directory }).await;
+            }
         }
     }
 
+    let _ = commands.send(NetworkCommand::Stop).await;
+    let _ = network_task.await;
     Ok(())
 }
 
+async fn start_turn_relay(
+    config: &crate::config::NodeConfig,
+    identity: &Identity,
+    metrics: Arc<NodeMetrics>,
+) -> Result<(RelayHandle, CredentialIssuer), NodeError> {
+    let settings = RelaySettings::from_config(config);
+    let turn = &config.turn.server;
+    if settings.advertised_ip().is_unspecified() {
+        tracing::warn!("turn.server.external_ip is not set, relayed addresses will be unreachable");
+    }
+
+    let udp_addr = config.turn_socket();
+    let tcp_addr = turn.enable_tcp.then_some(udp_addr);
+    let public = std::net::SocketAddr::new(settings.advertised_ip(), turn.port);
+    let mut uris = vec![format!("turn:{}?transport=udp", public)];
+    if turn.enable_tcp {
+        uris.push(format!("turn:{}?transport=tcp", public));
+    }
+
+    let issuer = CredentialIssuer::new(&settings, identity.peer_id(), uris);
+    let relay = TurnRelay::new(settings, metrics)
+        .start(udp_addr, tcp_addr)
+        .await?;
+    tracing::info!(
+        "TURN relay listening on {} (udp), {:?} (tcp)",
+        relay.udp_addr,
+        relay.tcp_addr
+    );
+    Ok((relay, issuer))
+}
+
 fn load_or_create_identity(path: &str, name: Option<&str>) -> Result<Identity, NodeError> {
     let path = Path::new(path);
 
@@ -36,6 +36,8 @@ pub enum SignalingMessage {
         to: String,
         sdp: String,
     },
+    /// Trickled one at a time; an empty `candidate` marks the end of the
+    /// sender's candidates.
     IceCandidate {
         from: String,
         to: String,
@@ -72,7 +74,6 @@ struct Room {
     peers: HashMap<String, PeerInfo>,
 }
 
-#[allow(dead_code)]
 impl Room {
     fn new() -> Self {
         Self {
@@ -130,14 +131,12 @@ impl Default for SignalingState {
     }
 }
 
-#[allow(dead_code)]
 pub fn signaling_router() -> Router<Arc<SignalingState>> {
     Router::new()
         .route("/ws", get(websocket_handler))
         .route("/rooms/{room_id}/peers", get(get_room_peers))
 }
 
-#[allow(dead_code)]
 async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<SignalingState>>,
@@ -151,7 +150,6 @@ async fn websocket_handler(
     ws.on_upgrade(move |socket| handle_websocket(socket, state, peer_id))
 }
 
-#[allow(dead_code)]
 async fn handle_websocket(socket: WebSocket, state: Arc<SignalingState>, peer_id: String) {
     let (mut tx, mut rx) = socket.split();
     let (msg_tx, mut msg_rx) = broadcast::channel::<SignalingMessage>(100);
@@ -214,7 +212,6 @@ async fn handle_websocket(socket: WebSocket, state: Arc<SignalingState>, peer_id
     info!("WebSocket disconnected: {}", peer_id);
 }
 
-#[allow(dead_code)]
 async fn handle_signaling_message(
     state: &Arc<SignalingState>,
     peer_id: &str,
@@ -293,16 +290,16 @@ async fn handle_signaling_message(
         }
 
         SignalingMessage::IceCandidate {
-            from,
             to,
             candidate,
             sdp_mid,
             sdp_mline_index,
+            ..
         } => {
             let peers = state.peers.read().await;
             if let Some(peer) = peers.get(&to) {
                 let _ = peer.tx.send(SignalingMessage::IceCandidate {
-                    from,
+                    from: peer_id.to_string(),
                     to,
                     candidate,
                     sdp_mid,
@@ -317,7 +314,6 @@ async fn handle_signaling_message(
     }
 }
 
-#[allow(dead_code)]
 async fn cleanup_peer(state: &Arc<SignalingState>, peer_id: &str) {
     let room_id = {
         let peers = state.peers.read().await;
@@ -337,7 +333,6 @@ async fn cleanup_peer(state: &Arc<SignalingState>, peer_id: &str) {
     }
 }
 
-#[allow(dead_code)]
 async fn get_room_peers(
     Path(room_id): Path<String>,
     State(state): State<Arc<SignalingState>>,
@@ -435,4 +430,49 @@ mod tests {
             _ => panic!("Expected IceCandidate"),
         }
     }
+
+    async fn connect_peer(
+        state: &Arc<SignalingState>,
+        peer_id: &str,
+    ) -> broadcast::Receiver<SignalingMessage> {
+        let (tx, rx) = broadcast::channel(16);
+        state.peers.write().await.insert(
+            peer_id.to_string(),
+            ConnectedPeer {
+                peer_id: peer_id.to_string(),
+                room_id: None,
+                tx,
+            },
+        );
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_ice_candidates_trickle_to_target() {
+        let state = Arc::new(SignalingState::new());
+        let mut alice = connect_peer(&state, "alice").await;
+        let mut bob = connect_peer(&state, "bob").await;
+
+        for candidate in ["candidate:1 1 udp 2130706431 192.0.2.1 5000 typ host", ""] {
+            let msg = SignalingMessage::IceCandidate {
+                from: "mallory".to_string(),
+                to: "bob".to_string(),
+                candidate: candidate.to_string(),
+                sdp_mid: Some("audio".to_string()),
+                sdp_mline_index: Some(0),
+            };
+            handle_signaling_message(&state, "alice", msg).await;
+
+            match bob.try_recv().unwrap() {
+                SignalingMessage::IceCandidate {
+                    from, candidate: c, ..
+                } => {
+                    assert_eq!(from, "alice");
+                    assert_eq!(c, candidate);
+                }
+                other => panic!("Expected IceCandidate, got {:?}", other),
+            }
+        }
+        assert!(alice.try_recv().is_err());
+    }
 }
@@ -0,0 +1,1286 @@
+use crate::config::NodeConfig;
+use crate::error::NodeError;
+use crate::metrics::NodeMetrics;
+use agora_core::{TurnCredentialRequest, TurnCredentials};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stun::attributes::{
+    AttrType, ATTR_CHANNEL_NUMBER, ATTR_DATA, ATTR_LIFETIME, ATTR_MESSAGE_INTEGRITY, ATTR_NONCE,
+    ATTR_REALM, ATTR_REQUESTED_TRANSPORT, ATTR_USERNAME, ATTR_XORMAPPED_ADDRESS,
+    ATTR_XOR_PEER_ADDRESS, ATTR_XOR_RELAYED_ADDRESS,
+};
+use stun::error_code::{
+    ErrorCode, ErrorCodeAttribute, CODE_ALLOC_MISMATCH, CODE_ALLOC_QUOTA_REACHED, CODE_BAD_REQUEST,
+    CODE_FORBIDDEN, CODE_INSUFFICIENT_CAPACITY, CODE_STALE_NONCE, CODE_UNAUTHORIZED,
+    CODE_UNSUPPORTED_TRANS_PROTO, CODE_WRONG_CREDENTIALS,
+};
+use stun::integrity::MessageIntegrity;
+use stun::message::{
+    Message, MessageClass, MessageType, Setter, CLASS_ERROR_RESPONSE, CLASS_INDICATION,
+    CLASS_REQUEST, CLASS_SUCCESS_RESPONSE, METHOD_ALLOCATE, METHOD_BINDING, METHOD_CHANNEL_BIND,
+    METHOD_CREATE_PERMISSION, METHOD_DATA, METHOD_REFRESH, METHOD_SEND,
+};
+use stun::textattrs::TextAttribute;
+use stun::xoraddr::XorMappedAddress;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+const NONCE_LIFETIME: Duration = Duration::from_secs(600);
+const DEFAULT_ALLOCATION_LIFETIME: Duration = Duration::from_secs(600);
+const MAX_ALLOCATION_LIFETIME: Duration = Duration::from_secs(3600);
+const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+const CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(5);
+const CHANNEL_NUMBER_MIN: u16 = 0x4000;
+const CHANNEL_NUMBER_MAX: u16 = 0x4FFF;
+const PROTOCOL_UDP: u8 = 17;
+const STUN_HEADER_SIZE: usize = 20;
+const CHANNEL_DATA_HEADER_SIZE: usize = 4;
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+type HmacSha1 = Hmac<Sha1>;
+type RequestResult = Result<Message, (ErrorCode, &'static str)>;
+
+#[derive(Debug, Clone)]
+pub struct RelaySettings {
+    pub realm: String,
+    pub secret: Vec<u8>,
+    /// Address relay sockets bind to.
+    pub relay_ip: IpAddr,
+    /// Address reported to clients as the relayed address, if different.
+    pub external_ip: Option<IpAddr>,
+    pub credential_ttl: Duration,
+    pub max_allocations: usize,
+    pub max_allocations_per_user: usize,
+    pub max_bandwidth_per_user: u64,
+    /// Non-public peer addresses clients may relay to anyway.
+    pub allowed_peer_ips: Vec<IpAddr>,
+}
+
+impl RelaySettings {
+    pub fn from_config(config: &NodeConfig) -> Self {
+        let turn = &config.turn.server;
+        let secret = if turn.secret.is_empty() {
+            tracing::warn!("No turn.server.secret set, TURN credentials won't survive a restart");
+            let mut secret = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            secret
+        } else {
+            turn.secret.as_bytes().to_vec()
+        };
+
+        Self {
+            realm: turn.realm.clone(),
+            secret,
+            relay_ip: config.node.listen_addr,
+            external_ip: turn.external_ip,
+            credential_ttl: Duration::from_secs(turn.credential_ttl_secs),
+            max_allocations: turn.max_allocations,
+            max_allocations_per_user: turn.max_allocations_per_user,
+            max_bandwidth_per_user: turn.max_bandwidth_per_user,
+            allowed_peer_ips: turn.allowed_peer_ips.clone(),
+        }
+    }
+
+    pub fn advertised_ip(&self) -> IpAddr {
+        self.external_ip.unwrap_or(self.relay_ip)
+    }
+
+    /// Whether clients may relay to `ip`. The relay itself, loopback,
+    /// private, link-local and other non-public addresses are refused
+    /// unless allowed, so credentials cannot reach into the node's own
+    /// host or LAN (RFC 8656 section 21).
+    fn permits_peer(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if self.allowed_peer_ips.contains(&ip) {
+            return true;
+        }
+        let own = ip == self.relay_ip || Some(ip) == self.external_ip;
+        let restricted = match ip {
+            IpAddr::V4(v4) => {
+                let [a, b, ..] = v4.octets();
+                v4.is_loopback()
+                    || v4.is_private()
+                    || v4.is_link_local()
+                    || v4.is_unspecified()
+                    || v4.is_broadcast()
+                    || v4.is_multicast()
+                    || a == 0
+                    // Shared address space (RFC 6598).
+                    || (a == 100 && (b & 0xc0) == 64)
+            }
+            IpAddr::V6(v6) => {
+                let first = v6.segments()[0];
+                v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    // Unique local and link-local unicast.
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80
+            }
+        };
+        !own && !restricted
+    }
+}
+
+/// Issues REST-style TURN credentials: the username is
+/// `<expiry>:<peer id>` and the password is its HMAC under the shared
+/// secret, so the server can check them without any per-user state.
+#[derive(Debug, Clone)]
+pub struct CredentialIssuer {
+    secret: Vec<u8>,
+    ttl: Duration,
+    relay_peer_id: String,
+    uris: Vec<String>,
+}
+
+impl CredentialIssuer {
+    pub fn new(settings: &RelaySettings, relay_peer_id: String, uris: Vec<String>) -> Self {
+        Self {
+            secret: settings.secret.clone(),
+            ttl: settings.credential_ttl,
+            relay_peer_id,
+            uris,
+        }
+    }
+
+    pub fn issue(&self, request: &TurnCredentialRequest) -> Result<TurnCredentials, NodeError> {
+        let peer_id = request
+            .verify(&self.relay_peer_id)
+            .map_err(|e| NodeError::Identity(format!("Credential request rejected: {}", e)))?;
+        Ok(self.issue_for(&peer_id.to_string()))
+    }
+
+    fn issue_for(&self, peer_id: &str) -> TurnCredentials {
+        let expiry = now_secs() + self.ttl.as_secs();
+        let username = format!("{}:{}", expiry, peer_id);
+        TurnCredentials {
+            password: rest_password(&self.secret, &username),
+            username,
+            ttl: self.ttl.as_secs(),
+            uris: self.uris.clone(),
+        }
+    }
+}
+
+fn rest_password(secret: &[u8], username: &str) -> String {
+    let mut mac = HmacSha1::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(username.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+/// Returns the peer ID from an unexpired REST username.
+fn user_from_username(username: &str, now: u64) -> Option<String> {
+    let (expiry, peer_id) = username.split_once(':')?;
+    let expiry: u64 = expiry.parse().ok()?;
+    if expiry <= now || peer_id.parse::<libp2p::PeerId>().is_err() {
+        return None;
+    }
+    Some(peer_id.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// The client side of an allocation's 5-tuple; the server side is fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ClientKey {
+    transport: Transport,
+    addr: SocketAddr,
+}
+
+#[derive(Clone)]
+enum ClientSink {
+    Udp(Arc<UdpSocket>),
+    Tcp(mpsc::UnboundedSender<Vec<u8>>),
+}
+
+impl ClientSink {
+    async fn send(&self, addr: SocketAddr, packet: Vec<u8>) {
+        match self {
+            ClientSink::Udp(socket) => {
+                if let Err(e) = socket.send_to(&packet, addr).await {
+                    tracing::debug!("Failed to send TURN packet to {}: {}", addr, e);
+                }
+            }
+            ClientSink::Tcp(tx) => {
+                let _ = tx.send(packet);
+            }
+        }
+    }
+
+    fn is_stream(&self) -> bool {
+        matches!(self, ClientSink::Tcp(_))
+    }
+}
+
+struct Allocation {
+    user: String,
+    transaction_id: stun::agent::TransactionId,
+    sink: ClientSink,
+    relay: Arc<UdpSocket>,
+    relayed_addr: SocketAddr,
+    lifetime: Duration,
+    expires_at: Instant,
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<u16, (SocketAddr, Instant)>,
+    reader: JoinHandle<()>,
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Allocation {
+    fn has_permission(&self, ip: IpAddr, now: Instant) -> bool {
+        self.permissions
+            .get(&ip)
+            .is_some_and(|expires| *expires > now)
+    }
+
+    fn channel_peer(&self, channel: u16, now: Instant) -> Option<SocketAddr> {
+        self.channels
+            .get(&channel)
+            .filter(|(_, expires)| *expires > now)
+            .map(|(peer, _)| *peer)
+    }
+
+    fn peer_channel(&self, peer: SocketAddr, now: Instant) -> Option<u16> {
+        self.channels
+            .iter()
+            .find(|(_, (p, expires))| *p == peer && *expires > now)
+            .map(|(channel, _)| *channel)
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Allows bursts of up to one second's worth of traffic.
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn try_consume(&mut self, bytes: usize) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.updated).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate);
+        self.updated = now;
+
+        if self.tokens >= bytes as f64 {
+            self.tokens -= bytes as f64;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Default)]
+struct RelayState {
+    allocations: HashMap<ClientKey, Allocation>,
+    bandwidth: HashMap<String, TokenBucket>,
+}
+
+impl RelayState {
+    fn allocations_for(&self, user: &str) -> usize {
+        self.allocations.values().filter(|a| a.user == user).count()
+    }
+
+    fn charge(&mut self, user: &str, bytes: usize, rate: u64) -> bool {
+        self.bandwidth
+            .entry(user.to_string())
+            .or_insert_with(|| TokenBucket::new(rate))
+            .try_consume(bytes)
+    }
+}
+
+/// TURN server (RFC 8656) relaying UDP for clients on UDP or TCP.
+///
+/// Clients authenticate with credentials from [`CredentialIssuer`]; each
+/// user's allocations share one bandwidth budget.
+pub struct TurnRelay {
+    settings: RelaySettings,
+    metrics: Arc<NodeMetrics>,
+    state: Mutex<RelayState>,
+}
+
+/// Keeps a started relay running; dropping it stops the server.
+pub struct RelayHandle {
+    pub relay: Arc<TurnRelay>,
+    pub udp_addr: SocketAddr,
+    pub tcp_addr: Option<SocketAddr>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for RelayHandle {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Ok(mut state) = self.relay.state.try_lock() {
+            state.allocations.clear();
+        }
+    }
+}
+
+impl TurnRelay {
+    pub fn new(settings: RelaySettings, metrics: Arc<NodeMetrics>) -> Arc<Self> {
+        Arc::new(Self {
+            settings,
+            metrics,
+            state: Mutex::new(RelayState::default()),
+        })
+    }
+
+    pub async fn start(
+        self: Arc<Self>,
+        udp_addr: SocketAddr,
+        tcp_addr: Option<SocketAddr>,
+    ) -> Result<RelayHandle, NodeError> {
+        let socket = Arc::new(
+            UdpSocket::bind(udp_addr)
+                .await
+                .map_err(|e| NodeError::Network(format!("Failed to bind TURN/UDP: {}", e)))?,
+        );
+        let udp_addr = socket.local_addr()?;
+        let mut tasks = vec![tokio::spawn(self.clone().serve_udp(socket))];
+
+        let tcp_addr = match tcp_addr {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .map_err(|e| NodeError::Network(format!("Failed to bind TURN/TCP: {}", e)))?;
+                let addr = listener.local_addr()?;
+                tasks.push(tokio::spawn(self.clone().serve_tcp(listener)));
+                Some(addr)
+            }
+            None => None,
+        };
+
+        let relay = Arc::downgrade(&self);
+        tasks.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HOUSEKEEPING_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(relay) = relay.upgrade() else {
+                    break;
+                };
+                relay.expire().await;
+            }
+        }));
+
+        tracing::info!(
+            "TURN relay listening on {} (udp) {:?} (tcp)",
+            udp_addr,
+            tcp_addr
+        );
+        Ok(RelayHandle {
+            relay: self,
+            udp_addr,
+            tcp_addr,
+            tasks,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub async fn allocation_count(&self) -> usize {
+        self.state.lock().await.allocations.len()
+    }
+
+    async fn serve_udp(self: Arc<Self>, socket: Arc<UdpSocket>) {
+        let sink = ClientSink::Udp(socket.clone());
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            let (n, from) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    tracing::debug!("TURN/UDP receive error: {}", e);
+                    continue;
+                }
+            };
+            let key = ClientKey {
+                transport: Transport::Udp,
+                addr: from,
+            };
+            self.handle_packet(key, &buf[..n], &sink).await;
+        }
+    }
+
+    async fn serve_tcp(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, from)) => {
+                    tokio::spawn(self.clone().serve_connection(stream, from));
+                }
+                Err(e) => tracing::debug!("TURN/TCP accept error: {}", e),
+            }
+        }
+    }
+
+    /// Over TCP, an allocation lives as long as its connection.
+    async fn serve_connection(self: Arc<Self>, stream: TcpStream, from: SocketAddr) {
+        let (mut reader, mut writer) = stream.into_split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let writer_task = tokio::spawn(async move {
+            while let Some(packet) = rx.recv().await {
+                if writer.write_all(&packet).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let key = ClientKey {
+            transport: Transport::Tcp,
+            addr: from,
+        };
+        let sink = ClientSink::Tcp(tx);
+        while let Ok(Some(frame)) = read_frame(&mut reader).await {
+            self.handle_packet(key, &frame, &sink).await;
+        }
+
+        writer_task.abort();
+        let mut state = self.state.lock().await;
+        if state.allocations.remove(&key).is_some() {
+            self.metrics
+                .set_turn_allocations(state.allocations.len() as i64);
+        }
+    }
+
+    async fn handle_packet(self: &Arc<Self>, key: ClientKey, packet: &[u8], sink: &ClientSink) {
+        if let Some((channel, data)) = decode_channel_data(packet) {
+            self.relay_channel_data(key, channel, data).await;
+            return;
+        }
+
+        let mut message = Message::new();
+        if message.unmarshal_binary(packet).is_err() {
+            return;
+        }
+        if message.typ == MessageType::new(METHOD_SEND, CLASS_INDICATION) {
+            self.relay_send_indication(key, &message).await;
+            return;
+        }
+        if message.typ.class != CLASS_REQUEST {
+            return;
+        }
+
+        let response = self.handle_request(key, &mut message, sink).await;
+        sink.send(key.addr, response.raw).await;
+    }
+
+    async fn handle_request(
+        self: &Arc<Self>,
+        key: ClientKey,
+        request: &mut Message,
+        sink: &ClientSink,
+    ) -> Message {
+        if request.typ.method == METHOD_BINDING {
+            let mut response = reply(request, CLASS_SUCCESS_RESPONSE);
+            let _ = add_xor_address(&mut response, ATTR_XORMAPPED_ADDRESS, key.addr);
+            return response;
+        }
+
+        let (user, integrity) = match self.authenticate(key, request) {
+            Ok(authenticated) => authenticated,
+            Err(response) => return response,
+        };
+
+        let result = match request.typ.method {
+            METHOD_ALLOCATE => self.allocate(key, request, &user, sink).await,
+            METHOD_REFRESH => self.refresh(key, request, &user).await,
+            METHOD_CREATE_PERMISSION => self.create_permission(key, request, &user).await,
+            METHOD_CHANNEL_BIND => self.bind_channel(key, request, &user).await,
+            _ => Err((CODE_BAD_REQUEST, "Unsupported method")),
+        };
+
+        let mut response = match result {
+            Ok(response) => response,
+            Err((code, reason)) => error_response(request, code, reason),
+        };
+        let _ = integrity.add_to(&mut response);
+        response
+    }
+
+    /// Checks long-term credentials. The nonce is derived from the client's
+    /// address and an expiry, so no per-client state is kept before auth.
+    fn authenticate(
+        &self,
+        key: ClientKey,
+        request: &mut Message,
+    ) -> Result<(String, MessageIntegrity), Message> {
+        if !request.contains(ATTR_MESSAGE_INTEGRITY) {
+            return Err(self.challenge(key, request, CODE_UNAUTHORIZED));
+        }
+
+        let text = |attr| TextAttribute::get_from_as(request, attr).map(|a| a.text);
+        let (Ok(username), Ok(realm), Ok(nonce)) =
+            (text(ATTR_USERNAME), text(ATTR_REALM), text(ATTR_NONCE))
+        else {
+            return Err(error_response(
+                request,
+                CODE_BAD_REQUEST,
+                "Missing credentials",
+            ));
+        };
+
+        let now = now_secs();
+        if !self.nonce_valid(key, &nonce, now) {
+            return Err(self.challenge(key, request, CODE_STALE_NONCE));
+        }
+
+        let user = user_from_username(&username, now).filter(|_| realm == self.settings.realm);
+        let password = rest_password(&self.settings.secret, &username);
+        let integrity = MessageIntegrity::new_long_term_integrity(username, realm, password);
+        match user {
+            Some(user) if integrity.check(request).is_ok() => Ok((user, integrity)),
+            _ => {
+                self.metrics.inc_turn_auth_failures();
+                Err(self.challenge(key, request, CODE_UNAUTHORIZED))
+            }
+        }
+    }
+
+    fn challenge(&self, key: ClientKey, request: &Message, code: ErrorCode) -> Message {
+        let mut response = error_response(request, code, "");
+        let nonce = self.nonce_for(key, now_secs() + NONCE_LIFETIME.as_secs());
+        let _ = TextAttribute::new(ATTR_REALM, self.settings.realm.clone()).add_to(&mut response);
+        let _ = TextAttribute::new(ATTR_NONCE, nonce).add_to(&mut response);
+        response
+    }
+
+    fn nonce_for(&self, key: ClientKey, expiry: u64) -> String {
+        let mut mac =
+            HmacSha1::new_from_slice(&self.settings.secret).expect("HMAC accepts any key length");
+        mac.update(&expiry.to_be_bytes());
+        mac.update(format!("{:?}", key).as_bytes());
+        let tag = mac.finalize().into_bytes();
+        format!("{:016x}{}", expiry, hex::encode(&tag[..8]))
+    }
+
+    fn nonce_valid(&self, key: ClientKey, nonce: &str, now: u64) -> bool {
+        let Some(expiry) = nonce
+            .get(..16)
+            .and_then(|e| u64::from_str_radix(e, 16).ok())
+        else {
+            return false;
+        };
+        expiry > now && self.nonce_for(key, expiry) == nonce
+    }
+
+    async fn allocate(
+        self: &Arc<Self>,
+        key: ClientKey,
+        request: &Message,
+        user: &str,
+        sink: &ClientSink,
+    ) -> RequestResult {
+        let mut state = self.state.lock().await;
+        if let Some(existing) = state.allocations.get(&key) {
+            // A retransmitted Allocate gets the original answer.
+            if existing.transaction_id == request.transaction_id {
+                return Ok(allocate_response(request, key, existing));
+            }
+            return Err((CODE_ALLOC_MISMATCH, "Allocation already exists"));
+        }
+
+        match request.get(ATTR_REQUESTED_TRANSPORT) {
+            Ok(value) if value.first() == Some(&PROTOCOL_UDP) => {}
+            Ok(_) => return Err((CODE_UNSUPPORTED_TRANS_PROTO, "Only UDP is relayed")),
+            Err(_) => return Err((CODE_BAD_REQUEST, "Missing REQUESTED-TRANSPORT")),
+        }
+
+        if state.allocations.len() >= self.settings.max_allocations {
+            self.metrics.inc_turn_quota_rejections();
+            return Err((CODE_INSUFFICIENT_CAPACITY, "Relay is full"));
+        }
+        if state.allocations_for(user) >= self.settings.max_allocations_per_user {
+            self.metrics.inc_turn_quota_rejections();
+            return Err((CODE_ALLOC_QUOTA_REACHED, "Allocation quota reached"));
+        }
+
+        let relay = match UdpSocket::bind(SocketAddr::new(self.settings.relay_ip, 0)).await {
+            Ok(socket) => Arc::new(socket),
+            Err(e) => {
+                tracing::warn!("Failed to bind TURN relay socket: {}", e);
+                return Err((CODE_INSUFFICIENT_CAPACITY, "No relay port available"));
+            }
+        };
+        let port = relay
+            .local_addr()
+            .map_err(|_| (CODE_INSUFFICIENT_CAPACITY, "No relay port available"))?
+            .port();
+
+        let lifetime = granted_lifetime(request);
+        let allocation = Allocation {
+            user: user.to_string(),
+            transaction_id: request.transaction_id,
+            sink: sink.clone(),
+            relay: relay.clone(),
+            relayed_addr: SocketAddr::new(self.settings.advertised_ip(), port),
+            lifetime,
+            expires_at: Instant::now() + lifetime,
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            reader: tokio::spawn(read_relay(Arc::downgrade(self), key, relay)),
+        };
+        let response = allocate_response(request, key, &allocation);
+
+        tracing::info!(
+            "TURN allocation {} for {} ({})",
+            allocation.relayed_addr,
+            user,
+            key.addr
+        );
+        state.allocations.insert(key, allocation);
+        self.metrics.inc_turn_allocations();
+        self.metrics
+            .set_turn_allocations(state.allocations.len() as i64);
+        Ok(response)
+    }
+
+    async fn refresh(&self, key: ClientKey, request: &Message, user: &str) -> RequestResult {
+        let mut state = self.state.lock().await;
+        let allocation = owned_allocation(&mut state, key, user)?;
+
+        let requested = lifetime_attribute(request);
+        let mut response = reply(request, CLASS_SUCCESS_RESPONSE);
+        if requested == Some(Duration::ZERO) {
+            state.allocations.remove(&key);
+            self.metrics
+                .set_turn_allocations(state.allocations.len() as i64);
+            response.add(ATTR_LIFETIME, &0u32.to_be_bytes());
+            return Ok(response);
+        }
+
+        let lifetime = granted_lifetime(request);
+        allocation.lifetime = lifetime;
+        allocation.expires_at = Instant::now() + lifetime;
+        response.add(ATTR_LIFETIME, &(lifetime.as_secs() as u32).to_be_bytes());
+        Ok(response)
+    }
+
+    async fn create_permission(
+        &self,
+        key: ClientKey,
+        request: &Message,
+        user: &str,
+    ) -> RequestResult {
+        let peers = peer_addresses(request);
+        if peers.is_empty() {
+            return Err((CODE_BAD_REQUEST, "Missing XOR-PEER-ADDRESS"));
+        }
+        if !peers
+            .iter()
+            .all(|peer| self.settings.permits_peer(peer.ip()))
+        {
+            return Err((CODE_FORBIDDEN, "Peer address not allowed"));
+        }
+
+        let mut state = self.state.lock().await;
+        let allocation = owned_allocation(&mut state, key, user)?;
+        let expires = Instant::now() + PERMISSION_LIFETIME;
+        for peer in peers {
+            allocation.permissions.insert(peer.ip(), expires);
+        }
+        Ok(reply(request, CLASS_SUCCESS_RESPONSE))
+    }
+
+    async fn bind_channel(&self, key: ClientKey, request: &Message, user: &str) -> RequestResult {
+        let channel = match request.get(ATTR_CHANNEL_NUMBER) {
+            Ok(value) if value.len() >= 2 => u16::from_be_bytes([value[0], value[1]]),
+            _ => return Err((CODE_BAD_REQUEST, "Missing CHANNEL-NUMBER")),
+        };
+        if !(CHANNEL_NUMBER_MIN..=CHANNEL_NUMBER_MAX).contains(&channel) {
+            return Err((CODE_BAD_REQUEST, "Invalid channel number"));
+        }
+        let Some(peer) = peer_addresses(request).into_iter().next() else {
+            return Err((CODE_BAD_REQUEST, "Missing XOR-PEER-ADDRESS"));
+        };
+        if !self.settings.permits_peer(peer.ip()) {
+            return Err((CODE_FORBIDDEN, "Peer address not allowed"));
+        }
+
+        let mut state = self.state.lock().await;
+        let allocation = owned_allocation(&mut state, key, user)?;
+        let now = Instant::now();
+        let channel_taken = allocation
+            .channel_peer(channel, now)
+            .is_some_and(|bound| bound != peer);
+        let peer_taken = allocation
+            .peer_channel(peer, now)
+            .is_some_and(|bound| bound != channel);
+        if channel_taken || peer_taken {
+            return Err((CODE_BAD_REQUEST, "Channel bound to another peer"));
+        }
+
+        allocation
+            .channels
+            .insert(channel, (peer, now + CHANNEL_LIFETIME));
+        allocation
+            .permissions
+            .insert(peer.ip(), now + PERMISSION_LIFETIME);
+        Ok(reply(request, CLASS_SUCCESS_RESPONSE))
+    }
+
+    async fn relay_channel_data(&self, key: ClientKey, channel: u16, data: &[u8]) {
+        let now = Instant::now();
+        let mut state = self.state.lock().await;
+        let Some(allocation) = state.allocations.get(&key) else {
+            return;
+        };
+        let Some(peer) = allocation.channel_peer(channel, now) else {
+            return;
+        };
+        if !allocation.has_permission(peer.ip(), now) {
+            return;
+        }
+        let relay = allocation.relay.clone();
+        let user = allocation.user.clone();
+        if !state.charge(&user, data.len(), self.settings.max_bandwidth_per_user) {
+            self.metrics.inc_turn_dropped();
+            return;
+        }
+        drop(state);
+
+        if relay.send_to(data, peer).await.is_ok() {
+            self.metrics.inc_turn_relayed(data.len());
+        }
+    }
+
+    async fn relay_send_indication(&self, key: ClientKey, indication: &Message) {
+        let (Some(peer), Ok(data)) = (
+            peer_addresses(indication).into_iter().next(),
+            indication.get(ATTR_DATA),
+        ) else {
+            return;
+        };
+
+        let mut state = self.state.lock().await;
+        let Some(allocation) = state.allocations.get(&key) else {
+            return;
+        };
+        if !allocation.has_permission(peer.ip(), Instant::now()) {
+            return;
+        }
+        let relay = allocation.relay.clone();
+        let user = allocation.user.clone();
+        if !state.charge(&user, data.len(), self.settings.max_bandwidth_per_user) {
+            self.metrics.inc_turn_dropped();
+            return;
+        }
+        drop(state);
+
+        if relay.send_to(&data, peer).await.is_ok() {
+            self.metrics.inc_turn_relayed(data.len());
+        }
+    }
+
+    /// Forwards a datagram a peer sent to the relayed address, over the
+    /// peer's channel if bound and as a Data indication otherwise.
+    async fn relay_to_client(&self, key: ClientKey, peer: SocketAddr, data: &[u8]) {
+        let now = Instant::now();
+        let mut state = self.state.lock().await;
+        let Some(allocation) = state.allocations.get(&key) else {
+            return;
+        };
+        if !allocation.has_permission(peer.ip(), now) {
+            return;
+        }
+
+        let packet = match allocation.peer_channel(peer, now) {
+            Some(channel) => encode_channel_data(channel, data, allocation.sink.is_stream()),
+            None => {
+                let mut indication = Message::new();
+                let built = indication.build(&[
+                    Box::new(MessageType::new(METHOD_DATA, CLASS_INDICATION)),
+                    Box::new(stun::agent::TransactionId::new()),
+                ]);
+                if built.is_err()
+                    || add_xor_address(&mut indication, ATTR_XOR_PEER_ADDRESS, peer).is_err()
+                {
+                    return;
+                }
+                indication.add(ATTR_DATA, data);
+                indication.raw
+            }
+        };
+        let sink = allocation.sink.clone();
+        let user = allocation.user.clone();
+        if !state.charge(&user, data.len(), self.settings.max_bandwidth_per_user) {
+            self.metrics.inc_turn_dropped();
+            return;
+        }
+        drop(state);
+
+        sink.send(key.addr, packet).await;
+        self.metrics.inc_turn_relayed(data.len());
+    }
+
+    async fn expire(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().await;
+        state.allocations.retain(|_, a| a.expires_at > now);
+        for allocation in state.allocations.values_mut() {
+            allocation.permissions.retain(|_, expires| *expires > now);
+            allocation.channels.retain(|_, (_, expires)| *expires > now);
+        }
+
+        let RelayState {
+            allocations,
+            bandwidth,
+        } = &mut *state;
+        bandwidth.retain(|user, _| allocations.values().any(|a| &a.user == user));
+        self.metrics.set_turn_allocations(allocations.len() as i64);
+    }
+}
+
+async fn read_relay(relay: Weak<TurnRelay>, key: ClientKey, socket: Arc<UdpSocket>) {
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+        let Some(relay) = relay.upgrade() else {
+            break;
+        };
+        relay.relay_to_client(key, peer, &buf[..n]).await;
+    }
+}
+
+fn owned_allocation<'a>(
+    state: &'a mut RelayState,
+    key: ClientKey,
+    user: &str,
+) -> Result<&'a mut Allocation, (ErrorCode, &'static str)> {
+    match state.allocations.get_mut(&key) {
+        Some(allocation) if allocation.user == user => Ok(allocation),
+        Some(_) => Err((CODE_WRONG_CREDENTIALS, "Allocation belongs to another user")),
+        None => Err((CODE_ALLOC_MISMATCH, "No allocation")),
+    }
+}
+
+fn allocate_response(request: &Message, key: ClientKey, allocation: &Allocation) -> Message {
+    let mut response = reply(request, CLASS_SUCCESS_RESPONSE);
+    let _ = add_xor_address(
+        &mut response,
+        ATTR_XOR_RELAYED_ADDRESS,
+        allocation.relayed_addr,
+    );
+    let _ = add_xor_address(&mut response, ATTR_XORMAPPED_ADDRESS, key.addr);
+    let lifetime = allocation
+        .expires_at
+        .saturating_duration_since(Instant::now());
+    let lifetime = lifetime.as_secs().min(allocation.lifetime.as_secs()) as u32;
+    response.add(ATTR_LIFETIME, &lifetime.to_be_bytes());
+    response
+}
+
+fn reply(request: &Message, class: MessageClass) -> Message {
+    let mut response = Message::new();
+    response.typ = MessageType::new(request.typ.method, class);
+    response.transaction_id = request.transaction_id;
+    response.write_header();
+    response
+}
+
+fn error_response(request: &Message, code: ErrorCode, reason: &str) -> Message {
+    let mut response = reply(request, CLASS_ERROR_RESPONSE);
+    let _ = ErrorCodeAttribute {
+        code,
+        reason: reason.as_bytes().to_vec(),
+    }
+    .add_to(&mut response);
+    response
+}
+
+fn add_xor_address(
+    message: &mut Message,
+    attribute: AttrType,
+    addr: SocketAddr,
+) -> Result<(), stun::Error> {
+    XorMappedAddress {
+        ip: addr.ip(),
+        port: addr.port(),
+    }
+    .add_to_as(message, attribute)
+}
+
+/// All XOR-PEER-ADDRESS attributes; CreatePermission may carry several.
+fn peer_addresses(message: &Message) -> Vec<SocketAddr> {
+    message
+        .attributes
+        .0
+        .iter()
+        .filter(|attr| attr.typ == ATTR_XOR_PEER_ADDRESS)
+        .filter_map(|attr| {
+            let mut single = Message::new();
+            single.transaction_id = message.transaction_id;
+            single.add(attr.typ, &attr.value);
+            let mut addr = XorMappedAddress::default();
+            addr.get_from_as(&single, ATTR_XOR_PEER_ADDRESS).ok()?;
+            Some(SocketAddr::new(addr.ip, addr.port))
+        })
+        .collect()
+}
+
+fn lifetime_attribute(message: &Message) -> Option<Duration> {
+    let value: [u8; 4] = message.get(ATTR_LIFETIME).ok()?.try_into().ok()?;
+    Some(Duration::from_secs(u32::from_be_bytes(value) as u64))
+}
+
+fn granted_lifetime(request: &Message) -> Duration {
+    lifetime_attribute(request)
+        .unwrap_or(DEFAULT_ALLOCATION_LIFETIME)
+        .clamp(DEFAULT_ALLOCATION_LIFETIME, MAX_ALLOCATION_LIFETIME)
+}
+
+/// Over TCP, ChannelData is padded to a multiple of four bytes.
+fn encode_channel_data(channel: u16, data: &[u8], pad: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(CHANNEL_DATA_HEADER_SIZE + data.len() + 3);
+    packet.extend_from_slice(&channel.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    if pad {
+        packet.resize(packet.len().next_multiple_of(4), 0);
+    }
+    packet
+}
+
+fn decode_channel_data(packet: &[u8]) -> Option<(u16, &[u8])> {
+    if packet.len() < CHANNEL_DATA_HEADER_SIZE {
+        return None;
+    }
+    let channel = u16::from_be_bytes([packet[0], packet[1]]);
+    let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if !(CHANNEL_NUMBER_MIN..=CHANNEL_NUMBER_MAX).contains(&channel)
+        || packet.len() < CHANNEL_DATA_HEADER_SIZE + len
+    {
+        return None;
+    }
+    Some((
+        channel,
+        &packet[CHANNEL_DATA_HEADER_SIZE..CHANNEL_DATA_HEADER_SIZE + len],
+    ))
+}
+
+/// Reads one STUN message or ChannelData frame from a TCP stream.
+async fn read_frame<R: AsyncReadExt + Unpin>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut frame = vec![0u8; CHANNEL_DATA_HEADER_SIZE];
+    match reader.read_exact(&mut frame).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u16::from_be_bytes([frame[2], frame[3]]) as usize;
+    let remaining = if frame[0] & 0xC0 == 0x40 {
+        len.next_multiple_of(4)
+    } else {
+        STUN_HEADER_SIZE - CHANNEL_DATA_HEADER_SIZE + len
+    };
+    frame.resize(CHANNEL_DATA_HEADER_SIZE + remaining, 0);
+    reader
+        .read_exact(&mut frame[CHANNEL_DATA_HEADER_SIZE..])
+        .await?;
+    Ok(Some(frame))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agora_core::{Identity, TurnClient, TurnServer};
+
+    fn settings() -> RelaySettings {
+        RelaySettings {
+            realm: "agora.test".to_string(),
+            secret: b"test secret".to_vec(),
+            relay_ip: "127.0.0.1".parse().unwrap(),
+            external_ip: None,
+            credential_ttl: Duration::from_secs(3600),
+            max_allocations: 10,
+            max_allocations_per_user: 2,
+            max_bandwidth_per_user: 1_000_000,
+            // Test peers live on loopback.
+            allowed_peer_ips: vec!["127.0.0.1".parse().unwrap()],
+        }
+    }
+
+    async fn start(settings: RelaySettings) -> (RelayHandle, CredentialIssuer) {
+        let issuer = CredentialIssuer::new(&settings, "relay".to_string(), vec![]);
+        let relay = TurnRelay::new(settings, Arc::new(NodeMetrics::new()))
+            .start(
+                "127.0.0.1:0".parse().unwrap(),
+                Some("127.0.0.1:0".parse().unwrap()),
+            )
+            .await
+            .unwrap();
+        (relay, issuer)
+    }
+
+    fn connect(relay: &RelayHandle, credentials: &TurnCredentials) -> (TurnClient, TurnServer) {
+        let server = TurnServer::new(relay.udp_addr);
+        let client = TurnClient::with_servers(vec![server.clone()])
+            .with_credentials(credentials.username.clone(), credentials.password.clone());
+        (client, server)
+    }
+
+    #[test]
+    fn test_credentials_bound_to_identity() {
+        let issuer = CredentialIssuer::new(&settings(), "relay".to_string(), vec![]);
+        let identity = Identity::generate().unwrap();
+
+        let request = TurnCredentialRequest::sign(&identity, "relay").unwrap();
+        let credentials = issuer.issue(&request).unwrap();
+        assert_eq!(
+            user_from_username(&credentials.username, now_secs()),
+            Some(identity.peer_id())
+        );
+        assert_eq!(
+            credentials.password,
+            rest_password(b"test secret", &credentials.username)
+        );
+
+        let elsewhere = TurnCredentialRequest::sign(&identity, "other relay").unwrap();
+        assert!(issuer.issue(&elsewhere).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_relay_roundtrip() {
+        let (relay, issuer) = start(settings()).await;
+        let credentials = issuer.issue_for(&Identity::generate().unwrap().peer_id());
+        let (mut client, server) = connect(&relay, &credentials);
+        let allocation = client.create_allocation(&server).await.unwrap();
+        assert_eq!(relay.relay.allocation_count().await, 1);
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let mut buf = [0u8; 64];
+
+        // Nothing gets through before a permission exists.
+        peer.send_to(b"too early", allocation.relayed_addr)
+            .await
+            .unwrap();
+
+        client.create_permission(peer_addr).await.unwrap();
+        client.send_to(peer_addr, b"via indication").await.unwrap();
+        let (n, from) = peer.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"via indication");
+        assert_eq!(from, allocation.relayed_addr);
+
+        peer.send_to(b"reply", from).await.unwrap();
+        assert_eq!(
+            client.recv_from().await.unwrap(),
+            (peer_addr, b"reply".to_vec())
+        );
+
+        client.bind_channel(peer_addr).await.unwrap();
+        client.send_to(peer_addr, b"via channel").await.unwrap();
+        let (n, _) = peer.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"via channel");
+
+        peer.send_to(b"channel reply", from).await.unwrap();
+        assert_eq!(
+            client.recv_from().await.unwrap(),
+            (peer_addr, b"channel reply".to_vec())
+        );
+
+        client.refresh_allocation(server.address).await.unwrap();
+        client.release_allocation(server.address).await.unwrap();
+        assert_eq!(relay.relay.allocation_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_restricted_peer_addresses_refused() {
+        let (relay, issuer) = start(RelaySettings {
+            allowed_peer_ips: Vec::new(),
+            ..settings()
+        })
+        .await;
+        let credentials = issuer.issue_for(&Identity::generate().unwrap().peer_id());
+        let (mut client, server) = connect(&relay, &credentials);
+        client.create_allocation(&server).await.unwrap();
+
+        let loopback: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let error = client.create_permission(loopback).await.unwrap_err();
+        assert!(error.to_string().contains("403"));
+        let error = client.bind_channel(loopback).await.unwrap_err();
+        assert!(error.to_string().contains("403"));
+    }
+
+    #[test]
+    fn test_permits_only_public_peers() {
+        let settings = RelaySettings {
+            external_ip: Some("203.0.113.10".parse().unwrap()),
+            allowed_peer_ips: vec!["192.168.1.20".parse().unwrap()],
+            ..settings()
+        };
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.0.1",
+            "0.0.0.0",
+            "100.64.0.1",
+            "203.0.113.10",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!settings.permits_peer(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "192.168.1.20", "2001:db8::1"] {
+            assert!(settings.permits_peer(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_expired_and_forged_credentials() {
+        let (relay, issuer) = start(settings()).await;
+        let peer_id = Identity::generate().unwrap().peer_id();
+
+        let mut forged = issuer.issue_for(&peer_id);
+        forged.password = "guess".to_string();
+        let (mut client, server) = connect(&relay, &forged);
+        assert!(client.create_allocation(&server).await.is_err());
+
+        let expired = CredentialIssuer {
+            ttl: Duration::ZERO,
+            ..issuer
+        }
+        .issue_for(&peer_id);
+        let (mut client, server) = connect(&relay, &expired);
+        assert!(client.create_allocation(&server).await.is_err());
+        assert_eq!(relay.relay.allocation_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_per_user_allocation_quota() {
+        let (relay, issuer) = start(settings()).await;
+        let credentials = issuer.issue_for(&Identity::generate().unwrap().peer_id());
+
+        let mut clients = Vec::new();
+        for _ in 0..2 {
+            let (mut client, server) = connect(&relay, &credentials);
+            client.create_allocation(&server).await.unwrap();
+            clients.push(client);
+        }
+
+        let (mut third, server) = connect(&relay, &credentials);
+        let error = third.create_allocation(&server).await.unwrap_err();
+        assert!(error.to_string().contains("486"));
+
+        let other = issuer.issue_for(&Identity::generate().unwrap().peer_id());
+        let (mut client, server) = connect(&relay, &other);
+        client.create_allocation(&server).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_quota_drops_excess() {
+        let (relay, issuer) = start(RelaySettings {
+            max_bandwidth_per_user: 1000,
+            ..settings()
+        })
+        .await;
+        let credentials = issuer.issue_for(&Identity::generate().unwrap().peer_id());
+        let (mut client, server) = connect(&relay, &credentials);
+        client.create_allocation(&server).await.unwrap();
+
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        client.bind_channel(peer_addr).await.unwrap();
+        for _ in 0..10 {
+            client.send_to(peer_addr, &[0u8; 400]).await.unwrap();
+        }
+
+        let mut received = 0;
+        let mut buf = [0u8; 512];
+        while tokio::time::timeout(Duration::from_millis(200), peer.recv_from(&mut buf))
+            .await
+            .is_ok()
+        {
+            received += 1;
+        }
+        assert!((2..10).contains(&received), "received {}", received);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_transport() {
+        let (relay, _) = start(settings()).await;
+        let mut stream = TcpStream::connect(relay.tcp_addr.unwrap()).await.unwrap();
+
+        let mut binding = Message::new();
+        binding
+            .build(&[
+                Box::new(MessageType::new(METHOD_BINDING, CLASS_REQUEST)),
+                Box::new(stun::agent::TransactionId::new()),
+            ])
+            .unwrap();
+        let mut allocate = Message::new();
+        allocate
+            .build(&[
+                Box::new(MessageType::new(METHOD_ALLOCATE, CLASS_REQUEST)),
+                Box::new(stun::agent::TransactionId::new()),
+            ])
+            .unwrap();
+        allocate.add(ATTR_REQUESTED_TRANSPORT, &[PROTOCOL_UDP, 0, 0, 0]);
+
+        stream.write_all(&binding.raw).await.unwrap();
+        stream.write_all(&allocate.raw).await.unwrap();
+
+        let mut response = Message::new();
+        response
+            .unmarshal_binary(&read_frame(&mut stream).await.unwrap().unwrap())
+            .unwrap();
+        let mut mapped = XorMappedAddress::default();
+        mapped
+            .get_from_as(&response, ATTR_XORMAPPED_ADDRESS)
+            .unwrap();
+        assert_eq!(
+            SocketAddr::new(mapped.ip, mapped.port),
+            stream.local_addr().unwrap()
+        );
+
+        response
+            .unmarshal_binary(&read_frame(&mut stream).await.unwrap().unwrap())
+            .unwrap();
+        let mut error = ErrorCodeAttribute::default();
+        stun::message::Getter::get_from(&mut error, &response).unwrap();
+        assert_eq!(error.code.0, CODE_UNAUTHORIZED.0);
+        assert!(TextAttribute::get_from_as(&response, ATTR_NONCE).is_ok());
+    }
+
+    #[test]
+    fn test_channel_data_padding() {
+        let packet = encode_channel_data(0x4000, b"abcde", true);
+        assert_eq!(packet.len(), 12);
+        assert_eq!(decode_channel_data(&packet), Some((0x4000, &b"abcde"[..])));
+    }
+}